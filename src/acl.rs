@@ -0,0 +1,373 @@
+use actix_web::web;
+
+use crate::db::{acl, groups, Pool};
+use crate::middleware::{Must, Requirement};
+use crate::models::{AclEntry, AclKind, AclQueries, Group, GroupQueries, Permission, User};
+use crate::reserr::ResErr;
+use crate::storage::{blocking, Store};
+use crate::trash::TRASH;
+use crate::versions::VERSIONS;
+
+// "/a//b/./c/" -> "/a/b/c", root is "/"
+pub fn normalize(path: &str) -> Result<String, ResErr> {
+    let mut parts = Vec::new();
+
+    for part in path.split(['/', '\\']) {
+        match part {
+            "" | "." => continue,
+            ".." => return Err(ResErr::BadClientData("you cant use '..' in path")),
+            part => parts.push(part),
+        }
+    }
+
+    Ok(format!("/{}", parts.join("/")))
+}
+
+// both paths must be normalized
+pub fn covers(prefix: &str, path: &str) -> bool {
+    prefix == "/" || path == prefix || path.starts_with(&format!("{}/", prefix))
+}
+
+// trash and old versions are reached only through their own endpoints
+pub fn hidden(path: &str) -> bool {
+    covers(TRASH, path) || covers(VERSIONS, path)
+}
+
+// existing folder given by admin, it has to be inside root of admin
+pub async fn inside_root(storage: &web::Data<Store>, root: &str, path: &str) -> Result<String, ResErr> {
+    let path = normalize(path)?;
+
+    if !covers(&normalize(root)?, &path) {
+        return Err(ResErr::BadClientData("folder is outside of your root"));
+    }
+
+    let storage = storage.clone();
+    blocking(move || {
+        if !storage.exists(&path) {
+            return Err(ResErr::BadClientData("path dont exist"));
+        }
+        Ok(path)
+    })
+    .await
+}
+
+fn rank(kind: AclKind) -> u8 {
+    match kind {
+        AclKind::User => 2,
+        AclKind::Group => 1,
+        AclKind::Everyone => 0,
+    }
+}
+
+// root folder with its own quota, of user or of group
+pub struct Home {
+    pub path: String,
+    pub size: u32,
+}
+
+pub struct Acl {
+    pub user_id: u32,
+    entries: Vec<AclEntry>,
+    role: Vec<Permission>,
+    home: Home,
+    pub groups: Vec<Group>,
+}
+
+impl Acl {
+    pub fn load<R: Requirement>(db: &Pool, token: &Must<R>) -> Result<Acl, ResErr> {
+        Acl::for_user(db, &token.user, token.permissions.clone())
+    }
+
+    pub fn for_user(db: &Pool, user: &User, permissions: Vec<Permission>) -> Result<Acl, ResErr> {
+        let entries = (acl::execute(db, AclQueries::GetEntriesForUser(user.id))
+            .map_err(|_| ResErr::InternalError("cant get acl"))?)
+        .many();
+
+        let groups = (groups::execute(db, GroupQueries::GetUserGroups(user.id))
+            .map_err(|_| ResErr::InternalError("cant get groups"))?)
+        .many();
+
+        Ok(Acl {
+            user_id: user.id,
+            entries,
+            role: permissions,
+            home: Home {
+                path: user.path.clone(),
+                size: user.size,
+            },
+            groups,
+        })
+    }
+
+    // "@name/..." is inside home folder of group, everything else inside root of user
+    fn split<'a>(&'a self, filename: &'a str) -> Result<(&'a str, u32, &'a str), ResErr> {
+        let filename = filename.trim_start_matches('/');
+
+        match filename.strip_prefix('@') {
+            Some(rest) => {
+                let (name, rest) = rest.split_once('/').unwrap_or((rest, ""));
+                let group = self
+                    .groups
+                    .iter()
+                    .find(|g| g.name == name)
+                    .ok_or(ResErr::BadClientData("you are not member of this group"))?;
+
+                Ok((&group.path, group.size, rest))
+            }
+            None => Ok((&self.home.path, self.home.size, filename)),
+        }
+    }
+
+    // home whose quota is used for path from request
+    pub fn home(&self, filename: &str) -> Result<Home, ResErr> {
+        let (path, size, _) = self.split(filename)?;
+
+        Ok(Home {
+            path: normalize(path)?,
+            size,
+        })
+    }
+
+    // entries of most specific folder win, entry for user is stronger than entry for everyone,
+    // without any entry rights of role are used, acl never gives more than role
+    pub fn permissions(&self, path: &str) -> Vec<Permission> {
+        let best = self
+            .entries
+            .iter()
+            .filter(|e| covers(&e.path, path))
+            .map(|e| (e.path.len(), rank(e.kind)))
+            .max();
+
+        let best = match best {
+            Some(v) => v,
+            None => return self.role.clone(),
+        };
+
+        let allowed: Vec<Permission> = self
+            .entries
+            .iter()
+            .filter(|e| covers(&e.path, path) && (e.path.len(), rank(e.kind)) == best)
+            .flat_map(|e| e.permissions.iter().copied())
+            .collect();
+
+        self.role
+            .iter()
+            .copied()
+            .filter(|p| allowed.contains(p))
+            .collect()
+    }
+
+    pub fn can(&self, path: &str, permission: Permission) -> bool {
+        !hidden(path) && self.permissions(path).contains(&permission)
+    }
+
+    // path in storage which user reaches through own root or home of some group
+    pub fn reaches(&self, path: &str, permission: Permission) -> bool {
+        let inside = |home: &str| normalize(home).is_ok_and(|home| covers(&home, path));
+
+        (inside(&self.home.path) || self.groups.iter().any(|g| inside(&g.path))) && self.can(path, permission)
+    }
+
+    // what user can do with path, home folders cant be deleted or renamed
+    pub fn allowed(&self, path: &str) -> Vec<Permission> {
+        let home = |home: &str| normalize(home).is_ok_and(|home| home == path);
+        let is_home = home(&self.home.path) || self.groups.iter().any(|g| home(&g.path));
+
+        [
+            Permission::Read,
+            Permission::Write,
+            Permission::Delete,
+            Permission::Rename,
+            Permission::Share,
+        ]
+        .iter()
+        .copied()
+        .filter(|&p| match p {
+            Permission::Delete | Permission::Rename => !is_home && self.can_all(path, p),
+            _ => self.can(path, p),
+        })
+        .collect()
+    }
+
+    // folders with own acl, deepest first, and whether they allow permission,
+    // everything inside folder is the same as folder until some deeper one has own acl
+    pub fn folders(&self, permission: Permission) -> Vec<(String, bool)> {
+        let mut paths: Vec<&String> = self.entries.iter().map(|e| &e.path).collect();
+        paths.sort_by(|a, b| b.len().cmp(&a.len()).then(a.cmp(b)));
+        paths.dedup();
+
+        paths
+            .into_iter()
+            .map(|path| (path.clone(), self.can(path, permission)))
+            .collect()
+    }
+
+    // for operations on whole folder, every folder with own acl inside must allow it too
+    pub fn can_all(&self, path: &str, permission: Permission) -> bool {
+        self.can(path, permission)
+            && self
+                .entries
+                .iter()
+                .filter(|e| covers(path, &e.path))
+                .all(|e| self.can(&e.path, permission))
+    }
+
+    // joins path from request with its home and checks it,
+    // delete and rename affect everything inside, so whole subtree must allow them
+    pub fn resolve(&self, filename: &str, permission: Permission) -> Result<String, ResErr> {
+        let (root, _, rest) = self.split(filename)?;
+        let path = normalize(&format!("{}/{}", root, rest))?;
+
+        if path == normalize(root)? && (permission == Permission::Delete || permission == Permission::Rename) {
+            return Err(ResErr::BadClientData("cant delete or rename home folder"));
+        }
+
+        let allowed = match permission {
+            Permission::Delete | Permission::Rename => self.can_all(&path, permission),
+            _ => self.can(&path, permission),
+        };
+
+        if !allowed {
+            return Err(ResErr::BadClientData("you dont have access to this path"));
+        }
+
+        Ok(path)
+    }
+
+    // same as resolve for path which is going to be created,
+    // names starting with "@" would be read as home of group, so nobody can create them
+    pub fn resolve_new(&self, filename: &str, permission: Permission) -> Result<String, ResErr> {
+        let (_, _, rest) = self.split(filename)?;
+
+        if rest.split(['/', '\\']).any(|part| part.starts_with('@')) {
+            return Err(ResErr::BadClientData("names starting with '@' are reserved for groups"));
+        }
+
+        self.resolve(filename, permission)
+    }
+}
+
+// for handlers which need only one path
+pub fn resolve<R: Requirement>(
+    db: &Pool,
+    token: &Must<R>,
+    filename: &str,
+    permission: Permission,
+) -> Result<String, ResErr> {
+    Acl::load(db, token)?.resolve(filename, permission)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, kind: AclKind, permissions: &[Permission]) -> AclEntry {
+        AclEntry {
+            id: 0,
+            path: path.to_string(),
+            kind,
+            subject: None,
+            permissions: permissions.to_vec(),
+        }
+    }
+
+    // bob has home "/bob" and is in group "team" with home "/teamdir"
+    fn bob(entries: Vec<AclEntry>) -> Acl {
+        Acl {
+            user_id: 2,
+            entries,
+            role: vec![
+                Permission::Read,
+                Permission::Write,
+                Permission::Delete,
+                Permission::Rename,
+            ],
+            home: Home {
+                path: "/bob".to_string(),
+                size: 100,
+            },
+            groups: vec![Group {
+                id: 1,
+                name: "team".to_string(),
+                size: 50,
+                path: "/teamdir".to_string(),
+            }],
+        }
+    }
+
+    #[test]
+    fn normalize_cleans_path() {
+        assert_eq!(normalize("").unwrap(), "/");
+        assert_eq!(normalize("a//b/./c/").unwrap(), "/a/b/c");
+        assert_eq!(normalize("a\\b").unwrap(), "/a/b");
+        assert!(normalize("a/../b").is_err());
+        assert!(normalize("..").is_err());
+    }
+
+    #[test]
+    fn covers_whole_names_only() {
+        assert!(covers("/", "/a"));
+        assert!(covers("/a", "/a"));
+        assert!(covers("/a", "/a/b"));
+        assert!(!covers("/a", "/ab"));
+        assert!(!covers("/a/b", "/a"));
+    }
+
+    #[test]
+    fn trash_and_versions_are_hidden() {
+        assert!(hidden(TRASH));
+        assert!(hidden(&format!("{}/1", VERSIONS)));
+        assert!(!hidden("/trash"));
+    }
+
+    #[test]
+    fn paths_are_inside_home_or_group() {
+        let acl = bob(vec![]);
+
+        assert_eq!(acl.resolve("docs/a.txt", Permission::Read).unwrap(), "/bob/docs/a.txt");
+        assert_eq!(acl.resolve("/@team/a.txt", Permission::Read).unwrap(), "/teamdir/a.txt");
+        assert_eq!(acl.home("@team/x").unwrap().size, 50);
+        assert!(acl.resolve("@other/a.txt", Permission::Read).is_err());
+        assert!(acl.resolve("", Permission::Delete).is_err());
+        assert!(acl.resolve("@team", Permission::Rename).is_err());
+    }
+
+    #[test]
+    fn names_starting_with_at_cant_be_created() {
+        let acl = bob(vec![]);
+
+        assert!(acl.resolve_new("@evil", Permission::Write).is_err());
+        assert!(acl.resolve_new("docs/@evil", Permission::Write).is_err());
+        assert!(acl.resolve_new("@team/@evil", Permission::Write).is_err());
+        assert_eq!(acl.resolve_new("@team/a@b", Permission::Write).unwrap(), "/teamdir/a@b");
+    }
+
+    #[test]
+    fn most_specific_entry_wins_and_never_gives_more_than_role() {
+        let acl = bob(vec![
+            entry("/bob/shared", AclKind::Everyone, &[Permission::Read]),
+            entry("/bob/shared", AclKind::User, &[Permission::Read, Permission::Write, Permission::Share]),
+            entry("/bob/shared/locked", AclKind::Everyone, &[Permission::Read]),
+        ]);
+
+        assert!(acl.can("/bob/shared/a", Permission::Write));
+        assert!(!acl.can("/bob/shared/a", Permission::Share));
+        assert!(!acl.can("/bob/shared/locked/a", Permission::Write));
+        assert!(acl.can("/bob/other", Permission::Delete));
+        assert!(!acl.can_all("/bob/shared", Permission::Delete));
+        assert!(!acl.can(&format!("{}/x", TRASH), Permission::Read));
+    }
+
+    #[test]
+    fn homes_cant_be_deleted_or_renamed() {
+        let acl = bob(vec![]);
+
+        assert_eq!(
+            acl.allowed("/teamdir"),
+            vec![Permission::Read, Permission::Write]
+        );
+        assert!(acl.allowed("/bob/a").contains(&Permission::Delete));
+        assert!(acl.reaches("/teamdir/a", Permission::Read));
+        assert!(!acl.reaches("/alice", Permission::Read));
+    }
+}
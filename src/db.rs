@@ -1,13 +1,47 @@
 use actix_web::web::Json;
 use bcrypt::{hash, DEFAULT_COST};
-use rusqlite::NO_PARAMS;
+use chrono::Utc;
+use rusqlite::{params, NO_PARAMS};
 
 use crate::models::{ChangingUser, Queries, User};
 
+pub mod acl;
+pub mod api_keys;
+pub mod attempts;
+pub mod blob_paths;
+pub mod blobs;
+pub mod contents;
+pub mod files;
+pub mod groups;
+pub mod index;
+pub mod jobs;
+pub mod labels;
+pub mod mail_tokens;
+pub mod roles;
+pub mod sessions;
+pub mod settings;
+pub mod shares;
+pub mod totp;
+pub mod trash;
+pub mod uploads;
+pub mod usage;
+pub mod versions;
+
 pub type Pool = r2d2::Pool<r2d2_sqlite::SqliteConnectionManager>;
 pub type Connection = r2d2::PooledConnection<r2d2_sqlite::SqliteConnectionManager>;
 use r2d2_sqlite::{self};
 
+// condition for column with path or anything inside it, param is path as it is, root included,
+// it is compared as range instead of substr, so index on column is used ('0' comes right after '/')
+pub fn inside(column: &str, param: usize) -> String {
+    format!("({0} = rtrim(?{1}, '/') OR {2})", column, param, below(column, param))
+}
+
+// only what is inside path, without path itself
+pub fn below(column: &str, param: usize) -> String {
+    format!("({0} >= rtrim(?{1}, '/') || '/' AND {0} < rtrim(?{1}, '/') || '0')", column, param)
+}
+
 pub enum QueryResult<T> {
     Many(Vec<T>),
     One(T),
@@ -27,7 +61,7 @@ impl<T> QueryResult<T> {
             _ => unreachable!(),
         }
     }
-    pub fn none(self) -> () {
+    pub fn none(self) {
         match self {
             QueryResult::None(t) => t,
             _ => unreachable!(),
@@ -74,6 +108,22 @@ pub fn execute(pool: &Pool, query: Queries) -> Result<QueryResult<User>, rusqlit
             id,
             &user,
         )?),
+        Queries::UpdatePassById(id, pass) => QueryResult::None(update_pass_by_id(
+            pool.get()
+                .map_err(|_| rusqlite::Error::QueryReturnedNoRows)?,
+            id,
+            &pass,
+        )?),
+        Queries::SetVerifiedById(id) => QueryResult::None(set_verified_by_id(
+            pool.get()
+                .map_err(|_| rusqlite::Error::QueryReturnedNoRows)?,
+            id,
+        )?),
+        Queries::GetUsersByGroup(id) => QueryResult::Many(get_users_by_group(
+            pool.get()
+                .map_err(|_| rusqlite::Error::QueryReturnedNoRows)?,
+            id,
+        )?),
     })
 }
 
@@ -95,6 +145,7 @@ fn get_user_by_id(conn: Connection, id: u32) -> Result<User, rusqlite::Error> {
             size: row.get(4)?,
             path: row.get(5)?,
             status: row.get(6)?,
+            verified: row.get(7)?,
         })
     })
 }
@@ -117,6 +168,7 @@ fn get_user_by_email(conn: Connection, email: String) -> Result<User, rusqlite::
             size: row.get(4)?,
             path: row.get(5)?,
             status: row.get(6)?,
+            verified: row.get(7)?,
         })
     })
 }
@@ -137,6 +189,31 @@ fn get_all_users(conn: Connection) -> Result<Vec<User>, rusqlite::Error> {
             size: row.get(4)?,
             path: row.get(5)?,
             status: row.get(6)?,
+            verified: row.get(7)?,
+        })
+    })
+    .and_then(Iterator::collect)
+}
+
+fn get_users_by_group(conn: Connection, group_id: u32) -> Result<Vec<User>, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT Users.*
+        FROM Users
+        JOIN GroupMembers ON GroupMembers.user_id = Users.id
+        WHERE GroupMembers.group_id=(?1)
+    ",
+    )?
+    .query_map(&[&group_id], |row| {
+        Ok(User {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            email: row.get(2)?,
+            pass: row.get(3)?,
+            size: row.get(4)?,
+            path: row.get(5)?,
+            status: row.get(6)?,
+            verified: row.get(7)?,
         })
     })
     .and_then(Iterator::collect)
@@ -217,6 +294,65 @@ fn update_me_by_id(
     Ok(())
 }
 
+fn update_pass_by_id(conn: Connection, id: u32, pass: &str) -> Result<(), rusqlite::Error> {
+    conn.prepare(
+        "
+        UPDATE Users 
+        SET 
+            pass = ?2
+        WHERE id=(?1)
+    ",
+    )?
+    .execute(&[&id.to_string(), pass])?;
+    Ok(())
+}
+
+fn set_verified_by_id(conn: Connection, id: u32) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        UPDATE Users
+        SET verified = ?2
+        WHERE id=(?1) AND verified IS NULL
+    ",
+        params![id, Utc::now().timestamp()],
+    )?;
+    Ok(())
+}
+
+// users from before verified column are verified unless they still wait for token from mail,
+// runs only once, when column is added
+fn add_verified_column(conn: &Pool) {
+    if conn
+        .get()
+        .unwrap()
+        .execute("ALTER TABLE Users ADD COLUMN verified INTEGER", NO_PARAMS)
+        .is_err()
+    {
+        return;
+    }
+
+    mail_tokens::create_table(conn);
+    conn.get()
+        .unwrap()
+        .execute(
+            "
+            UPDATE Users
+            SET verified = ?1
+            WHERE id NOT IN (SELECT user_id FROM MailTokens WHERE kind = 'verify' AND used = 0)
+        ",
+            &[&Utc::now().timestamp()],
+        )
+        .unwrap();
+}
+
+// every test gets its own database file, test makes tables it needs
+#[cfg(test)]
+pub fn test_pool() -> Pool {
+    let path = std::env::temp_dir().join(format!("cloud-test-{}.db", crate::utils::random_token(16)));
+
+    Pool::new(r2d2_sqlite::SqliteConnectionManager::file(path)).unwrap()
+}
+
 pub fn create_tables(conn: &Pool) {
     conn.get()
         .unwrap()
@@ -228,17 +364,19 @@ pub fn create_tables(conn: &Pool) {
             pass TEXT NOT NULL,
             size UNSIGNED INT,
 	        path TEXT NOT NULL,
-            status UNSIGNED TINYINT)
+            status UNSIGNED TINYINT,
+            verified INTEGER)
         ",
             NO_PARAMS,
         )
         .unwrap();
+    add_verified_column(conn);
     conn.get()
         .unwrap()
         .execute(
             "
-            INSERT INTO Users (name, email, pass, size, path, status) 
-            SELECT ?1, ?2, ?3, ?4, ?5, ?6
+            INSERT INTO Users (name, email, pass, size, path, status, verified)
+            SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7
             WHERE NOT EXISTS(SELECT 1 FROM Users WHERE status = 1)
         ",
             &[
@@ -248,7 +386,122 @@ pub fn create_tables(conn: &Pool) {
                 &*u32::MAX.to_string(),
                 "/",
                 "1",
+                &*Utc::now().timestamp().to_string(),
             ],
         )
         .unwrap();
+
+    acl::create_table(conn);
+    api_keys::create_table(conn);
+    attempts::create_table(conn);
+    blob_paths::create_table(conn);
+    blobs::create_table(conn);
+    contents::create_table(conn);
+    files::create_table(conn);
+    groups::create_table(conn);
+    index::create_table(conn);
+    jobs::create_table(conn);
+    labels::create_table(conn);
+    mail_tokens::create_table(conn);
+    roles::create_table(conn);
+    sessions::create_table(conn);
+    settings::create_table(conn);
+    shares::create_table(conn);
+    totp::create_table(conn);
+    trash::create_table(conn);
+    uploads::create_table(conn);
+    usage::create_table(conn);
+    versions::create_table(conn);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::MailTokenQueries;
+
+    fn matching(condition: &str, path: &str) -> Vec<String> {
+        let db = test_pool();
+        let conn = db.get().unwrap();
+        conn.execute("create table Paths (path TEXT)", NO_PARAMS).unwrap();
+        for path in ["/", "/a", "/a/b", "/a/b/c", "/ab", "/a0", "/a.txt"].iter() {
+            conn.execute("INSERT INTO Paths (path) VALUES (?1)", &[path]).unwrap();
+        }
+
+        let paths = conn
+            .prepare(&format!("SELECT path FROM Paths WHERE {} ORDER BY path", condition))
+            .unwrap()
+            .query_map(&[path], |row| row.get(0))
+            .and_then(Iterator::collect)
+            .unwrap();
+        paths
+    }
+
+    #[test]
+    fn prefix_holds_only_what_is_inside_path() {
+        assert_eq!(matching(&inside("path", 1), "/a"), ["/a", "/a/b", "/a/b/c"]);
+        assert_eq!(matching(&inside("path", 1), "/a/"), ["/a", "/a/b", "/a/b/c"]);
+        assert_eq!(matching(&below("path", 1), "/a"), ["/a/b", "/a/b/c"]);
+        assert_eq!(matching(&inside("path", 1), "/").len(), 7);
+    }
+
+    #[test]
+    fn users_waiting_for_mail_stay_unverified_after_upgrade() {
+        let db = test_pool();
+        let conn = db.get().unwrap();
+        conn.execute(
+            "create table Users (
+            id integer primary key,
+            name VARCHAR(10) NOT NULL UNIQUE,
+            email VARCHAR(30) NOT NULL UNIQUE,
+            pass TEXT NOT NULL,
+            size UNSIGNED INT,
+            path TEXT NOT NULL,
+            status UNSIGNED TINYINT)",
+            NO_PARAMS,
+        )
+        .unwrap();
+        for name in ["old", "waiting"].iter() {
+            conn.execute(
+                "INSERT INTO Users (name, email, pass, size, path, status) VALUES (?1, ?1, '', 1, '/', 3)",
+                &[name],
+            )
+            .unwrap();
+        }
+        let waiting = conn.last_insert_rowid() as u32;
+        mail_tokens::create_table(&db);
+        (mail_tokens::execute(&db, MailTokenQueries::Issue(waiting, "verify", "hash".to_string(), 0)).unwrap()).none();
+
+        create_tables(&db);
+
+        let verified = |email: &str| (execute(&db, Queries::GetUserByEmail(email.to_string())).unwrap()).one().verified;
+        assert!(verified("old").is_some());
+        assert!(verified("waiting").is_none());
+    }
+
+    #[test]
+    fn search_index_from_before_gets_lowercase_names() {
+        let db = test_pool();
+        let conn = db.get().unwrap();
+        conn.execute(
+            "create table SearchIndex (
+            path TEXT primary key,
+            name TEXT NOT NULL,
+            ext TEXT NOT NULL,
+            is_dir INTEGER NOT NULL,
+            size INTEGER NOT NULL,
+            modified INTEGER,
+            indexed INTEGER NOT NULL)",
+            NO_PARAMS,
+        )
+        .unwrap();
+        conn.execute("INSERT INTO SearchIndex VALUES ('/a/Notes.TXT', 'Notes.TXT', 'txt', 0, 1, 0, 0)", NO_PARAMS)
+            .unwrap();
+
+        index::create_table(&db);
+
+        let lower: String = conn
+            .query_row("SELECT lower_name FROM SearchIndex", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(lower, "notes.txt");
+    }
 }
@@ -0,0 +1,135 @@
+use rusqlite::{params, NO_PARAMS};
+
+use super::roles::{permissions_to_text, text_to_permissions};
+use super::{Connection, Pool, QueryResult};
+use crate::models::{AclEntry, AclKind, AclQueries, NewAclEntry};
+
+pub fn execute(pool: &Pool, query: AclQueries) -> Result<QueryResult<AclEntry>, rusqlite::Error> {
+    let conn = pool
+        .get()
+        .map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
+
+    Ok(match query {
+        AclQueries::GetEntriesByPath(path) => {
+            QueryResult::Many(get_entries_by_path(conn, &path)?)
+        }
+        AclQueries::GetEntriesForUser(user_id) => {
+            QueryResult::Many(get_entries_for_user(conn, user_id)?)
+        }
+        AclQueries::SetEntries(path, entries) => {
+            QueryResult::None(set_entries(conn, &path, &entries)?)
+        }
+        AclQueries::DeleteUserEntries(user_id) => {
+            QueryResult::None(delete_subject_entries(conn, AclKind::User, user_id)?)
+        }
+        AclQueries::DeleteGroupEntries(group_id) => {
+            QueryResult::None(delete_subject_entries(conn, AclKind::Group, group_id)?)
+        }
+    })
+}
+
+fn row_to_entry(row: &rusqlite::Row) -> Result<AclEntry, rusqlite::Error> {
+    let kind: String = row.get(2)?;
+    let permissions: String = row.get(4)?;
+
+    Ok(AclEntry {
+        id: row.get(0)?,
+        path: row.get(1)?,
+        kind: AclKind::parse(&kind).ok_or(rusqlite::Error::InvalidQuery)?,
+        subject: row.get(3)?,
+        permissions: text_to_permissions(&permissions),
+    })
+}
+
+fn get_entries_by_path(conn: Connection, path: &str) -> Result<Vec<AclEntry>, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Acl
+        WHERE path=(?1)
+    ",
+    )?
+    .query_map(&[&path], row_to_entry)
+    .and_then(Iterator::collect)
+}
+
+// every entry which can apply to user, matching path is done by caller
+fn get_entries_for_user(conn: Connection, user_id: u32) -> Result<Vec<AclEntry>, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Acl
+        WHERE (kind = 'user' AND subject = ?1)
+            OR (kind = 'group' AND subject IN (SELECT group_id FROM GroupMembers WHERE user_id = ?1))
+            OR kind = 'everyone'
+    ",
+    )?
+    .query_map(&[&user_id], row_to_entry)
+    .and_then(Iterator::collect)
+}
+
+// acl of folder is always replaced as whole
+fn set_entries(mut conn: Connection, path: &str, entries: &[NewAclEntry]) -> Result<(), rusqlite::Error> {
+    let tx = conn.transaction()?;
+
+    tx.execute(
+        "
+        DELETE
+        FROM Acl
+        WHERE path=(?1)
+    ",
+        &[&path],
+    )?;
+
+    for entry in entries {
+        tx.execute(
+            "
+            INSERT INTO Acl (path, kind, subject, permissions)
+            VALUES(?1, ?2, ?3, ?4)
+        ",
+            params![
+                path,
+                entry.kind.as_str(),
+                entry.subject,
+                permissions_to_text(&entry.permissions)
+            ],
+        )?;
+    }
+
+    tx.commit()
+}
+
+fn delete_subject_entries(conn: Connection, kind: AclKind, subject: u32) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        DELETE
+        FROM Acl
+        WHERE kind=(?1) AND subject=(?2)
+    ",
+        params![kind.as_str(), subject],
+    )?;
+    Ok(())
+}
+
+pub fn create_table(conn: &Pool) {
+    conn.get()
+        .unwrap()
+        .execute(
+            "create table if not exists Acl (
+            id integer primary key,
+            path TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            subject UNSIGNED INT,
+            permissions TEXT NOT NULL)
+        ",
+            NO_PARAMS,
+        )
+        .unwrap();
+    conn.get()
+        .unwrap()
+        .execute(
+            "create index if not exists AclPath on Acl (path)",
+            NO_PARAMS,
+        )
+        .unwrap();
+}
@@ -0,0 +1,159 @@
+use actix_web::web::Json;
+use chrono::Utc;
+use rusqlite::{params, NO_PARAMS};
+
+use super::{Connection, Pool, QueryResult};
+use crate::models::{ApiKey, ApiKeyQueries, NewApiKey};
+
+pub fn execute(pool: &Pool, query: ApiKeyQueries) -> Result<QueryResult<ApiKey>, rusqlite::Error> {
+    let conn = pool
+        .get()
+        .map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
+
+    Ok(match query {
+        ApiKeyQueries::AddApiKey(user_id, key, key_hash) => {
+            QueryResult::One(add_api_key(conn, user_id, &key, &key_hash)?)
+        }
+        ApiKeyQueries::GetApiKeyByHash(key_hash) => {
+            QueryResult::One(get_api_key_by_hash(conn, &key_hash)?)
+        }
+        ApiKeyQueries::GetUserApiKeys(user_id) => {
+            QueryResult::Many(get_user_api_keys(conn, user_id)?)
+        }
+        ApiKeyQueries::DeleteApiKey(user_id, id) => {
+            QueryResult::None(delete_api_key(conn, user_id, id)?)
+        }
+        ApiKeyQueries::DeleteUserApiKeys(user_id) => {
+            QueryResult::None(delete_user_api_keys(conn, user_id)?)
+        }
+        ApiKeyQueries::TouchApiKey(id) => QueryResult::None(touch_api_key(conn, id)?),
+    })
+}
+
+// column 3 is hash of the key, which never leaves db
+fn row_to_api_key(row: &rusqlite::Row) -> Result<ApiKey, rusqlite::Error> {
+    Ok(ApiKey {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        name: row.get(2)?,
+        created: row.get(4)?,
+        last_used: row.get(5)?,
+        expires: row.get(6)?,
+        scope: row.get(7)?,
+    })
+}
+
+fn add_api_key(
+    conn: Connection,
+    user_id: u32,
+    key: &Json<NewApiKey>,
+    key_hash: &str,
+) -> Result<ApiKey, rusqlite::Error> {
+    conn.execute(
+        "
+        INSERT INTO ApiKeys (user_id, name, key_hash, created, last_used, expires, scope)
+        VALUES(?1, ?2, ?3, ?4, NULL, ?5, ?6)
+    ",
+        params![
+            user_id,
+            key.name,
+            key_hash,
+            Utc::now().timestamp(),
+            key.expires,
+            key.scope
+        ],
+    )?;
+
+    conn.prepare(
+        "
+        SELECT *
+        FROM ApiKeys
+        WHERE id=(?1)
+        LIMIT 1
+    ",
+    )?
+    .query_row(&[&conn.last_insert_rowid()], row_to_api_key)
+}
+
+fn get_api_key_by_hash(conn: Connection, key_hash: &str) -> Result<ApiKey, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM ApiKeys
+        WHERE key_hash=(?1)
+        LIMIT 1
+    ",
+    )?
+    .query_row(&[&key_hash], row_to_api_key)
+}
+
+fn get_user_api_keys(conn: Connection, user_id: u32) -> Result<Vec<ApiKey>, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM ApiKeys
+        WHERE user_id=(?1)
+    ",
+    )?
+    .query_map(&[&user_id], row_to_api_key)
+    .and_then(Iterator::collect)
+}
+
+fn delete_api_key(conn: Connection, user_id: u32, id: u32) -> Result<(), rusqlite::Error> {
+    let changed = conn.execute(
+        "
+        DELETE
+        FROM ApiKeys
+        WHERE user_id=(?1) AND id=(?2)
+    ",
+        &[&user_id, &id],
+    )?;
+
+    if changed == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    Ok(())
+}
+
+fn delete_user_api_keys(conn: Connection, user_id: u32) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        DELETE
+        FROM ApiKeys
+        WHERE user_id=(?1)
+    ",
+        &[&user_id],
+    )?;
+    Ok(())
+}
+
+fn touch_api_key(conn: Connection, id: u32) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        UPDATE ApiKeys
+        SET last_used = ?2
+        WHERE id=(?1)
+    ",
+        params![id, Utc::now().timestamp()],
+    )?;
+    Ok(())
+}
+
+pub fn create_table(conn: &Pool) {
+    conn.get()
+        .unwrap()
+        .execute(
+            "create table if not exists ApiKeys (
+            id integer primary key,
+            user_id integer NOT NULL,
+            name TEXT NOT NULL,
+            key_hash TEXT NOT NULL UNIQUE,
+            created INTEGER NOT NULL,
+            last_used INTEGER,
+            expires INTEGER,
+            scope TEXT NOT NULL)
+        ",
+            NO_PARAMS,
+        )
+        .unwrap();
+}
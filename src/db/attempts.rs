@@ -0,0 +1,84 @@
+use rusqlite::{params, NO_PARAMS};
+
+use super::{Connection, Pool, QueryResult};
+use crate::models::{AttemptQueries, LoginAttempt};
+
+pub fn execute(pool: &Pool, query: AttemptQueries) -> Result<QueryResult<LoginAttempt>, rusqlite::Error> {
+    let conn = pool
+        .get()
+        .map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
+
+    Ok(match query {
+        AttemptQueries::GetAttempt(key) => QueryResult::One(get_attempt(conn, &key)?),
+        AttemptQueries::SaveAttempt(attempt) => QueryResult::None(save_attempt(conn, &attempt)?),
+        AttemptQueries::ClearAttempts(key) => QueryResult::None(delete_attempt(conn, &key)?),
+    })
+}
+
+fn get_attempt(conn: Connection, key: &str) -> Result<LoginAttempt, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM LoginAttempts
+        WHERE key=(?1)
+        LIMIT 1
+    ",
+    )?
+    .query_row(&[&key], |row| {
+        Ok(LoginAttempt {
+            key: row.get(0)?,
+            failures: row.get(1)?,
+            last_failure: row.get(2)?,
+            locked_until: row.get(3)?,
+            unlocked_at: row.get(4)?,
+            unlocked_by: row.get(5)?,
+        })
+    })
+}
+
+fn save_attempt(conn: Connection, attempt: &LoginAttempt) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        INSERT OR REPLACE INTO LoginAttempts (key, failures, last_failure, locked_until, unlocked_at, unlocked_by)
+        VALUES(?1, ?2, ?3, ?4, ?5, ?6)
+    ",
+        params![
+            attempt.key,
+            attempt.failures,
+            attempt.last_failure,
+            attempt.locked_until,
+            attempt.unlocked_at,
+            attempt.unlocked_by
+        ],
+    )?;
+    Ok(())
+}
+
+fn delete_attempt(conn: Connection, key: &str) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        DELETE
+        FROM LoginAttempts
+        WHERE key=(?1)
+    ",
+        &[&key],
+    )?;
+    Ok(())
+}
+
+pub fn create_table(conn: &Pool) {
+    conn.get()
+        .unwrap()
+        .execute(
+            "create table if not exists LoginAttempts (
+            key TEXT primary key,
+            failures UNSIGNED INT NOT NULL,
+            last_failure INTEGER NOT NULL,
+            locked_until INTEGER NOT NULL,
+            unlocked_at INTEGER,
+            unlocked_by integer)
+        ",
+            NO_PARAMS,
+        )
+        .unwrap();
+}
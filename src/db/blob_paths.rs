@@ -0,0 +1,144 @@
+use rusqlite::{params, NO_PARAMS};
+
+use super::{below, inside, Connection, Pool, QueryResult};
+use crate::models::{BlobPath, BlobPathQueries};
+
+pub fn execute(pool: &Pool, query: BlobPathQueries) -> Result<QueryResult<BlobPath>, rusqlite::Error> {
+    let conn = pool
+        .get()
+        .map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
+
+    Ok(match query {
+        BlobPathQueries::GetPath(path) => QueryResult::One(get_path(conn, &path)?),
+        BlobPathQueries::GetChildren(folder) => QueryResult::Many(get_children(conn, &folder)?),
+        BlobPathQueries::GetPathsOf(hash) => QueryResult::Many(get_paths_of(conn, &hash)?),
+        BlobPathQueries::AddPath(path) => QueryResult::None(add_path(conn, &path)?),
+        BlobPathQueries::SetPath(path) => QueryResult::None(set_path(conn, &path)?),
+        BlobPathQueries::MovePaths(from, to) => QueryResult::None(move_paths(conn, &from, &to)?),
+        BlobPathQueries::DeletePaths(path) => QueryResult::None(delete_paths(conn, &path)?),
+    })
+}
+
+fn row_to_path(row: &rusqlite::Row) -> Result<BlobPath, rusqlite::Error> {
+    Ok(BlobPath {
+        path: row.get(0)?,
+        hash: row.get(1)?,
+        size: row.get::<_, i64>(2)? as u64,
+        modified: row.get(3)?,
+        created: row.get(4)?,
+    })
+}
+
+fn get_path(conn: Connection, path: &str) -> Result<BlobPath, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM BlobPaths
+        WHERE path=(?1)
+        LIMIT 1
+    ",
+    )?
+    .query_row(&[&path], row_to_path)
+}
+
+// files right inside folder, not in its subfolders
+fn get_children(conn: Connection, folder: &str) -> Result<Vec<BlobPath>, rusqlite::Error> {
+    conn.prepare(
+        &format!(
+            "
+            SELECT *
+            FROM BlobPaths
+            WHERE {}
+                AND instr(substr(path, length(?1) + 2), '/') = 0
+        ",
+            below("path", 1),
+        ),
+    )?
+    .query_map(&[&folder.trim_end_matches('/')], row_to_path)
+    .and_then(Iterator::collect)
+}
+
+fn get_paths_of(conn: Connection, hash: &str) -> Result<Vec<BlobPath>, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM BlobPaths
+        WHERE hash=(?1)
+    ",
+    )?
+    .query_map(&[&hash], row_to_path)
+    .and_then(Iterator::collect)
+}
+
+// fails when path is taken
+fn add_path(conn: Connection, path: &BlobPath) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        INSERT INTO BlobPaths (path, hash, size, modified, created)
+        VALUES(?1, ?2, ?3, ?4, ?5)
+    ",
+        params![path.path, path.hash, path.size as i64, path.modified, path.created],
+    )?;
+    Ok(())
+}
+
+// update instead of replace, so triggers move refs from old blob to new one,
+// replaced file keeps time when it was created
+fn set_path(conn: Connection, path: &BlobPath) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        INSERT INTO BlobPaths (path, hash, size, modified, created)
+        VALUES(?1, ?2, ?3, ?4, ?5)
+        ON CONFLICT(path) DO UPDATE SET hash=excluded.hash, size=excluded.size, modified=excluded.modified
+    ",
+        params![path.path, path.hash, path.size as i64, path.modified, path.created],
+    )?;
+    Ok(())
+}
+
+// file or folder was moved, everything inside goes with it
+fn move_paths(conn: Connection, from: &str, to: &str) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        &format!(
+            "
+            UPDATE BlobPaths
+            SET path = (?2) || substr(path, length(?1) + 1)
+            WHERE {}
+        ",
+            inside("path", 1),
+        ),
+        params![from, to],
+    )?;
+    Ok(())
+}
+
+fn delete_paths(conn: Connection, path: &str) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        &format!(
+            "
+            DELETE
+            FROM BlobPaths
+            WHERE {}
+        ",
+            inside("path", 1),
+        ),
+        &[&path],
+    )?;
+    Ok(())
+}
+
+pub fn create_table(conn: &Pool) {
+    conn.get()
+        .unwrap()
+        .execute(
+            "create table if not exists BlobPaths (
+            path TEXT primary key,
+            hash TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            modified INTEGER NOT NULL,
+            created INTEGER NOT NULL)
+        ",
+            NO_PARAMS,
+        )
+        .unwrap();
+}
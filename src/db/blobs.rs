@@ -0,0 +1,131 @@
+use rusqlite::NO_PARAMS;
+
+use super::{Connection, Pool, QueryResult};
+use crate::models::{Blob, BlobQueries};
+
+pub fn execute(pool: &Pool, query: BlobQueries) -> Result<QueryResult<Blob>, rusqlite::Error> {
+    let conn = pool
+        .get()
+        .map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
+
+    Ok(match query {
+        BlobQueries::GetBlob(hash) => QueryResult::One(get_blob(conn, &hash)?),
+        BlobQueries::GetOrphans => QueryResult::Many(get_orphans(conn)?),
+        BlobQueries::GetDropped => QueryResult::Many(get_dropped(conn)?),
+        BlobQueries::DeleteBlob(hash) => QueryResult::None(delete_blob(conn, &hash)?),
+    })
+}
+
+fn row_to_blob(row: &rusqlite::Row) -> Result<Blob, rusqlite::Error> {
+    Ok(Blob {
+        hash: row.get(0)?,
+        size: row.get::<_, i64>(1)? as u64,
+        refs: row.get(2)?,
+    })
+}
+
+fn get_blob(conn: Connection, hash: &str) -> Result<Blob, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Blobs
+        WHERE hash=(?1)
+        LIMIT 1
+    ",
+    )?
+    .query_row(&[&hash], row_to_blob)
+}
+
+// blobs which no path points to anymore
+fn get_orphans(conn: Connection) -> Result<Vec<Blob>, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Blobs
+        WHERE refs <= 0
+    ",
+    )?
+    .query_map(NO_PARAMS, row_to_blob)
+    .and_then(Iterator::collect)
+}
+
+// blobs whose last path was removed or replaced since they were checked,
+// some of them can have new path already
+fn get_dropped(conn: Connection) -> Result<Vec<Blob>, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT Blobs.*
+        FROM DroppedBlobs
+        JOIN Blobs ON Blobs.hash = DroppedBlobs.hash
+    ",
+    )?
+    .query_map(NO_PARAMS, row_to_blob)
+    .and_then(Iterator::collect)
+}
+
+// blob which got new path meanwhile stays, it is not dropped anymore either way
+fn delete_blob(conn: Connection, hash: &str) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        DELETE
+        FROM Blobs
+        WHERE hash=(?1) AND refs <= 0
+    ",
+        &[&hash],
+    )?;
+    conn.execute(
+        "
+        DELETE
+        FROM DroppedBlobs
+        WHERE hash=(?1)
+    ",
+        &[&hash],
+    )?;
+    Ok(())
+}
+
+// refs are counted by triggers on BlobPaths, so they cant get out of sync with paths,
+// blob which lost its last path is noted, so only it is checked and not every blob,
+// triggers are made again on start, so databases from before get them as they are now
+pub fn create_table(conn: &Pool) {
+    conn.get()
+        .unwrap()
+        .execute_batch(
+            "create table if not exists Blobs (
+            hash TEXT primary key,
+            size INTEGER NOT NULL,
+            refs INTEGER NOT NULL);
+
+            create table if not exists DroppedBlobs (
+            hash TEXT primary key);
+
+            drop trigger if exists BlobPathAdded;
+            drop trigger if exists BlobPathChanged;
+            drop trigger if exists BlobPathDeleted;
+
+            create trigger BlobPathAdded AFTER INSERT ON BlobPaths
+            BEGIN
+                INSERT OR IGNORE INTO Blobs (hash, size, refs) VALUES(new.hash, new.size, 0);
+                UPDATE Blobs SET refs = refs + 1 WHERE hash = new.hash;
+            END;
+
+            create trigger BlobPathChanged AFTER UPDATE OF hash ON BlobPaths
+            WHEN old.hash != new.hash
+            BEGIN
+                INSERT OR IGNORE INTO Blobs (hash, size, refs) VALUES(new.hash, new.size, 0);
+                UPDATE Blobs SET refs = refs + 1 WHERE hash = new.hash;
+                UPDATE Blobs SET refs = refs - 1 WHERE hash = old.hash;
+                INSERT OR IGNORE INTO DroppedBlobs (hash)
+                SELECT hash FROM Blobs WHERE hash = old.hash AND refs <= 0;
+            END;
+
+            create trigger BlobPathDeleted AFTER DELETE ON BlobPaths
+            BEGIN
+                UPDATE Blobs SET refs = refs - 1 WHERE hash = old.hash;
+                INSERT OR IGNORE INTO DroppedBlobs (hash)
+                SELECT hash FROM Blobs WHERE hash = old.hash AND refs <= 0;
+            END;
+        ",
+        )
+        .unwrap();
+}
@@ -0,0 +1,167 @@
+use rusqlite::{params, NO_PARAMS};
+
+use super::{below, inside, Connection, Pool, QueryResult};
+use crate::models::{ContentFile, ContentHit, ContentQueries};
+
+pub fn execute(pool: &Pool, query: ContentQueries) -> Result<QueryResult<ContentFile>, rusqlite::Error> {
+    let conn = pool
+        .get()
+        .map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
+
+    Ok(match query {
+        ContentQueries::GetFiles => QueryResult::Many(get_files(conn)?),
+        ContentQueries::SetContent(file, text) => QueryResult::None(set_content(conn, &file, &text)?),
+        ContentQueries::MoveFiles(from, to) => QueryResult::None(move_files(conn, &from, &to)?),
+        ContentQueries::DeleteFiles(path) => QueryResult::None(delete_files(conn, &path)?),
+    })
+}
+
+fn row_to_file(row: &rusqlite::Row) -> Result<ContentFile, rusqlite::Error> {
+    Ok(ContentFile {
+        id: row.get(0)?,
+        path: row.get(1)?,
+        size: row.get::<_, i64>(2)? as u64,
+        modified: row.get(3)?,
+    })
+}
+
+fn get_files(conn: Connection) -> Result<Vec<ContentFile>, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM ContentFiles
+    ",
+    )?
+    .query_map(NO_PARAMS, row_to_file)
+    .and_then(Iterator::collect)
+}
+
+// file keeps its id, so its text is replaced in place
+fn set_content(mut conn: Connection, file: &ContentFile, text: &str) -> Result<(), rusqlite::Error> {
+    let tx = conn.transaction()?;
+
+    tx.execute(
+        "
+        INSERT INTO ContentFiles (path, size, modified)
+        VALUES(?1, ?2, ?3)
+        ON CONFLICT(path) DO UPDATE SET size=excluded.size, modified=excluded.modified
+    ",
+        params![file.path, file.size as i64, file.modified],
+    )?;
+
+    let id: i64 = tx.query_row(
+        "
+        SELECT id
+        FROM ContentFiles
+        WHERE path=(?1)
+    ",
+        &[&file.path],
+        |row| row.get(0),
+    )?;
+
+    tx.execute(
+        "
+        DELETE
+        FROM ContentIndex
+        WHERE rowid=(?1)
+    ",
+        &[&id],
+    )?;
+
+    tx.execute(
+        "
+        INSERT INTO ContentIndex (rowid, text)
+        VALUES(?1, ?2)
+    ",
+        params![id, text],
+    )?;
+
+    tx.commit()
+}
+
+// text stays in index, only path changes
+fn move_files(conn: Connection, from: &str, to: &str) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        &format!(
+            "
+            UPDATE OR REPLACE ContentFiles
+            SET path = (?2) || substr(path, length(?1) + 1)
+            WHERE {}
+        ",
+            inside("path", 1),
+        ),
+        params![from, to],
+    )?;
+    Ok(())
+}
+
+fn delete_files(conn: Connection, path: &str) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        &format!(
+            "
+            DELETE
+            FROM ContentFiles
+            WHERE {}
+        ",
+            inside("path", 1),
+        ),
+        &[&path],
+    )?;
+    Ok(())
+}
+
+// files inside root matching fts5 query, best first, matched words in snippet are between "**"
+pub fn search(pool: &Pool, root: &str, query: &str) -> Result<Vec<ContentHit>, rusqlite::Error> {
+    let conn = pool
+        .get()
+        .map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
+
+    get_hits(conn, root, query)
+}
+
+fn get_hits(conn: Connection, root: &str, query: &str) -> Result<Vec<ContentHit>, rusqlite::Error> {
+    conn.prepare(
+        &format!(
+            "
+            SELECT f.path, snippet(ContentIndex, 0, '**', '**', '...', 16), bm25(ContentIndex)
+            FROM ContentIndex
+            JOIN ContentFiles f ON f.id = ContentIndex.rowid
+            WHERE ContentIndex MATCH (?1)
+                AND {}
+            ORDER BY bm25(ContentIndex)
+        ",
+            below("f.path", 2),
+        ),
+    )?
+    .query_map(params![query, root.trim_end_matches('/')], |row| {
+        Ok(ContentHit {
+            path: row.get(0)?,
+            snippet: row.get(1)?,
+            score: -row.get::<_, f64>(2)?,
+        })
+    })
+    .and_then(Iterator::collect)
+}
+
+// text of deleted file leaves index with it
+pub fn create_table(conn: &Pool) {
+    conn.get()
+        .unwrap()
+        .execute_batch(
+            "create table if not exists ContentFiles (
+            id INTEGER primary key AUTOINCREMENT,
+            path TEXT NOT NULL UNIQUE,
+            size INTEGER NOT NULL,
+            modified INTEGER);
+
+            create virtual table if not exists ContentIndex
+            USING fts5(text, tokenize = 'unicode61 remove_diacritics 2');
+
+            create trigger if not exists ContentFileDeleted AFTER DELETE ON ContentFiles
+            BEGIN
+                DELETE FROM ContentIndex WHERE rowid = old.id;
+            END;
+        ",
+        )
+        .unwrap();
+}
@@ -0,0 +1,95 @@
+use rusqlite::{params, NO_PARAMS};
+
+use super::{inside, Connection, Pool, QueryResult};
+use crate::models::{FileMeta, FileQueries};
+
+pub fn execute(pool: &Pool, query: FileQueries) -> Result<QueryResult<FileMeta>, rusqlite::Error> {
+    let conn = pool
+        .get()
+        .map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
+
+    Ok(match query {
+        FileQueries::GetFile(path) => QueryResult::One(get_file(conn, &path)?),
+        FileQueries::SetFile(file) => QueryResult::None(set_file(conn, &file)?),
+        FileQueries::MoveFiles(from, to) => QueryResult::None(move_files(conn, &from, &to)?),
+        FileQueries::DeleteFiles(path) => QueryResult::None(delete_files(conn, &path)?),
+    })
+}
+
+fn row_to_file(row: &rusqlite::Row) -> Result<FileMeta, rusqlite::Error> {
+    Ok(FileMeta {
+        path: row.get(0)?,
+        user_id: row.get(1)?,
+        checksum: row.get(2)?,
+        created: row.get(3)?,
+    })
+}
+
+fn get_file(conn: Connection, path: &str) -> Result<FileMeta, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Files
+        WHERE path=(?1)
+        LIMIT 1
+    ",
+    )?
+    .query_row(&[&path], row_to_file)
+}
+
+fn set_file(conn: Connection, file: &FileMeta) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        INSERT OR REPLACE INTO Files (path, user_id, checksum, created)
+        VALUES(?1, ?2, ?3, ?4)
+    ",
+        params![file.path, file.user_id, file.checksum, file.created],
+    )?;
+    Ok(())
+}
+
+// file or folder was moved, everything inside goes with it
+fn move_files(conn: Connection, from: &str, to: &str) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        &format!(
+            "
+            UPDATE Files
+            SET path = (?2) || substr(path, length(?1) + 1)
+            WHERE {}
+        ",
+            inside("path", 1),
+        ),
+        params![from, to],
+    )?;
+    Ok(())
+}
+
+fn delete_files(conn: Connection, path: &str) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        &format!(
+            "
+            DELETE
+            FROM Files
+            WHERE {}
+        ",
+            inside("path", 1),
+        ),
+        &[&path],
+    )?;
+    Ok(())
+}
+
+pub fn create_table(conn: &Pool) {
+    conn.get()
+        .unwrap()
+        .execute(
+            "create table if not exists Files (
+            path TEXT primary key,
+            user_id UNSIGNED INT,
+            checksum TEXT NOT NULL,
+            created INTEGER NOT NULL)
+        ",
+            NO_PARAMS,
+        )
+        .unwrap();
+}
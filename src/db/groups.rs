@@ -0,0 +1,200 @@
+use actix_web::web::Json;
+use rusqlite::{params, NO_PARAMS};
+
+use super::{Connection, Pool, QueryResult};
+use crate::models::{Group, GroupQueries};
+
+pub fn execute(pool: &Pool, query: GroupQueries) -> Result<QueryResult<Group>, rusqlite::Error> {
+    let conn = pool
+        .get()
+        .map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
+
+    Ok(match query {
+        GroupQueries::GetGroupById(id) => QueryResult::One(get_group_by_id(&conn, id)?),
+        GroupQueries::GetAllGroups => QueryResult::Many(get_all_groups(conn)?),
+        GroupQueries::GetUserGroups(user_id) => {
+            QueryResult::Many(get_user_groups(conn, user_id)?)
+        }
+        GroupQueries::AddGroup(group) => QueryResult::One(add_group(conn, &group)?),
+        GroupQueries::UpdateGroupById(id, group) => {
+            QueryResult::None(update_group_by_id(conn, id, &group)?)
+        }
+        GroupQueries::DeleteGroupById(id) => QueryResult::None(delete_group_by_id(conn, id)?),
+        GroupQueries::AddMember(id, user_id) => QueryResult::None(add_member(conn, id, user_id)?),
+        GroupQueries::RemoveMember(id, user_id) => {
+            QueryResult::None(remove_member(conn, id, user_id)?)
+        }
+        GroupQueries::DeleteUserMemberships(user_id) => {
+            QueryResult::None(delete_user_memberships(conn, user_id)?)
+        }
+    })
+}
+
+fn row_to_group(row: &rusqlite::Row) -> Result<Group, rusqlite::Error> {
+    Ok(Group {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        size: row.get(2)?,
+        path: row.get(3)?,
+    })
+}
+
+fn get_group_by_id(conn: &Connection, id: u32) -> Result<Group, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Groups
+        WHERE id=(?1)
+        LIMIT 1
+    ",
+    )?
+    .query_row(&[&id], row_to_group)
+}
+
+fn get_all_groups(conn: Connection) -> Result<Vec<Group>, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Groups
+    ",
+    )?
+    .query_map(NO_PARAMS, row_to_group)
+    .and_then(Iterator::collect)
+}
+
+fn get_user_groups(conn: Connection, user_id: u32) -> Result<Vec<Group>, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT Groups.*
+        FROM Groups
+        JOIN GroupMembers ON GroupMembers.group_id = Groups.id
+        WHERE GroupMembers.user_id=(?1)
+    ",
+    )?
+    .query_map(&[&user_id], row_to_group)
+    .and_then(Iterator::collect)
+}
+
+fn add_group(conn: Connection, group: &Json<Group>) -> Result<Group, rusqlite::Error> {
+    conn.execute(
+        "
+        INSERT INTO Groups (name, size, path)
+        VALUES(?1, ?2, ?3)
+    ",
+        params![group.name, group.size, group.path],
+    )?;
+
+    get_group_by_id(&conn, conn.last_insert_rowid() as u32)
+}
+
+fn update_group_by_id(conn: Connection, id: u32, group: &Json<Group>) -> Result<(), rusqlite::Error> {
+    let changed = conn.execute(
+        "
+        UPDATE Groups
+        SET
+            name = ?2,
+            size = ?3,
+            path = ?4
+        WHERE id=(?1)
+    ",
+        params![id, group.name, group.size, group.path],
+    )?;
+
+    if changed == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    Ok(())
+}
+
+fn delete_group_by_id(mut conn: Connection, id: u32) -> Result<(), rusqlite::Error> {
+    let tx = conn.transaction()?;
+
+    let changed = tx.execute(
+        "
+        DELETE
+        FROM Groups
+        WHERE id=(?1)
+    ",
+        &[&id],
+    )?;
+
+    if changed == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+
+    tx.execute(
+        "
+        DELETE
+        FROM GroupMembers
+        WHERE group_id=(?1)
+    ",
+        &[&id],
+    )?;
+
+    tx.commit()
+}
+
+fn add_member(conn: Connection, id: u32, user_id: u32) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        INSERT OR IGNORE INTO GroupMembers (group_id, user_id)
+        VALUES(?1, ?2)
+    ",
+        &[&id, &user_id],
+    )?;
+    Ok(())
+}
+
+fn remove_member(conn: Connection, id: u32, user_id: u32) -> Result<(), rusqlite::Error> {
+    let changed = conn.execute(
+        "
+        DELETE
+        FROM GroupMembers
+        WHERE group_id=(?1) AND user_id=(?2)
+    ",
+        &[&id, &user_id],
+    )?;
+
+    if changed == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    Ok(())
+}
+
+fn delete_user_memberships(conn: Connection, user_id: u32) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        DELETE
+        FROM GroupMembers
+        WHERE user_id=(?1)
+    ",
+        &[&user_id],
+    )?;
+    Ok(())
+}
+
+pub fn create_table(conn: &Pool) {
+    conn.get()
+        .unwrap()
+        .execute(
+            "create table if not exists Groups (
+            id integer primary key,
+            name TEXT NOT NULL UNIQUE,
+            size UNSIGNED INT,
+            path TEXT NOT NULL)
+        ",
+            NO_PARAMS,
+        )
+        .unwrap();
+    conn.get()
+        .unwrap()
+        .execute(
+            "create table if not exists GroupMembers (
+            group_id UNSIGNED INT NOT NULL,
+            user_id UNSIGNED INT NOT NULL,
+            PRIMARY KEY (group_id, user_id))
+        ",
+            NO_PARAMS,
+        )
+        .unwrap();
+}
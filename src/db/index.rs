@@ -0,0 +1,237 @@
+use rusqlite::{params, ToSql, NO_PARAMS};
+
+use super::labels::labeled;
+use super::{below, inside, Connection, Pool, QueryResult};
+use crate::models::{IndexEntry, IndexFilter, IndexQueries};
+
+pub fn execute(pool: &Pool, query: IndexQueries) -> Result<QueryResult<IndexEntry>, rusqlite::Error> {
+    let conn = pool
+        .get()
+        .map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
+
+    Ok(match query {
+        IndexQueries::SetEntry(entry) => QueryResult::None(set_entry(conn, &entry)?),
+        IndexQueries::MoveEntries(from, to) => QueryResult::None(move_entries(conn, &from, &to)?),
+        IndexQueries::DeleteEntries(path) => QueryResult::None(delete_entries(conn, &path)?),
+        IndexQueries::DeleteStale(indexed) => QueryResult::None(delete_stale(conn, indexed)?),
+        IndexQueries::Search(root, filter, page) => QueryResult::Many(search(conn, &root, &filter, page)?),
+    })
+}
+
+fn row_to_entry(row: &rusqlite::Row) -> Result<IndexEntry, rusqlite::Error> {
+    Ok(IndexEntry {
+        path: row.get(0)?,
+        name: row.get(1)?,
+        ext: row.get(2)?,
+        is_dir: row.get(3)?,
+        size: row.get::<_, i64>(4)? as u64,
+        modified: row.get(5)?,
+        indexed: row.get(6)?,
+    })
+}
+
+fn set_entry(conn: Connection, entry: &IndexEntry) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        INSERT OR REPLACE INTO SearchIndex (path, name, ext, is_dir, size, modified, indexed, lower_name)
+        VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+    ",
+        params![
+            entry.path,
+            entry.name,
+            entry.ext,
+            entry.is_dir,
+            entry.size as i64,
+            entry.modified,
+            entry.indexed,
+            entry.name.to_lowercase()
+        ],
+    )?;
+    Ok(())
+}
+
+// folder was moved with everything inside, names stay the same
+fn move_entries(conn: Connection, from: &str, to: &str) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        &format!(
+            "
+            UPDATE OR REPLACE SearchIndex
+            SET path = (?2) || substr(path, length(?1) + 1)
+            WHERE {}
+        ",
+            inside("path", 1),
+        ),
+        params![from, to],
+    )?;
+    Ok(())
+}
+
+fn delete_entries(conn: Connection, path: &str) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        &format!(
+            "
+            DELETE
+            FROM SearchIndex
+            WHERE {}
+        ",
+            inside("path", 1),
+        ),
+        &[&path],
+    )?;
+    Ok(())
+}
+
+fn delete_stale(conn: Connection, indexed: i64) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        DELETE
+        FROM SearchIndex
+        WHERE indexed < (?1)
+    ",
+        &[&indexed],
+    )?;
+    Ok(())
+}
+
+// everything inside root what filter lets through, extensions are given as ",pdf,txt,",
+// folders with own acl come after fixed params, deepest first, so the one closest to entry decides
+fn conditions(root: &str, filter: &IndexFilter) -> (String, Vec<Box<dyn ToSql>>) {
+    let exts = match filter.exts.is_empty() {
+        true => None,
+        false => Some(format!(",{},", filter.exts.join(","))),
+    };
+    let (user_id, labels) = filter.labeled.clone().unwrap_or_default();
+
+    let mut sql = format!(
+        "
+            {}
+                AND (?2 IS NULL OR is_dir = ?2)
+                AND (?3 IS NULL OR size >= ?3)
+                AND (?4 IS NULL OR size <= ?4)
+                AND (?5 IS NULL OR modified >= ?5)
+                AND (?6 IS NULL OR modified <= ?6)
+                AND (?7 IS NULL OR (ext != '' AND instr(?7, ',' || ext || ',') > 0))
+                AND (?8 IS NULL OR instr(lower_name, ?8) > 0)
+                AND (?9 = 0 OR path IN (SELECT i.path FROM FileIds i WHERE {}))
+        ",
+        below("path", 1),
+        labeled(10),
+    );
+    let mut params: Vec<Box<dyn ToSql>> = vec![
+        Box::new(root.trim_end_matches('/').to_string()),
+        Box::new(filter.is_dir),
+        Box::new(filter.min_size.map(|v| v as i64)),
+        Box::new(filter.max_size.map(|v| v as i64)),
+        Box::new(filter.after),
+        Box::new(filter.before),
+        Box::new(exts),
+        Box::new(filter.name.clone()),
+        Box::new(filter.labeled.is_some()),
+        Box::new(labels.tag),
+        Box::new(labels.favorite),
+        Box::new(user_id),
+        Box::new(labels.property),
+        Box::new(labels.value),
+    ];
+
+    // without own acl role decides, and search needs read from it already
+    if !filter.readable.is_empty() {
+        sql.push_str("AND CASE");
+        for (path, readable) in filter.readable.iter() {
+            let param = params.len() + 1;
+            sql.push_str(&format!(" WHEN {} THEN ?{}", inside("path", param), param + 1));
+            params.push(Box::new(path.clone()));
+            params.push(Box::new(*readable));
+        }
+        sql.push_str(" ELSE 1 END");
+    }
+    (sql, params)
+}
+
+// page of entries ordered by path, all of them without limit
+fn search(
+    conn: Connection,
+    root: &str,
+    filter: &IndexFilter,
+    page: Option<(u64, u64)>,
+) -> Result<Vec<IndexEntry>, rusqlite::Error> {
+    let (conditions, mut params) = conditions(root, filter);
+    let (limit, offset) = page.map(|(l, o)| (l as i64, o as i64)).unwrap_or((-1, 0));
+    let param = params.len() + 1;
+    params.push(Box::new(limit));
+    params.push(Box::new(offset));
+
+    conn.prepare(
+        &format!(
+            "
+            SELECT *
+            FROM SearchIndex
+            WHERE {}
+            ORDER BY path
+            LIMIT ?{} OFFSET ?{}
+        ",
+            conditions,
+            param,
+            param + 1,
+        ),
+    )?
+    .query_map(params, row_to_entry)
+    .and_then(Iterator::collect)
+}
+
+// how many entries search would give without limit
+pub fn count(pool: &Pool, root: &str, filter: &IndexFilter) -> Result<u64, rusqlite::Error> {
+    let conn = pool
+        .get()
+        .map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
+    let (conditions, params) = conditions(root, filter);
+
+    conn.query_row(
+        &format!(
+            "
+            SELECT COUNT(*)
+            FROM SearchIndex
+            WHERE {}
+        ",
+            conditions,
+        ),
+        params,
+        |row| row.get::<_, i64>(0),
+    )
+    .map(|count| count as u64)
+}
+
+pub fn create_table(conn: &Pool) {
+    conn.get()
+        .unwrap()
+        .execute(
+            "create table if not exists SearchIndex (
+            path TEXT primary key,
+            name TEXT NOT NULL,
+            ext TEXT NOT NULL,
+            is_dir INTEGER NOT NULL,
+            size INTEGER NOT NULL,
+            modified INTEGER,
+            indexed INTEGER NOT NULL,
+            lower_name TEXT NOT NULL)
+        ",
+            NO_PARAMS,
+        )
+        .unwrap();
+
+    add_lower_name_column(conn);
+}
+
+// name is matched without case by database, sqlite knows case only of ascii,
+// so names from before get it right with next reindex, which runs on start
+fn add_lower_name_column(conn: &Pool) {
+    let conn = conn.get().unwrap();
+
+    if conn
+        .execute("ALTER TABLE SearchIndex ADD COLUMN lower_name TEXT NOT NULL DEFAULT ''", NO_PARAMS)
+        .is_ok()
+    {
+        conn.execute("UPDATE SearchIndex SET lower_name = lower(name)", NO_PARAMS)
+            .unwrap();
+    }
+}
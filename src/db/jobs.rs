@@ -0,0 +1,210 @@
+use rusqlite::{params, NO_PARAMS};
+
+use super::{Connection, Pool, QueryResult};
+use crate::models::{Job, JobKind, JobQueries, JobStatus};
+
+pub fn execute(pool: &Pool, query: JobQueries) -> Result<QueryResult<Job>, rusqlite::Error> {
+    let conn = pool
+        .get()
+        .map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
+
+    Ok(match query {
+        JobQueries::AddJob(job) => QueryResult::None(add_job(conn, &job)?),
+        JobQueries::GetJob(id) => QueryResult::One(get_job(conn, &id)?),
+        JobQueries::GetUserJobs(user_id) => QueryResult::Many(get_user_jobs(conn, user_id)?),
+        JobQueries::GetRunningJobs => QueryResult::Many(get_running_jobs(conn)?),
+        JobQueries::SetProgress(id, done, files_done) => {
+            QueryResult::None(set_progress(conn, &id, done, files_done)?)
+        }
+        JobQueries::FinishJob(id, status, error, finished) => {
+            QueryResult::None(finish_job(conn, &id, status, error, finished)?)
+        }
+        JobQueries::FailRunningJobs(error, finished) => {
+            QueryResult::None(fail_running_jobs(conn, &error, finished)?)
+        }
+        JobQueries::DeleteFinishedJobs(before) => {
+            QueryResult::None(delete_finished_jobs(conn, before)?)
+        }
+        JobQueries::DeleteJob(id) => QueryResult::None(delete_job(conn, &id)?),
+    })
+}
+
+fn row_to_job(row: &rusqlite::Row) -> Result<Job, rusqlite::Error> {
+    let kind: String = row.get(2)?;
+    let status: String = row.get(7)?;
+
+    Ok(Job {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        kind: JobKind::parse(&kind).ok_or(rusqlite::Error::InvalidQuery)?,
+        from: row.get(3)?,
+        to: row.get(4)?,
+        source: row.get(5)?,
+        target: row.get(6)?,
+        status: JobStatus::parse(&status).ok_or(rusqlite::Error::InvalidQuery)?,
+        total: row.get::<_, i64>(8)? as u64,
+        done: row.get::<_, i64>(9)? as u64,
+        files: row.get(10)?,
+        files_done: row.get(11)?,
+        error: row.get(12)?,
+        created: row.get(13)?,
+        finished: row.get(14)?,
+    })
+}
+
+fn add_job(conn: Connection, job: &Job) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        INSERT INTO Jobs (id, user_id, kind, from_path, to_path, source, target, status,
+            total, done, files, files_done, error, created, finished)
+        VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)
+    ",
+        params![
+            job.id,
+            job.user_id,
+            job.kind.as_str(),
+            job.from,
+            job.to,
+            job.source,
+            job.target,
+            job.status.as_str(),
+            job.total as i64,
+            job.done as i64,
+            job.files,
+            job.files_done,
+            job.error,
+            job.created,
+            job.finished
+        ],
+    )?;
+    Ok(())
+}
+
+fn get_job(conn: Connection, id: &str) -> Result<Job, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Jobs
+        WHERE id=(?1)
+        LIMIT 1
+    ",
+    )?
+    .query_row(&[&id], row_to_job)
+}
+
+fn get_user_jobs(conn: Connection, user_id: u32) -> Result<Vec<Job>, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Jobs
+        WHERE user_id=(?1)
+        ORDER BY created DESC
+    ",
+    )?
+    .query_map(&[&user_id], row_to_job)
+    .and_then(Iterator::collect)
+}
+
+fn get_running_jobs(conn: Connection) -> Result<Vec<Job>, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Jobs
+        WHERE status='running'
+    ",
+    )?
+    .query_map(NO_PARAMS, row_to_job)
+    .and_then(Iterator::collect)
+}
+
+fn set_progress(conn: Connection, id: &str, done: u64, files_done: u32) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        UPDATE Jobs
+        SET done=(?2), files_done=(?3)
+        WHERE id=(?1)
+    ",
+        params![id, done as i64, files_done],
+    )?;
+    Ok(())
+}
+
+fn finish_job(
+    conn: Connection,
+    id: &str,
+    status: JobStatus,
+    error: Option<String>,
+    finished: i64,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        UPDATE Jobs
+        SET status=(?2), error=(?3), finished=(?4)
+        WHERE id=(?1) AND status='running'
+    ",
+        params![id, status.as_str(), error, finished],
+    )?;
+    Ok(())
+}
+
+fn fail_running_jobs(conn: Connection, error: &str, finished: i64) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        UPDATE Jobs
+        SET status='failed', error=(?1), finished=(?2)
+        WHERE status='running'
+    ",
+        params![error, finished],
+    )?;
+    Ok(())
+}
+
+fn delete_finished_jobs(conn: Connection, before: i64) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        DELETE
+        FROM Jobs
+        WHERE status!='running' AND finished <= (?1)
+    ",
+        &[&before],
+    )?;
+    Ok(())
+}
+
+fn delete_job(conn: Connection, id: &str) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        DELETE
+        FROM Jobs
+        WHERE id=(?1)
+    ",
+        &[&id],
+    )?;
+    Ok(())
+}
+
+pub fn create_table(conn: &Pool) {
+    conn.get()
+        .unwrap()
+        .execute(
+            "create table if not exists Jobs (
+            id TEXT primary key,
+            user_id UNSIGNED INT NOT NULL,
+            kind TEXT NOT NULL,
+            from_path TEXT NOT NULL,
+            to_path TEXT NOT NULL,
+            source TEXT NOT NULL,
+            target TEXT NOT NULL,
+            status TEXT NOT NULL,
+            total INTEGER NOT NULL,
+            done INTEGER NOT NULL,
+            files UNSIGNED INT NOT NULL,
+            files_done UNSIGNED INT NOT NULL,
+            error TEXT,
+            created INTEGER NOT NULL,
+            finished INTEGER)
+        ",
+            NO_PARAMS,
+        )
+        .unwrap();
+}
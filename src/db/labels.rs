@@ -0,0 +1,327 @@
+use rusqlite::{params, OptionalExtension};
+
+use super::{inside, Connection, Pool, QueryResult};
+use crate::models::{LabelFilter, LabelQueries, Labels};
+
+pub fn execute(pool: &Pool, query: LabelQueries) -> Result<QueryResult<Labels>, rusqlite::Error> {
+    let conn = pool
+        .get()
+        .map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
+
+    Ok(match query {
+        LabelQueries::GetLabels(path, user_id) => QueryResult::One(get_labels(&conn, &path, user_id)?),
+        LabelQueries::FindLabeled(user_id, filter) => QueryResult::Many(find_labeled(conn, user_id, &filter)?),
+        LabelQueries::AddTag(path, tag) => QueryResult::None(add_tag(conn, &path, &tag)?),
+        LabelQueries::RemoveTag(path, tag) => QueryResult::None(remove_tag(conn, &path, &tag)?),
+        LabelQueries::SetFavorite(path, user_id, favorite) => {
+            QueryResult::None(set_favorite(conn, &path, user_id, favorite)?)
+        }
+        LabelQueries::SetProperty(path, key, value) => QueryResult::None(set_property(conn, &path, &key, value)?),
+        LabelQueries::MoveLabels(from, to) => QueryResult::None(move_labels(conn, &from, &to)?),
+        LabelQueries::DeleteLabels(path) => QueryResult::None(delete_labels(conn, &path)?),
+        LabelQueries::DeleteUserFavorites(user_id) => QueryResult::None(delete_user_favorites(conn, user_id)?),
+    })
+}
+
+fn id_of(conn: &Connection, path: &str) -> Result<Option<i64>, rusqlite::Error> {
+    conn.query_row(
+        "
+        SELECT id
+        FROM FileIds
+        WHERE path=(?1)
+    ",
+        &[&path],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+// path gets its id when it is labeled first time
+fn new_id(conn: &Connection, path: &str) -> Result<i64, rusqlite::Error> {
+    conn.execute(
+        "
+        INSERT OR IGNORE INTO FileIds (path)
+        VALUES(?1)
+    ",
+        &[&path],
+    )?;
+
+    conn.query_row(
+        "
+        SELECT id
+        FROM FileIds
+        WHERE path=(?1)
+    ",
+        &[&path],
+        |row| row.get(0),
+    )
+}
+
+// path without any label has no id and empty labels
+fn get_labels(conn: &Connection, path: &str, user_id: u32) -> Result<Labels, rusqlite::Error> {
+    let id = match id_of(conn, path)? {
+        Some(id) => id,
+        None => {
+            return Ok(Labels {
+                path: path.to_string(),
+                ..Labels::default()
+            })
+        }
+    };
+
+    let tags = conn
+        .prepare(
+            "
+        SELECT tag
+        FROM FileTags
+        WHERE file_id=(?1)
+        ORDER BY tag
+    ",
+        )?
+        .query_map(&[&id], |row| row.get(0))
+        .and_then(Iterator::collect)?;
+
+    let favorite = conn
+        .query_row(
+            "
+        SELECT 1
+        FROM Favorites
+        WHERE file_id=(?1) AND user_id=(?2)
+    ",
+            params![id, user_id],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some();
+
+    let properties = conn
+        .prepare(
+            "
+        SELECT key, value
+        FROM FileProperties
+        WHERE file_id=(?1)
+    ",
+        )?
+        .query_map(&[&id], |row| Ok((row.get(0)?, row.get(1)?)))
+        .and_then(Iterator::collect)?;
+
+    Ok(Labels {
+        id: Some(id),
+        path: path.to_string(),
+        tags,
+        favorite,
+        properties,
+    })
+}
+
+// only paths with labels have id, so this goes through few rows
+// condition for FileIds i with everything filter asks for, params from first one are
+// tag, favorite, user id, property and its value, search uses it too
+pub fn labeled(param: usize) -> String {
+    format!(
+        "
+        (?{0} IS NULL OR EXISTS(SELECT 1 FROM FileTags t WHERE t.file_id = i.id AND t.tag = ?{0}))
+            AND (?{1} = 0 OR EXISTS(SELECT 1 FROM Favorites f WHERE f.file_id = i.id AND f.user_id = ?{2}))
+            AND (?{3} IS NULL OR EXISTS(
+                SELECT 1
+                FROM FileProperties p
+                WHERE p.file_id = i.id AND p.key = ?{3} AND (?{4} IS NULL OR p.value = ?{4})
+            ))
+    ",
+        param,
+        param + 1,
+        param + 2,
+        param + 3,
+        param + 4,
+    )
+}
+
+fn find_labeled(conn: Connection, user_id: u32, filter: &LabelFilter) -> Result<Vec<Labels>, rusqlite::Error> {
+    let paths: Vec<String> = conn
+        .prepare(&format!(
+            "
+        SELECT i.path
+        FROM FileIds i
+        WHERE {}
+    ",
+            labeled(1),
+        ))?
+        .query_map(
+            params![filter.tag, filter.favorite, user_id, filter.property, filter.value],
+            |row| row.get(0),
+        )
+        .and_then(Iterator::collect)?;
+
+    paths.iter().map(|path| get_labels(&conn, path, user_id)).collect()
+}
+
+fn add_tag(conn: Connection, path: &str, tag: &str) -> Result<(), rusqlite::Error> {
+    let id = new_id(&conn, path)?;
+
+    conn.execute(
+        "
+        INSERT OR IGNORE INTO FileTags (file_id, tag)
+        VALUES(?1, ?2)
+    ",
+        params![id, tag],
+    )?;
+    Ok(())
+}
+
+fn remove_tag(conn: Connection, path: &str, tag: &str) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        DELETE
+        FROM FileTags
+        WHERE file_id = (SELECT id FROM FileIds WHERE path = ?1) AND tag = (?2)
+    ",
+        params![path, tag],
+    )?;
+    Ok(())
+}
+
+fn set_favorite(conn: Connection, path: &str, user_id: u32, favorite: bool) -> Result<(), rusqlite::Error> {
+    match favorite {
+        true => {
+            let id = new_id(&conn, path)?;
+            conn.execute(
+                "
+                INSERT OR IGNORE INTO Favorites (user_id, file_id)
+                VALUES(?1, ?2)
+            ",
+                params![user_id, id],
+            )?
+        }
+        false => conn.execute(
+            "
+            DELETE
+            FROM Favorites
+            WHERE user_id = (?1) AND file_id = (SELECT id FROM FileIds WHERE path = ?2)
+        ",
+            params![user_id, path],
+        )?,
+    };
+    Ok(())
+}
+
+// property without value is removed
+fn set_property(conn: Connection, path: &str, key: &str, value: Option<String>) -> Result<(), rusqlite::Error> {
+    match value {
+        Some(value) => {
+            let id = new_id(&conn, path)?;
+            conn.execute(
+                "
+                INSERT OR REPLACE INTO FileProperties (file_id, key, value)
+                VALUES(?1, ?2, ?3)
+            ",
+                params![id, key, value],
+            )?
+        }
+        None => conn.execute(
+            "
+            DELETE
+            FROM FileProperties
+            WHERE file_id = (SELECT id FROM FileIds WHERE path = ?1) AND key = (?2)
+        ",
+            params![path, key],
+        )?,
+    };
+    Ok(())
+}
+
+// ids go with moved file or folder, what was on new path loses its labels
+fn move_labels(mut conn: Connection, from: &str, to: &str) -> Result<(), rusqlite::Error> {
+    if from == to {
+        return Ok(());
+    }
+    let tx = conn.transaction()?;
+
+    tx.execute(
+        &format!(
+            "
+            DELETE
+            FROM FileIds
+            WHERE {}
+        ",
+            inside("path", 1),
+        ),
+        &[&to],
+    )?;
+
+    tx.execute(
+        &format!(
+            "
+            UPDATE FileIds
+            SET path = (?2) || substr(path, length(?1) + 1)
+            WHERE {}
+        ",
+            inside("path", 1),
+        ),
+        params![from, to],
+    )?;
+
+    tx.commit()
+}
+
+fn delete_labels(conn: Connection, path: &str) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        &format!(
+            "
+            DELETE
+            FROM FileIds
+            WHERE {}
+        ",
+            inside("path", 1),
+        ),
+        &[&path],
+    )?;
+    Ok(())
+}
+
+fn delete_user_favorites(conn: Connection, user_id: u32) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        DELETE
+        FROM Favorites
+        WHERE user_id=(?1)
+    ",
+        &[&user_id],
+    )?;
+    Ok(())
+}
+
+// labels of removed id are removed by trigger
+pub fn create_table(conn: &Pool) {
+    conn.get()
+        .unwrap()
+        .execute_batch(
+            "create table if not exists FileIds (
+            id INTEGER primary key AUTOINCREMENT,
+            path TEXT NOT NULL UNIQUE);
+
+            create table if not exists FileTags (
+            file_id INTEGER NOT NULL,
+            tag TEXT NOT NULL,
+            primary key (file_id, tag));
+
+            create table if not exists Favorites (
+            user_id INTEGER NOT NULL,
+            file_id INTEGER NOT NULL,
+            primary key (user_id, file_id));
+
+            create table if not exists FileProperties (
+            file_id INTEGER NOT NULL,
+            key TEXT NOT NULL,
+            value TEXT NOT NULL,
+            primary key (file_id, key));
+
+            create trigger if not exists FileIdDeleted AFTER DELETE ON FileIds
+            BEGIN
+                DELETE FROM FileTags WHERE file_id = old.id;
+                DELETE FROM Favorites WHERE file_id = old.id;
+                DELETE FROM FileProperties WHERE file_id = old.id;
+            END;
+        ",
+        )
+        .unwrap();
+}
@@ -0,0 +1,96 @@
+use chrono::Utc;
+use rusqlite::{params, NO_PARAMS};
+
+use super::{Connection, Pool, QueryResult};
+use crate::models::{MailToken, MailTokenQueries};
+
+pub fn execute(pool: &Pool, query: MailTokenQueries) -> Result<QueryResult<MailToken>, rusqlite::Error> {
+    let conn = pool
+        .get()
+        .map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
+
+    Ok(match query {
+        MailTokenQueries::Issue(user_id, kind, token_hash, expires) => {
+            QueryResult::None(add_mail_token(conn, user_id, kind, &token_hash, expires)?)
+        }
+        MailTokenQueries::Consume(kind, token_hash) => {
+            QueryResult::One(use_mail_token(conn, kind, &token_hash)?)
+        }
+    })
+}
+
+fn row_to_mail_token(row: &rusqlite::Row) -> Result<MailToken, rusqlite::Error> {
+    Ok(MailToken {
+        user_id: row.get(1)?,
+        kind: row.get(2)?,
+        expires: row.get(3)?,
+        used: row.get(4)?,
+    })
+}
+
+// only newest token of every kind is valid
+fn add_mail_token(
+    conn: Connection,
+    user_id: u32,
+    kind: &str,
+    token_hash: &str,
+    expires: i64,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        DELETE
+        FROM MailTokens
+        WHERE user_id=(?1) AND kind=(?2)
+    ",
+        params![user_id, kind],
+    )?;
+    conn.execute(
+        "
+        INSERT INTO MailTokens (token_hash, user_id, kind, expires, used)
+        VALUES(?1, ?2, ?3, ?4, 0)
+    ",
+        params![token_hash, user_id, kind, expires],
+    )?;
+    Ok(())
+}
+
+fn use_mail_token(conn: Connection, kind: &str, token_hash: &str) -> Result<MailToken, rusqlite::Error> {
+    let changed = conn.execute(
+        "
+        UPDATE MailTokens
+        SET used = 1
+        WHERE token_hash=(?1) AND kind=(?2) AND used = 0 AND expires > ?3
+    ",
+        params![token_hash, kind, Utc::now().timestamp()],
+    )?;
+
+    if changed == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+
+    conn.prepare(
+        "
+        SELECT *
+        FROM MailTokens
+        WHERE token_hash=(?1)
+        LIMIT 1
+    ",
+    )?
+    .query_row(&[&token_hash], row_to_mail_token)
+}
+
+pub fn create_table(conn: &Pool) {
+    conn.get()
+        .unwrap()
+        .execute(
+            "create table if not exists MailTokens (
+            token_hash TEXT primary key,
+            user_id integer NOT NULL,
+            kind TEXT NOT NULL,
+            expires INTEGER NOT NULL,
+            used BOOLEAN NOT NULL DEFAULT 0)
+        ",
+            NO_PARAMS,
+        )
+        .unwrap();
+}
@@ -0,0 +1,250 @@
+use actix_web::web::Json;
+use rusqlite::{params, NO_PARAMS};
+use std::convert::TryFrom;
+
+use super::{Connection, Pool, QueryResult};
+use crate::models::{NewRole, Permission, Role, RoleQueries};
+
+// ids of built-in roles are same as old status values of users
+pub const ADMIN: u8 = 1;
+pub const OP: u8 = 2;
+pub const UPLOADER: u8 = 3;
+pub const VIEWER: u8 = 4;
+
+pub fn execute(pool: &Pool, query: RoleQueries) -> Result<QueryResult<Role>, rusqlite::Error> {
+    let conn = pool
+        .get()
+        .map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
+
+    Ok(match query {
+        RoleQueries::GetRoleById(id) => QueryResult::One(get_role_by_id(&conn, id)?),
+        RoleQueries::GetAllRoles => QueryResult::Many(get_all_roles(conn)?),
+        RoleQueries::AddRole(role) => QueryResult::One(add_role(conn, &role)?),
+        RoleQueries::UpdateRoleById(id, role) => {
+            QueryResult::None(update_role_by_id(conn, id, &role)?)
+        }
+        RoleQueries::DeleteRoleById(id) => QueryResult::None(delete_role_by_id(conn, id)?),
+    })
+}
+
+fn builtin() -> [(u8, &'static str, Vec<Permission>); 4] {
+    [
+        (ADMIN, "admin", Permission::ALL.to_vec()),
+        (
+            OP,
+            "op",
+            vec![
+                Permission::Read,
+                Permission::Write,
+                Permission::Delete,
+                Permission::Rename,
+                Permission::Share,
+                Permission::ManageUsers,
+            ],
+        ),
+        (
+            UPLOADER,
+            "uploader",
+            vec![
+                Permission::Read,
+                Permission::Write,
+                Permission::Delete,
+                Permission::Rename,
+                Permission::Share,
+            ],
+        ),
+        (VIEWER, "viewer", vec![Permission::Read]),
+    ]
+}
+
+// custom role cant take name of built-in one, not even with other case
+pub fn is_builtin_name(name: &str) -> bool {
+    let name = name.trim().to_lowercase();
+
+    builtin().iter().any(|(_, builtin, _)| *builtin == name)
+}
+
+pub fn permissions_to_text(permissions: &[Permission]) -> String {
+    permissions
+        .iter()
+        .map(|p| p.as_str())
+        .collect::<Vec<&str>>()
+        .join(",")
+}
+
+pub fn text_to_permissions(text: &str) -> Vec<Permission> {
+    text.split(',').filter_map(Permission::parse).collect()
+}
+
+fn row_to_role(row: &rusqlite::Row) -> Result<Role, rusqlite::Error> {
+    let permissions: String = row.get(2)?;
+
+    Ok(Role {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        permissions: text_to_permissions(&permissions),
+        builtin: row.get(3)?,
+    })
+}
+
+fn get_role_by_id(conn: &rusqlite::Connection, id: u8) -> Result<Role, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Roles
+        WHERE id=(?1)
+        LIMIT 1
+    ",
+    )?
+    .query_row(&[&id], row_to_role)
+}
+
+fn get_all_roles(conn: Connection) -> Result<Vec<Role>, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Roles
+    ",
+    )?
+    .query_map(NO_PARAMS, row_to_role)
+    .and_then(Iterator::collect)
+}
+
+// role id is stored in status of user, which has only one byte, role which dont fit is not added
+fn add_role(mut conn: Connection, role: &Json<NewRole>) -> Result<Role, rusqlite::Error> {
+    let tx = conn.transaction()?;
+
+    tx.execute(
+        "
+        INSERT INTO Roles (name, permissions, builtin)
+        VALUES(?1, ?2, 0)
+    ",
+        params![role.name, permissions_to_text(&role.permissions)],
+    )?;
+
+    let rowid = tx.last_insert_rowid();
+    let id = u8::try_from(rowid).map_err(|_| rusqlite::Error::IntegralValueOutOfRange(0, rowid))?;
+    let role = get_role_by_id(&tx, id)?;
+
+    tx.commit()?;
+    Ok(role)
+}
+
+fn update_role_by_id(conn: Connection, id: u8, role: &Json<NewRole>) -> Result<(), rusqlite::Error> {
+    let changed = conn.execute(
+        "
+        UPDATE Roles
+        SET
+            name = ?2,
+            permissions = ?3
+        WHERE id=(?1) AND builtin = 0
+    ",
+        params![id, role.name, permissions_to_text(&role.permissions)],
+    )?;
+
+    if changed == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    Ok(())
+}
+
+// role which is still used by somebody cant be deleted
+fn delete_role_by_id(conn: Connection, id: u8) -> Result<(), rusqlite::Error> {
+    let changed = conn.execute(
+        "
+        DELETE
+        FROM Roles
+        WHERE id=(?1) AND builtin = 0 AND NOT EXISTS(SELECT 1 FROM Users WHERE status = ?1)
+    ",
+        &[&id],
+    )?;
+
+    if changed == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    Ok(())
+}
+
+pub fn create_table(conn: &Pool) {
+    conn.get()
+        .unwrap()
+        .execute(
+            "create table if not exists Roles (
+            id integer primary key,
+            name TEXT NOT NULL UNIQUE,
+            permissions TEXT NOT NULL,
+            builtin BOOLEAN NOT NULL DEFAULT 0)
+        ",
+            NO_PARAMS,
+        )
+        .unwrap();
+
+    // update in place, replace would delete row and take users of other role with same name
+    for (id, name, permissions) in builtin().iter() {
+        conn.get()
+            .unwrap()
+            .execute(
+                "
+                INSERT INTO Roles (id, name, permissions, builtin)
+                VALUES(?1, ?2, ?3, 1)
+                ON CONFLICT(id) DO UPDATE SET
+                    name = excluded.name,
+                    permissions = excluded.permissions,
+                    builtin = 1
+            ",
+                params![id, name, permissions_to_text(permissions)],
+            )
+            .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_pool;
+
+    fn new_role(name: &str) -> Json<NewRole> {
+        Json(NewRole {
+            name: name.to_string(),
+            permissions: vec![Permission::Read],
+        })
+    }
+
+    #[test]
+    fn builtin_names_are_found_without_case() {
+        assert!(is_builtin_name("admin"));
+        assert!(is_builtin_name(" Viewer "));
+        assert!(!is_builtin_name("editor"));
+    }
+
+    #[test]
+    fn seeding_again_keeps_custom_roles() {
+        let db = test_pool();
+        create_table(&db);
+
+        let role = (execute(&db, RoleQueries::AddRole(new_role("editor"))).unwrap()).one();
+        create_table(&db);
+
+        let roles = (execute(&db, RoleQueries::GetAllRoles).unwrap()).many();
+        assert_eq!(roles.len(), 5);
+        assert_eq!((execute(&db, RoleQueries::GetRoleById(role.id)).unwrap()).one().name, "editor");
+        assert!((execute(&db, RoleQueries::GetRoleById(ADMIN)).unwrap()).one().builtin);
+    }
+
+    #[test]
+    fn role_id_over_byte_is_not_added() {
+        let db = test_pool();
+        create_table(&db);
+
+        db.get()
+            .unwrap()
+            .execute(
+                "INSERT INTO Roles (id, name, permissions, builtin) VALUES(255, 'last', 'read', 0)",
+                NO_PARAMS,
+            )
+            .unwrap();
+
+        assert!(execute(&db, RoleQueries::AddRole(new_role("overflow"))).is_err());
+        assert_eq!((execute(&db, RoleQueries::GetAllRoles).unwrap()).many().len(), 5);
+    }
+}
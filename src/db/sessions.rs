@@ -0,0 +1,152 @@
+use chrono::Utc;
+use rusqlite::{params, NO_PARAMS};
+
+use super::{Connection, Pool, QueryResult};
+use crate::models::{Session, SessionQueries};
+
+pub fn execute(pool: &Pool, query: SessionQueries) -> Result<QueryResult<Session>, rusqlite::Error> {
+    let conn = pool
+        .get()
+        .map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
+
+    Ok(match query {
+        SessionQueries::AddSession(user_id, token_hash, expires) => {
+            QueryResult::One(add_session(conn, user_id, &token_hash, expires)?)
+        }
+        SessionQueries::GetSessionById(id) => QueryResult::One(get_session_by_id(&conn, id)?),
+        SessionQueries::GetSessionByHash(token_hash) => {
+            QueryResult::One(get_session_by_hash(conn, &token_hash)?)
+        }
+        SessionQueries::RotateSession(id, token_hash, expires) => {
+            QueryResult::None(rotate_session(conn, id, &token_hash, expires)?)
+        }
+        SessionQueries::RevokeSession(id) => QueryResult::None(revoke_session(conn, id)?),
+        SessionQueries::RevokeUserSessions(user_id, kept) => {
+            QueryResult::None(revoke_user_sessions(conn, user_id, kept)?)
+        }
+    })
+}
+
+fn row_to_session(row: &rusqlite::Row) -> Result<Session, rusqlite::Error> {
+    Ok(Session {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        token_hash: row.get(2)?,
+        created: row.get(3)?,
+        expires: row.get(4)?,
+        revoked: row.get(5)?,
+    })
+}
+
+fn add_session(
+    conn: Connection,
+    user_id: u32,
+    token_hash: &str,
+    expires: i64,
+) -> Result<Session, rusqlite::Error> {
+    let now = Utc::now().timestamp();
+
+    // forget sessions of this user which cant be used anymore
+    conn.execute(
+        "
+        DELETE
+        FROM Sessions
+        WHERE user_id = ?1 AND (expires < ?2 OR revoked = 1)
+    ",
+        params![user_id, now],
+    )?;
+
+    conn.execute(
+        "
+        INSERT INTO Sessions (user_id, token_hash, created, expires, revoked)
+        VALUES(?1, ?2, ?3, ?4, 0)
+    ",
+        params![user_id, token_hash, now, expires],
+    )?;
+
+    get_session_by_id(&conn, conn.last_insert_rowid() as u32)
+}
+
+fn get_session_by_id(conn: &Connection, id: u32) -> Result<Session, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Sessions
+        WHERE id=(?1)
+        LIMIT 1
+    ",
+    )?
+    .query_row(&[&id], row_to_session)
+}
+
+fn get_session_by_hash(conn: Connection, token_hash: &str) -> Result<Session, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Sessions
+        WHERE token_hash=(?1)
+        LIMIT 1
+    ",
+    )?
+    .query_row(&[&token_hash], row_to_session)
+}
+
+fn rotate_session(
+    conn: Connection,
+    id: u32,
+    token_hash: &str,
+    expires: i64,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        UPDATE Sessions
+        SET
+            token_hash = ?2,
+            expires = ?3
+        WHERE id=(?1)
+    ",
+        params![id, token_hash, expires],
+    )?;
+    Ok(())
+}
+
+fn revoke_session(conn: Connection, id: u32) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        UPDATE Sessions
+        SET revoked = 1
+        WHERE id=(?1)
+    ",
+        &[&id],
+    )?;
+    Ok(())
+}
+
+fn revoke_user_sessions(conn: Connection, user_id: u32, kept: Option<u32>) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        UPDATE Sessions
+        SET revoked = 1
+        WHERE user_id=(?1) AND id IS NOT (?2)
+    ",
+        params![user_id, kept],
+    )?;
+    Ok(())
+}
+
+pub fn create_table(conn: &Pool) {
+    conn.get()
+        .unwrap()
+        .execute(
+            "create table if not exists Sessions (
+            id integer primary key,
+            user_id integer NOT NULL,
+            token_hash TEXT NOT NULL UNIQUE,
+            created INTEGER NOT NULL,
+            expires INTEGER NOT NULL,
+            revoked BOOLEAN NOT NULL DEFAULT 0)
+        ",
+            NO_PARAMS,
+        )
+        .unwrap();
+}
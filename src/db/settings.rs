@@ -0,0 +1,61 @@
+use rusqlite::{params, NO_PARAMS};
+
+use super::{Connection, Pool, QueryResult};
+use crate::models::SettingQueries;
+
+pub fn execute(pool: &Pool, query: SettingQueries) -> Result<QueryResult<String>, rusqlite::Error> {
+    let conn = pool
+        .get()
+        .map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
+
+    Ok(match query {
+        SettingQueries::GetSetting(key) => QueryResult::One(get_setting(conn, &key)?),
+        SettingQueries::SetSetting(key, value) => {
+            QueryResult::None(set_setting(conn, &key, &value)?)
+        }
+    })
+}
+
+fn get_setting(conn: Connection, key: &str) -> Result<String, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT value
+        FROM Settings
+        WHERE key=(?1)
+        LIMIT 1
+    ",
+    )?
+    .query_row(&[&key], |row| row.get(0))
+}
+
+fn set_setting(conn: Connection, key: &str, value: &str) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        INSERT OR REPLACE INTO Settings (key, value)
+        VALUES(?1, ?2)
+    ",
+        params![key, value],
+    )?;
+    Ok(())
+}
+
+// missing setting is same as "false"
+pub fn get_flag(pool: &Pool, key: &str) -> bool {
+    match execute(pool, SettingQueries::GetSetting(key.to_string())) {
+        Ok(v) => v.one() == "true",
+        Err(_) => false,
+    }
+}
+
+pub fn create_table(conn: &Pool) {
+    conn.get()
+        .unwrap()
+        .execute(
+            "create table if not exists Settings (
+            key TEXT primary key,
+            value TEXT NOT NULL)
+        ",
+            NO_PARAMS,
+        )
+        .unwrap();
+}
@@ -0,0 +1,212 @@
+use actix_web::web::Json;
+use chrono::Utc;
+use rusqlite::{params, NO_PARAMS};
+
+use super::{Connection, Pool, QueryResult};
+use crate::models::{NewShare, Share, ShareMode, ShareQueries};
+
+pub fn execute(pool: &Pool, query: ShareQueries) -> Result<QueryResult<Share>, rusqlite::Error> {
+    let conn = pool
+        .get()
+        .map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
+
+    Ok(match query {
+        ShareQueries::AddShare(user_id, slug, share) => {
+            QueryResult::One(add_share(conn, user_id, &slug, &share)?)
+        }
+        ShareQueries::GetShareBySlug(slug) => QueryResult::One(get_share_by_slug(&conn, &slug)?),
+        ShareQueries::GetUserShares(user_id) => {
+            QueryResult::Many(get_user_shares(conn, user_id)?)
+        }
+        ShareQueries::DeleteShare(user_id, id) => {
+            QueryResult::None(delete_share(conn, user_id, id)?)
+        }
+        ShareQueries::DeleteUserShares(user_id) => {
+            QueryResult::None(delete_user_shares(conn, user_id)?)
+        }
+        ShareQueries::CountDownload(id) => QueryResult::None(count_download(conn, id)?),
+        ShareQueries::CountFile(id) => QueryResult::None(count_file(conn, id)?),
+        ShareQueries::UncountFile(id) => QueryResult::None(uncount_file(conn, id)?),
+    })
+}
+
+fn row_to_share(row: &rusqlite::Row) -> Result<Share, rusqlite::Error> {
+    let pass: Option<String> = row.get(4)?;
+    let mode: String = row.get(5)?;
+    let extensions: Option<String> = row.get(13)?;
+
+    Ok(Share {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        slug: row.get(2)?,
+        path: row.get(3)?,
+        password: pass.is_some(),
+        pass,
+        mode: ShareMode::parse(&mode).ok_or(rusqlite::Error::InvalidQuery)?,
+        created: row.get(6)?,
+        expires: row.get(7)?,
+        max_downloads: row.get(8)?,
+        downloads: row.get(9)?,
+        max_file_size: row.get::<_, Option<i64>>(10)?.map(|v| v as u64),
+        max_files: row.get(11)?,
+        files: row.get(12)?,
+        extensions: extensions.map(|v| v.split(',').map(|e| e.to_string()).collect()),
+    })
+}
+
+// password in share is already hashed
+fn add_share(
+    conn: Connection,
+    user_id: u32,
+    slug: &str,
+    share: &Json<NewShare>,
+) -> Result<Share, rusqlite::Error> {
+    conn.execute(
+        "
+        INSERT INTO Shares (
+            user_id, slug, path, pass, mode, created, expires, max_downloads, downloads,
+            max_file_size, max_files, files, extensions
+        )
+        VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, 0, ?9, ?10, 0, ?11)
+    ",
+        params![
+            user_id,
+            slug,
+            share.path,
+            share.password,
+            share.mode.as_str(),
+            Utc::now().timestamp(),
+            share.expires,
+            share.max_downloads,
+            share.max_file_size.map(|v| v as i64),
+            share.max_files,
+            share.extensions.as_ref().map(|v| v.join(","))
+        ],
+    )?;
+
+    get_share_by_slug(&conn, slug)
+}
+
+fn get_share_by_slug(conn: &Connection, slug: &str) -> Result<Share, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Shares
+        WHERE slug=(?1)
+        LIMIT 1
+    ",
+    )?
+    .query_row(&[&slug], row_to_share)
+}
+
+fn get_user_shares(conn: Connection, user_id: u32) -> Result<Vec<Share>, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Shares
+        WHERE user_id=(?1)
+    ",
+    )?
+    .query_map(&[&user_id], row_to_share)
+    .and_then(Iterator::collect)
+}
+
+// only owner can revoke link
+fn delete_share(conn: Connection, user_id: u32, id: u32) -> Result<(), rusqlite::Error> {
+    let changed = conn.execute(
+        "
+        DELETE
+        FROM Shares
+        WHERE id=(?1) AND user_id=(?2)
+    ",
+        &[&id, &user_id],
+    )?;
+
+    if changed == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    Ok(())
+}
+
+fn delete_user_shares(conn: Connection, user_id: u32) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        DELETE
+        FROM Shares
+        WHERE user_id=(?1)
+    ",
+        &[&user_id],
+    )?;
+    Ok(())
+}
+
+// counting and checking limit in one query, so parallel downloads cant go over it
+fn count_download(conn: Connection, id: u32) -> Result<(), rusqlite::Error> {
+    let changed = conn.execute(
+        "
+        UPDATE Shares
+        SET downloads = downloads + 1
+        WHERE id=(?1) AND (max_downloads IS NULL OR downloads < max_downloads)
+    ",
+        &[&id],
+    )?;
+
+    if changed == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    Ok(())
+}
+
+fn count_file(conn: Connection, id: u32) -> Result<(), rusqlite::Error> {
+    let changed = conn.execute(
+        "
+        UPDATE Shares
+        SET files = files + 1
+        WHERE id=(?1) AND (max_files IS NULL OR files < max_files)
+    ",
+        &[&id],
+    )?;
+
+    if changed == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    Ok(())
+}
+
+// file which failed to upload is not counted
+fn uncount_file(conn: Connection, id: u32) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        UPDATE Shares
+        SET files = files - 1
+        WHERE id=(?1) AND files > 0
+    ",
+        &[&id],
+    )?;
+    Ok(())
+}
+
+pub fn create_table(conn: &Pool) {
+    conn.get()
+        .unwrap()
+        .execute(
+            "create table if not exists Shares (
+            id integer primary key,
+            user_id UNSIGNED INT NOT NULL,
+            slug TEXT NOT NULL UNIQUE,
+            path TEXT NOT NULL,
+            pass TEXT,
+            mode TEXT NOT NULL,
+            created INTEGER NOT NULL,
+            expires INTEGER,
+            max_downloads UNSIGNED INT,
+            downloads UNSIGNED INT NOT NULL DEFAULT 0,
+            max_file_size INTEGER,
+            max_files UNSIGNED INT,
+            files UNSIGNED INT NOT NULL DEFAULT 0,
+            extensions TEXT)
+        ",
+            NO_PARAMS,
+        )
+        .unwrap();
+}
@@ -0,0 +1,171 @@
+use rusqlite::{params, NO_PARAMS};
+
+use super::{Connection, Pool, QueryResult};
+use crate::models::{Totp, TotpQueries};
+
+pub fn execute(pool: &Pool, query: TotpQueries) -> Result<QueryResult<Totp>, rusqlite::Error> {
+    let conn = pool
+        .get()
+        .map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
+
+    Ok(match query {
+        TotpQueries::GetTotp(user_id) => QueryResult::One(get_totp(conn, user_id)?),
+        TotpQueries::SetSecret(user_id, secret, codes) => {
+            QueryResult::None(set_secret(conn, user_id, &secret, &codes)?)
+        }
+        TotpQueries::EnableTotp(user_id) => QueryResult::None(enable_totp(conn, user_id)?),
+        TotpQueries::DeleteTotp(user_id) => QueryResult::None(delete_totp(conn, user_id)?),
+        TotpQueries::UseRecoveryCode(user_id, code_hash) => {
+            QueryResult::None(use_recovery_code(conn, user_id, &code_hash)?)
+        }
+        TotpQueries::UseStep(user_id, step) => QueryResult::None(use_step(conn, user_id, step)?),
+    })
+}
+
+fn get_totp(conn: Connection, user_id: u32) -> Result<Totp, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Totp
+        WHERE user_id=(?1)
+        LIMIT 1
+    ",
+    )?
+    .query_row(&[&user_id], |row| {
+        Ok(Totp {
+            user_id: row.get(0)?,
+            secret: row.get(1)?,
+            enabled: row.get(2)?,
+        })
+    })
+}
+
+// new secret is not enabled until user confirms it with code
+fn set_secret(
+    mut conn: Connection,
+    user_id: u32,
+    secret: &str,
+    codes: &[String],
+) -> Result<(), rusqlite::Error> {
+    let tx = conn.transaction()?;
+
+    tx.execute(
+        "
+        INSERT OR REPLACE INTO Totp (user_id, secret, enabled, last_step)
+        VALUES(?1, ?2, 0, 0)
+    ",
+        params![user_id, secret],
+    )?;
+    tx.execute(
+        "
+        DELETE
+        FROM RecoveryCodes
+        WHERE user_id=(?1)
+    ",
+        &[&user_id],
+    )?;
+    for code in codes {
+        tx.execute(
+            "
+            INSERT INTO RecoveryCodes (user_id, code_hash, used)
+            VALUES(?1, ?2, 0)
+        ",
+            params![user_id, code],
+        )?;
+    }
+
+    tx.commit()
+}
+
+fn enable_totp(conn: Connection, user_id: u32) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        UPDATE Totp
+        SET enabled = 1
+        WHERE user_id=(?1)
+    ",
+        &[&user_id],
+    )?;
+    Ok(())
+}
+
+fn delete_totp(conn: Connection, user_id: u32) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        DELETE
+        FROM Totp
+        WHERE user_id=(?1)
+    ",
+        &[&user_id],
+    )?;
+    conn.execute(
+        "
+        DELETE
+        FROM RecoveryCodes
+        WHERE user_id=(?1)
+    ",
+        &[&user_id],
+    )?;
+    Ok(())
+}
+
+fn use_recovery_code(conn: Connection, user_id: u32, code_hash: &str) -> Result<(), rusqlite::Error> {
+    let changed = conn.execute(
+        "
+        UPDATE RecoveryCodes
+        SET used = 1
+        WHERE user_id=(?1) AND code_hash=(?2) AND used = 0
+    ",
+        params![user_id, code_hash],
+    )?;
+
+    if changed == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    Ok(())
+}
+
+// code is valid for few steps, so step of used code is kept and only newer one is accepted,
+// update with condition makes two requests with same code unable to both pass
+fn use_step(conn: Connection, user_id: u32, step: i64) -> Result<(), rusqlite::Error> {
+    let changed = conn.execute(
+        "
+        UPDATE Totp
+        SET last_step = (?2)
+        WHERE user_id=(?1) AND last_step < (?2)
+    ",
+        params![user_id, step],
+    )?;
+
+    if changed == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    Ok(())
+}
+
+pub fn create_table(conn: &Pool) {
+    conn.get()
+        .unwrap()
+        .execute(
+            "create table if not exists Totp (
+            user_id integer primary key,
+            secret TEXT NOT NULL,
+            enabled BOOLEAN NOT NULL DEFAULT 0,
+            last_step INTEGER NOT NULL DEFAULT 0)
+        ",
+            NO_PARAMS,
+        )
+        .unwrap();
+    conn.get()
+        .unwrap()
+        .execute(
+            "create table if not exists RecoveryCodes (
+            id integer primary key,
+            user_id integer NOT NULL,
+            code_hash TEXT NOT NULL,
+            used BOOLEAN NOT NULL DEFAULT 0)
+        ",
+            NO_PARAMS,
+        )
+        .unwrap();
+}
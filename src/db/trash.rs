@@ -0,0 +1,141 @@
+use rusqlite::{params, NO_PARAMS};
+
+use super::{inside, Connection, Pool, QueryResult};
+use crate::models::{TrashItem, TrashQueries};
+
+pub fn execute(pool: &Pool, query: TrashQueries) -> Result<QueryResult<TrashItem>, rusqlite::Error> {
+    let conn = pool
+        .get()
+        .map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
+
+    Ok(match query {
+        TrashQueries::AddItem(item) => QueryResult::None(add_item(conn, &item)?),
+        TrashQueries::GetItem(id) => QueryResult::One(get_item(conn, &id)?),
+        TrashQueries::GetItemsIn(home) => QueryResult::Many(get_items_in(conn, &home)?),
+        TrashQueries::GetUserItems(user_id) => QueryResult::Many(get_user_items(conn, user_id)?),
+        TrashQueries::GetExpiredItems(before) => {
+            QueryResult::Many(get_expired_items(conn, before)?)
+        }
+        TrashQueries::DeleteItem(id) => QueryResult::None(delete_item(conn, &id)?),
+    })
+}
+
+fn row_to_item(row: &rusqlite::Row) -> Result<TrashItem, rusqlite::Error> {
+    Ok(TrashItem {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        path: row.get(2)?,
+        target: row.get(3)?,
+        is_dir: row.get(4)?,
+        size: row.get::<_, i64>(5)? as u64,
+        deleted: row.get(6)?,
+    })
+}
+
+fn add_item(conn: Connection, item: &TrashItem) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        INSERT INTO Trash (id, user_id, path, target, is_dir, size, deleted)
+        VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7)
+    ",
+        params![
+            item.id,
+            item.user_id,
+            item.path,
+            item.target,
+            item.is_dir,
+            item.size as i64,
+            item.deleted
+        ],
+    )?;
+    Ok(())
+}
+
+fn get_item(conn: Connection, id: &str) -> Result<TrashItem, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Trash
+        WHERE id=(?1)
+        LIMIT 1
+    ",
+    )?
+    .query_row(&[&id], row_to_item)
+}
+
+fn get_items_in(conn: Connection, home: &str) -> Result<Vec<TrashItem>, rusqlite::Error> {
+    conn.prepare(
+        &format!(
+            "
+            SELECT *
+            FROM Trash
+            WHERE {}
+        ",
+            inside("target", 1),
+        ),
+    )?
+    .query_map(&[home], row_to_item)
+    .and_then(Iterator::collect)
+}
+
+fn get_user_items(conn: Connection, user_id: u32) -> Result<Vec<TrashItem>, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Trash
+        WHERE user_id=(?1)
+        ORDER BY deleted DESC
+    ",
+    )?
+    .query_map(&[&user_id], row_to_item)
+    .and_then(Iterator::collect)
+}
+
+fn get_expired_items(conn: Connection, before: i64) -> Result<Vec<TrashItem>, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Trash
+        WHERE deleted <= (?1)
+    ",
+    )?
+    .query_map(&[&before], row_to_item)
+    .and_then(Iterator::collect)
+}
+
+fn delete_item(conn: Connection, id: &str) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        DELETE
+        FROM Trash
+        WHERE id=(?1)
+    ",
+        &[&id],
+    )?;
+    Ok(())
+}
+
+pub fn create_table(conn: &Pool) {
+    conn.get()
+        .unwrap()
+        .execute(
+            "create table if not exists Trash (
+            id TEXT primary key,
+            user_id UNSIGNED INT NOT NULL,
+            path TEXT NOT NULL,
+            target TEXT NOT NULL,
+            is_dir BOOLEAN NOT NULL,
+            size INTEGER NOT NULL,
+            deleted INTEGER NOT NULL)
+        ",
+            NO_PARAMS,
+        )
+        .unwrap();
+    conn.get()
+        .unwrap()
+        .execute(
+            "create index if not exists TrashTarget on Trash (target)",
+            NO_PARAMS,
+        )
+        .unwrap();
+}
@@ -0,0 +1,165 @@
+use rusqlite::{params, NO_PARAMS};
+
+use super::{inside, Connection, Pool, QueryResult};
+use crate::models::{Upload, UploadQueries};
+
+pub fn execute(pool: &Pool, query: UploadQueries) -> Result<QueryResult<Upload>, rusqlite::Error> {
+    let conn = pool
+        .get()
+        .map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
+
+    Ok(match query {
+        UploadQueries::AddUpload(upload) => QueryResult::None(add_upload(conn, &upload)?),
+        UploadQueries::GetUpload(id) => QueryResult::One(get_upload(conn, &id)?),
+        UploadQueries::GetUploadsIn(home) => QueryResult::Many(get_uploads_in(conn, &home)?),
+        UploadQueries::GetUserUploads(user_id) => {
+            QueryResult::Many(get_user_uploads(conn, user_id)?)
+        }
+        UploadQueries::GetExpiredUploads(now) => {
+            QueryResult::Many(get_expired_uploads(conn, now)?)
+        }
+        UploadQueries::SetOffset(id, old, new) => {
+            QueryResult::None(set_offset(conn, &id, old, new)?)
+        }
+        UploadQueries::DeleteUpload(id) => QueryResult::None(delete_upload(conn, &id)?),
+    })
+}
+
+fn row_to_upload(row: &rusqlite::Row) -> Result<Upload, rusqlite::Error> {
+    Ok(Upload {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        path: row.get(2)?,
+        target: row.get(3)?,
+        length: row.get::<_, i64>(4)? as u64,
+        offset: row.get::<_, i64>(5)? as u64,
+        created: row.get(6)?,
+        expires: row.get(7)?,
+    })
+}
+
+fn add_upload(conn: Connection, upload: &Upload) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        INSERT INTO Uploads (id, user_id, path, target, length, received, created, expires)
+        VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+    ",
+        params![
+            upload.id,
+            upload.user_id,
+            upload.path,
+            upload.target,
+            upload.length as i64,
+            upload.offset as i64,
+            upload.created,
+            upload.expires
+        ],
+    )?;
+    Ok(())
+}
+
+fn get_upload(conn: Connection, id: &str) -> Result<Upload, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Uploads
+        WHERE id=(?1)
+        LIMIT 1
+    ",
+    )?
+    .query_row(&[&id], row_to_upload)
+}
+
+fn get_uploads_in(conn: Connection, home: &str) -> Result<Vec<Upload>, rusqlite::Error> {
+    conn.prepare(
+        &format!(
+            "
+            SELECT *
+            FROM Uploads
+            WHERE {}
+        ",
+            inside("target", 1),
+        ),
+    )?
+    .query_map(&[home], row_to_upload)
+    .and_then(Iterator::collect)
+}
+
+fn get_user_uploads(conn: Connection, user_id: u32) -> Result<Vec<Upload>, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Uploads
+        WHERE user_id=(?1)
+    ",
+    )?
+    .query_map(&[&user_id], row_to_upload)
+    .and_then(Iterator::collect)
+}
+
+fn get_expired_uploads(conn: Connection, now: i64) -> Result<Vec<Upload>, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Uploads
+        WHERE expires <= (?1)
+    ",
+    )?
+    .query_map(&[&now], row_to_upload)
+    .and_then(Iterator::collect)
+}
+
+// offset moves only from value which request saw, so parallel requests cant both append
+fn set_offset(conn: Connection, id: &str, old: u64, new: u64) -> Result<(), rusqlite::Error> {
+    let changed = conn.execute(
+        "
+        UPDATE Uploads
+        SET received = ?3
+        WHERE id=(?1) AND received=(?2)
+    ",
+        params![id, old as i64, new as i64],
+    )?;
+
+    if changed == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    Ok(())
+}
+
+fn delete_upload(conn: Connection, id: &str) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        DELETE
+        FROM Uploads
+        WHERE id=(?1)
+    ",
+        &[&id],
+    )?;
+    Ok(())
+}
+
+pub fn create_table(conn: &Pool) {
+    conn.get()
+        .unwrap()
+        .execute(
+            "create table if not exists Uploads (
+            id TEXT primary key,
+            user_id UNSIGNED INT NOT NULL,
+            path TEXT NOT NULL,
+            target TEXT NOT NULL,
+            length INTEGER NOT NULL,
+            received INTEGER NOT NULL,
+            created INTEGER NOT NULL,
+            expires INTEGER NOT NULL)
+        ",
+            NO_PARAMS,
+        )
+        .unwrap();
+    conn.get()
+        .unwrap()
+        .execute(
+            "create index if not exists UploadTarget on Uploads (target)",
+            NO_PARAMS,
+        )
+        .unwrap();
+}
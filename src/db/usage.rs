@@ -0,0 +1,97 @@
+use rusqlite::{params, NO_PARAMS};
+
+use super::{Connection, Pool, QueryResult};
+use crate::models::{Usage, UsageQueries};
+
+pub fn execute(pool: &Pool, query: UsageQueries) -> Result<QueryResult<Usage>, rusqlite::Error> {
+    let conn = pool
+        .get()
+        .map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
+
+    Ok(match query {
+        UsageQueries::GetUsage(path) => QueryResult::One(get_usage(conn, &path)?),
+        UsageQueries::GetAllUsage => QueryResult::Many(get_all_usage(conn)?),
+        UsageQueries::SetUsed(path, used) => QueryResult::None(set_usage(conn, &path, used)?),
+        UsageQueries::AddUsed(path, delta) => QueryResult::None(add_usage(conn, &path, delta)?),
+        UsageQueries::DeleteUsage(path) => QueryResult::None(delete_usage(conn, &path)?),
+    })
+}
+
+fn row_to_usage(row: &rusqlite::Row) -> Result<Usage, rusqlite::Error> {
+    Ok(Usage {
+        path: row.get(0)?,
+        used: row.get::<_, i64>(1)? as u64,
+    })
+}
+
+fn get_usage(conn: Connection, path: &str) -> Result<Usage, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Usage
+        WHERE path=(?1)
+        LIMIT 1
+    ",
+    )?
+    .query_row(&[&path], row_to_usage)
+}
+
+fn get_all_usage(conn: Connection) -> Result<Vec<Usage>, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Usage
+    ",
+    )?
+    .query_map(NO_PARAMS, row_to_usage)
+    .and_then(Iterator::collect)
+}
+
+fn set_usage(conn: Connection, path: &str, used: u64) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        INSERT OR REPLACE INTO Usage (path, used)
+        VALUES(?1, ?2)
+    ",
+        params![path, used as i64],
+    )?;
+    Ok(())
+}
+
+// every home containing path is changed, homes can be inside each other
+fn add_usage(conn: Connection, path: &str, delta: i64) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        UPDATE Usage
+        SET used = MAX(used + (?2), 0)
+        WHERE path = '/' OR path = (?1) OR substr(?1, 1, length(path) + 1) = path || '/'
+    ",
+        params![path, delta],
+    )?;
+    Ok(())
+}
+
+fn delete_usage(conn: Connection, path: &str) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        DELETE
+        FROM Usage
+        WHERE path=(?1)
+    ",
+        &[&path],
+    )?;
+    Ok(())
+}
+
+pub fn create_table(conn: &Pool) {
+    conn.get()
+        .unwrap()
+        .execute(
+            "create table if not exists Usage (
+            path TEXT primary key,
+            used INTEGER NOT NULL)
+        ",
+            NO_PARAMS,
+        )
+        .unwrap();
+}
@@ -0,0 +1,157 @@
+use rusqlite::{params, NO_PARAMS};
+
+use super::{inside, Connection, Pool, QueryResult};
+use crate::models::{Version, VersionQueries};
+
+pub fn execute(pool: &Pool, query: VersionQueries) -> Result<QueryResult<Version>, rusqlite::Error> {
+    let conn = pool
+        .get()
+        .map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
+
+    Ok(match query {
+        VersionQueries::AddVersion(version) => QueryResult::None(add_version(conn, &version)?),
+        VersionQueries::GetVersion(path, number) => {
+            QueryResult::One(get_version(conn, &path, number)?)
+        }
+        VersionQueries::GetVersions(path) => QueryResult::Many(get_versions(conn, &path)?),
+        VersionQueries::GetAllVersions => QueryResult::Many(get_all_versions(conn)?),
+        VersionQueries::GetVersionsIn(home) => QueryResult::Many(get_versions_in(conn, &home)?),
+        VersionQueries::MoveVersions(from, to) => {
+            QueryResult::None(move_versions(conn, &from, &to)?)
+        }
+        VersionQueries::DeleteVersion(id) => QueryResult::None(delete_version(conn, &id)?),
+    })
+}
+
+fn row_to_version(row: &rusqlite::Row) -> Result<Version, rusqlite::Error> {
+    Ok(Version {
+        id: row.get(0)?,
+        path: row.get(1)?,
+        number: row.get(2)?,
+        user_id: row.get(3)?,
+        size: row.get::<_, i64>(4)? as u64,
+        checksum: row.get(5)?,
+        created: row.get(6)?,
+    })
+}
+
+// number is one after last version of path, so it is chosen inside the insert
+fn add_version(conn: Connection, version: &Version) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        INSERT INTO Versions (id, path, number, user_id, size, checksum, created)
+        VALUES(?1, ?2, (SELECT IFNULL(MAX(number), 0) + 1 FROM Versions WHERE path=(?2)), ?3, ?4, ?5, ?6)
+    ",
+        params![
+            version.id,
+            version.path,
+            version.user_id,
+            version.size as i64,
+            version.checksum,
+            version.created
+        ],
+    )?;
+    Ok(())
+}
+
+fn get_version(conn: Connection, path: &str, number: u32) -> Result<Version, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Versions
+        WHERE path=(?1) AND number=(?2)
+        LIMIT 1
+    ",
+    )?
+    .query_row(params![path, number], row_to_version)
+}
+
+// newest first
+fn get_versions(conn: Connection, path: &str) -> Result<Vec<Version>, rusqlite::Error> {
+    conn.prepare(
+        &format!(
+            "
+            SELECT *
+            FROM Versions
+            WHERE {}
+            ORDER BY path, number DESC
+        ",
+            inside("path", 1),
+        ),
+    )?
+    .query_map(&[&path], row_to_version)
+    .and_then(Iterator::collect)
+}
+
+fn get_all_versions(conn: Connection) -> Result<Vec<Version>, rusqlite::Error> {
+    conn.prepare(
+        "
+        SELECT *
+        FROM Versions
+    ",
+    )?
+    .query_map(NO_PARAMS, row_to_version)
+    .and_then(Iterator::collect)
+}
+
+fn get_versions_in(conn: Connection, home: &str) -> Result<Vec<Version>, rusqlite::Error> {
+    conn.prepare(
+        &format!(
+            "
+            SELECT *
+            FROM Versions
+            WHERE {}
+        ",
+            inside("path", 1),
+        ),
+    )?
+    .query_map(&[home], row_to_version)
+    .and_then(Iterator::collect)
+}
+
+// history goes with file when it is moved, also with folder it is in
+fn move_versions(conn: Connection, from: &str, to: &str) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        &format!(
+            "
+            UPDATE Versions
+            SET path = (?2) || substr(path, length(?1) + 1)
+            WHERE {}
+        ",
+            inside("path", 1),
+        ),
+        params![from, to],
+    )?;
+    Ok(())
+}
+
+fn delete_version(conn: Connection, id: &str) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "
+        DELETE
+        FROM Versions
+        WHERE id=(?1)
+    ",
+        &[&id],
+    )?;
+    Ok(())
+}
+
+pub fn create_table(conn: &Pool) {
+    conn.get()
+        .unwrap()
+        .execute(
+            "create table if not exists Versions (
+            id TEXT primary key,
+            path TEXT NOT NULL,
+            number UNSIGNED INT NOT NULL,
+            user_id UNSIGNED INT,
+            size INTEGER NOT NULL,
+            checksum TEXT NOT NULL,
+            created INTEGER NOT NULL,
+            UNIQUE(path, number))
+        ",
+            NO_PARAMS,
+        )
+        .unwrap();
+}
@@ -0,0 +1,550 @@
+use flate2::read::GzDecoder;
+use sha2::{Digest, Sha256};
+use std::cell::Cell;
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::rc::Rc;
+
+use crate::acl::{normalize, Acl};
+use crate::db::Pool;
+use crate::handlers::file::free_name;
+use crate::models::{Conflict, EntryStatus, Extracted, ExtractedEntry, Permission, Version};
+use crate::quota;
+use crate::reserr::ResErr;
+use crate::storage::{self, Store};
+use crate::versions;
+
+// output is checked against ratio only after this many bytes, small files compress a lot
+const RATIO_AFTER: u64 = 1024 * 1024;
+
+pub enum Kind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+enum EntryKind {
+    File,
+    Dir,
+    Link,
+    Other,
+}
+
+// archive is recognized by its first bytes, not by its name
+pub fn detect(file: &mut File) -> io::Result<Option<Kind>> {
+    let mut head = Vec::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.take(512).read_to_end(&mut head)?;
+    file.seek(SeekFrom::Start(0))?;
+
+    Ok(match head.as_slice() {
+        [b'P', b'K', 3, 4, ..] | [b'P', b'K', 5, 6, ..] => Some(Kind::Zip),
+        [0x1f, 0x8b, ..] => Some(Kind::TarGz),
+        h if h.len() >= 262 && &h[257..262] == b"ustar" => Some(Kind::Tar),
+        _ => None,
+    })
+}
+
+// bytes read from archive file, ratio of output to them finds decompression bombs
+struct Counting<R: Read> {
+    inner: R,
+    count: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for Counting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.count.set(self.count.get() + len as u64);
+        Ok(len)
+    }
+}
+
+// "a/./b" and "a\b" are "a/b", absolute paths and ".." are refused so nothing gets out of folder
+fn safe_name(name: &str) -> Option<String> {
+    let name = name.replace('\\', "/");
+    if name.starts_with('/') || name.split('/').next().is_some_and(|p| p.contains(':')) {
+        return None;
+    }
+
+    let mut parts = Vec::new();
+    for part in name.split('/') {
+        match part {
+            "" | "." => continue,
+            ".." => return None,
+            part => parts.push(part),
+        }
+    }
+
+    match parts.is_empty() {
+        true => None,
+        false => Some(parts.join("/")),
+    }
+}
+
+// "/a/b" -> "/a", "/a" -> "/"
+fn parent(path: &str) -> &str {
+    match path.rsplit_once('/') {
+        Some(("", _)) | None => "/",
+        Some((p, _)) => p,
+    }
+}
+
+// file written from archive, it is counted into quota only when whole archive is done
+struct Written {
+    path: String,
+    size: u64,
+    checksum: String,
+    replaced: Option<Version>,
+}
+
+struct Extraction<'a> {
+    acl: &'a Acl,
+    db: &'a Pool,
+    storage: &'a Store,
+    folder: String,
+    conflict: Conflict,
+    limit: u64,
+    max_entries: usize,
+    max_ratio: u64,
+    entries: usize,
+    size: u64,
+    compressed: Rc<Cell<u64>>,
+    report: Vec<ExtractedEntry>,
+    dirs: Vec<String>,
+    files: Vec<Written>,
+}
+
+impl<'a> Extraction<'a> {
+    fn report(&mut self, name: &str, path: Option<String>, status: EntryStatus, reason: Option<&str>) {
+        self.report.push(ExtractedEntry {
+            name: name.to_string(),
+            path,
+            status,
+            reason: reason.map(|r| r.to_string()),
+        });
+    }
+
+    fn skip(&mut self, name: &str, reason: &str) -> Result<(), ResErr> {
+        self.report(name, None, EntryStatus::Skipped, Some(reason));
+        Ok(())
+    }
+
+    // missing folders are created, highest created one is kept so it can be removed again
+    fn mkdir(&mut self, path: &str) -> Result<(), ResErr> {
+        let mut top = None;
+        let mut current = path;
+        while current != "/" && !self.storage.exists(current) {
+            top = Some(current.to_string());
+            current = parent(current);
+        }
+
+        if let Some(top) = top {
+            self.storage
+                .mkdir(path)
+                .map_err(|_| ResErr::InternalError("cant create folder"))?;
+            self.dirs.push(top);
+        }
+        Ok(())
+    }
+
+    fn entry(&mut self, name: &str, kind: EntryKind, reader: &mut dyn Read) -> Result<(), ResErr> {
+        self.entries += 1;
+        if self.entries > self.max_entries {
+            return Err(ResErr::BadClientData("archive has too many entries"));
+        }
+
+        let clean = match kind {
+            EntryKind::Link => return self.skip(name, "links are not extracted"),
+            EntryKind::Other => return self.skip(name, "unsupported entry type"),
+            _ => match safe_name(name) {
+                Some(v) => v,
+                None => return self.skip(name, "unsafe path"),
+            },
+        };
+
+        let logical = format!("{}/{}", self.folder.trim_end_matches('/'), clean);
+        let mut target = match self.acl.resolve_new(&logical, Permission::Write) {
+            Ok(v) => v,
+            Err(ResErr::BadClientData(reason)) => return self.skip(name, reason),
+            Err(_) => return self.skip(name, "you dont have access to this path"),
+        };
+
+        // folder cant be created inside file
+        let folder = parent(&target).to_string();
+        let mut ancestor = folder.as_str();
+        while ancestor != "/" && !self.storage.exists(ancestor) {
+            ancestor = parent(ancestor);
+        }
+        if self.storage.stat(ancestor).is_ok_and(|meta| !meta.is_dir) {
+            return self.skip(name, "file is in the way");
+        }
+
+        let existing = self.storage.stat(&target).ok();
+
+        if let EntryKind::Dir = kind {
+            return match existing {
+                Some(meta) if meta.is_dir => {
+                    self.report(name, Some(logical), EntryStatus::Exists, None);
+                    Ok(())
+                }
+                Some(_) => self.skip(name, "file is in the way"),
+                None => {
+                    self.mkdir(&target)?;
+                    self.report(name, Some(logical), EntryStatus::Created, None);
+                    Ok(())
+                }
+            };
+        }
+
+        let mut status = EntryStatus::Created;
+        let mut path = logical.clone();
+        let mut replaced = None;
+
+        match existing {
+            Some(meta) if meta.is_dir => return self.skip(name, "folder is in the way"),
+            Some(_) => match self.conflict {
+                Conflict::Fail => return self.skip(name, "file already exists"),
+                Conflict::Rename => {
+                    let (folder, file) = logical.rsplit_once('/').unwrap_or(("", &logical));
+                    target = free_name(self.acl, self.storage, folder, file)?;
+                    path = format!("{}/{}", folder, storage::name(&target));
+                    status = EntryStatus::Renamed;
+                }
+                Conflict::Overwrite => {
+                    replaced = versions::archive(self.db, self.storage, &target)?;
+                    status = EntryStatus::Replaced;
+                }
+            },
+            None => self.mkdir(&folder)?,
+        }
+
+        match self.write(reader, &target) {
+            Ok((size, checksum)) => {
+                self.files.push(Written {
+                    path: target,
+                    size,
+                    checksum,
+                    replaced,
+                });
+                self.report(name, Some(path), status, None);
+                Ok(())
+            }
+            Err(err) => {
+                if let Some(version) = &replaced {
+                    versions::unarchive(self.db, self.storage, version);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    // output of whole archive has to fit into quota and cant grow too much over what was read
+    fn write(&mut self, reader: &mut dyn Read, target: &str) -> Result<(u64, String), ResErr> {
+        let mut writer = self
+            .storage
+            .create(target)
+            .map_err(|_| ResErr::InternalError("cant write file"))?;
+
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0; 64 * 1024];
+        let mut size: u64 = 0;
+
+        loop {
+            let len = reader
+                .read(&mut buf)
+                .map_err(|_| ResErr::BadClientData("archive is broken"))?;
+            if len == 0 {
+                break;
+            }
+
+            size += len as u64;
+            self.size += len as u64;
+            if self.size > self.limit {
+                return Err(ResErr::BadClientData("you dont have size"));
+            }
+            if self.size > RATIO_AFTER && self.size > self.compressed.get().saturating_mul(self.max_ratio) {
+                return Err(ResErr::BadClientData("archive unpacks too much, it looks like zip bomb"));
+            }
+
+            hasher.update(&buf[..len]);
+            writer
+                .write_all(&buf[..len])
+                .map_err(|_| ResErr::InternalError("cant write file"))?;
+        }
+
+        writer.finish().map_err(|err| match err.kind() {
+            io::ErrorKind::AlreadyExists => ResErr::BadClientData("file already exists"),
+            _ => ResErr::InternalError("cant write file"),
+        })?;
+
+        Ok((size, hex::encode(hasher.finalize())))
+    }
+
+    // failed archive leaves nothing behind, replaced files get their old content back
+    fn rollback(&mut self) {
+        for file in self.files.iter().rev() {
+            let _ = self.storage.delete(&file.path);
+
+            if let Some(version) = &file.replaced {
+                versions::unarchive(self.db, self.storage, version);
+            }
+        }
+
+        for dir in self.dirs.iter().rev() {
+            let _ = self.storage.delete(dir);
+        }
+    }
+
+    fn commit(&self) -> Result<(), ResErr> {
+        for file in self.files.iter() {
+            quota::add(self.db, &file.path, file.size as i64)?;
+            versions::stored(self.db, &file.path, Some(self.acl.user_id), file.checksum.clone())?;
+
+            if file.replaced.is_some() {
+                versions::prune(self.db, self.storage, &file.path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn zip(&mut self, file: File) -> Result<(), ResErr> {
+        let mut zip = zip::ZipArchive::new(file).map_err(|_| ResErr::BadClientData("archive is broken"))?;
+
+        // sizes in zip can lie, but when even they dont fit, nothing is extracted
+        let mut declared: u64 = 0;
+        for i in 0..zip.len() {
+            let entry = zip
+                .by_index_raw(i)
+                .map_err(|_| ResErr::BadClientData("archive is broken"))?;
+            declared = declared.saturating_add(entry.size());
+        }
+        if declared > self.limit {
+            return Err(ResErr::BadClientData("you dont have size"));
+        }
+
+        for i in 0..zip.len() {
+            let (name, compressed, kind) = {
+                let entry = zip
+                    .by_index_raw(i)
+                    .map_err(|_| ResErr::BadClientData("archive is broken"))?;
+
+                let kind = match entry.unix_mode() {
+                    _ if entry.is_dir() => EntryKind::Dir,
+                    Some(mode) if mode & 0o170000 == 0o120000 => EntryKind::Link,
+                    Some(mode) if mode & 0o170000 != 0o100000 && mode & 0o170000 != 0 => EntryKind::Other,
+                    _ => EntryKind::File,
+                };
+                (entry.name().to_string(), entry.compressed_size(), kind)
+            };
+            self.compressed.set(self.compressed.get() + compressed);
+
+            // encrypted entry or unknown compression
+            match zip.by_index(i) {
+                Ok(mut entry) => self.entry(&name, kind, &mut entry)?,
+                Err(_) => self.skip(&name, "entry cant be read")?,
+            }
+        }
+        Ok(())
+    }
+
+    fn tar(&mut self, reader: impl Read) -> Result<(), ResErr> {
+        let mut archive = tar::Archive::new(reader);
+        let entries = archive
+            .entries()
+            .map_err(|_| ResErr::BadClientData("archive is broken"))?;
+
+        for entry in entries {
+            let mut entry = entry.map_err(|_| ResErr::BadClientData("archive is broken"))?;
+            let name = String::from_utf8_lossy(&entry.path_bytes()).to_string();
+
+            let kind = match entry.header().entry_type() {
+                tar::EntryType::Regular | tar::EntryType::Continuous => EntryKind::File,
+                tar::EntryType::Directory => EntryKind::Dir,
+                tar::EntryType::Symlink | tar::EntryType::Link => EntryKind::Link,
+                _ => EntryKind::Other,
+            };
+
+            self.entry(&name, kind, &mut entry)?;
+        }
+        Ok(())
+    }
+}
+
+// extracts archive into folder, folder is path from request of user owning acl,
+// entries which cant be extracted are skipped and reported, other errors undo everything
+pub fn extract(
+    acl: &Acl,
+    db: &Pool,
+    storage: &Store,
+    folder: &str,
+    conflict: Conflict,
+    free: u64,
+    mut file: File,
+) -> Result<Extracted, ResErr> {
+    let kind = detect(&mut file)
+        .map_err(|_| ResErr::InternalError("cant read archive"))?
+        .ok_or(ResErr::BadClientData("only zip, tar and tar.gz archives can be extracted"))?;
+
+    let compressed = Rc::new(Cell::new(0));
+    let mut extraction = Extraction {
+        acl,
+        db,
+        storage,
+        folder: normalize(folder)?,
+        conflict,
+        limit: free,
+        max_entries: env::var("EXTRACT_MAX_ENTRIES").unwrap().parse().unwrap(),
+        max_ratio: env::var("EXTRACT_MAX_RATIO").unwrap().parse().unwrap(),
+        entries: 0,
+        size: 0,
+        compressed: compressed.clone(),
+        report: Vec::new(),
+        dirs: Vec::new(),
+        files: Vec::new(),
+    };
+
+    let result = match kind {
+        Kind::Zip => extraction.zip(file),
+        Kind::Tar => extraction.tar(Counting { inner: file, count: compressed }),
+        Kind::TarGz => extraction.tar(GzDecoder::new(Counting { inner: file, count: compressed })),
+    };
+
+    if let Err(err) = result.and_then(|_| extraction.commit()) {
+        extraction.rollback();
+        return Err(err);
+    }
+
+    Ok(Extracted {
+        size: extraction.size,
+        entries: extraction.report,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::zip::write::{FileOptions, ZipWriter};
+    use ::zip::CompressionMethod;
+
+    use crate::db::{acl as acl_entries, files, groups, test_pool, usage, versions as history};
+    use crate::models::User;
+    use crate::storage::memory::MemoryStorage;
+
+    // same values in every test, they run at once and share environment
+    fn setup() -> (Pool, Store, Acl) {
+        env::set_var("EXTRACT_MAX_ENTRIES", "10");
+        env::set_var("EXTRACT_MAX_RATIO", "100");
+
+        let db = test_pool();
+        acl_entries::create_table(&db);
+        groups::create_table(&db);
+        files::create_table(&db);
+        history::create_table(&db);
+        usage::create_table(&db);
+
+        let storage: Store = Box::<MemoryStorage>::default();
+        storage.mkdir("/bob").unwrap();
+
+        let user = User {
+            id: 2,
+            name: "bobby".to_string(),
+            email: "b@b.com".to_string(),
+            pass: String::new(),
+            size: 1,
+            path: "/bob".to_string(),
+            status: 3,
+            verified: None,
+        };
+        let acl = Acl::for_user(&db, &user, vec![Permission::Read, Permission::Write]).unwrap();
+
+        (db, storage, acl)
+    }
+
+    fn archive(entries: &[(&str, Vec<u8>)]) -> File {
+        let mut zip = ZipWriter::new(tempfile::tempfile().unwrap());
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+        for (name, content) in entries {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(content).unwrap();
+        }
+        zip.finish().unwrap()
+    }
+
+    fn run(db: &Pool, storage: &Store, acl: &Acl, file: File) -> Result<Extracted, ResErr> {
+        extract(acl, db, storage, "/", Conflict::Fail, 100 * 1024 * 1024, file)
+    }
+
+    #[test]
+    fn names_cant_leave_folder() {
+        assert_eq!(safe_name("a/./b").as_deref(), Some("a/b"));
+        assert_eq!(safe_name("a\\b//c/").as_deref(), Some("a/b/c"));
+        assert_eq!(safe_name("/etc/passwd"), None);
+        assert_eq!(safe_name("\\etc"), None);
+        assert_eq!(safe_name("a/../../b"), None);
+        assert_eq!(safe_name("C:/windows"), None);
+        assert_eq!(safe_name("./"), None);
+    }
+
+    #[test]
+    fn unsafe_entries_are_skipped() {
+        let (db, storage, acl) = setup();
+        let file = archive(&[
+            ("../evil.txt", b"evil".to_vec()),
+            ("/abs.txt", b"abs".to_vec()),
+            ("ok/a.txt", b"ok".to_vec()),
+        ]);
+
+        let extracted = run(&db, &storage, &acl, file).unwrap();
+
+        let skipped: Vec<_> = extracted
+            .entries
+            .iter()
+            .filter(|e| matches!(e.status, EntryStatus::Skipped))
+            .map(|e| e.name.as_str())
+            .collect();
+        assert_eq!(skipped, vec!["../evil.txt", "/abs.txt"]);
+        assert_eq!(extracted.size, 2);
+        assert!(storage.exists("/bob/ok/a.txt"));
+        assert!(!storage.exists("/evil.txt"));
+        assert!(!storage.exists("/abs.txt"));
+    }
+
+    #[test]
+    fn bomb_is_refused_and_nothing_is_left() {
+        let (db, storage, acl) = setup();
+        let file = archive(&[("small.txt", b"small".to_vec()), ("zeros.bin", vec![0; 4 * 1024 * 1024])]);
+
+        assert!(matches!(
+            run(&db, &storage, &acl, file),
+            Err(ResErr::BadClientData("archive unpacks too much, it looks like zip bomb"))
+        ));
+        assert!(!storage.exists("/bob/small.txt"));
+        assert!(!storage.exists("/bob/zeros.bin"));
+    }
+
+    #[test]
+    fn too_many_entries_are_refused() {
+        let (db, storage, acl) = setup();
+        let names: Vec<String> = (0..11).map(|i| format!("{}.txt", i)).collect();
+        let entries: Vec<(&str, Vec<u8>)> = names.iter().map(|n| (n.as_str(), b"x".to_vec())).collect();
+
+        assert!(matches!(
+            run(&db, &storage, &acl, archive(&entries)),
+            Err(ResErr::BadClientData("archive has too many entries"))
+        ));
+        assert!(storage.list("/bob").unwrap().is_empty());
+    }
+
+    #[test]
+    fn archive_has_to_fit_into_quota() {
+        let (db, storage, acl) = setup();
+        let file = archive(&[("a.bin", vec![1; 2000])]);
+
+        assert!(matches!(
+            extract(&acl, &db, &storage, "/", Conflict::Fail, 1000, file),
+            Err(ResErr::BadClientData("you dont have size"))
+        ));
+    }
+}
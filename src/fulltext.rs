@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc};
+use std::time::UNIX_EPOCH;
+use std::{env, thread};
+
+use crate::acl::hidden;
+use crate::db::{contents, index, Pool};
+use crate::models::{ContentFile, ContentQueries, IndexFilter, IndexQueries};
+use crate::reserr::ResErr;
+use crate::sniff;
+use crate::storage::Store;
+
+// with FULLTEXT text of documents is kept in fts5 index, files are read in background after they are stored,
+// files which are not text or pdf, or are too big, are in index without text, so they are not read again
+
+pub fn enabled() -> bool {
+    env::var("FULLTEXT").unwrap() == "true"
+}
+
+// pdf parser can panic on broken file, that only means there is no text
+fn pdf_text(data: &[u8]) -> Option<String> {
+    panic::catch_unwind(AssertUnwindSafe(|| pdf_extract::extract_text_from_mem(data)))
+        .ok()?
+        .ok()
+}
+
+fn text(storage: &Store, path: &str, size: u64) -> Option<String> {
+    let max: u64 = env::var("FULLTEXT_MAX_SIZE").unwrap().parse().unwrap();
+    if size > max {
+        return None;
+    }
+
+    let mut data = Vec::new();
+    storage.read(path).ok()?.take(max).read_to_end(&mut data).ok()?;
+
+    match () {
+        _ if data.starts_with(b"%PDF-") => pdf_text(&data),
+        _ if sniff::is_text(&data) => Some(String::from_utf8_lossy(&data).into_owned()),
+        _ => None,
+    }
+}
+
+// file is read again, path which is gone leaves index, folders are left alone
+pub fn update(db: &Pool, storage: &Store, path: &str) {
+    if hidden(path) {
+        return;
+    }
+
+    let query = match storage.stat(path) {
+        Ok(meta) if meta.is_dir => return,
+        Ok(meta) => {
+            let file = ContentFile {
+                id: 0,
+                path: path.to_string(),
+                size: meta.size,
+                modified: meta
+                    .modified
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs() as i64),
+            };
+            ContentQueries::SetContent(file, text(storage, path, meta.size).unwrap_or_default())
+        }
+        Err(_) => ContentQueries::DeleteFiles(path.to_string()),
+    };
+
+    if contents::execute(db, query).is_err() {
+        eprintln!("cant update content index");
+    }
+}
+
+pub fn remove(db: &Pool, path: &str) {
+    if contents::execute(db, ContentQueries::DeleteFiles(path.to_string())).is_err() {
+        eprintln!("cant update content index");
+    }
+}
+
+pub fn rename(db: &Pool, from: &str, to: &str) {
+    if from == to {
+        return;
+    }
+
+    let moved = contents::execute(db, ContentQueries::DeleteFiles(to.to_string()))
+        .and_then(|_| contents::execute(db, ContentQueries::MoveFiles(from.to_string(), to.to_string())));
+
+    if moved.is_err() {
+        eprintln!("cant update content index");
+    }
+}
+
+// files are read one after another in own thread, so upload dont wait for them
+pub fn start_queue(db: Pool, storage: Arc<Store>) -> mpsc::Sender<String> {
+    let (tx, rx) = mpsc::channel::<String>();
+
+    thread::spawn(move || {
+        for path in rx {
+            update(&db, &storage, &path);
+        }
+    });
+    tx
+}
+
+// runs after reindex of names, so files in search index are what is in storage,
+// only new and changed files are read
+pub fn reindex(db: &Pool, storage: &Store) -> Result<(), ResErr> {
+    let filter = IndexFilter {
+        is_dir: Some(false),
+        ..IndexFilter::default()
+    };
+    let files = (index::execute(db, IndexQueries::Search("/".to_string(), filter, None))
+        .map_err(|_| ResErr::InternalError("cant get search index"))?)
+    .many();
+
+    let mut known: HashMap<String, (u64, Option<i64>)> = (contents::execute(db, ContentQueries::GetFiles)
+        .map_err(|_| ResErr::InternalError("cant get content index"))?)
+    .many()
+    .into_iter()
+    .map(|f| (f.path, (f.size, f.modified)))
+    .collect();
+
+    for file in files.iter() {
+        if known.remove(&file.path) != Some((file.size, file.modified)) {
+            update(db, storage, &file.path);
+        }
+    }
+
+    for path in known.keys() {
+        remove(db, path);
+    }
+    Ok(())
+}
+
+// words are searched as they are, "*" at end of word finds words starting with it
+pub fn query(q: &str) -> Option<String> {
+    let words: Vec<String> = q
+        .split_whitespace()
+        .filter_map(|word| {
+            let (word, prefix) = match word.strip_suffix('*') {
+                Some(word) => (word, "*"),
+                None => (word, ""),
+            };
+            match word.is_empty() {
+                true => None,
+                false => Some(format!("\"{}\"{}", word.replace('"', "\"\""), prefix)),
+            }
+        })
+        .collect();
+
+    match words.is_empty() {
+        true => None,
+        false => Some(words.join(" ")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_pool;
+
+    #[test]
+    fn words_are_quoted() {
+        assert_eq!(query("hello world").unwrap(), r#""hello" "world""#);
+        assert_eq!(query(r#"say "hi""#).unwrap(), r#""say" """hi""""#);
+        assert_eq!(query("  spaced\tout \n").unwrap(), r#""spaced" "out""#);
+    }
+
+    #[test]
+    fn star_at_end_is_prefix() {
+        assert_eq!(query("doc*").unwrap(), r#""doc"*"#);
+        assert_eq!(query("a*b").unwrap(), r#""a*b""#);
+        assert_eq!(query("doc**").unwrap(), r#""doc*"*"#);
+    }
+
+    #[test]
+    fn nothing_to_search_is_none() {
+        assert!(query("").is_none());
+        assert!(query("   ").is_none());
+        assert!(query("*").is_none());
+        assert!(query("* *").is_none());
+    }
+
+    // operators of fts5 would change query or make it invalid, they are searched as words
+    #[test]
+    fn operators_are_words() {
+        assert_eq!(query("cats OR dogs").unwrap(), r#""cats" "OR" "dogs""#);
+        assert_eq!(query("NEAR(a b)").unwrap(), r#""NEAR(a" "b)""#);
+
+        let db = test_pool();
+        contents::create_table(&db);
+        for (path, text) in [("/a.txt", "cats and dogs"), ("/b.txt", "cats OR dogs, she said \"hi\"")].iter() {
+            let file = ContentFile {
+                id: 0,
+                path: path.to_string(),
+                size: 1,
+                modified: None,
+            };
+            (contents::execute(&db, ContentQueries::SetContent(file, text.to_string())).unwrap()).none();
+        }
+
+        let found = |q: &str| -> Vec<String> {
+            let mut paths: Vec<String> =
+                contents::search(&db, "/", &query(q).unwrap()).unwrap().into_iter().map(|h| h.path).collect();
+            paths.sort();
+            paths
+        };
+        assert_eq!(found("cats dogs"), ["/a.txt", "/b.txt"]);
+        assert_eq!(found("cats OR"), ["/b.txt"]);
+        assert_eq!(found("NEAR(cats"), Vec::<String>::new());
+        assert_eq!(found("\"hi\""), ["/b.txt"]);
+        assert_eq!(found("ca* do*"), ["/a.txt", "/b.txt"]);
+    }
+}
@@ -2,12 +2,27 @@ use actix_web::{web, HttpResponse};
 use bcrypt::{hash, DEFAULT_COST};
 use validator::Validate;
 
-use crate::db::{execute, Pool};
-use crate::middleware::MustAdminOrOp;
-use crate::models::{Queries, User};
+use crate::acl::{inside_root, normalize};
+use crate::db::{
+    acl, api_keys, attempts, execute, groups, labels, roles, sessions, settings, shares, totp,
+    trash, uploads, Pool,
+};
+use crate::handlers::password::{send_verification, verification_required};
+use crate::handlers::tus::remove_all;
+use crate::lockout::{email_key, get_attempt, is_locked, unlock};
+use crate::mail::Mailer;
+use crate::middleware::perm::{ManageRoles, ManageSettings, ManageUsers};
+use crate::middleware::{role_permissions, Must, Requirement};
+use crate::models::{
+    AclKind, AclQueries, ApiKeyQueries, AttemptQueries, GroupQueries, LabelQueries, NewAclEntry, NewRole,
+    Permission, Queries, RoleQueries, SessionQueries, SettingQueries, Settings, ShareQueries,
+    TotpQueries, TrashQueries, UploadQueries, User, UserDetail,
+};
 use crate::reserr::ResErr;
+use crate::storage::{blocking, Store};
+use crate::trash::purge_all;
 
-pub async fn get_users(_: MustAdminOrOp, db: web::Data<Pool>) -> Result<HttpResponse, ResErr> {
+pub async fn get_users(_: Must<ManageUsers>, db: web::Data<Pool>) -> Result<HttpResponse, ResErr> {
     let mut user = (execute(&db, Queries::GetAllUsers)
         .map_err(|_| ResErr::BadClientData("cant get users"))?)
     .many();
@@ -22,7 +37,7 @@ pub async fn get_users(_: MustAdminOrOp, db: web::Data<Pool>) -> Result<HttpResp
 }
 
 pub async fn get_user_by_id(
-    _: MustAdminOrOp,
+    _: Must<ManageUsers>,
     db: web::Data<Pool>,
     path: web::Path<(u32,)>,
 ) -> Result<HttpResponse, ResErr> {
@@ -32,18 +47,68 @@ pub async fn get_user_by_id(
 
     user.pass = "".to_string();
 
-    Ok(HttpResponse::Ok().json(user))
+    let lock = get_attempt(&db, &email_key(&user.email));
+
+    Ok(HttpResponse::Ok().json(UserDetail {
+        locked: is_locked(&lock),
+        user,
+        lock,
+    }))
+}
+
+pub async fn unlock_user(
+    token: Must<ManageUsers>,
+    db: web::Data<Pool>,
+    path: web::Path<(u32,)>,
+) -> Result<HttpResponse, ResErr> {
+    let user = (execute(&db, Queries::GetUserById(path.into_inner().0))
+        .map_err(|_| ResErr::BadClientData("cant get user"))?)
+    .one();
+
+    unlock(&db, &user.email, token.id)?;
+
+    Ok(HttpResponse::Ok().body("user unlocked"))
+}
+
+// nobody can give or take rights which he doesnt have himself
+fn check_role<R: Requirement>(
+    db: &Pool,
+    token: &Must<R>,
+    role: u8,
+    error: &'static str,
+) -> Result<(), ResErr> {
+    if role_permissions(db, role)?.iter().any(|p| !token.can(*p)) {
+        return Err(ResErr::BadClientData(error));
+    }
+    Ok(())
+}
+
+// validator cant look into storage
+async fn user_path_exists(storage: &web::Data<Store>, path: &str) -> Result<(), ResErr> {
+    let (storage, path) = (storage.clone(), normalize(path)?);
+
+    blocking(move || {
+        if !storage.exists(&path) {
+            return Err(ResErr::BadClientData("path dont exist"));
+        }
+        Ok(())
+    })
+    .await
 }
 
 pub async fn add_user(
-    _: MustAdminOrOp,
+    token: Must<ManageUsers>,
     db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    mailer: web::Data<Mailer>,
     mut user: web::Json<User>,
 ) -> Result<HttpResponse, ResErr> {
-    if user.status == 1 {
+    if user.status == roles::ADMIN {
         return Err(ResErr::BadClientData("cant create admin"));
     }
 
+    check_role(&db, &token, user.status, "cant give more rights than you have")?;
+
     // error message dont work
     user.validate().map_err(|err| {
         ResErr::BadClientDataOwned(
@@ -54,19 +119,39 @@ pub async fn add_user(
         )
     })?;
 
+    user_path_exists(&storage, &user.path).await?;
+
     user.pass =
         hash(user.pass.clone(), DEFAULT_COST).map_err(|_| ResErr::InternalError("bad hash"))?;
 
+    let email = user.email.clone();
+
     // error message dont work
     (execute(&db, Queries::AddUser(user)).map_err(|_| ResErr::BadClientData("cant add user"))?)
         .none();
 
+    let user = (execute(&db, Queries::GetUserByEmail(email))
+        .map_err(|_| ResErr::BadClientData("cant get user"))?)
+    .one();
+
+    if verification_required() {
+        send_verification(&db, mailer, &user).await?;
+
+        return Ok(HttpResponse::Ok().body("user added, verification email sent"));
+    }
+
+    // turning verification on later dont lock out users added without it
+    (execute(&db, Queries::SetVerifiedById(user.id))
+        .map_err(|_| ResErr::InternalError("cant verify email"))?)
+    .none();
+
     Ok(HttpResponse::Ok().body("user added"))
 }
 
 pub async fn delete_user(
-    _: MustAdminOrOp,
+    token: Must<ManageUsers>,
     db: web::Data<Pool>,
+    storage: web::Data<Store>,
     path: web::Path<(u32,)>,
 ) -> Result<HttpResponse, ResErr> {
     let id = path.into_inner().0;
@@ -75,22 +160,69 @@ pub async fn delete_user(
         .map_err(|_| ResErr::BadClientData("cant get user"))?)
     .one();
 
-    if user_stat.status == 1 {
+    if user_stat.status == roles::ADMIN {
         return Err(ResErr::BadClientData("cant delete admin"));
     };
 
+    check_role(&db, &token, user_stat.status, "cant delete user with more rights than you")?;
+
     (execute(&db, Queries::DeleteUserById(id)).map_err(|err| {
         println!("{:?}", err);
         ResErr::BadClientData("cant delete user")
     })?)
     .none();
 
+    (sessions::execute(&db, SessionQueries::RevokeUserSessions(id, None))
+        .map_err(|_| ResErr::InternalError("cant revoke sessions"))?)
+    .none();
+
+    // ids can be reused by sqlite, so nothing may stay behind
+    (api_keys::execute(&db, ApiKeyQueries::DeleteUserApiKeys(id))
+        .map_err(|_| ResErr::InternalError("cant delete api keys"))?)
+    .none();
+
+    (totp::execute(&db, TotpQueries::DeleteTotp(id))
+        .map_err(|_| ResErr::InternalError("cant delete 2fa"))?)
+    .none();
+
+    (labels::execute(&db, LabelQueries::DeleteUserFavorites(id))
+        .map_err(|_| ResErr::InternalError("cant delete favorites"))?)
+    .none();
+
+    (attempts::execute(&db, AttemptQueries::ClearAttempts(email_key(&user_stat.email)))
+        .map_err(|_| ResErr::InternalError("cant delete login attempts"))?)
+    .none();
+
+    (acl::execute(&db, AclQueries::DeleteUserEntries(id))
+        .map_err(|_| ResErr::InternalError("cant delete acl entries"))?)
+    .none();
+
+    (groups::execute(&db, GroupQueries::DeleteUserMemberships(id))
+        .map_err(|_| ResErr::InternalError("cant delete group memberships"))?)
+    .none();
+
+    (shares::execute(&db, ShareQueries::DeleteUserShares(id))
+        .map_err(|_| ResErr::InternalError("cant delete shares"))?)
+    .none();
+
+    let uploads = (uploads::execute(&db, UploadQueries::GetUserUploads(id))
+        .map_err(|_| ResErr::InternalError("cant get uploads"))?)
+    .many();
+    remove_all(&db, uploads)?;
+
+    // nobody could restore it anymore
+    let items = (trash::execute(&db, TrashQueries::GetUserItems(id))
+        .map_err(|_| ResErr::InternalError("cant get trash"))?)
+    .many();
+    blocking(move || purge_all(&db, &storage, items)).await?;
+
     Ok(HttpResponse::Ok().body("user deleted"))
 }
 
 pub async fn update_user(
-    _: MustAdminOrOp,
+    token: Must<ManageUsers>,
     db: web::Data<Pool>,
+    storage: web::Data<Store>,
     path: web::Path<(u32,)>,
     mut user: web::Json<User>,
 ) -> Result<HttpResponse, ResErr> {
@@ -105,7 +237,7 @@ pub async fn update_user(
                 .to_string(),
         )
     })?;
-    if user.status == 1 {
+    if user.status == roles::ADMIN {
         return Err(ResErr::BadClientData("cant add admin"));
     };
 
@@ -113,10 +245,14 @@ pub async fn update_user(
         .map_err(|_| ResErr::BadClientData("cant get user"))?)
     .one();
 
-    if user_stat.status == 1 {
+    if user_stat.status == roles::ADMIN {
         return Err(ResErr::BadClientData("cant update admin"));
     };
 
+    check_role(&db, &token, user_stat.status, "cant update user with more rights than you")?;
+    check_role(&db, &token, user.status, "cant give more rights than you have")?;
+    user_path_exists(&storage, &user.path).await?;
+
     user.pass =
         hash(user.pass.clone(), DEFAULT_COST).map_err(|_| ResErr::InternalError("bad hash"))?;
 
@@ -127,3 +263,175 @@ pub async fn update_user(
 
     Ok(HttpResponse::Ok().body("user updated"))
 }
+
+pub async fn get_settings(_: Must<ManageSettings>, db: web::Data<Pool>) -> Result<HttpResponse, ResErr> {
+    Ok(HttpResponse::Ok().json(Settings {
+        require_2fa: settings::get_flag(&db, "require_2fa"),
+    }))
+}
+
+pub async fn update_settings(
+    _: Must<ManageSettings>,
+    db: web::Data<Pool>,
+    body: web::Json<Settings>,
+) -> Result<HttpResponse, ResErr> {
+    (settings::execute(
+        &db,
+        SettingQueries::SetSetting("require_2fa".to_string(), body.require_2fa.to_string()),
+    )
+    .map_err(|_| ResErr::InternalError("cant save settings"))?)
+    .none();
+
+    Ok(HttpResponse::Ok().body("settings updated"))
+}
+
+pub async fn get_roles(_: Must<ManageUsers>, db: web::Data<Pool>) -> Result<HttpResponse, ResErr> {
+    let roles = (roles::execute(&db, RoleQueries::GetAllRoles)
+        .map_err(|_| ResErr::BadClientData("cant get roles"))?)
+    .many();
+
+    Ok(HttpResponse::Ok().json(roles))
+}
+
+pub async fn add_role(
+    token: Must<ManageRoles>,
+    db: web::Data<Pool>,
+    role: web::Json<NewRole>,
+) -> Result<HttpResponse, ResErr> {
+    // error message dont work
+    role.validate().map_err(|err| {
+        ResErr::BadClientDataOwned(
+            err.field_errors().into_values().next().unwrap()[0]
+                .code
+                .as_ref()
+                .to_string(),
+        )
+    })?;
+
+    if roles::is_builtin_name(&role.name) {
+        return Err(ResErr::BadClientData("name of built-in role cant be used"));
+    }
+
+    if role.permissions.iter().any(|p| !token.can(*p)) {
+        return Err(ResErr::BadClientData("cant give more rights than you have"));
+    }
+
+    let role = (roles::execute(&db, RoleQueries::AddRole(role))
+        .map_err(|_| ResErr::BadClientData("cant add role"))?)
+    .one();
+
+    Ok(HttpResponse::Ok().json(role))
+}
+
+pub async fn update_role(
+    token: Must<ManageRoles>,
+    db: web::Data<Pool>,
+    path: web::Path<(u8,)>,
+    role: web::Json<NewRole>,
+) -> Result<HttpResponse, ResErr> {
+    let id = path.into_inner().0;
+
+    // error message dont work
+    role.validate().map_err(|err| {
+        ResErr::BadClientDataOwned(
+            err.field_errors().into_values().next().unwrap()[0]
+                .code
+                .as_ref()
+                .to_string(),
+        )
+    })?;
+
+    check_role(&db, &token, id, "cant update role with more rights than you")?;
+
+    if roles::is_builtin_name(&role.name) {
+        return Err(ResErr::BadClientData("name of built-in role cant be used"));
+    }
+
+    if role.permissions.iter().any(|p| !token.can(*p)) {
+        return Err(ResErr::BadClientData("cant give more rights than you have"));
+    }
+
+    (roles::execute(&db, RoleQueries::UpdateRoleById(id, role))
+        .map_err(|_| ResErr::BadClientData("cant update role, built-in roles are read only"))?)
+    .none();
+
+    Ok(HttpResponse::Ok().body("role updated"))
+}
+
+pub async fn delete_role(
+    token: Must<ManageRoles>,
+    db: web::Data<Pool>,
+    path: web::Path<(u8,)>,
+) -> Result<HttpResponse, ResErr> {
+    let id = path.into_inner().0;
+
+    check_role(&db, &token, id, "cant delete role with more rights than you")?;
+
+    (roles::execute(&db, RoleQueries::DeleteRoleById(id)).map_err(|_| {
+        ResErr::BadClientData("cant delete role, it is built-in or somebody still has it")
+    })?)
+    .none();
+
+    Ok(HttpResponse::Ok().body("role deleted"))
+}
+
+pub async fn get_acl(
+    token: Must<ManageUsers>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    path: web::Path<(String,)>,
+) -> Result<HttpResponse, ResErr> {
+    let path = inside_root(&storage, &token.path, &path.into_inner().0).await?;
+
+    let entries = (acl::execute(&db, AclQueries::GetEntriesByPath(path))
+        .map_err(|_| ResErr::BadClientData("cant get acl"))?)
+    .many();
+
+    Ok(HttpResponse::Ok().json(entries))
+}
+
+pub async fn set_acl(
+    token: Must<ManageUsers>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    path: web::Path<(String,)>,
+    entries: web::Json<Vec<NewAclEntry>>,
+) -> Result<HttpResponse, ResErr> {
+    let path = inside_root(&storage, &token.path, &path.into_inner().0).await?;
+
+    for entry in entries.iter() {
+        match (entry.kind, entry.subject) {
+            (AclKind::User, Some(id)) => {
+                execute(&db, Queries::GetUserById(id))
+                    .map_err(|_| ResErr::BadClientData("user in acl dont exist"))?;
+            }
+            (AclKind::Group, Some(id)) => {
+                groups::execute(&db, GroupQueries::GetGroupById(id))
+                    .map_err(|_| ResErr::BadClientData("group in acl dont exist"))?;
+            }
+            (AclKind::Everyone, None) => {}
+            _ => return Err(ResErr::BadClientData("only user and group entry has subject")),
+        }
+
+        for p in entry.permissions.iter() {
+            match p {
+                Permission::Read
+                | Permission::Write
+                | Permission::Delete
+                | Permission::Rename
+                | Permission::Share => {}
+                _ => return Err(ResErr::BadClientData("acl can have only file permissions")),
+            }
+
+            if !token.can(*p) {
+                return Err(ResErr::BadClientData("cant give more rights than you have"));
+            }
+        }
+    }
+
+    (acl::execute(&db, AclQueries::SetEntries(path, entries.into_inner()))
+        .map_err(|_| ResErr::InternalError("cant save acl"))?)
+    .none();
+
+    Ok(HttpResponse::Ok().body("acl updated"))
+}
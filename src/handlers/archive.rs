@@ -0,0 +1,314 @@
+use actix_multipart::Multipart;
+use actix_web::http::header::{Charset, ContentDisposition, DispositionParam, DispositionType, ExtendedValue};
+use actix_web::{web, web::Bytes, HttpRequest, HttpResponse};
+use futures::channel::mpsc;
+use futures::executor::block_on;
+use futures::{SinkExt, StreamExt, TryStreamExt};
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::thread;
+use std::time::SystemTime;
+
+use crate::acl::{normalize, Acl};
+use crate::db::Pool;
+use crate::extract::extract;
+use crate::middleware::perm;
+use crate::middleware::Must;
+use crate::models::{ConflictOptions, Permission, Selection};
+use crate::quota;
+use crate::reserr::ResErr;
+use crate::storage::{self, blocking, Store};
+use crate::zip::ZipWriter;
+
+// file or folder going into zip, path is in storage and name inside zip
+struct Item {
+    path: String,
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+// zip is sent in chunks of this size
+const CHUNK: usize = 64 * 1024;
+
+// zip is made in its own thread and sent into response through channel,
+// channel is bounded, so zip is made only as fast as client reads it
+struct Pipe {
+    tx: mpsc::Sender<Result<Bytes, io::Error>>,
+    buf: Vec<u8>,
+}
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        if self.buf.len() >= CHUNK {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    // fails when client went away
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::take(&mut self.buf));
+
+        block_on(self.tx.send(Ok(chunk))).map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+
+fn join(folder: &str, name: &str) -> String {
+    match folder.is_empty() {
+        true => name.to_string(),
+        false => format!("{}/{}", folder, name),
+    }
+}
+
+// everything inside path which user can read, folders with own acl denying it are left out
+fn collect(acl: &Acl, storage: &Store, path: &str, name: &str, items: &mut Vec<Item>) -> Result<(), ResErr> {
+    if !acl.can(path, Permission::Read) {
+        return Ok(());
+    }
+
+    let meta = storage
+        .stat(path)
+        .map_err(|_| ResErr::BadClientData("path dont exist"))?;
+
+    if !name.is_empty() {
+        items.push(Item {
+            path: path.to_string(),
+            name: name.to_string(),
+            is_dir: meta.is_dir,
+            size: meta.size,
+            modified: meta.modified,
+        });
+    }
+
+    if meta.is_dir {
+        let entries = storage
+            .list(path)
+            .map_err(|_| ResErr::InternalError("cant list folder"))?;
+
+        for entry in entries.iter() {
+            let full = format!("{}/{}", path.trim_end_matches('/'), entry.name);
+            collect(acl, storage, &full, &join(name, &entry.name), items)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_zip(storage: &Store, items: &[Item], out: Pipe) -> io::Result<()> {
+    let mut zip = ZipWriter::new(out);
+
+    for item in items.iter() {
+        match item.is_dir {
+            true => zip.add_dir(&item.name, item.modified)?,
+            false => zip.add_file(&item.name, item.size, item.modified, &mut storage.read(&item.path)?)?,
+        }
+    }
+
+    zip.finish()?.flush()
+}
+
+// name of zip can have any characters, old clients get it without them
+fn attachment(name: &str) -> ContentDisposition {
+    let ascii: String = name.chars().map(|c| if c.is_ascii() && c != '"' { c } else { '_' }).collect();
+
+    ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![
+            DispositionParam::Filename(ascii),
+            DispositionParam::FilenameExt(ExtendedValue {
+                charset: Charset::Ext("UTF-8".to_string()),
+                language_tag: None,
+                value: name.as_bytes().to_vec(),
+            }),
+        ],
+    }
+}
+
+// zip is streamed while it is made, so it has no length and error in the middle cuts it
+fn send_zip(storage: web::Data<Store>, items: Vec<Item>, name: &str) -> HttpResponse {
+    let (tx, rx) = mpsc::channel(4);
+
+    thread::spawn(move || {
+        let mut err_tx = tx.clone();
+        let out = Pipe { tx, buf: Vec::new() };
+
+        if let Err(err) = write_zip(&storage, &items, out) {
+            let _ = block_on(err_tx.send(Err(err)));
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type("application/zip")
+        .set(attachment(&format!("{}.zip", name)))
+        .streaming(rx)
+}
+
+// folder is root of zip, everything inside keeps its path relative to it
+pub async fn get_zip(
+    token: Must<perm::Read>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ResErr> {
+    let acl = Acl::load(&db, &token)?;
+    let filename = normalize(req.match_info().query("filename"))?;
+    let path = acl.resolve(&filename, Permission::Read)?;
+
+    let name = match storage::name(&filename) {
+        name if name.is_empty() => "files".to_string(),
+        name => name,
+    };
+
+    let items = {
+        let (storage, name) = (storage.clone(), name.clone());
+        blocking(move || {
+            let meta = storage
+                .stat(&path)
+                .map_err(|_| ResErr::BadClientData("path dont exist"))?;
+
+            let mut items = Vec::new();
+            match meta.is_dir {
+                true => collect(&acl, &storage, &path, "", &mut items)?,
+                false => collect(&acl, &storage, &path, &name, &mut items)?,
+            }
+            Ok(items)
+        })
+        .await?
+    };
+
+    Ok(send_zip(storage, items, &name))
+}
+
+// every selected path has to exist, returns items with name of folder containing all of them
+fn selected(acl: &Acl, storage: &Store, selection: &[String]) -> Result<(Vec<Item>, String), ResErr> {
+    let mut paths = Vec::new();
+    for filename in selection.iter() {
+        let filename = normalize(filename)?;
+        if filename == "/" {
+            return Err(ResErr::BadClientData("use folder zip for whole home"));
+        }
+
+        let path = acl.resolve(&filename, Permission::Read)?;
+        if !storage.exists(&path) {
+            return Err(ResErr::BadClientData("path dont exist"));
+        }
+        paths.push((filename, path));
+    }
+
+    // common parent folder of all selected paths
+    let parents: Vec<Vec<&str>> = paths
+        .iter()
+        .map(|(f, _)| f.split('/').filter(|p| !p.is_empty()).collect::<Vec<_>>())
+        .map(|mut parts| {
+            parts.pop();
+            parts
+        })
+        .collect();
+
+    let root = parents
+        .iter()
+        .skip(1)
+        .fold(parents[0].clone(), |common, parts| {
+            common.iter().zip(parts.iter()).take_while(|(a, b)| a == b).map(|(a, _)| *a).collect()
+        });
+
+    let mut items = Vec::new();
+    for (filename, path) in paths.iter() {
+        let parts: Vec<&str> = filename.split('/').filter(|p| !p.is_empty()).collect();
+        collect(acl, storage, path, &parts[root.len()..].join("/"), &mut items)?;
+    }
+
+    // selection inside other selected folder would be there twice
+    let mut seen = HashSet::new();
+    items.retain(|i| seen.insert(i.name.clone()));
+
+    let name = root.last().map(|n| n.to_string()).unwrap_or_else(|| "files".to_string());
+    Ok((items, name))
+}
+
+// selected paths keep their path relative to folder which contains all of them
+pub async fn post_zip(
+    token: Must<perm::Read>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    selection: web::Json<Selection>,
+) -> Result<HttpResponse, ResErr> {
+    let acl = Acl::load(&db, &token)?;
+
+    if selection.paths.is_empty() {
+        return Err(ResErr::BadClientData("nothing selected"));
+    }
+
+    let (items, name) = {
+        let storage = storage.clone();
+        blocking(move || selected(&acl, &storage, &selection.paths)).await?
+    };
+
+    Ok(send_zip(storage, items, &name))
+}
+
+// archive from multipart is extracted into folder, it has to fit into quota itself too
+pub async fn extract_archive(
+    token: Must<perm::Write>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    req: HttpRequest,
+    options: web::Query<ConflictOptions>,
+    mut payload: Multipart,
+) -> Result<HttpResponse, ResErr> {
+    let acl = Acl::load(&db, &token)?;
+    let folder = normalize(req.match_info().query("filename"))?;
+
+    let path = acl.resolve(&folder, Permission::Write)?;
+    let home = acl.home(&folder)?;
+
+    let free = {
+        let (db, storage) = (db.clone(), storage.clone());
+        blocking(move || {
+            match storage.stat(&path) {
+                Ok(meta) if meta.is_dir => (),
+                _ => return Err(ResErr::BadClientData("folder dont exist")),
+            }
+            quota::free(&db, &storage, &home, None)
+        })
+        .await?
+    };
+
+    // zip is read from its end, so archive waits in temporary file
+    let mut file = tempfile::tempfile().map_err(|_| ResErr::InternalError("cant save archive"))?;
+    let mut size: u64 = 0;
+
+    while let Ok(Some(mut field)) = payload.try_next().await {
+        if field.content_disposition().and_then(|cd| cd.get_filename().map(|_| ())).is_none() {
+            continue;
+        }
+
+        while let Some(chunk) = field.next().await {
+            let data = chunk.map_err(|_| ResErr::InternalError("field stream of bytes"))?;
+
+            size += data.len() as u64;
+            if size > free {
+                return Err(ResErr::BadClientData("you dont have size"));
+            }
+
+            file = web::block(move || file.write_all(&data).map(|_| file))
+                .await
+                .map_err(|_| ResErr::InternalError("cant save archive"))?;
+        }
+        break;
+    }
+
+    if size == 0 {
+        return Err(ResErr::BadClientData("no archive sent"));
+    }
+
+    let conflict = options.conflict;
+    let extracted = blocking(move || extract(&acl, &db, &storage, &folder, conflict, free, file)).await?;
+
+    Ok(HttpResponse::Ok().json(extracted))
+}
@@ -1,24 +1,42 @@
 use actix_files::NamedFile;
-use actix_multipart::Multipart;
-use actix_web::{web, HttpRequest, HttpResponse};
-use futures::{StreamExt, TryStreamExt};
-use std::{env, fs, io::Write, path::Path, path::PathBuf};
+use actix_multipart::{Field, Multipart};
+use actix_web::dev::SizedStream;
+use actix_web::http::header::{
+    self, Charset, ContentDisposition, DispositionParam, DispositionType, ExtendedValue,
+};
+use actix_web::{error, web, web::Bytes, HttpRequest, HttpResponse};
+use futures::{stream, StreamExt, TryStreamExt};
+use mime_guess::mime;
+use sha2::{Digest, Sha256};
+use std::io::{self, Read};
+use std::sync::Arc;
+use std::time::SystemTime;
 
-use crate::middleware::{CanDownload, CanUpload};
-use crate::models::Rename;
+use crate::acl::{covers, normalize, resolve, Acl};
+use crate::db::{blob_paths, Pool};
+use crate::middleware::perm;
+use crate::middleware::Must;
+use crate::models::{BlobPathQueries, Conflict, ConflictOptions, Permission, Rename, Saved, Version};
+use crate::quota;
 use crate::reserr::ResErr;
-use crate::utils::dir_size;
-use crate::utils::valid_path;
-
-pub async fn get_file_exist(token: CanDownload, req: HttpRequest) -> Result<HttpResponse, ResErr> {
-    let path: String = format!(
-        "./{}{}/{}",
-        env::var("CLOUD_PATH").unwrap(),
-        &token.path,
-        req.match_info().query("filename")
-    );
-
-    if !Path::new(&path).exists() {
+use crate::storage::{self, blocking, Store, Writer};
+use crate::trash;
+use crate::versions;
+
+pub async fn get_file_exist(
+    token: Must<perm::Read>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ResErr> {
+    let path = resolve(
+        &db,
+        &token,
+        req.match_info().query("filename"),
+        Permission::Read,
+    )?;
+
+    if !blocking(move || Ok(storage.exists(&path))).await? {
         return Err(ResErr::BadClientData("file dont exist"));
     };
 
@@ -26,96 +44,709 @@ pub async fn get_file_exist(token: CanDownload, req: HttpRequest) -> Result<Http
 }
 
 pub async fn rename_file(
-    token: CanUpload,
+    token: Must<perm::Rename>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
     req: HttpRequest,
     rename: web::Json<Rename>,
 ) -> Result<HttpResponse, ResErr> {
-    let old_path: String = format!(
-        "./{}{}/{}",
-        env::var("CLOUD_PATH").unwrap(),
-        &token.path,
-        req.match_info().query("filename")
-    );
-
-    let new_path: String = format!(
-        "./{}{}/{}",
-        env::var("CLOUD_PATH").unwrap(),
-        &token.path,
-        rename.name
-    );
-
-    fs::rename(old_path, new_path).map_err(|_| ResErr::BadClientData("can not be renamed"))?;
+    let acl = Acl::load(&db, &token)?;
+
+    let old_path = acl.resolve(req.match_info().query("filename"), Permission::Rename)?;
+
+    let new_path = acl.resolve_new(&rename.name, Permission::Rename)?;
+
+    let home = acl.home(&rename.name)?;
+    let tags = if_match_tags(&req);
+
+    blocking(move || {
+        if_match(tags.as_deref(), &db, &storage, &old_path)?;
+
+        // history goes with file, what is on new path gets replaced together with its history
+        let size = quota::size_of(&storage, &old_path)? + versions::versioned(&db, &old_path)?;
+        let replaced = quota::size_of(&storage, &new_path)? + versions::versioned(&db, &new_path)?;
+
+        // moving into other home takes its quota
+        if !covers(&home.path, &old_path) && size > quota::free(&db, &storage, &home, None)? + replaced {
+            return Err(ResErr::BadClientData("you dont have size"));
+        }
+
+        storage
+            .rename(&old_path, &new_path)
+            .map_err(|_| ResErr::BadClientData("can not be renamed"))?;
+
+        versions::moved(&db, &storage, &old_path, &new_path)?;
+
+        quota::add(&db, &old_path, -(size as i64))?;
+        quota::add(&db, &new_path, size as i64 - replaced as i64)
+    })
+    .await?;
 
     Ok(HttpResponse::Ok().body("renamed"))
 }
 
-pub async fn get_file(token: CanDownload, req: HttpRequest) -> Result<NamedFile, ResErr> {
-    let path: String = format!(
-        "./{}{}/{}",
-        env::var("CLOUD_PATH").unwrap(),
-        &token.path,
-        req.match_info().query("filename")
-    );
+pub async fn get_file(
+    token: Must<perm::Read>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ResErr> {
+    let path = resolve(
+        &db,
+        &token,
+        req.match_info().query("filename"),
+        Permission::Read,
+    )?;
+
+    let checksum = versions::known(&db, &storage, &path);
+
+    send_file(&storage, &path, &req, checksum.as_deref()).await
+}
+
+// strong etag is sha256 of content
+fn etag(checksum: &str) -> String {
+    format!("\"{}\"", checksum)
+}
+
+// client has this content already, If-None-Match wins over If-Modified-Since
+fn not_modified(req: &HttpRequest, etag: Option<&str>, modified: Option<SystemTime>) -> bool {
+    if let Some(tags) = req.headers().get(header::IF_NONE_MATCH) {
+        return tags
+            .to_str()
+            .unwrap_or("")
+            .split(',')
+            .map(|t| t.trim().trim_start_matches("W/"))
+            .any(|t| t == "*" || Some(t) == etag);
+    }
+
+    let since = req
+        .headers()
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| httpdate::parse_http_date(v).ok());
+
+    // http dates have only seconds
+    match (since, modified) {
+        (Some(since), Some(modified)) => modified.duration_since(since).map_or(true, |d| d.as_secs() == 0),
+        _ => false,
+    }
+}
+
+// request cant go into threadpool, so If-Match is taken out of it before
+pub fn if_match_tags(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(header::IF_MATCH)
+        .map(|v| v.to_str().unwrap_or("").to_string())
+}
+
+// file is changed only when client has seen its current content, If-Match has etag it got with it,
+// missing file or folder never matches
+pub fn if_match(tags: Option<&str>, db: &Pool, storage: &Store, path: &str) -> Result<(), ResErr> {
+    let tags = match tags {
+        Some(v) => v,
+        None => return Ok(()),
+    };
 
-    Ok(NamedFile::open(path).map_err(|_| ResErr::BadClientData("file not found"))?)
+    let current = match storage.stat(path) {
+        Ok(meta) if !meta.is_dir => Some(etag(&versions::current(db, storage, path)?.checksum)),
+        _ => None,
+    };
+
+    let matched = current.is_some_and(|current| tags.split(',').map(str::trim).any(|t| t == "*" || t == current));
+    if !matched {
+        return Err(ResErr::PreconditionFailed("file was changed meanwhile"));
+    }
+    Ok(())
+}
+
+// file from local disk is sent by NamedFile, from other storages it is streamed,
+// checksum of content is its etag, file without known checksum is sent without it
+pub async fn send_file(
+    storage: &web::Data<Store>,
+    path: &str,
+    req: &HttpRequest,
+    checksum: Option<&str>,
+) -> Result<HttpResponse, ResErr> {
+    let (meta, local) = {
+        let (storage, path) = (storage.clone(), path.to_string());
+        blocking(move || match storage.stat(&path) {
+            Ok(meta) if !meta.is_dir => Ok((meta, storage.local_path(&path))),
+            _ => Err(ResErr::BadClientData("file not found")),
+        })
+        .await?
+    };
+
+    let etag = checksum.map(etag);
+    if not_modified(req, etag.as_deref(), meta.modified) {
+        let mut res = HttpResponse::NotModified();
+        if let Some(etag) = etag {
+            res.header(header::ETAG, etag);
+        }
+        return Ok(res.finish());
+    }
+
+    if let Some(local) = local {
+        // file on disk can have other name than path, e.g. when its content is deduplicated
+        let mime = mime_guess::from_path(path).first_or_octet_stream();
+        let disposition = match mime.type_() {
+            mime::IMAGE | mime::TEXT | mime::VIDEO => DispositionType::Inline,
+            _ => DispositionType::Attachment,
+        };
+        let filename = storage::name(path);
+
+        let mut parameters = vec![DispositionParam::Filename(filename.clone())];
+        if !filename.is_ascii() {
+            parameters.push(DispositionParam::FilenameExt(ExtendedValue {
+                charset: Charset::Ext("UTF-8".to_string()),
+                language_tag: None,
+                value: filename.into_bytes(),
+            }));
+        }
+
+        let mut res = NamedFile::open(local)
+            .map_err(|_| ResErr::BadClientData("file not found"))?
+            .set_content_type(mime)
+            .set_content_disposition(ContentDisposition { disposition, parameters })
+            .use_etag(false)
+            .into_response(req)
+            .map_err(|_| ResErr::InternalError("cant send file"))?;
+
+        if let Some(Ok(v)) = etag.map(|v| v.parse()) {
+            res.headers_mut().insert(header::ETAG, v);
+        }
+        return Ok(res);
+    }
+
+    let reader = {
+        let (storage, path) = (storage.clone(), path.to_string());
+        blocking(move || storage.read(&path).map_err(|_| ResErr::BadClientData("file not found"))).await?
+    };
+
+    // reading is blocking operation, every chunk is read in threadpool
+    let body = stream::unfold(Some(reader), |reader| async move {
+        let mut reader = reader?;
+        let chunk = web::block(move || {
+            let mut buf = vec![0; 64 * 1024];
+            let len = reader.read(&mut buf)?;
+            buf.truncate(len);
+            Ok::<_, io::Error>((buf, reader))
+        })
+        .await;
+
+        match chunk {
+            Ok((buf, _)) if buf.is_empty() => None,
+            Ok((buf, reader)) => Some((Ok(Bytes::from(buf)), Some(reader))),
+            Err(err) => Some((Err(error::ErrorInternalServerError(err)), None)),
+        }
+    });
+
+    let mut res = HttpResponse::Ok();
+    res.content_type(mime_guess::from_path(path).first_or_octet_stream().to_string());
+    if let Some(etag) = etag {
+        res.header(header::ETAG, etag);
+    }
+    if let Some(modified) = meta.modified {
+        res.header(header::LAST_MODIFIED, httpdate::fmt_http_date(modified));
+    }
+
+    Ok(res.body(SizedStream::new(meta.size, Box::pin(body))))
 }
 
 pub async fn post_file(
-    token: CanUpload,
+    token: Must<perm::Write>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
     req: HttpRequest,
+    options: web::Query<ConflictOptions>,
+    payload: Multipart,
+) -> Result<HttpResponse, ResErr> {
+    let acl = Acl::load(&db, &token)?;
+
+    let rules = IfMatch {
+        tags: if_match_tags(&req),
+        db: db.clone(),
+        storage: storage.clone(),
+    };
+
+    upload(
+        Upload {
+            acl,
+            db,
+            storage,
+            folder: req.match_info().query("filename").to_string(),
+            conflict: options.conflict,
+            rules,
+        },
+        payload,
+    )
+    .await
+}
+
+// limits of upload, share links use them
+pub trait UploadRules {
+    fn max_size(&self) -> Option<u64> {
+        None
+    }
+
+    // gets path of file in storage before it is created, it is called in threadpool
+    fn before(&self, _path: &str) -> Result<(), ResErr> {
+        Ok(())
+    }
+
+    fn failed(&self) {}
+
+    // upload by sha256 alone, without content
+    fn can_link(&self) -> bool {
+        true
+    }
+
+    // file can get other name than client sent, with false client gets back its own name
+    fn shows_names(&self) -> bool {
+        true
+    }
+}
+
+// user replaces only file they have seen, when they send If-Match
+pub struct IfMatch {
+    tags: Option<String>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+}
+
+impl UploadRules for IfMatch {
+    fn before(&self, path: &str) -> Result<(), ResErr> {
+        if_match(self.tags.as_deref(), &self.db, &self.storage, path)
+    }
+}
+
+// "name.ext" becomes "name (1).ext", then "name (2).ext" until free name is found,
+// every name is checked by acl as rules for single file can exist
+pub fn free_name(acl: &Acl, storage: &Store, folder: &str, filename: &str) -> Result<String, ResErr> {
+    let (stem, ext) = match filename.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{}", ext)),
+        _ => (filename, String::new()),
+    };
+
+    for n in 1.. {
+        let path = acl.resolve_new(&format!("{}/{} ({}){}", folder, stem, n, ext), Permission::Write)?;
+
+        if !storage.exists(&path) {
+            return Ok(path);
+        }
+    }
+    unreachable!()
+}
+
+// files of multipart go into folder, folder is path from request of user owning acl
+pub struct Upload<R: UploadRules> {
+    pub acl: Acl,
+    pub db: web::Data<Pool>,
+    pub storage: web::Data<Store>,
+    pub folder: String,
+    pub conflict: Conflict,
+    pub rules: R,
+}
+
+// where content of file goes, linked content is complete already
+enum Content {
+    Linked(Box<dyn Writer>, u64, String),
+    Sent(Box<dyn Writer>),
+}
+
+impl<R: UploadRules> Upload<R> {
+    // everything done with storage before content of file is read, returns path of file,
+    // free bytes of home and writer
+    fn open(&self, filename: &str, known: Option<&str>) -> Result<(String, u64, Content), ResErr> {
+        if !self.storage.exists(&self.acl.resolve(&self.folder, Permission::Write)?) {
+            return Err(ResErr::BadClientData("path dont exist"));
+        }
+
+        // name of file comes from client too, so it is checked as well
+        let mut filepath = self
+            .acl
+            .resolve_new(&format!("{}/{}", self.folder, filename), Permission::Write)?;
+
+        if self.conflict != Conflict::Overwrite && self.storage.exists(&filepath) {
+            match self.conflict {
+                Conflict::Rename => filepath = free_name(&self.acl, &self.storage, &self.folder, filename)?,
+                _ => return Err(ResErr::BadClientData("file already exists")),
+            }
+        }
+
+        self.rules.before(&filepath)?;
+
+        match self.writer(&filepath, known) {
+            Ok((free, content)) => Ok((filepath, free, content)),
+            Err(err) => {
+                self.rules.failed();
+                Err(err)
+            }
+        }
+    }
+
+    // client can send sha256 of file in Content-SHA256 header of the part,
+    // content which is stored already is not sent again, uploaded one has to match it
+    fn writer(&self, filepath: &str, known: Option<&str>) -> Result<(u64, Content), ResErr> {
+        // quota of group is used inside its folder, replaced file stays there as old version
+        let free = quota::free(&self.db, &self.storage, &self.acl.home(&self.folder)?, None)?;
+
+        let overwrite = self.conflict == Conflict::Overwrite;
+
+        let size = match known {
+            Some(hash) if self.rules.can_link() => readable_content(&self.acl, &self.db, hash)?,
+            _ => None,
+        };
+        let linked = match (known, size) {
+            (Some(hash), Some(size)) => self
+                .storage
+                .link(hash, filepath, overwrite)
+                .map_err(|_| ResErr::InternalError("field creating file"))?
+                .map(|writer| Content::Linked(writer, size, hash.to_string())),
+            _ => None,
+        };
+
+        match linked {
+            Some(Content::Linked(_, size, _)) if self.rules.max_size().is_some_and(|max| size > max) => {
+                Err(ResErr::BadClientData("file is too big"))
+            }
+            Some(Content::Linked(_, size, _)) if size > free => Err(ResErr::BadClientData("you dont have size")),
+            Some(content) => Ok((free, content)),
+            // file gets into place only when whole, what was there before stays after failure
+            None => Ok((
+                free,
+                Content::Sent(
+                    match overwrite {
+                        true => self.storage.write(filepath),
+                        false => self.storage.create(filepath),
+                    }
+                    .map_err(|_| ResErr::InternalError("field creating file"))?,
+                ),
+            )),
+        }
+    }
+
+    // old content goes into history just before new one takes its place
+    fn finish(&self, path: &str, f: Box<dyn Writer>) -> Result<Option<Version>, ResErr> {
+        let version = match self.conflict {
+            Conflict::Overwrite => versions::archive(&self.db, &self.storage, path)?,
+            _ => None,
+        };
+
+        if let Err(err) = f.finish() {
+            if let Some(version) = &version {
+                versions::unarchive(&self.db, &self.storage, version);
+            }
+
+            // other upload could take the name meanwhile
+            return Err(match err.kind() {
+                io::ErrorKind::AlreadyExists => ResErr::BadClientData("file already exists"),
+                _ => ResErr::InternalError("field saving file"),
+            });
+        }
+        Ok(version)
+    }
+
+    fn stored(&self, path: &str, size: u64, checksum: String, version: Option<Version>) -> Result<(), ResErr> {
+        quota::add(&self.db, path, size as i64)?;
+        versions::stored(&self.db, path, Some(self.acl.user_id), checksum)?;
+
+        if version.is_some() {
+            versions::prune(&self.db, &self.storage, path)?;
+        }
+        Ok(())
+    }
+}
+
+// saves every file from multipart, storage is used only in threadpool
+pub async fn upload<R: UploadRules + Send + Sync + 'static>(
+    upload: Upload<R>,
     mut payload: Multipart,
 ) -> Result<HttpResponse, ResErr> {
+    let upload = Arc::new(upload);
+    let mut saved = Vec::new();
+
     while let Ok(Some(mut field)) = payload.try_next().await {
         let content_type = match field.content_disposition() {
-            Some(v) => (v),
+            Some(v) => v,
             None => return Ok(HttpResponse::BadRequest().body("cant find content disposition")),
         };
 
         let filename = match content_type.get_filename() {
-            Some(v) => (v),
+            Some(v) => v.to_string(),
             None => return Ok(HttpResponse::BadRequest().body("cant find filename")),
         };
 
-        let main_folder: String = format!("./{}{}", env::var("CLOUD_PATH").unwrap(), &token.path);
+        let known = match field.headers().get("content-sha256").map(|v| v.to_str()) {
+            Some(Ok(hash)) => Some(hash.to_lowercase()),
+            Some(Err(_)) => return Err(ResErr::BadClientData("checksum is not valid")),
+            None => None,
+        };
 
-        let folder_size: u32 = dir_size(&main_folder)
-            .map_err(|_| ResErr::InternalError("folder size counter is broaken"))?;
+        let (filepath, free, content) = {
+            let (upload, filename, known) = (upload.clone(), filename.clone(), known.clone());
+            blocking(move || upload.open(&filename, known.as_deref())).await?
+        };
 
-        if folder_size > token.size {
-            return Err(ResErr::BadClientData("you dont have size"));
+        let sent = match content {
+            Content::Linked(writer, size, hash) => Ok((writer, size, hash)),
+            Content::Sent(writer) => match save_field(&mut field, writer, upload.rules.max_size(), free).await {
+                Ok((_, _, checksum)) if known.is_some_and(|hash| hash != checksum) => {
+                    Err(ResErr::BadClientData("checksum dont match"))
+                }
+                sent => sent,
+            },
+        };
+
+        let finished = match sent {
+            Ok((writer, size, checksum)) => {
+                let (upload, filepath) = (upload.clone(), filepath.clone());
+                blocking(move || Ok((upload.finish(&filepath, writer)?, size, checksum))).await
+            }
+            Err(err) => Err(err),
+        };
+
+        let (version, size, checksum) = match finished {
+            Ok(v) => v,
+            Err(err) => {
+                upload.rules.failed();
+                return Err(err);
+            }
+        };
+
+        {
+            let (upload, filepath, checksum) = (upload.clone(), filepath.clone(), checksum.clone());
+            blocking(move || upload.stored(&filepath, size, checksum, version)).await?;
         }
 
-        let mut filepath: String =
-            format!("{}/{}", main_folder, req.match_info().query("filename"));
+        match upload.rules.shows_names() {
+            true => saved.push((storage::name(&filepath), checksum)),
+            false => saved.push((filename, checksum)),
+        }
+    }
 
-        valid_path(&filepath).map_err(|err| ResErr::BadClientData(err))?;
+    // with single file client gets its etag for next conditional request
+    let mut res = HttpResponse::Ok();
+    if let [(_, checksum)] = saved.as_slice() {
+        res.header(header::ETAG, etag(checksum));
+    }
+    Ok(res.json(Saved {
+        files: saved.into_iter().map(|(name, _)| name).collect(),
+    }))
+}
 
-        filepath = filepath + "/" + filename;
+// size of content with this sha256 when user can read some file which has it already,
+// so nobody gets content just by knowing its hash
+fn readable_content(acl: &Acl, db: &Pool, hash: &str) -> Result<Option<u64>, ResErr> {
+    Ok((blob_paths::execute(db, BlobPathQueries::GetPathsOf(hash.to_string()))
+        .map_err(|_| ResErr::InternalError("cant find content"))?)
+    .many()
+    .into_iter()
+    .find(|f| acl.reaches(&f.path, Permission::Read))
+    .map(|f| f.size))
+}
 
-        // File::create is blocking operation, use threadpool
-        let mut f = web::block(|| std::fs::File::create(filepath))
-            .await
-            .map_err(|_| ResErr::InternalError("field creating file"))?;
+// quota is checked with every chunk, upload stops as soon as it doesnt fit,
+// returns unfinished file with its size and sha256
+async fn save_field(
+    field: &mut Field,
+    mut f: Box<dyn Writer>,
+    max_size: Option<u64>,
+    free: u64,
+) -> Result<(Box<dyn Writer>, u64, String), ResErr> {
+    let mut size: u64 = 0;
+    let mut hasher = Sha256::new();
 
-        // Field in turn is stream of *Bytes* object
-        while let Some(chunk) = field.next().await {
-            let data = chunk.map_err(|_| ResErr::InternalError("field stream of bytes"))?;
-            // filesystem operations are blocking, we have to use threadpool
-            f = web::block(move || f.write_all(&data).map(|_| f))
-                .await
-                .map_err(|_| ResErr::InternalError("field stream of bytes"))?;
+    // Field in turn is stream of *Bytes* object
+    while let Some(chunk) = field.next().await {
+        let data = chunk.map_err(|_| ResErr::InternalError("field stream of bytes"))?;
+
+        size += data.len() as u64;
+        if max_size.is_some_and(|max| size > max) {
+            return Err(ResErr::BadClientData("file is too big"));
         }
+        if size > free {
+            return Err(ResErr::BadClientData("you dont have size"));
+        }
+        hasher.update(&data);
+
+        // storage operations are blocking, we have to use threadpool
+        f = web::block(move || f.write_all(&data).map(|_| f))
+            .await
+            .map_err(|_| ResErr::InternalError("field stream of bytes"))?;
     }
-    Ok(HttpResponse::Ok().body("file saved"))
+
+    Ok((f, size, hex::encode(hasher.finalize())))
 }
 
-pub async fn delete_file(token: CanUpload, req: HttpRequest) -> Result<HttpResponse, ResErr> {
-    let path: PathBuf =
-        (env::var("CLOUD_PATH").unwrap() + &token.path + "/" + req.match_info().query("filename"))
-            .parse()
-            .map_err(|_| ResErr::BadClientData("cant parse path"))?;
+pub async fn delete_file(
+    token: Must<perm::Delete>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ResErr> {
+    let filename = req.match_info().query("filename");
+    let path = resolve(&db, &token, filename, Permission::Delete)?;
+    let filename = normalize(filename)?;
+    let (tags, user_id) = (if_match_tags(&req), token.id);
+
+    blocking(move || {
+        // folders have their own endpoint
+        match storage.stat(&path) {
+            Ok(meta) if !meta.is_dir => (),
+            _ => return Err(ResErr::BadClientData("file not found")),
+        }
+
+        if_match(tags.as_deref(), &db, &storage, &path)?;
 
-    fs::remove_file(path).map_err(|_| ResErr::BadClientData("file not found"))?;
+        trash::put(&db, &storage, user_id, &filename, &path)
+    })
+    .await?;
 
     Ok(HttpResponse::Ok().body("file deleted"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+
+    use crate::db::acl;
+    use crate::db::roles::UPLOADER;
+    use crate::handlers::testing::{add_user, multipart, setup, token};
+    use crate::models::{AclKind, AclQueries, NewAclEntry};
+
+    fn with_token(req: test::TestRequest, token: &str) -> test::TestRequest {
+        req.header("token", token)
+    }
+
+    fn post(uri: &str, token: &str, filename: &str, content: &str) -> test::TestRequest {
+        multipart(with_token(test::TestRequest::post().uri(uri), token), &[(filename, content)])
+    }
+
+    fn get(uri: &str, token: &str) -> test::TestRequest {
+        with_token(test::TestRequest::get().uri(uri), token)
+    }
+
+    fn delete(uri: &str, token: &str) -> test::TestRequest {
+        with_token(test::TestRequest::delete().uri(uri), token)
+    }
+
+    macro_rules! app {
+        ($db:expr, $storage:expr) => {
+            test::init_service(
+                App::new()
+                    .app_data($db.clone())
+                    .app_data($storage.clone())
+                    .route("/file/{filename:.*}", web::get().to(get_file))
+                    .route("/file/{filename:.*}", web::patch().to(rename_file))
+                    .route("/file/{filename:.*}", web::post().to(post_file))
+                    .route("/file/{filename:.*}", web::delete().to(delete_file)),
+            )
+            .await
+        };
+    }
+
+    #[actix_rt::test]
+    async fn acl_limits_what_role_allows() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        let token = token(&db, &bob);
+        storage.mkdir("/bob/docs").unwrap();
+        let mut app = app!(db, storage);
+
+        let res = test::call_service(&mut app, post("/file/docs", &token, "a.txt", "hello").to_request()).await;
+        assert_eq!(res.status(), 200);
+
+        let entry = NewAclEntry {
+            kind: AclKind::User,
+            subject: Some(bob.id),
+            permissions: vec![Permission::Read],
+        };
+        (acl::execute(&db, AclQueries::SetEntries("/bob/docs".to_string(), vec![entry])).unwrap()).none();
+
+        // reading is still allowed, changing is not
+        let res = test::call_service(&mut app, get("/file/docs/a.txt", &token).to_request()).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(test::read_body(res).await, "hello");
+
+        let res = test::call_service(&mut app, post("/file/docs", &token, "b.txt", "hello").to_request()).await;
+        assert_eq!(res.status(), 400);
+        let res = test::call_service(&mut app, delete("/file/docs/a.txt", &token).to_request()).await;
+        assert_eq!(res.status(), 400);
+        assert!(storage.exists("/bob/docs/a.txt"));
+        assert!(!storage.exists("/bob/docs/b.txt"));
+
+        // rest of home keeps rights of role
+        let res = test::call_service(&mut app, post("/file/", &token, "b.txt", "hello").to_request()).await;
+        assert_eq!(res.status(), 200);
+        let res = test::call_service(&mut app, delete("/file/b.txt", &token).to_request()).await;
+        assert_eq!(res.status(), 200);
+    }
+
+    #[actix_rt::test]
+    async fn conditional_headers_follow_content() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        let token = token(&db, &bob);
+        let mut app = app!(db, storage);
+
+        let res = test::call_service(&mut app, post("/file/", &token, "a.txt", "hello").to_request()).await;
+        assert_eq!(res.status(), 200);
+        let tag = res.headers().get(header::ETAG).unwrap().to_str().unwrap().to_string();
+        assert_eq!(tag, etag(&hex::encode(Sha256::digest(b"hello"))));
+
+        let req = get("/file/a.txt", &token).header(header::IF_NONE_MATCH, tag.as_str());
+        assert_eq!(test::call_service(&mut app, req.to_request()).await.status(), 304);
+        let req = get("/file/a.txt", &token).header(header::IF_NONE_MATCH, "\"other\"");
+        let res = test::call_service(&mut app, req.to_request()).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers().get(header::ETAG).unwrap(), tag.as_str());
+
+        // nothing is changed when client saw other content
+        let req = post("/file/?conflict=overwrite", &token, "a.txt", "world").header(header::IF_MATCH, "\"other\"");
+        assert_eq!(test::call_service(&mut app, req.to_request()).await.status(), 412);
+        let req = with_token(test::TestRequest::patch().uri("/file/a.txt"), &token)
+            .header(header::IF_MATCH, "\"other\"")
+            .set_json(&Rename { name: "b.txt".to_string() });
+        assert_eq!(test::call_service(&mut app, req.to_request()).await.status(), 412);
+        let req = delete("/file/a.txt", &token).header(header::IF_MATCH, "\"other\"");
+        assert_eq!(test::call_service(&mut app, req.to_request()).await.status(), 412);
+
+        let res = test::call_service(&mut app, get("/file/a.txt", &token).to_request()).await;
+        assert_eq!(test::read_body(res).await, "hello");
+
+        let req = post("/file/?conflict=overwrite", &token, "a.txt", "world").header(header::IF_MATCH, tag.as_str());
+        let res = test::call_service(&mut app, req.to_request()).await;
+        assert_eq!(res.status(), 200);
+        let changed = res.headers().get(header::ETAG).unwrap().to_str().unwrap().to_string();
+
+        // old etag doesnt match anymore
+        let req = delete("/file/a.txt", &token).header(header::IF_MATCH, tag.as_str());
+        assert_eq!(test::call_service(&mut app, req.to_request()).await.status(), 412);
+        let req = delete("/file/a.txt", &token).header(header::IF_MATCH, changed.as_str());
+        assert_eq!(test::call_service(&mut app, req.to_request()).await.status(), 200);
+    }
+
+    #[actix_rt::test]
+    async fn upload_has_to_fit_into_quota() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        let token = token(&db, &bob);
+        let mut app = app!(db, storage);
+
+        let half = "a".repeat(600 * 1024);
+        let res = test::call_service(&mut app, post("/file/", &token, "a.txt", &half).to_request()).await;
+        assert_eq!(res.status(), 200);
+
+        // second half doesnt fit and nothing of it is left
+        let res = test::call_service(&mut app, post("/file/", &token, "b.txt", &half).to_request()).await;
+        assert_eq!(res.status(), 400);
+        assert!(!storage.exists("/bob/b.txt"));
+
+        // deleted file waits in trash and still takes its size
+        let res = test::call_service(&mut app, delete("/file/a.txt", &token).to_request()).await;
+        assert_eq!(res.status(), 200);
+        let res = test::call_service(&mut app, post("/file/", &token, "b.txt", &half).to_request()).await;
+        assert_eq!(res.status(), 400);
+    }
+}
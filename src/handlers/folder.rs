@@ -1,82 +1,243 @@
-use crate::utils::valid_path;
-use actix_web::{Error, HttpRequest, HttpResponse};
-use std::env;
-use std::fs;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use std::collections::HashSet;
 use std::time::SystemTime;
 
-use crate::middleware::{CanDownload, CanUpload};
-use crate::models::File;
+use crate::acl::{normalize, resolve, Acl};
+use crate::db::Pool;
+use crate::middleware::perm::{Delete, Read, Write};
+use crate::middleware::Must;
+use crate::handlers::stat::{Owners, Stats};
+use crate::labels;
+use crate::models::{File, Folder, ListOptions, Permission, Stat};
 use crate::reserr::ResErr;
+use crate::storage::{blocking, Meta, Store};
+use crate::trash;
 use crate::utils::get_folder_and_files;
 
-pub async fn get_folder(token: CanDownload, req: HttpRequest) -> Result<HttpResponse, ResErr> {
-    let path: String =
-        (env::var("CLOUD_PATH").unwrap() + &token.path + "/" + req.match_info().query("filename"))
-            .parse()
-            .map_err(|_| ResErr::BadClientData("cant parse path"))?;
+pub async fn get_folder(
+    token: Must<Read>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    req: HttpRequest,
+    options: web::Query<ListOptions>,
+) -> Result<HttpResponse, ResErr> {
+    let acl = Acl::load(&db, &token)?;
+    let filename = req.match_info().query("filename").to_string();
+
+    // with tag, favorite or property only labeled entries are listed
+    let labeled = labels::matching(&db, acl.user_id, options.labels())?;
+
+    if options.extended {
+        let res = blocking(move || list_extended(&acl, &db, &storage, &filename, &labeled)).await?;
+        return Ok(HttpResponse::Ok().json(res));
+    }
+
+    let res = blocking(move || list_labeled(&acl, &storage, &filename, &labeled)).await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+fn shown(labeled: &Option<HashSet<String>>, path: &str) -> bool {
+    labeled.as_ref().is_none_or(|l| l.contains(path))
+}
+
+fn list_labeled(
+    acl: &Acl,
+    storage: &Store,
+    filename: &str,
+    labeled: &Option<HashSet<String>>,
+) -> Result<Vec<File>, ResErr> {
+    let folder = acl.resolve(filename, Permission::Read)?;
+    let mut res = list(acl, storage, filename)?;
+    res.retain(|f| shown(labeled, &format!("{}/{}", folder.trim_end_matches('/'), f.name)));
+    let now = SystemTime::now();
 
-    valid_path(&path).map_err(|err| ResErr::BadClientData(err))?;
+    // folders of groups are shown in root of user as "@name"
+    if normalize(filename)? == "/" {
+        for group in acl.groups.iter() {
+            let path = normalize(&group.path)?;
 
-    let paths = fs::read_dir(path).map_err(|_| ResErr::BadClientData("cant find path"))?;
+            if acl.can(&path, Permission::Read) && shown(labeled, &path) {
+                let meta = storage
+                    .stat(&path)
+                    .map_err(|_| ResErr::BadClientData("cant get metadata"))?;
+
+                res.push(file_info(&meta, format!("@{}", group.name), now));
+            }
+        }
+    }
+
+    Ok(res)
+}
+
+// content of folder from request of user owning acl
+pub fn list(acl: &Acl, storage: &Store, filename: &str) -> Result<Vec<File>, ResErr> {
+    let folder = acl.resolve(filename, Permission::Read)?;
+
+    let paths = storage
+        .list(&folder)
+        .map_err(|_| ResErr::BadClientData("cant find path"))?;
 
     let mut res: Vec<File> = Vec::new();
     let now = SystemTime::now();
 
-    for path in paths {
-        let better_path = path
-            .map_err(|_| ResErr::BadClientData("cant do path"))?
-            .path();
-        let metadata =
-            fs::metadata(&better_path).map_err(|_| ResErr::BadClientData("cant get metadata"))?;
-        res.push(File {
-            name: better_path
-                .to_str()
-                .unwrap()
-                .replace("\\", "/")
-                .split('/')
-                .last()
-                .unwrap()
-                .to_string(),
-            date: match metadata.modified() {
-                Ok(v) => match now.duration_since(v) {
-                    Ok(v) => v.as_secs().to_string(),
-                    Err(_) => "time is broken".to_string(),
-                },
-                Err(_) => "no supported".to_string(),
-            },
-            size: metadata.len(),
-        });
+    for meta in paths {
+        // what cant be read is not even listed
+        if !acl.can(&format!("{}/{}", folder.trim_end_matches('/'), meta.name), Permission::Read) {
+            continue;
+        }
+
+        res.push(file_info(&meta, meta.name.clone(), now));
     }
 
-    Ok(HttpResponse::Ok().json(res))
+    Ok(res)
 }
 
-pub async fn create_folder(token: CanUpload, req: HttpRequest) -> Result<HttpResponse, ResErr> {
-    let path: String =
-        (env::var("CLOUD_PATH").unwrap() + &token.path + "/" + req.match_info().query("filename"))
-            .parse()
-            .map_err(|_| ResErr::BadClientData("cant parse path"))?;
+// same entries as list, each with everything stat tells about it
+fn list_extended(
+    acl: &Acl,
+    db: &Pool,
+    storage: &Store,
+    filename: &str,
+    labeled: &Option<HashSet<String>>,
+) -> Result<Vec<Stat>, ResErr> {
+    let filename = normalize(filename)?;
+    let folder = acl.resolve(&filename, Permission::Read)?;
+
+    let paths = storage
+        .list(&folder)
+        .map_err(|_| ResErr::BadClientData("cant find path"))?;
+
+    let stats = Stats {
+        acl,
+        db,
+        storage,
+        owners: Owners::load(db)?,
+    };
+    let mut res = Vec::new();
+
+    for meta in paths {
+        let path = format!("{}/{}", folder.trim_end_matches('/'), meta.name);
+        if !acl.can(&path, Permission::Read) || !shown(labeled, &path) {
+            continue;
+        }
+
+        let name = format!("{}/{}", filename.trim_end_matches('/'), meta.name);
+        res.push(stats.of(&name, &path, &meta, false)?);
+    }
 
-    fs::create_dir_all(path).map_err(|_| ResErr::BadClientData("cant create folder"))?;
+    if filename == "/" {
+        for group in acl.groups.iter() {
+            let path = normalize(&group.path)?;
 
-    Ok(HttpResponse::Ok().body("folder created"))
+            if acl.can(&path, Permission::Read) && shown(labeled, &path) {
+                let meta = storage
+                    .stat(&path)
+                    .map_err(|_| ResErr::BadClientData("cant get metadata"))?;
+
+                res.push(stats.of(&format!("/@{}", group.name), &path, &meta, false)?);
+            }
+        }
+    }
+
+    Ok(res)
 }
 
-pub async fn delete_folder(token: CanUpload, req: HttpRequest) -> Result<HttpResponse, Error> {
-    let path: String =
-        (env::var("CLOUD_PATH").unwrap() + &token.path + "/" + req.match_info().query("filename"))
-            .parse()?;
+fn file_info(meta: &Meta, name: String, now: SystemTime) -> File {
+    File {
+        name,
+        date: match meta.modified {
+            Some(v) => match now.duration_since(v) {
+                Ok(v) => v.as_secs().to_string(),
+                Err(_) => "time is broken".to_string(),
+            },
+            None => "no supported".to_string(),
+        },
+        size: meta.size,
+    }
+}
 
-    valid_path(&path).map_err(|err| ResErr::BadClientData(err))?;
+pub async fn create_folder(
+    token: Must<Write>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ResErr> {
+    let path = Acl::load(&db, &token)?.resolve_new(req.match_info().query("filename"), Permission::Write)?;
+
+    blocking(move || {
+        storage
+            .mkdir(&path)
+            .map_err(|_| ResErr::BadClientData("cant create folder"))
+    })
+    .await?;
 
-    fs::remove_dir_all(path).map_err(|_| ResErr::BadClientData("cant remove folder"))?;
+    Ok(HttpResponse::Ok().body("folder created"))
+}
+
+pub async fn delete_folder(
+    token: Must<Delete>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let filename = req.match_info().query("filename");
+    let path = resolve(&db, &token, filename, Permission::Delete)?;
+    let (filename, user_id) = (normalize(filename)?, token.id);
+
+    blocking(move || {
+        match storage.stat(&path) {
+            Ok(meta) if meta.is_dir => (),
+            Ok(_) => return Err(ResErr::BadClientData("cant remove folder")),
+            Err(_) => return Err(ResErr::BadClientData("path dont exist")),
+        }
+
+        trash::put(&db, &storage, user_id, &filename, &path)
+    })
+    .await?;
     Ok(HttpResponse::Ok().body("folder deleted"))
 }
 
-pub async fn get_tree(token: CanDownload, _req: HttpRequest) -> Result<HttpResponse, Error> {
-    let main_folder: String = format!("./{}{}", env::var("CLOUD_PATH").unwrap(), &token.path);
+pub async fn get_tree(
+    token: Must<Read>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    _req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let acl = Acl::load(&db, &token)?;
+    let root = acl.resolve("", Permission::Read)?;
+
+    let tree = blocking(move || {
+        if !storage.exists(&root) {
+            return Err(ResErr::BadClientData("path dont exist"));
+        }
+
+        let mut tree = get_folder_and_files(&storage, &root);
+        hide_unreadable(&acl, &root, &mut tree);
+
+        for group in acl.groups.iter() {
+            let path = normalize(&group.path)?;
+
+            if acl.can(&path, Permission::Read) {
+                let mut folder = get_folder_and_files(&storage, &path);
+                folder.name = format!("@{}", group.name);
+                hide_unreadable(&acl, &path, &mut folder);
+                tree.folders.push(folder);
+            }
+        }
+        Ok(tree)
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().json(tree))
+}
 
-    valid_path(&main_folder).map_err(|err| ResErr::BadClientData(err))?;
+fn hide_unreadable(acl: &Acl, path: &str, folder: &mut Folder) {
+    folder.folders.retain(|f| {
+        acl.can(&format!("{}/{}", path.trim_end_matches('/'), f.name), Permission::Read)
+    });
 
-    Ok(HttpResponse::Ok().json(get_folder_and_files(main_folder)))
+    for f in folder.folders.iter_mut() {
+        let path = format!("{}/{}", path.trim_end_matches('/'), f.name);
+        hide_unreadable(acl, &path, f);
+    }
 }
@@ -0,0 +1,170 @@
+use actix_web::{web, HttpResponse};
+use validator::Validate;
+
+use crate::acl::inside_root;
+use crate::db::{acl, execute, groups, Pool};
+use crate::middleware::perm::ManageUsers;
+use crate::middleware::Must;
+use crate::models::{AclQueries, Group, GroupDetail, GroupQueries, Queries};
+use crate::reserr::ResErr;
+use crate::storage::Store;
+
+pub async fn get_groups(_: Must<ManageUsers>, db: web::Data<Pool>) -> Result<HttpResponse, ResErr> {
+    let groups = (groups::execute(&db, GroupQueries::GetAllGroups)
+        .map_err(|_| ResErr::BadClientData("cant get groups"))?)
+    .many();
+
+    Ok(HttpResponse::Ok().json(groups))
+}
+
+pub async fn get_group(
+    _: Must<ManageUsers>,
+    db: web::Data<Pool>,
+    path: web::Path<(u32,)>,
+) -> Result<HttpResponse, ResErr> {
+    let id = path.into_inner().0;
+
+    let group = (groups::execute(&db, GroupQueries::GetGroupById(id))
+        .map_err(|_| ResErr::BadClientData("cant get group"))?)
+    .one();
+
+    let mut members = (execute(&db, Queries::GetUsersByGroup(id))
+        .map_err(|_| ResErr::BadClientData("cant get members"))?)
+    .many();
+
+    for member in members.iter_mut() {
+        member.pass = "".to_string();
+    }
+
+    Ok(HttpResponse::Ok().json(GroupDetail { group, members }))
+}
+
+pub async fn add_group(
+    token: Must<ManageUsers>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    mut group: web::Json<Group>,
+) -> Result<HttpResponse, ResErr> {
+    // error message dont work
+    group.validate().map_err(|err| {
+        ResErr::BadClientDataOwned(
+            err.field_errors().into_values().next().unwrap()[0]
+                .code
+                .as_ref()
+                .to_string(),
+        )
+    })?;
+
+    group.path = inside_root(&storage, &token.path, &group.path).await?;
+
+    let group = (groups::execute(&db, GroupQueries::AddGroup(group))
+        .map_err(|_| ResErr::BadClientData("cant add group"))?)
+    .one();
+
+    Ok(HttpResponse::Ok().json(group))
+}
+
+pub async fn update_group(
+    token: Must<ManageUsers>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    path: web::Path<(u32,)>,
+    mut group: web::Json<Group>,
+) -> Result<HttpResponse, ResErr> {
+    let id = path.into_inner().0;
+
+    // error message dont work
+    group.validate().map_err(|err| {
+        ResErr::BadClientDataOwned(
+            err.field_errors().into_values().next().unwrap()[0]
+                .code
+                .as_ref()
+                .to_string(),
+        )
+    })?;
+
+    let old = (groups::execute(&db, GroupQueries::GetGroupById(id))
+        .map_err(|_| ResErr::BadClientData("cant get group"))?)
+    .one();
+
+    inside_root(&storage, &token.path, &old.path).await?;
+    group.path = inside_root(&storage, &token.path, &group.path).await?;
+
+    (groups::execute(&db, GroupQueries::UpdateGroupById(id, group))
+        .map_err(|_| ResErr::BadClientData("cant update group"))?)
+    .none();
+
+    Ok(HttpResponse::Ok().body("group updated"))
+}
+
+pub async fn delete_group(
+    token: Must<ManageUsers>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    path: web::Path<(u32,)>,
+) -> Result<HttpResponse, ResErr> {
+    let id = path.into_inner().0;
+
+    let group = (groups::execute(&db, GroupQueries::GetGroupById(id))
+        .map_err(|_| ResErr::BadClientData("cant get group"))?)
+    .one();
+
+    inside_root(&storage, &token.path, &group.path).await?;
+
+    // files in home folder of group are kept
+    (groups::execute(&db, GroupQueries::DeleteGroupById(id))
+        .map_err(|_| ResErr::BadClientData("cant delete group"))?)
+    .none();
+
+    // ids can be reused by sqlite, so nothing may stay behind
+    (acl::execute(&db, AclQueries::DeleteGroupEntries(id))
+        .map_err(|_| ResErr::InternalError("cant delete acl entries"))?)
+    .none();
+
+    Ok(HttpResponse::Ok().body("group deleted"))
+}
+
+pub async fn add_member(
+    token: Must<ManageUsers>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    path: web::Path<(u32, u32)>,
+) -> Result<HttpResponse, ResErr> {
+    let (id, user_id) = path.into_inner();
+
+    let group = (groups::execute(&db, GroupQueries::GetGroupById(id))
+        .map_err(|_| ResErr::BadClientData("cant get group"))?)
+    .one();
+
+    inside_root(&storage, &token.path, &group.path).await?;
+
+    execute(&db, Queries::GetUserById(user_id))
+        .map_err(|_| ResErr::BadClientData("user dont exist"))?;
+
+    (groups::execute(&db, GroupQueries::AddMember(id, user_id))
+        .map_err(|_| ResErr::BadClientData("cant add member"))?)
+    .none();
+
+    Ok(HttpResponse::Ok().body("member added"))
+}
+
+pub async fn remove_member(
+    token: Must<ManageUsers>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    path: web::Path<(u32, u32)>,
+) -> Result<HttpResponse, ResErr> {
+    let (id, user_id) = path.into_inner();
+
+    let group = (groups::execute(&db, GroupQueries::GetGroupById(id))
+        .map_err(|_| ResErr::BadClientData("cant get group"))?)
+    .one();
+
+    inside_root(&storage, &token.path, &group.path).await?;
+
+    (groups::execute(&db, GroupQueries::RemoveMember(id, user_id))
+        .map_err(|_| ResErr::BadClientData("user is not member of group"))?)
+    .none();
+
+    Ok(HttpResponse::Ok().body("member removed"))
+}
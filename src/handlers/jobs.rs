@@ -0,0 +1,342 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::Utc;
+
+use crate::acl::{covers, normalize, Acl};
+use crate::db::{jobs, Pool};
+use crate::handlers::file::free_name;
+use crate::jobs::{measure, start};
+use crate::middleware::perm;
+use crate::middleware::Must;
+use crate::models::{Conflict, ConflictOptions, Job, JobKind, JobQueries, JobStatus, Permission, Transfer};
+use crate::quota;
+use crate::reserr::ResErr;
+use crate::storage::{self, blocking, Store};
+use crate::trash;
+use crate::utils::random_token;
+use crate::versions;
+
+// job of somebody else looks like missing one
+fn find(db: &Pool, user_id: u32, req: &HttpRequest) -> Result<Job, ResErr> {
+    let job = (jobs::execute(db, JobQueries::GetJob(req.match_info().query("id").to_string()))
+        .map_err(|_| ResErr::BadClientData("job not found"))?)
+    .one();
+
+    if job.user_id != user_id {
+        return Err(ResErr::BadClientData("job not found"));
+    }
+    Ok(job)
+}
+
+// where copy or move goes, conflict is solved here so job only writes into free path,
+// returns path as user sees it and path in storage
+fn destination(
+    acl: &Acl,
+    db: &Pool,
+    storage: &Store,
+    source: &str,
+    to: &str,
+    conflict: Conflict,
+) -> Result<(String, String), ResErr> {
+    let to = normalize(to)?;
+    let mut target = acl.resolve_new(&to, Permission::Write)?;
+
+    if covers(source, &target) || covers(&target, source) {
+        return Err(ResErr::BadClientData("source and destination overlap"));
+    }
+
+    let parent = target.rsplit_once('/').map(|(f, _)| f).unwrap_or("");
+    if !storage.exists(&normalize(parent)?) {
+        return Err(ResErr::BadClientData("path dont exist"));
+    }
+
+    if !storage.exists(&target) {
+        return Ok((to, target));
+    }
+
+    let (folder, name) = to.rsplit_once('/').unwrap_or(("", &to));
+    if name.is_empty() {
+        return Err(ResErr::BadClientData("file already exists"));
+    }
+
+    match conflict {
+        Conflict::Fail => Err(ResErr::BadClientData("file already exists")),
+        Conflict::Rename => {
+            target = free_name(acl, storage, folder, name)?;
+            Ok((format!("{}/{}", folder, storage::name(&target)), target))
+        }
+        // what is in the way goes into trash, so nothing is lost
+        Conflict::Overwrite => {
+            acl.resolve(&to, Permission::Delete)?;
+            trash::put(db, storage, acl.user_id, &to, &target)?;
+            Ok((to, target))
+        }
+    }
+}
+
+fn new_job(
+    user_id: u32,
+    kind: JobKind,
+    from: &str,
+    to: String,
+    source: String,
+    target: String,
+    size: (u64, u32),
+) -> Job {
+    Job {
+        id: random_token(32),
+        user_id,
+        kind,
+        from: from.to_string(),
+        to,
+        source,
+        target,
+        status: JobStatus::Running,
+        total: size.0,
+        done: 0,
+        files: size.1,
+        files_done: 0,
+        error: None,
+        created: Utc::now().timestamp(),
+        finished: None,
+    }
+}
+
+pub async fn copy_path(
+    token: Must<perm::Write>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    req: HttpRequest,
+    transfer: web::Json<Transfer>,
+    options: web::Query<ConflictOptions>,
+) -> Result<HttpResponse, ResErr> {
+    let acl = Acl::load(&db, &token)?;
+    let from = normalize(req.match_info().query("filename"))?;
+
+    // everything inside has to be readable, folder with own acl cant leak through copy
+    let source = acl.resolve(&from, Permission::Read)?;
+    if !acl.can_all(&source, Permission::Read) {
+        return Err(ResErr::BadClientData("you dont have access to this path"));
+    }
+
+    let home = acl.home(&transfer.to)?;
+    let (user_id, to, conflict) = (token.id, transfer.into_inner().to, options.conflict);
+
+    let job = blocking(move || {
+        let size = measure(&storage, &source).map_err(|_| ResErr::BadClientData("path dont exist"))?;
+
+        // copy is checked against quota before anything is written, then its size stays reserved
+        if size.0 > quota::free(&db, &storage, &home, None)? {
+            return Err(ResErr::BadClientData("you dont have size"));
+        }
+
+        let (to, target) = destination(&acl, &db, &storage, &source, &to, conflict)?;
+
+        let job = new_job(user_id, JobKind::Copy, &from, to, source, target, size);
+        start(&db, storage.clone(), job)
+    })
+    .await?;
+
+    Ok(HttpResponse::Accepted().json(job))
+}
+
+pub async fn move_path(
+    token: Must<perm::Rename>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    req: HttpRequest,
+    transfer: web::Json<Transfer>,
+    options: web::Query<ConflictOptions>,
+) -> Result<HttpResponse, ResErr> {
+    let acl = Acl::load(&db, &token)?;
+    let from = normalize(req.match_info().query("filename"))?;
+
+    let source = acl.resolve(&from, Permission::Rename)?;
+    let home = acl.home(&transfer.to)?;
+    let (user_id, to, conflict) = (token.id, transfer.into_inner().to, options.conflict);
+
+    let job = blocking(move || {
+        let size = measure(&storage, &source).map_err(|_| ResErr::BadClientData("path dont exist"))?;
+
+        // moving into other home takes its quota, history goes with files
+        if !covers(&home.path, &source)
+            && size.0 + versions::versioned(&db, &source)? > quota::free(&db, &storage, &home, None)?
+        {
+            return Err(ResErr::BadClientData("you dont have size"));
+        }
+
+        let (to, target) = destination(&acl, &db, &storage, &source, &to, conflict)?;
+
+        let job = new_job(user_id, JobKind::Move, &from, to, source, target, size);
+        start(&db, storage.clone(), job)
+    })
+    .await?;
+
+    Ok(HttpResponse::Accepted().json(job))
+}
+
+pub async fn get_jobs(token: Must<perm::Read>, db: web::Data<Pool>) -> Result<HttpResponse, ResErr> {
+    let jobs = (jobs::execute(&db, JobQueries::GetUserJobs(token.id))
+        .map_err(|_| ResErr::InternalError("cant get jobs"))?)
+    .many();
+
+    Ok(HttpResponse::Ok().json(jobs))
+}
+
+pub async fn get_job(
+    token: Must<perm::Read>,
+    db: web::Data<Pool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ResErr> {
+    Ok(HttpResponse::Ok().json(find(&db, token.id, &req)?))
+}
+
+// running job is cancelled, finished one is forgotten
+pub async fn delete_job(
+    token: Must<perm::Write>,
+    db: web::Data<Pool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ResErr> {
+    let job = find(&db, token.id, &req)?;
+
+    if job.status == JobStatus::Running {
+        let cancel = JobQueries::FinishJob(job.id, JobStatus::Cancelled, None, Utc::now().timestamp());
+        (jobs::execute(&db, cancel).map_err(|_| ResErr::InternalError("cant cancel job"))?).none();
+
+        return Ok(HttpResponse::Ok().body("job cancelled"));
+    }
+
+    (jobs::execute(&db, JobQueries::DeleteJob(job.id))
+        .map_err(|_| ResErr::InternalError("cant delete job"))?)
+    .none();
+
+    Ok(HttpResponse::Ok().body("job deleted"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+    use serde_json::{json, Value};
+    use std::io::Read;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::db::roles::UPLOADER;
+    use crate::db::trash as trash_items;
+    use crate::handlers::file::post_file;
+    use crate::handlers::testing::{add_user, multipart, setup, token};
+    use crate::models::TrashQueries;
+    use crate::versions::{history, place};
+
+    macro_rules! app {
+        ($db:expr, $storage:expr) => {
+            test::init_service(
+                App::new()
+                    .app_data($db.clone())
+                    .app_data($storage.clone())
+                    .route("/file/{filename:.*}", web::post().to(post_file))
+                    .route("/copy/{filename:.*}", web::post().to(copy_path))
+                    .route("/move/{filename:.*}", web::post().to(move_path)),
+            )
+            .await
+        };
+    }
+
+    fn upload(token: &str, filename: &str, content: &str) -> test::TestRequest {
+        let req = test::TestRequest::post().uri("/file/?conflict=overwrite").header("token", token);
+        multipart(req, &[(filename, content)])
+    }
+
+    fn transfer(token: &str, uri: &str, to: &str) -> test::TestRequest {
+        test::TestRequest::post().uri(uri).header("token", token).set_json(&json!({ "to": to }))
+    }
+
+    fn content(storage: &Store, path: &str) -> String {
+        let mut content = String::new();
+        storage.read(path).unwrap().read_to_string(&mut content).unwrap();
+        content
+    }
+
+    // job runs in its own thread, test waits until it is finished
+    fn finished(db: &Pool, job: &Value) -> Job {
+        let id = job["id"].as_str().unwrap();
+        for _ in 0..500 {
+            let job = (jobs::execute(db, JobQueries::GetJob(id.to_string())).unwrap()).one();
+            if job.status != JobStatus::Running {
+                return job;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("job didnt finish");
+    }
+
+    #[actix_rt::test]
+    async fn copy_over_quota_is_refused_before_it_starts() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        let token = token(&db, &bob);
+        let mut app = app!(db, storage);
+
+        let half = "a".repeat(600 * 1000);
+        let res = test::call_service(&mut app, upload(&token, "a.txt", &half).to_request()).await;
+        assert_eq!(res.status(), 200);
+
+        let res = test::call_service(&mut app, transfer(&token, "/copy/a.txt", "b.txt").to_request()).await;
+        assert_eq!(res.status(), 400);
+        assert!(!storage.exists("/bob/b.txt"));
+        assert!((jobs::execute(&db, JobQueries::GetUserJobs(bob.id)).unwrap()).many().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn overwritten_target_goes_into_trash() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        let token = token(&db, &bob);
+        let mut app = app!(db, storage);
+
+        for (filename, content) in [("a.txt", "one"), ("b.txt", "two")].iter() {
+            let res = test::call_service(&mut app, upload(&token, filename, content).to_request()).await;
+            assert_eq!(res.status(), 200);
+        }
+
+        let req = transfer(&token, "/copy/a.txt?conflict=overwrite", "b.txt");
+        let res = test::call_service(&mut app, req.to_request()).await;
+        assert_eq!(res.status(), 202);
+        let job = finished(&db, &test::read_body_json(res).await);
+        assert_eq!(job.status, JobStatus::Done);
+
+        assert_eq!(content(&storage, "/bob/a.txt"), "one");
+        assert_eq!(content(&storage, "/bob/b.txt"), "one");
+        let items = (trash_items::execute(&db, TrashQueries::GetUserItems(bob.id)).unwrap()).many();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].path, "/b.txt");
+        assert_eq!(content(&storage, &trash::place(&items[0])), "two");
+    }
+
+    #[actix_rt::test]
+    async fn moved_file_keeps_its_versions() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        let token = token(&db, &bob);
+        storage.mkdir("/bob/docs").unwrap();
+        let mut app = app!(db, storage);
+
+        for content in ["one", "two"].iter() {
+            let res = test::call_service(&mut app, upload(&token, "a.txt", content).to_request()).await;
+            assert_eq!(res.status(), 200);
+        }
+
+        let req = transfer(&token, "/move/a.txt", "docs/a.txt");
+        let res = test::call_service(&mut app, req.to_request()).await;
+        assert_eq!(res.status(), 202);
+        let job = finished(&db, &test::read_body_json(res).await);
+        assert_eq!(job.status, JobStatus::Done);
+
+        assert!(history(&db, "/bob/a.txt").unwrap().is_empty());
+        let moved = history(&db, "/bob/docs/a.txt").unwrap();
+        assert_eq!(moved.len(), 1);
+        assert_eq!(moved[0].path, "/bob/docs/a.txt");
+        assert_eq!(content(&storage, &place(&moved[0])), "one");
+        assert_eq!(content(&storage, "/bob/docs/a.txt"), "two");
+    }
+}
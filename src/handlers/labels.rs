@@ -0,0 +1,282 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use validator::Validate;
+
+use crate::acl;
+use crate::db::{labels, Pool};
+use crate::middleware::perm::{Read, Write};
+use crate::middleware::{Must, Requirement};
+use crate::models::{LabelQueries, Labels, Permission, PropertyValue};
+use crate::reserr::ResErr;
+use crate::storage::{blocking, Store};
+
+// tag and key of property are part of url, they cant be empty or too long
+fn label_name(name: &str) -> Result<String, ResErr> {
+    let name = name.trim();
+
+    if name.is_empty() || name.chars().count() > 50 || name.chars().any(char::is_control) {
+        return Err(ResErr::BadClientData("name of label has to have 1 to 50 letters"));
+    }
+    Ok(name.to_string())
+}
+
+// existing path from request, user has to have permission for it
+async fn target<R: Requirement>(
+    token: &Must<R>,
+    db: &Pool,
+    storage: &web::Data<Store>,
+    req: &HttpRequest,
+    permission: Permission,
+) -> Result<String, ResErr> {
+    let path = acl::resolve(db, token, req.match_info().query("filename"), permission)?;
+
+    let storage = storage.clone();
+    blocking(move || {
+        if !storage.exists(&path) {
+            return Err(ResErr::BadClientData("path dont exist"));
+        }
+        Ok(path)
+    })
+    .await
+}
+
+fn labels_of(db: &Pool, path: String, user_id: u32) -> Result<HttpResponse, ResErr> {
+    let labels = (labels::execute(db, LabelQueries::GetLabels(path, user_id))
+        .map_err(|_| ResErr::InternalError("cant get labels"))?)
+    .one();
+
+    Ok(HttpResponse::Ok().json::<Labels>(labels))
+}
+
+fn change(db: &Pool, query: LabelQueries) -> Result<(), ResErr> {
+    (labels::execute(db, query).map_err(|_| ResErr::InternalError("cant save labels"))?).none();
+    Ok(())
+}
+
+pub async fn get_labels(
+    token: Must<Read>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ResErr> {
+    let path = target(&token, &db, &storage, &req, Permission::Read).await?;
+
+    labels_of(&db, path, token.user.id)
+}
+
+// tags and properties are shared by everyone who can read path, so they need write permission
+pub async fn add_tag(
+    token: Must<Write>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ResErr> {
+    let path = target(&token, &db, &storage, &req, Permission::Write).await?;
+    let tag = label_name(req.match_info().query("tag"))?;
+
+    change(&db, LabelQueries::AddTag(path.clone(), tag))?;
+    labels_of(&db, path, token.user.id)
+}
+
+pub async fn remove_tag(
+    token: Must<Write>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ResErr> {
+    let path = target(&token, &db, &storage, &req, Permission::Write).await?;
+    let tag = label_name(req.match_info().query("tag"))?;
+
+    change(&db, LabelQueries::RemoveTag(path.clone(), tag))?;
+    labels_of(&db, path, token.user.id)
+}
+
+// favorites are only of user, so reading is enough
+pub async fn add_favorite(
+    token: Must<Read>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ResErr> {
+    let path = target(&token, &db, &storage, &req, Permission::Read).await?;
+
+    change(&db, LabelQueries::SetFavorite(path.clone(), token.user.id, true))?;
+    labels_of(&db, path, token.user.id)
+}
+
+pub async fn remove_favorite(
+    token: Must<Read>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ResErr> {
+    let path = target(&token, &db, &storage, &req, Permission::Read).await?;
+
+    change(&db, LabelQueries::SetFavorite(path.clone(), token.user.id, false))?;
+    labels_of(&db, path, token.user.id)
+}
+
+pub async fn set_property(
+    token: Must<Write>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    req: HttpRequest,
+    value: web::Json<PropertyValue>,
+) -> Result<HttpResponse, ResErr> {
+    value
+        .validate()
+        .map_err(|err| {
+            ResErr::BadClientDataOwned(
+                err.field_errors().into_values().next().unwrap()[0]
+                    .code
+                    .as_ref()
+                    .to_string(),
+            )
+        })?;
+
+    let path = target(&token, &db, &storage, &req, Permission::Write).await?;
+    let key = label_name(req.match_info().query("key"))?;
+
+    change(&db, LabelQueries::SetProperty(path.clone(), key, Some(value.into_inner().value)))?;
+    labels_of(&db, path, token.user.id)
+}
+
+pub async fn remove_property(
+    token: Must<Write>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ResErr> {
+    let path = target(&token, &db, &storage, &req, Permission::Write).await?;
+    let key = label_name(req.match_info().query("key"))?;
+
+    change(&db, LabelQueries::SetProperty(path.clone(), key, None))?;
+    labels_of(&db, path, token.user.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+    use serde_json::{json, Value};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::db::roles::UPLOADER;
+    use crate::db::{jobs, trash};
+    use crate::handlers::file::{delete_file, post_file, rename_file};
+    use crate::handlers::jobs::{copy_path, move_path};
+    use crate::handlers::testing::{add_user, multipart, setup, token};
+    use crate::handlers::trash::{delete_item, restore_item};
+    use crate::models::{JobQueries, JobStatus, TrashQueries};
+    use crate::storage::indexed::IndexedStorage;
+    use crate::storage::memory::MemoryStorage;
+
+    macro_rules! status {
+        ($app:expr, $req:expr) => {
+            test::call_service(&mut $app, $req.to_request()).await.status()
+        };
+    }
+
+    fn upload(token: &str, content: &str) -> test::TestRequest {
+        let req = test::TestRequest::post().uri("/file/?conflict=overwrite").header("token", token);
+        multipart(req, &[("a.txt", content)])
+    }
+
+    fn request(req: test::TestRequest, uri: &str, token: &str) -> test::TestRequest {
+        req.uri(uri).header("token", token)
+    }
+
+    // rename, copy and move take where path goes in body
+    fn to(req: test::TestRequest, uri: &str, token: &str, key: &str, to: &str) -> test::TestRequest {
+        request(req, uri, token).set_json(&json!({ key: to }))
+    }
+
+    // jobs run in their own threads, test waits until all of them are finished
+    fn wait_for_jobs(db: &Pool, user_id: u32) {
+        for _ in 0..500 {
+            let jobs = (jobs::execute(db, JobQueries::GetUserJobs(user_id)).unwrap()).many();
+            if jobs.iter().all(|j| j.status != JobStatus::Running) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("job didnt finish");
+    }
+
+    // labels are kept by storage, so test has the same one as app
+    #[actix_rt::test]
+    async fn labels_follow_file_until_it_is_gone() {
+        let (db, _) = setup();
+        let storage: Store = Box::new(IndexedStorage::new(Box::<MemoryStorage>::default(), (**db).clone()));
+        let storage = web::Data::new(storage);
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        let token = token(&db, &bob);
+        storage.mkdir("/bob/docs").unwrap();
+        let mut app = test::init_service(
+            App::new()
+                .app_data(db.clone())
+                .app_data(storage.clone())
+                .route("/file/{filename:.*}", web::post().to(post_file))
+                .route("/file/{filename:.*}", web::patch().to(rename_file))
+                .route("/file/{filename:.*}", web::delete().to(delete_file))
+                .route("/copy/{filename:.*}", web::post().to(copy_path))
+                .route("/move/{filename:.*}", web::post().to(move_path))
+                .route("/trash/{id}", web::post().to(restore_item))
+                .route("/trash/{id}", web::delete().to(delete_item))
+                .route("/labels/{filename:.*}", web::get().to(get_labels))
+                .route("/tags/{tag}/{filename:.*}", web::put().to(add_tag))
+                .route("/favorites/{filename:.*}", web::put().to(add_favorite)),
+        )
+        .await;
+
+        macro_rules! labels {
+            ($filename:expr) => {{
+                let req = request(test::TestRequest::get(), &format!("/labels/{}", $filename), &token);
+                let res = test::call_service(&mut app, req.to_request()).await;
+                let labels: Value = test::read_body_json(res).await;
+                (labels["tags"].clone(), labels["favorite"].clone())
+            }};
+        }
+        let labeled = (json!(["red"]), json!(true));
+        let unlabeled = (json!([]), json!(false));
+
+        assert_eq!(status!(app, upload(&token, "one")), 200);
+        assert_eq!(status!(app, request(test::TestRequest::put(), "/tags/red/a.txt", &token)), 200);
+        assert_eq!(status!(app, request(test::TestRequest::put(), "/favorites/a.txt", &token)), 200);
+
+        let rename = to(test::TestRequest::patch(), "/file/a.txt", &token, "name", "b.txt");
+        assert_eq!(status!(app, rename), 200);
+        assert_eq!(labels!("b.txt"), labeled);
+
+        let copy = to(test::TestRequest::post(), "/copy/b.txt", &token, "to", "c.txt");
+        assert_eq!(status!(app, copy), 202);
+        wait_for_jobs(&db, bob.id);
+        let moved = to(test::TestRequest::post(), "/move/b.txt", &token, "to", "docs/a.txt");
+        assert_eq!(status!(app, moved), 202);
+        wait_for_jobs(&db, bob.id);
+        assert_eq!(labels!("c.txt"), unlabeled);
+        assert_eq!(labels!("docs/a.txt"), labeled);
+
+        let rename = to(test::TestRequest::patch(), "/file/docs/a.txt", &token, "name", "a.txt");
+        assert_eq!(status!(app, rename), 200);
+
+        // old content goes into history, labels stay with file on its path
+        assert_eq!(status!(app, upload(&token, "two")), 200);
+        assert_eq!(labels!("a.txt"), labeled);
+
+        assert_eq!(status!(app, request(test::TestRequest::delete(), "/file/a.txt", &token)), 200);
+        let item = (trash::execute(&db, TrashQueries::GetUserItems(bob.id)).unwrap()).many().remove(0);
+        let restore = request(test::TestRequest::post(), &format!("/trash/{}", item.id), &token);
+        assert_eq!(status!(app, restore), 200);
+        assert_eq!(labels!("a.txt"), labeled);
+
+        assert_eq!(status!(app, request(test::TestRequest::delete(), "/file/a.txt", &token)), 200);
+        let item = (trash::execute(&db, TrashQueries::GetUserItems(bob.id)).unwrap()).many().remove(0);
+        let purge = request(test::TestRequest::delete(), &format!("/trash/{}", item.id), &token);
+        assert_eq!(status!(app, purge), 200);
+
+        // new file with same name starts without labels of the old one
+        assert_eq!(status!(app, upload(&token, "three")), 200);
+        assert_eq!(labels!("a.txt"), unlabeled);
+    }
+}
@@ -1,27 +1,336 @@
 use actix_web::{HttpResponse, web, HttpRequest};
 use bcrypt::verify;
 
-use crate::models::{Queries, Login};
-use crate::db::{execute, Pool};
-use crate::jwt::{create_jwt, authorize};
+use crate::models::{Queries, Login, Refresh, SessionQueries, TotpQueries, Challenge, ChallengeLogin, ChallengeEnroll};
+use crate::db::{execute, sessions, settings, totp, Pool};
+use crate::handlers::password::{is_verified, verification_required};
+use crate::jwt::{create_session, refresh_session, authorize, create_challenge, verify_challenge};
+use crate::lockout::{self, email_key, ip_key};
+use crate::middleware::perm::LoggedIn;
+use crate::middleware::Must;
 use crate::reserr::ResErr;
+use crate::totp::{check_code, enroll, verify as verify_totp};
 
-pub async fn login(db: web::Data<Pool>, user: web::Json<Login>) -> Result<HttpResponse, ResErr> {
+pub async fn login(db: web::Data<Pool>, req: HttpRequest, user: web::Json<Login>) -> Result<HttpResponse, ResErr> {
 
-    let res = (execute(&db, Queries::GetUserByEmail(user.email.clone()))
-        .map_err(|_| ResErr::BadClientData("bad email or password"))?).one();
+    let keys = [email_key(&user.email), ip_key(&req)];
+
+    lockout::check(&db, &keys)?;
+
+    let res = match execute(&db, Queries::GetUserByEmail(user.email.clone())) {
+        Ok(v) => v.one(),
+        Err(_) => {
+            lockout::fail(&db, &keys)?;
+            return Err(ResErr::BadClientData("bad email or password"));
+        }
+    };
 
     let verify_res = verify(&user.pass, &res.pass).map_err(|_| ResErr::BadClientData("bad email or password"))?;
 
     if !verify_res {
+        lockout::fail(&db, &keys)?;
         return Err(ResErr::BadClientData("bad email or password"));
     }
-    
-    Ok(HttpResponse::Ok().json(create_jwt(res.id)))
+
+    if verification_required() && !is_verified(&db, res.id) {
+        return Err(ResErr::BadClientData("email not verified"));
+    }
+
+    let enabled = match totp::execute(&db, TotpQueries::GetTotp(res.id)) {
+        Ok(v) => v.one().enabled,
+        Err(_) => false,
+    };
+
+    // password is fine, but user still have to prove second factor,
+    // failures are cleared only after it, otherwise password would reset failed codes
+    if enabled || settings::get_flag(&db, "require_2fa") {
+        return Ok(HttpResponse::Ok().json(Challenge {
+            challenge: create_challenge(res.id),
+            enroll: !enabled,
+        }));
+    }
+
+    lockout::success(&db, &res.email)?;
+
+    Ok(HttpResponse::Ok().json(create_session(&db, res.id).map_err(ResErr::InternalError)?))
+}
+
+pub async fn login_2fa(db: web::Data<Pool>, req: HttpRequest, body: web::Json<ChallengeLogin>) -> Result<HttpResponse, ResErr> {
+    let id = verify_challenge(&body.challenge).map_err(ResErr::BadClientData)?;
+
+    let user = (execute(&db, Queries::GetUserById(id))
+        .map_err(|_| ResErr::BadClientData("cant get user"))?).one();
+
+    let keys = [email_key(&user.email), ip_key(&req)];
+
+    lockout::check(&db, &keys)?;
+
+    let secret = (totp::execute(&db, TotpQueries::GetTotp(id))
+        .map_err(|_| ResErr::BadClientData("2fa not enrolled"))?).one();
+
+    if secret.enabled {
+        if !check_code(&db, id, &secret.secret, &body.code) {
+            lockout::fail(&db, &keys)?;
+            return Err(ResErr::BadClientData("bad code"));
+        }
+    } else {
+        // first login after enrollment confirms the secret
+        if !verify_totp(&db, id, &secret.secret, &body.code) {
+            lockout::fail(&db, &keys)?;
+            return Err(ResErr::BadClientData("bad code"));
+        }
+
+        (totp::execute(&db, TotpQueries::EnableTotp(id))
+            .map_err(|_| ResErr::InternalError("cant enable 2fa"))?).none();
+    }
+
+    lockout::success(&db, &user.email)?;
+
+    Ok(HttpResponse::Ok().json(create_session(&db, id).map_err(ResErr::InternalError)?))
+}
+
+pub async fn login_2fa_enroll(db: web::Data<Pool>, body: web::Json<ChallengeEnroll>) -> Result<HttpResponse, ResErr> {
+    let id = verify_challenge(&body.challenge).map_err(ResErr::BadClientData)?;
+
+    if let Ok(v) = totp::execute(&db, TotpQueries::GetTotp(id)) {
+        if v.one().enabled {
+            return Err(ResErr::BadClientData("2fa already enabled"));
+        }
+    }
+
+    let user = (execute(&db, Queries::GetUserById(id))
+        .map_err(|_| ResErr::BadClientData("cant get user"))?).one();
+
+    Ok(HttpResponse::Ok().json(enroll(&db, id, &user.email)?))
 }
 
 pub async fn check_login(req: HttpRequest) -> Result<HttpResponse, ResErr> {
-    let claims = authorize(&req).map_err(|err| ResErr::BadClientData(err))?;
+    let claims = authorize(&req).map_err(ResErr::BadClientData)?;
 
     Ok(HttpResponse::Ok().json(claims))
-}
\ No newline at end of file
+}
+
+pub async fn refresh(db: web::Data<Pool>, body: web::Json<Refresh>) -> Result<HttpResponse, ResErr> {
+    let token = refresh_session(&db, &body.refresh_token).map_err(ResErr::BadClientData)?;
+
+    Ok(HttpResponse::Ok().json(token))
+}
+
+pub async fn logout(_: Must<LoggedIn>, db: web::Data<Pool>, req: HttpRequest) -> Result<HttpResponse, ResErr> {
+    let claims = authorize(&req).map_err(ResErr::BadClientData)?;
+
+    (sessions::execute(&db, SessionQueries::RevokeSession(claims.sid))
+        .map_err(|_| ResErr::InternalError("cant logout"))?).none();
+
+    Ok(HttpResponse::Ok().body("logged out"))
+}
+
+pub async fn logout_all(token: Must<LoggedIn>, db: web::Data<Pool>) -> Result<HttpResponse, ResErr> {
+    (sessions::execute(&db, SessionQueries::RevokeUserSessions(token.id, None))
+        .map_err(|_| ResErr::InternalError("cant logout"))?).none();
+
+    Ok(HttpResponse::Ok().body("all sessions logged out"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+    use serde_json::{json, Value};
+
+    use crate::db::roles::UPLOADER;
+    use crate::handlers::testing::{add_user, setup, wait, PASSWORD};
+    use crate::totp::{current_code, generate_secret};
+
+    fn login_request(email: &str, pass: &str) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/login")
+            .set_json(&json!({ "email": email, "pass": pass }))
+    }
+
+    #[actix_rt::test]
+    async fn failed_logins_slow_down_and_lock_account() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        let keys = [email_key(&bob.email), "ip:unknown".to_string()];
+
+        let mut app = test::init_service(App::new().app_data(db.clone()).route("/login", web::post().to(login))).await;
+
+        let res = test::call_service(&mut app, login_request(&bob.email, "wrong").to_request()).await;
+        assert_eq!(res.status(), 400);
+
+        // right password doesnt help while backoff lasts
+        let res = test::call_service(&mut app, login_request(&bob.email, PASSWORD).to_request()).await;
+        assert_eq!(res.status(), 429);
+        assert!(String::from_utf8_lossy(&test::read_body(res).await).contains("slow down"));
+
+        for _ in 0..2 {
+            wait(&db, &keys);
+            let res = test::call_service(&mut app, login_request(&bob.email, "wrong").to_request()).await;
+            assert_eq!(res.status(), 400);
+        }
+
+        wait(&db, &keys);
+        let res = test::call_service(&mut app, login_request(&bob.email, PASSWORD).to_request()).await;
+        assert_eq!(res.status(), 429);
+        assert!(String::from_utf8_lossy(&test::read_body(res).await).contains("too many failed logins"));
+    }
+
+    #[actix_rt::test]
+    async fn password_alone_doesnt_clear_failures_with_second_factor() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        let keys = [email_key(&bob.email), "ip:unknown".to_string()];
+        let secret = generate_secret();
+        (totp::execute(&db, TotpQueries::SetSecret(bob.id, secret.clone(), vec![])).unwrap()).none();
+        (totp::execute(&db, TotpQueries::EnableTotp(bob.id)).unwrap()).none();
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(db.clone())
+                .route("/login", web::post().to(login))
+                .route("/login/2fa", web::post().to(login_2fa)),
+        )
+        .await;
+
+        let res = test::call_service(&mut app, login_request(&bob.email, "wrong").to_request()).await;
+        assert_eq!(res.status(), 400);
+        wait(&db, &keys);
+
+        let res = test::call_service(&mut app, login_request(&bob.email, PASSWORD).to_request()).await;
+        assert_eq!(res.status(), 200);
+        let challenge: Value = test::read_body_json(res).await;
+        assert_eq!(lockout::get_attempt(&db, &keys[0]).failures, 1);
+
+        let req = test::TestRequest::post()
+            .uri("/login/2fa")
+            .set_json(&json!({ "challenge": challenge["challenge"], "code": current_code(&secret) }))
+            .to_request();
+        assert_eq!(test::call_service(&mut app, req).await.status(), 200);
+        assert_eq!(lockout::get_attempt(&db, &keys[0]).failures, 0);
+    }
+
+    #[actix_rt::test]
+    async fn second_factor_is_needed_and_code_works_once() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        let secret = generate_secret();
+        (totp::execute(&db, TotpQueries::SetSecret(bob.id, secret.clone(), vec![])).unwrap()).none();
+        (totp::execute(&db, TotpQueries::EnableTotp(bob.id)).unwrap()).none();
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(db.clone())
+                .route("/login", web::post().to(login))
+                .route("/login/2fa", web::post().to(login_2fa)),
+        )
+        .await;
+
+        let res = test::call_service(&mut app, login_request(&bob.email, PASSWORD).to_request()).await;
+        assert_eq!(res.status(), 200);
+        let challenge: Value = test::read_body_json(res).await;
+        assert_eq!(challenge["enroll"], false);
+        assert!(challenge.get("token").is_none());
+
+        let code = |code: &str| {
+            test::TestRequest::post()
+                .uri("/login/2fa")
+                .set_json(&json!({ "challenge": challenge["challenge"], "code": code }))
+                .to_request()
+        };
+
+        assert_eq!(test::call_service(&mut app, code("abc")).await.status(), 400);
+        wait(&db, &[email_key(&bob.email), "ip:unknown".to_string()]);
+
+        let res = test::call_service(&mut app, code(&current_code(&secret))).await;
+        assert_eq!(res.status(), 200);
+        let token: Value = test::read_body_json(res).await;
+        assert!(token["token"].is_string());
+
+        // code seen once cant be used by someone who watched it
+        wait(&db, &["ip:unknown".to_string()]);
+        assert_eq!(test::call_service(&mut app, code(&current_code(&secret))).await.status(), 400);
+    }
+
+    fn with_token(uri: &str, token: &str) -> test::TestRequest {
+        test::TestRequest::post().uri(uri).header("token", token)
+    }
+
+    fn refresh_request(refresh_token: &str) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/token/refresh")
+            .set_json(&json!({ "refresh_token": refresh_token }))
+    }
+
+    #[actix_rt::test]
+    async fn refresh_token_works_once() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        let first = create_session(&db, bob.id).unwrap();
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(db.clone())
+                .route("/check_login", web::post().to(check_login))
+                .route("/token/refresh", web::post().to(refresh)),
+        )
+        .await;
+
+        let res = test::call_service(&mut app, refresh_request(&first.refresh_token).to_request()).await;
+        assert_eq!(res.status(), 200);
+        let second: Value = test::read_body_json(res).await;
+        assert_ne!(second["refresh_token"], first.refresh_token.as_str());
+
+        let req = with_token("/check_login", second["token"].as_str().unwrap());
+        assert_eq!(test::call_service(&mut app, req.to_request()).await.status(), 200);
+
+        // stolen refresh token which was already used is worth nothing
+        let res = test::call_service(&mut app, refresh_request(&first.refresh_token).to_request()).await;
+        assert_eq!(res.status(), 400);
+
+        let req = refresh_request(second["refresh_token"].as_str().unwrap());
+        assert_eq!(test::call_service(&mut app, req.to_request()).await.status(), 200);
+    }
+
+    #[actix_rt::test]
+    async fn logout_revokes_access_tokens() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        let (phone, laptop, tablet) = (
+            create_session(&db, bob.id).unwrap(),
+            create_session(&db, bob.id).unwrap(),
+            create_session(&db, bob.id).unwrap(),
+        );
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(db.clone())
+                .route("/check_login", web::post().to(check_login))
+                .route("/token/refresh", web::post().to(refresh))
+                .route("/logout", web::post().to(logout))
+                .route("/logout_all", web::post().to(logout_all)),
+        )
+        .await;
+
+        let res = test::call_service(&mut app, with_token("/logout", &phone.token).to_request()).await;
+        assert_eq!(res.status(), 200);
+
+        // only session which logged out is gone
+        let res = test::call_service(&mut app, with_token("/check_login", &phone.token).to_request()).await;
+        assert_eq!(res.status(), 400);
+        let res = test::call_service(&mut app, refresh_request(&phone.refresh_token).to_request()).await;
+        assert_eq!(res.status(), 400);
+        let res = test::call_service(&mut app, with_token("/check_login", &laptop.token).to_request()).await;
+        assert_eq!(res.status(), 200);
+
+        let res = test::call_service(&mut app, with_token("/logout_all", &laptop.token).to_request()).await;
+        assert_eq!(res.status(), 200);
+
+        for session in [&laptop, &tablet].iter() {
+            let res = test::call_service(&mut app, with_token("/check_login", &session.token).to_request()).await;
+            assert_eq!(res.status(), 400);
+            let res = test::call_service(&mut app, refresh_request(&session.refresh_token).to_request()).await;
+            assert_eq!(res.status(), 400);
+        }
+    }
+}
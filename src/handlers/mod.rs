@@ -1,5 +1,18 @@
 pub mod admin;
+pub mod archive;
 pub mod file;
 pub mod folder;
+pub mod group;
+pub mod jobs;
+pub mod labels;
 pub mod login;
-pub mod user;
\ No newline at end of file
+pub mod password;
+pub mod search;
+pub mod share;
+pub mod stat;
+pub mod trash;
+pub mod tus;
+pub mod user;
+pub mod versions;
+#[cfg(test)]
+pub mod testing;
@@ -0,0 +1,231 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use bcrypt::{hash, DEFAULT_COST};
+use chrono::Utc;
+use std::{env, thread};
+use validator::Validate;
+
+use crate::db::{execute, mail_tokens, sessions, Pool};
+use crate::lockout::{self, email_key, ip_key};
+use crate::mail::Mailer;
+use crate::models::{Forgot, MailTokenQueries, Queries, Reset, SessionQueries, User, Verify};
+use crate::reserr::ResErr;
+use crate::utils::{hash_token, random_token};
+
+fn issue_token(db: &Pool, user_id: u32, kind: &'static str, minutes: i64) -> Result<String, ResErr> {
+    let token = random_token(48);
+
+    (mail_tokens::execute(
+        db,
+        MailTokenQueries::Issue(
+            user_id,
+            kind,
+            hash_token(&token),
+            Utc::now().timestamp() + minutes * 60,
+        ),
+    )
+    .map_err(|_| ResErr::InternalError("cant create token"))?)
+    .none();
+
+    Ok(token)
+}
+
+async fn send(mailer: web::Data<Mailer>, to: String, subject: &'static str, body: String) -> Result<(), ResErr> {
+    // smtp is blocking, use threadpool
+    web::block(move || mailer.send(&to, subject, &body))
+        .await
+        .map_err(|_| ResErr::InternalError("cant send mail"))
+}
+
+// mails asked by anonymous client are sent in background, so answer is same and takes same time
+// whether account exists or not, failure is only logged
+fn in_background<F>(job: F)
+where
+    F: FnOnce() -> Result<(), String> + Send + 'static,
+{
+    thread::spawn(move || {
+        if let Err(err) = job() {
+            eprintln!("cant send mail: {}", err);
+        }
+    });
+}
+
+// every request counts as failed login under own keys, so one address or client cant get many mails
+fn throttle(db: &Pool, req: &HttpRequest, email: &str) -> Result<(), ResErr> {
+    let keys = [format!("{}:mail", email_key(email)), format!("{}:mail", ip_key(req))];
+
+    lockout::throttle(db, &keys)
+}
+
+fn verification_body(db: &Pool, user: &User) -> Result<String, ResErr> {
+    let hours: i64 = env::var("VERIFY_TOKEN_EXPIRATION").unwrap().parse().unwrap();
+    let token = issue_token(db, user.id, "verify", hours * 60)?;
+
+    Ok(format!(
+        "Hello {},\n\nplease verify your email address by opening\n{}/verify_email?token={}\n\nor use this token: {}\n",
+        user.name,
+        env::var("PUBLIC_URL").unwrap(),
+        token,
+        token
+    ))
+}
+
+pub async fn send_verification(db: &Pool, mailer: web::Data<Mailer>, user: &User) -> Result<(), ResErr> {
+    let body = verification_body(db, user)?;
+
+    send(mailer, user.email.clone(), "Verify your email", body).await
+}
+
+pub fn verification_required() -> bool {
+    env::var("EMAIL_VERIFICATION").unwrap() == "true"
+}
+
+pub fn is_verified(db: &Pool, id: u32) -> bool {
+    match execute(db, Queries::GetUserById(id)) {
+        Ok(v) => v.one().verified.is_some(),
+        Err(_) => false,
+    }
+}
+
+// unknown email is not an error, nobody can find out who has account
+fn mail_reset(db: &Pool, mailer: &Mailer, email: &str) -> Result<(), String> {
+    let user = match execute(db, Queries::GetUserByEmail(email.to_string())) {
+        Ok(v) => v.one(),
+        Err(_) => return Ok(()),
+    };
+    let minutes: i64 = env::var("RESET_TOKEN_EXPIRATION").unwrap().parse().unwrap();
+    let token = issue_token(db, user.id, "reset", minutes).map_err(|err| err.to_string())?;
+
+    mailer.send(
+        &user.email,
+        "Password reset",
+        &format!(
+            "Hello {},\n\nsomebody asked to reset your password. If it was you, open\n{}/reset_password?token={}\n\nor use this token: {}\n\nThe link expires in {} minutes and works only once.\n",
+            user.name,
+            env::var("PUBLIC_URL").unwrap(),
+            token,
+            token,
+            minutes
+        ),
+    )
+}
+
+fn mail_verification(db: &Pool, mailer: &Mailer, email: &str) -> Result<(), String> {
+    let user = match execute(db, Queries::GetUserByEmail(email.to_string())) {
+        Ok(v) => v.one(),
+        Err(_) => return Ok(()),
+    };
+    if user.verified.is_some() {
+        return Ok(());
+    }
+    let body = verification_body(db, &user).map_err(|err| err.to_string())?;
+
+    mailer.send(&user.email, "Verify your email", &body)
+}
+
+pub async fn forgot_password(
+    db: web::Data<Pool>,
+    mailer: web::Data<Mailer>,
+    req: HttpRequest,
+    body: web::Json<Forgot>,
+) -> Result<HttpResponse, ResErr> {
+    throttle(&db, &req, &body.email)?;
+
+    let (db, email) = (db.get_ref().clone(), body.into_inner().email);
+    in_background(move || mail_reset(&db, &mailer, &email));
+
+    Ok(HttpResponse::Ok().body("if account exists, email was sent"))
+}
+
+pub async fn reset_password(db: web::Data<Pool>, body: web::Json<Reset>) -> Result<HttpResponse, ResErr> {
+    // error message dont work
+    body.validate().map_err(|err| {
+        ResErr::BadClientDataOwned(
+            err.field_errors().into_values().next().unwrap()[0]
+                .code
+                .as_ref()
+                .to_string(),
+        )
+    })?;
+
+    let token = (mail_tokens::execute(&db, MailTokenQueries::Consume("reset", hash_token(&body.token)))
+        .map_err(|_| ResErr::BadClientData("invalid or expired token"))?)
+    .one();
+
+    let pass = hash(body.pass.clone(), DEFAULT_COST).map_err(|_| ResErr::InternalError("cant hash"))?;
+
+    (execute(&db, Queries::UpdatePassById(token.user_id, pass))
+        .map_err(|_| ResErr::InternalError("cant update password"))?)
+    .none();
+
+    // whoever knew old password is logged out
+    (sessions::execute(&db, SessionQueries::RevokeUserSessions(token.user_id, None))
+        .map_err(|_| ResErr::InternalError("cant revoke sessions"))?)
+    .none();
+
+    Ok(HttpResponse::Ok().body("password changed"))
+}
+
+pub async fn verify_email(db: web::Data<Pool>, body: web::Json<Verify>) -> Result<HttpResponse, ResErr> {
+    let token = (mail_tokens::execute(&db, MailTokenQueries::Consume("verify", hash_token(&body.token)))
+        .map_err(|_| ResErr::BadClientData("invalid or expired token"))?)
+    .one();
+
+    (execute(&db, Queries::SetVerifiedById(token.user_id))
+        .map_err(|_| ResErr::InternalError("cant verify email"))?)
+    .none();
+
+    Ok(HttpResponse::Ok().body("email verified"))
+}
+
+pub async fn resend_verification(
+    db: web::Data<Pool>,
+    mailer: web::Data<Mailer>,
+    req: HttpRequest,
+    body: web::Json<Forgot>,
+) -> Result<HttpResponse, ResErr> {
+    throttle(&db, &req, &body.email)?;
+
+    let (db, email) = (db.get_ref().clone(), body.into_inner().email);
+    in_background(move || mail_verification(&db, &mailer, &email));
+
+    Ok(HttpResponse::Ok().body("if account needs verification, email was sent"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+    use serde_json::json;
+
+    use crate::db::roles::UPLOADER;
+    use crate::handlers::testing::{add_user, setup};
+
+    fn verify(token: &str) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/email/verify")
+            .set_json(&json!({ "token": token }))
+    }
+
+    #[actix_rt::test]
+    async fn only_token_from_mail_verifies_email() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        let mut app = test::init_service(
+            App::new()
+                .app_data(db.clone())
+                .route("/email/verify", web::post().to(verify_email)),
+        )
+        .await;
+
+        // token which was never issued or is gone dont make anyone verified
+        assert!(!is_verified(&db, bob.id));
+
+        let expired = issue_token(&db, bob.id, "verify", -1).unwrap();
+        assert_eq!(test::call_service(&mut app, verify(&expired).to_request()).await.status(), 400);
+        assert!(!is_verified(&db, bob.id));
+
+        let token = issue_token(&db, bob.id, "verify", 60).unwrap();
+        assert_eq!(test::call_service(&mut app, verify(&token).to_request()).await.status(), 200);
+        assert!(is_verified(&db, bob.id));
+    }
+}
@@ -0,0 +1,301 @@
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, NaiveDate};
+use std::cmp::Ordering;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::acl::{covers, normalize, Acl};
+use crate::db::{contents, index, Pool};
+use crate::fulltext;
+use crate::handlers::stat::rfc3339;
+use crate::middleware::perm::Read;
+use crate::middleware::Must;
+use crate::models::{
+    ContentResult, ContentResults, ContentSearchOptions, EntryType, IndexEntry, IndexFilter, IndexQueries, Permission,
+    SearchHit, SearchOptions, SearchResults,
+};
+use crate::reserr::ResErr;
+use crate::search::Matcher;
+use crate::storage;
+
+const MAX_PER_PAGE: u32 = 1000;
+
+// rfc 3339 time, or date which means its midnight in utc
+fn timestamp(time: &Option<String>) -> Result<Option<i64>, ResErr> {
+    let time = match time {
+        Some(time) => time,
+        None => return Ok(None),
+    };
+
+    if let Ok(t) = DateTime::parse_from_rfc3339(time) {
+        return Ok(Some(t.timestamp()));
+    }
+    NaiveDate::parse_from_str(time, "%Y-%m-%d")
+        .ok()
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .map(|t| Some(t.and_utc().timestamp()))
+        .ok_or(ResErr::BadClientData("invalid time"))
+}
+
+// home of user and homes of groups with prefix of their paths in requests,
+// group folder inside home is found through home already
+fn roots(acl: &Acl) -> Result<Vec<(String, String)>, ResErr> {
+    let home = acl.home("")?.path;
+    let mut roots = vec![(home.clone(), String::new())];
+
+    for group in acl.groups.iter() {
+        let path = normalize(&group.path)?;
+
+        if !covers(&home, &path) {
+            roots.push((path, format!("/@{}", group.name)));
+        }
+    }
+    Ok(roots)
+}
+
+// path in storage as user sees it
+fn request_path(root: &str, prefix: &str, path: &str) -> String {
+    format!("{}{}", prefix, &path[root.trim_end_matches('/').len()..])
+}
+
+fn check_page(page: u32, per_page: u32) -> Result<(), ResErr> {
+    match page == 0 || per_page == 0 || per_page > MAX_PER_PAGE {
+        true => Err(ResErr::BadClientData("page starts at 1 and per_page is 1 to 1000")),
+        false => Ok(()),
+    }
+}
+
+fn page<T>(items: Vec<T>, page: u32, per_page: u32) -> Vec<T> {
+    let start = (page as usize - 1) * per_page as usize;
+    items.into_iter().skip(start).take(per_page as usize).collect()
+}
+
+// part of root which falls into page, as limit and offset, roots are paged one after another
+// and found counts how many entries were in roots before
+fn window(found: u64, count: u64, page: u32, per_page: u32) -> (u64, u64) {
+    let start = (page as u64 - 1) * per_page as u64;
+    let offset = start.saturating_sub(found).min(count);
+    let end = (start + per_page as u64).saturating_sub(found).min(count);
+
+    (end - offset, offset)
+}
+
+// searches home of user and homes of groups, paths in results are the same as in other routes,
+// database pages results, only regex and glob have to read whole root
+pub async fn search(
+    token: Must<Read>,
+    db: web::Data<Pool>,
+    options: web::Query<SearchOptions>,
+) -> Result<HttpResponse, ResErr> {
+    let acl = Acl::load(&db, &token)?;
+    let matcher = Matcher::new(&options.q, options.mode)?;
+
+    check_page(options.page, options.per_page)?;
+
+    let labels = options.labels();
+    let filter = IndexFilter {
+        is_dir: options.kind.map(|kind| kind == EntryType::Folder),
+        min_size: options.min_size,
+        max_size: options.max_size,
+        after: timestamp(&options.modified_after)?,
+        before: timestamp(&options.modified_before)?,
+        exts: options
+            .ext
+            .iter()
+            .flat_map(|ext| ext.split(','))
+            .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+            .filter(|ext| !ext.is_empty())
+            .collect(),
+        name: match &matcher {
+            Matcher::Substring(query) => Some(query.clone()),
+            _ => None,
+        },
+        labeled: match labels.is_empty() {
+            true => None,
+            false => Some((acl.user_id, labels)),
+        },
+        readable: acl.folders(Permission::Read),
+    };
+
+    let mut found = 0;
+    let mut hits = Vec::new();
+    for (root, prefix) in roots(&acl)?.iter() {
+        let entries = match matcher {
+            Matcher::Pattern(_) => {
+                let query = IndexQueries::Search(root.clone(), filter.clone(), None);
+                let entries: Vec<IndexEntry> = (index::execute(&db, query)
+                    .map_err(|_| ResErr::InternalError("cant search"))?)
+                .many()
+                .into_iter()
+                .filter(|entry| matcher.matches(&entry.name))
+                .collect();
+
+                let (limit, offset) = window(found, entries.len() as u64, options.page, options.per_page);
+                found += entries.len() as u64;
+                entries.into_iter().skip(offset as usize).take(limit as usize).collect()
+            }
+            _ => {
+                let count = index::count(&db, root, &filter).map_err(|_| ResErr::InternalError("cant search"))?;
+
+                let (limit, offset) = window(found, count, options.page, options.per_page);
+                found += count;
+                if limit == 0 {
+                    continue;
+                }
+
+                let query = IndexQueries::Search(root.clone(), filter.clone(), Some((limit, offset)));
+                (index::execute(&db, query).map_err(|_| ResErr::InternalError("cant search"))?).many()
+            }
+        };
+
+        for entry in entries {
+            hits.push(SearchHit {
+                path: request_path(root, prefix, &entry.path),
+                name: entry.name,
+                kind: match entry.is_dir {
+                    true => EntryType::Folder,
+                    false => EntryType::File,
+                },
+                size: entry.size,
+                modified: rfc3339(entry.modified.map(|t| UNIX_EPOCH + Duration::from_secs(t.max(0) as u64))),
+            });
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(SearchResults {
+        total: found as usize,
+        page: options.page,
+        per_page: options.per_page,
+        results: hits,
+    }))
+}
+
+// words in text of documents, best matches from all roots first
+pub async fn search_content(
+    token: Must<Read>,
+    db: web::Data<Pool>,
+    options: web::Query<ContentSearchOptions>,
+) -> Result<HttpResponse, ResErr> {
+    if !fulltext::enabled() {
+        return Err(ResErr::BadClientData("full-text search is turned off"));
+    }
+
+    let acl = Acl::load(&db, &token)?;
+    check_page(options.page, options.per_page)?;
+
+    let query = fulltext::query(&options.q).ok_or(ResErr::BadClientData("nothing to search"))?;
+
+    let mut hits = Vec::new();
+    for (root, prefix) in roots(&acl)?.iter() {
+        let found = contents::search(&db, root, &query).map_err(|_| ResErr::InternalError("cant search"))?;
+
+        for hit in found {
+            if !acl.can(&hit.path, Permission::Read) {
+                continue;
+            }
+
+            hits.push(ContentResult {
+                name: storage::name(&hit.path),
+                path: request_path(root, prefix, &hit.path),
+                snippet: hit.snippet,
+                score: hit.score,
+            });
+        }
+    }
+    hits.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+
+    Ok(HttpResponse::Ok().json(ContentResults {
+        total: hits.len(),
+        page: options.page,
+        per_page: options.per_page,
+        results: page(hits, options.page, options.per_page),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+    use serde_json::Value;
+
+    use crate::db::roles::UPLOADER;
+    use crate::db::{acl as acl_entries, groups, labels as file_labels};
+    use crate::handlers::testing::{add_user, put, setup, token};
+    use crate::models::{AclKind, AclQueries, Group, GroupQueries, LabelQueries, NewAclEntry};
+    use crate::search::reindex;
+
+    // bob has five files at home, two in group and one in folder he cant read
+    #[actix_rt::test]
+    async fn pages_go_through_home_and_groups() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        let group = Group {
+            id: 0,
+            name: "team".to_string(),
+            size: 1,
+            path: "/teamdir".to_string(),
+        };
+        let group = (groups::execute(&db, GroupQueries::AddGroup(web::Json(group))).unwrap()).one();
+        (groups::execute(&db, GroupQueries::AddMember(group.id, bob.id)).unwrap()).none();
+
+        storage.mkdir("/bob/secret").unwrap();
+        storage.mkdir("/teamdir").unwrap();
+        for i in 1..=5 {
+            put(&storage, &format!("/bob/a{}.txt", i), "x");
+        }
+        put(&storage, "/bob/secret/a6.txt", "x");
+        put(&storage, "/teamdir/a7.txt", "x");
+        put(&storage, "/teamdir/A8.txt", "x");
+        let denied = NewAclEntry {
+            kind: AclKind::User,
+            subject: Some(bob.id),
+            permissions: vec![],
+        };
+        let query = AclQueries::SetEntries("/bob/secret".to_string(), vec![denied]);
+        (acl_entries::execute(&db, query).unwrap()).none();
+        let query = LabelQueries::AddTag("/bob/a2.txt".to_string(), "red".to_string());
+        (file_labels::execute(&db, query).unwrap()).none();
+        reindex(&db, &storage).unwrap();
+
+        let token = token(&db, &bob);
+        let mut app = test::init_service(
+            App::new()
+                .app_data(db.clone())
+                .route("/search", web::get().to(search)),
+        )
+        .await;
+
+        let pages = [
+            vec!["/a1.txt", "/a2.txt", "/a3.txt"],
+            vec!["/a4.txt", "/a5.txt", "/@team/A8.txt"],
+            vec!["/@team/a7.txt"],
+            vec![],
+        ];
+        // substring is matched by database, glob after it, both are paged the same
+        for query in ["q=A&per_page=3", "q=a*.TXT&mode=glob&per_page=3"].iter() {
+            for (i, expected) in pages.iter().enumerate() {
+                let uri = format!("/search?{}&page={}", query, i + 1);
+                let req = test::TestRequest::get().uri(&uri).header("token", token.as_str());
+                let res = test::call_service(&mut app, req.to_request()).await;
+                let res: Value = test::read_body_json(res).await;
+
+                assert_eq!(res["total"], 7);
+                let hits = res["results"].as_array().unwrap();
+                assert_eq!(&hits.iter().map(|h| &h["path"]).collect::<Vec<_>>(), expected);
+            }
+        }
+
+        let req = test::TestRequest::get().uri("/search?tag=red").header("token", token.as_str());
+        let res: Value = test::read_body_json(test::call_service(&mut app, req.to_request()).await).await;
+        assert_eq!(res["total"], 1);
+        assert_eq!(res["results"][0]["path"], "/a2.txt");
+    }
+
+    #[test]
+    fn window_is_cut_from_roots_one_after_another() {
+        assert_eq!(window(0, 5, 1, 3), (3, 0));
+        assert_eq!(window(0, 5, 2, 3), (2, 3));
+        assert_eq!(window(5, 2, 2, 3), (1, 0));
+        assert_eq!(window(5, 2, 1, 3).0, 0);
+        assert_eq!(window(7, 4, 4, 3), (2, 2));
+    }
+}
@@ -0,0 +1,518 @@
+use actix_multipart::Multipart;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bcrypt::{hash, verify, DEFAULT_COST};
+use chrono::Utc;
+use std::path::Path;
+use validator::Validate;
+
+use crate::acl::{normalize, Acl};
+use crate::db::{execute, shares, Pool};
+use crate::handlers::file::{send_file, upload, Upload, UploadRules};
+use crate::handlers::folder::list;
+use crate::lockout::{self, ip_key};
+use crate::middleware::perm;
+use crate::middleware::{role_permissions, Must};
+use crate::models::{
+    Conflict, ConflictOptions, NewShare, Permission, Queries, Share, ShareInfo, ShareMode,
+    ShareQueries,
+};
+use crate::reserr::ResErr;
+use crate::storage::{blocking, Store};
+use crate::utils::random_token;
+use crate::versions;
+
+pub async fn get_shares(token: Must<perm::Share>, db: web::Data<Pool>) -> Result<HttpResponse, ResErr> {
+    let shares = (shares::execute(&db, ShareQueries::GetUserShares(token.id))
+        .map_err(|_| ResErr::BadClientData("cant get shares"))?)
+    .many();
+
+    Ok(HttpResponse::Ok().json(shares))
+}
+
+pub async fn create_share(
+    token: Must<perm::Share>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    mut share: web::Json<NewShare>,
+) -> Result<HttpResponse, ResErr> {
+    // error message dont work
+    share.validate().map_err(|err| {
+        ResErr::BadClientDataOwned(
+            err.field_errors().into_values().next().unwrap()[0]
+                .code
+                .as_ref()
+                .to_string(),
+        )
+    })?;
+
+    if share.expires.is_some_and(|v| v <= Utc::now().timestamp()) {
+        return Err(ResErr::BadClientData("share would be expired already"));
+    }
+
+    let acl = Acl::load(&db, &token)?;
+    let path = acl.resolve(&share.path, Permission::Share)?;
+
+    let meta = blocking(move || {
+        storage
+            .stat(&path)
+            .map_err(|_| ResErr::BadClientData("path dont exist"))
+    })
+    .await?;
+
+    if share.mode != ShareMode::Read && !meta.is_dir {
+        return Err(ResErr::BadClientData("uploads can go only into folder"));
+    }
+
+    // link cant do more than its owner
+    let needed = match share.mode {
+        ShareMode::Read => Permission::Read,
+        ShareMode::Upload | ShareMode::Drop => Permission::Write,
+    };
+    acl.resolve(&share.path, needed)?;
+
+    // "PDF", ".pdf" and "pdf" are same extension
+    if let Some(extensions) = &share.extensions {
+        let extensions: Vec<String> = extensions
+            .iter()
+            .map(|e| e.trim().trim_start_matches('.').to_lowercase())
+            .collect();
+
+        if extensions.iter().any(|e| e.is_empty() || e.contains(',')) {
+            return Err(ResErr::BadClientData("bad extension"));
+        }
+        share.extensions = Some(extensions);
+    }
+
+    // path is kept as user sees it, so it follows groups and root of owner
+    share.path = normalize(&share.path)?;
+    share.password = match &share.password {
+        Some(v) => Some(hash(v, DEFAULT_COST).map_err(|_| ResErr::InternalError("bad hash"))?),
+        None => None,
+    };
+
+    let share = (shares::execute(&db, ShareQueries::AddShare(token.id, random_token(22), share))
+        .map_err(|_| ResErr::InternalError("cant create share"))?)
+    .one();
+
+    Ok(HttpResponse::Ok().json(share))
+}
+
+pub async fn delete_share(
+    token: Must<perm::Share>,
+    db: web::Data<Pool>,
+    path: web::Path<(u32,)>,
+) -> Result<HttpResponse, ResErr> {
+    (shares::execute(&db, ShareQueries::DeleteShare(token.id, path.into_inner().0))
+        .map_err(|_| ResErr::BadClientData("share dont exist"))?)
+    .none();
+
+    Ok(HttpResponse::Ok().body("share revoked"))
+}
+
+// checks everything what can make link unusable and returns acl of its owner
+fn open_share(db: &Pool, req: &HttpRequest) -> Result<(Share, Acl), ResErr> {
+    let share = (shares::execute(
+        db,
+        ShareQueries::GetShareBySlug(req.match_info().query("slug").to_string()),
+    )
+    .map_err(|_| ResErr::BadClientData("share dont exist"))?)
+    .one();
+
+    if share.expires.is_some_and(|v| v <= Utc::now().timestamp()) {
+        return Err(ResErr::BadClientData("share expired"));
+    }
+
+    if let Some(pass) = &share.pass {
+        let keys = [format!("share:{}", share.slug), ip_key(req)];
+
+        lockout::check(db, &keys)?;
+
+        let given = req
+            .headers()
+            .get("password")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+
+        if !verify(given, pass).unwrap_or(false) {
+            lockout::fail(db, &keys)?;
+            return Err(ResErr::BadClientData("bad password"));
+        }
+    }
+
+    let owner = (execute(db, Queries::GetUserById(share.user_id))
+        .map_err(|_| ResErr::BadClientData("share dont exist"))?)
+    .one();
+
+    let acl = Acl::for_user(db, &owner, role_permissions(db, owner.status)?)?;
+
+    // owner could lose rights since link was created
+    acl.resolve(&share.path, Permission::Share)
+        .map_err(|_| ResErr::BadClientData("share dont exist"))?;
+
+    Ok((share, acl))
+}
+
+pub async fn get_shared(
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ResErr> {
+    let (share, acl) = open_share(&db, &req)?;
+
+    // visitor of drop link cant see anything, only limits of upload
+    if share.mode == ShareMode::Drop {
+        return Ok(HttpResponse::Ok().json(ShareInfo {
+            mode: share.mode,
+            expires: share.expires,
+            max_file_size: share.max_file_size,
+            max_files: share.max_files,
+            files: share.files,
+            extensions: share.extensions,
+        }));
+    }
+
+    let filename = format!("{}/{}", share.path, req.match_info().query("path"));
+    let path = acl.resolve(&filename, Permission::Read)?;
+
+    let files = {
+        let (storage, path) = (storage.clone(), path.clone());
+        blocking(move || match storage.stat(&path) {
+            Ok(meta) if meta.is_dir => Ok(Some(list(&acl, &storage, &filename)?)),
+            Ok(_) => Ok(None),
+            Err(_) => Err(ResErr::BadClientData("file not found")),
+        })
+        .await?
+    };
+
+    if let Some(files) = files {
+        return Ok(HttpResponse::Ok().json(files));
+    }
+
+    (shares::execute(&db, ShareQueries::CountDownload(share.id))
+        .map_err(|_| ResErr::BadClientData("download limit reached"))?)
+    .none();
+
+    let checksum = versions::known(&db, &storage, &path);
+
+    send_file(&storage, &path, &req, checksum.as_deref()).await
+}
+
+pub async fn post_shared(
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    req: HttpRequest,
+    options: web::Query<ConflictOptions>,
+    payload: Multipart,
+) -> Result<HttpResponse, ResErr> {
+    let (share, acl) = open_share(&db, &req)?;
+
+    if share.mode == ShareMode::Read {
+        return Err(ResErr::BadClientData("share is read only"));
+    }
+
+    // drop link has only one folder, without subfolders
+    let target = acl.resolve(&share.path, Permission::Write)?;
+    if share.mode == ShareMode::Drop && !req.match_info().query("path").is_empty() {
+        return Err(ResErr::BadClientData("drop link has no subfolders"));
+    }
+
+    // visitor of drop link cant see files, so cant replace them either,
+    // taken name is always renamed, so visitor cant find out which files are there
+    if share.mode == ShareMode::Drop && options.conflict == Conflict::Overwrite {
+        return Err(ResErr::BadClientData("drop link cant replace files"));
+    }
+    let conflict = match share.mode {
+        ShareMode::Drop => Conflict::Rename,
+        _ => options.conflict,
+    };
+
+    // quota of owner is used
+    upload(
+        Upload {
+            acl,
+            db: db.clone(),
+            storage,
+            folder: format!("{}/{}", share.path, req.match_info().query("path")),
+            conflict,
+            rules: ShareRules { db, share, target },
+        },
+        payload,
+    )
+    .await
+}
+
+struct ShareRules {
+    db: web::Data<Pool>,
+    share: Share,
+    target: String,
+}
+
+impl UploadRules for ShareRules {
+    fn max_size(&self) -> Option<u64> {
+        self.share.max_file_size
+    }
+
+    // visitor would learn what owner has stored
+    fn can_link(&self) -> bool {
+        false
+    }
+
+    // renamed file would tell visitor of drop link that the name was taken
+    fn shows_names(&self) -> bool {
+        self.share.mode != ShareMode::Drop
+    }
+
+    fn before(&self, path: &str) -> Result<(), ResErr> {
+        if let Some(extensions) = &self.share.extensions {
+            let extension = Path::new(path)
+                .extension()
+                .and_then(|e| e.to_str())
+                .unwrap_or("")
+                .to_lowercase();
+
+            if !extensions.contains(&extension) {
+                return Err(ResErr::BadClientData("file type is not allowed"));
+            }
+        }
+
+        let inside_target = Path::new(path).parent() == Some(Path::new(&self.target));
+        if self.share.mode == ShareMode::Drop && !inside_target {
+            return Err(ResErr::BadClientData("drop link has no subfolders"));
+        }
+
+        // file is counted before upload, so parallel uploads cant go over limit
+        (shares::execute(&self.db, ShareQueries::CountFile(self.share.id))
+            .map_err(|_| ResErr::BadClientData("file limit reached"))?)
+        .none();
+
+        Ok(())
+    }
+
+    fn failed(&self) {
+        let _ = shares::execute(&self.db, ShareQueries::UncountFile(self.share.id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+    use serde_json::Value;
+    use std::io::Read;
+
+    use crate::db::roles::UPLOADER;
+    use crate::handlers::testing::{add_user, multipart, put, setup, wait};
+    use crate::models::User;
+
+    fn new_share(path: &str, mode: ShareMode) -> NewShare {
+        NewShare {
+            path: path.to_string(),
+            password: None,
+            mode,
+            expires: None,
+            max_downloads: None,
+            max_file_size: None,
+            max_files: None,
+            extensions: None,
+        }
+    }
+
+    // straight into database, so link can be expired already
+    fn add_share(db: &Pool, owner: &User, mut share: NewShare) -> Share {
+        share.password = share.password.map(|v| hash(v, 4).unwrap());
+        let query = ShareQueries::AddShare(owner.id, random_token(22), web::Json(share));
+        (shares::execute(db, query).unwrap()).one()
+    }
+
+    fn get(uri: &str) -> test::TestRequest {
+        test::TestRequest::get().uri(uri)
+    }
+
+    macro_rules! app {
+        ($db:expr, $storage:expr) => {
+            test::init_service(
+                App::new()
+                    .app_data($db.clone())
+                    .app_data($storage.clone())
+                    .route("/s/{slug}", web::get().to(get_shared))
+                    .route("/s/{slug}", web::post().to(post_shared))
+                    .route("/s/{slug}/{path:.*}", web::get().to(get_shared))
+                    .route("/s/{slug}/{path:.*}", web::post().to(post_shared)),
+            )
+            .await
+        };
+    }
+
+    #[actix_rt::test]
+    async fn expired_link_is_refused() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        put(&storage, "/bob/a.txt", "hello");
+        let mut app = app!(db, storage);
+
+        let mut share = new_share("/a.txt", ShareMode::Read);
+        share.expires = Some(Utc::now().timestamp() - 1);
+        let expired = add_share(&db, &bob, share.clone());
+        share.expires = Some(Utc::now().timestamp() + 60);
+        let valid = add_share(&db, &bob, share);
+
+        let res = test::call_service(&mut app, get(&format!("/s/{}", expired.slug)).to_request()).await;
+        assert_eq!(res.status(), 400);
+        let res = test::call_service(&mut app, get(&format!("/s/{}", valid.slug)).to_request()).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(test::read_body(res).await, "hello");
+    }
+
+    #[actix_rt::test]
+    async fn wrong_passwords_lock_link() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        put(&storage, "/bob/a.txt", "hello");
+        let mut app = app!(db, storage);
+
+        let mut share = new_share("/a.txt", ShareMode::Read);
+        share.password = Some("secret".to_string());
+        let share = add_share(&db, &bob, share);
+        let keys = [format!("share:{}", share.slug), "ip:unknown".to_string()];
+        let uri = format!("/s/{}", share.slug);
+        let open = |password: &str| get(&uri).header("password", password).to_request();
+
+        assert_eq!(test::call_service(&mut app, get(&uri).to_request()).await.status(), 400);
+        assert_eq!(test::call_service(&mut app, open("secret")).await.status(), 429);
+        wait(&db, &keys);
+        assert_eq!(test::call_service(&mut app, open("secret")).await.status(), 200);
+
+        for _ in 0..2 {
+            assert_eq!(test::call_service(&mut app, open("wrong")).await.status(), 400);
+            wait(&db, &keys);
+        }
+
+        // third failure locks link even for who knows password
+        let res = test::call_service(&mut app, open("secret")).await;
+        assert_eq!(res.status(), 429);
+        assert!(String::from_utf8_lossy(&test::read_body(res).await).contains("too many failed logins"));
+    }
+
+    #[actix_rt::test]
+    async fn downloads_stop_at_limit() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        storage.mkdir("/bob/docs").unwrap();
+        put(&storage, "/bob/docs/a.txt", "hello");
+        let mut app = app!(db, storage);
+
+        let mut share = new_share("/docs", ShareMode::Read);
+        share.max_downloads = Some(2);
+        let share = add_share(&db, &bob, share);
+
+        // listing of folder isnt download
+        for _ in 0..3 {
+            let res = test::call_service(&mut app, get(&format!("/s/{}", share.slug)).to_request()).await;
+            assert_eq!(res.status(), 200);
+        }
+
+        for status in [200, 200, 400].iter() {
+            let res = test::call_service(&mut app, get(&format!("/s/{}/a.txt", share.slug)).to_request()).await;
+            assert_eq!(res.status(), *status);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn path_cant_leave_shared_folder() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        storage.mkdir("/bob/docs").unwrap();
+        put(&storage, "/bob/docs/a.txt", "hello");
+        put(&storage, "/bob/secret.txt", "secret");
+        let mut app = app!(db, storage);
+
+        let share = add_share(&db, &bob, new_share("/docs", ShareMode::Upload));
+
+        let escapes = ["../secret.txt", "a.txt/../../secret.txt", "%2E%2E/secret.txt", "..%2Fsecret.txt"];
+        for path in escapes.iter() {
+            let uri = format!("/s/{}/{}", share.slug, path);
+
+            let res = test::call_service(&mut app, get(&uri).to_request()).await;
+            assert_eq!(res.status(), 400, "{}", path);
+
+            let req = multipart(test::TestRequest::post().uri(&uri), &[("b.txt", "b")]);
+            assert_eq!(test::call_service(&mut app, req.to_request()).await.status(), 400, "{}", path);
+        }
+        assert!(!storage.exists("/b.txt"));
+        assert!(!storage.exists("/bob/b.txt"));
+
+        let res = test::call_service(&mut app, get(&format!("/s/{}", share.slug)).to_request()).await;
+        let files: Value = test::read_body_json(res).await;
+        assert!(!files.to_string().contains("secret"));
+    }
+
+    fn drop_into(share: &Share, files: &[(&str, &str)]) -> test::TestRequest {
+        multipart(test::TestRequest::post().uri(&format!("/s/{}", share.slug)), files)
+    }
+
+    #[actix_rt::test]
+    async fn drop_link_keeps_its_limits() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        storage.mkdir("/bob/drop").unwrap();
+        let mut app = app!(db, storage);
+
+        let mut share = new_share("/drop", ShareMode::Drop);
+        share.max_file_size = Some(5);
+        share.max_files = Some(2);
+        share.extensions = Some(vec!["pdf".to_string()]);
+        let share = add_share(&db, &bob, share);
+
+        let res = test::call_service(&mut app, drop_into(&share, &[("big.pdf", "too big")]).to_request()).await;
+        assert_eq!(res.status(), 400);
+        let res = test::call_service(&mut app, drop_into(&share, &[("a.txt", "a")]).to_request()).await;
+        assert_eq!(res.status(), 400);
+        assert!(storage.list("/bob/drop").unwrap().is_empty());
+
+        // refused files dont count into limit
+        let req = drop_into(&share, &[("a.PDF", "a"), ("b.pdf", "b")]);
+        assert_eq!(test::call_service(&mut app, req.to_request()).await.status(), 200);
+        let res = test::call_service(&mut app, drop_into(&share, &[("c.pdf", "c")]).to_request()).await;
+        assert_eq!(res.status(), 400);
+        assert!(!storage.exists("/bob/drop/c.pdf"));
+    }
+
+    #[actix_rt::test]
+    async fn drop_link_hides_what_is_in_folder() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        storage.mkdir("/bob/drop").unwrap();
+        put(&storage, "/bob/drop/report.pdf", "old");
+        let mut app = app!(db, storage);
+
+        let share = add_share(&db, &bob, new_share("/drop", ShareMode::Drop));
+
+        for uri in [format!("/s/{}", share.slug), format!("/s/{}/report.pdf", share.slug)].iter() {
+            let res = test::call_service(&mut app, get(uri).to_request()).await;
+            assert_eq!(res.status(), 200);
+            let info: Value = test::read_body_json(res).await;
+            assert_eq!(info["mode"], "drop");
+            assert!(!info.to_string().contains("report"));
+        }
+
+        let req = multipart(
+            test::TestRequest::post().uri(&format!("/s/{}?conflict=overwrite", share.slug)),
+            &[("report.pdf", "new")],
+        );
+        assert_eq!(test::call_service(&mut app, req.to_request()).await.status(), 400);
+
+        // taken name is renamed, but visitor gets back name they sent
+        let res = test::call_service(&mut app, drop_into(&share, &[("report.pdf", "new")]).to_request()).await;
+        assert_eq!(res.status(), 200);
+        let saved: Value = test::read_body_json(res).await;
+        assert_eq!(saved["files"], serde_json::json!(["report.pdf"]));
+
+        let content = |path: &str| {
+            let mut content = String::new();
+            storage.read(path).unwrap().read_to_string(&mut content).unwrap();
+            content
+        };
+        assert_eq!(content("/bob/drop/report.pdf"), "old");
+        assert_eq!(content("/bob/drop/report (1).pdf"), "new");
+    }
+}
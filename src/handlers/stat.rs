@@ -0,0 +1,135 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::time::SystemTime;
+
+use crate::acl::{covers, normalize, Acl};
+use crate::db::{execute, files, groups, Pool};
+use crate::middleware::perm::Read;
+use crate::middleware::Must;
+use crate::models::{AclKind, EntryType, FileQueries, GroupQueries, Owner, Permission, Queries, Stat};
+use crate::reserr::ResErr;
+use crate::sniff;
+use crate::storage::{self, blocking, Meta, Store};
+use crate::versions;
+
+// homes of all users and groups, entry belongs to the one with longest path covering it
+pub struct Owners {
+    homes: Vec<(String, Owner)>,
+}
+
+impl Owners {
+    pub fn load(db: &Pool) -> Result<Owners, ResErr> {
+        let users = (execute(db, Queries::GetAllUsers).map_err(|_| ResErr::InternalError("cant get users"))?)
+            .many();
+        let groups = (groups::execute(db, GroupQueries::GetAllGroups)
+            .map_err(|_| ResErr::InternalError("cant get groups"))?)
+        .many();
+
+        let mut homes = Vec::new();
+        for user in users {
+            let owner = Owner {
+                kind: AclKind::User,
+                id: user.id,
+                name: user.name,
+            };
+            homes.push((normalize(&user.path)?, owner));
+        }
+        for group in groups {
+            let owner = Owner {
+                kind: AclKind::Group,
+                id: group.id,
+                name: group.name,
+            };
+            homes.push((normalize(&group.path)?, owner));
+        }
+
+        Ok(Owners { homes })
+    }
+
+    // users with same home, like admins in root, are decided by lowest id
+    fn of(&self, path: &str) -> Option<Owner> {
+        self.homes
+            .iter()
+            .filter(|(home, _)| covers(home, path))
+            .min_by_key(|(home, owner)| (std::cmp::Reverse(home.len()), owner.kind != AclKind::User, owner.id))
+            .map(|(_, owner)| owner.clone())
+    }
+}
+
+pub fn rfc3339(time: Option<SystemTime>) -> Option<String> {
+    time.map(|t| DateTime::<Utc>::from(t).to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+// everything needed to describe entries for one user, loaded once per request
+pub struct Stats<'a> {
+    pub acl: &'a Acl,
+    pub db: &'a Pool,
+    pub storage: &'a Store,
+    pub owners: Owners,
+}
+
+impl Stats<'_> {
+    // only head of single file is read, listed file has type from its name,
+    // checksum is there only when it is known, reading whole files would make stat as slow as download
+    pub fn of(&self, filename: &str, path: &str, meta: &Meta, single: bool) -> Result<Stat, ResErr> {
+        let (kind, mime) = match (meta.is_dir, single) {
+            (true, _) => (EntryType::Folder, None),
+            (false, true) => (EntryType::File, Some(sniff::mime(self.storage, path))),
+            (false, false) => (EntryType::File, Some(sniff::guess(path))),
+        };
+        let checksum = match meta.is_dir {
+            true => None,
+            false => files::execute(self.db, FileQueries::GetFile(path.to_string()))
+                .ok()
+                .map(|v| v.one().checksum),
+        };
+
+        Ok(Stat {
+            name: storage::name(filename),
+            path: filename.to_string(),
+            kind,
+            size: meta.size,
+            created: rfc3339(meta.created),
+            modified: rfc3339(meta.modified),
+            mime,
+            checksum,
+            owner: self.owners.of(path),
+            permissions: self.acl.allowed(path),
+        })
+    }
+}
+
+pub async fn get_stat(
+    token: Must<Read>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ResErr> {
+    let acl = Acl::load(&db, &token)?;
+    let filename = normalize(req.match_info().query("filename"))?;
+    let path = acl.resolve(&filename, Permission::Read)?;
+
+    let (pool, store, file) = (db.clone(), storage.clone(), path.clone());
+    let mut stat = blocking(move || {
+        let meta = store
+            .stat(&file)
+            .map_err(|_| ResErr::BadClientData("path dont exist"))?;
+
+        let stats = Stats {
+            acl: &acl,
+            db: &pool,
+            storage: &store,
+            owners: Owners::load(&pool)?,
+        };
+
+        stats.of(&filename, &file, &meta, true)
+    })
+    .await?;
+
+    // file from before checksums were kept gets it counted in background, next stat has it
+    if stat.kind == EntryType::File && stat.checksum.is_none() {
+        stat.checksum = versions::known(&db, &storage, &path);
+    }
+
+    Ok(HttpResponse::Ok().json(stat))
+}
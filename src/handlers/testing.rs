@@ -0,0 +1,104 @@
+use actix_web::http::header;
+use actix_web::{test, web};
+use bcrypt::hash;
+use std::env;
+use std::io::Write;
+
+use crate::db::{attempts, create_tables, execute, test_pool, Pool};
+use crate::jwt::create_session;
+use crate::lockout;
+use crate::models::{AttemptQueries, Queries, User};
+use crate::storage::memory::MemoryStorage;
+use crate::storage::Store;
+
+// handlers are tested through http, on memory storage and with own database for every test
+
+pub const PASSWORD: &str = "Passw0rd1";
+
+// same values in every test, they run at once and share environment,
+// unit tests of lockout and extract set the same ones
+pub fn setup() -> (web::Data<Pool>, web::Data<Store>) {
+    let uploads = env::temp_dir().join("cloud-test-uploads");
+    let vars = [
+        ("JWT_SECRET", "test"),
+        ("ACCESS_TOKEN_MINUTES", "15"),
+        ("REFRESH_TOKEN_EXPIRATION", "720"),
+        ("EMAIL_VERIFICATION", "false"),
+        ("FULLTEXT", "false"),
+        ("LOGIN_BACKOFF", "1"),
+        ("LOGIN_LOCKOUT", "15"),
+        ("LOGIN_MAX_ATTEMPTS", "3"),
+        ("LOGIN_MAX_IP_ATTEMPTS", "10"),
+        ("TUS_DIR", uploads.to_str().unwrap()),
+        ("TUS_EXPIRATION", "24"),
+        ("TRASH_RETENTION", "30"),
+        ("VERSIONS_KEEP", "10"),
+        ("VERSIONS_DAILY", "30"),
+        ("EXTRACT_MAX_ENTRIES", "10"),
+        ("EXTRACT_MAX_RATIO", "100"),
+    ];
+    for (key, value) in vars.iter() {
+        env::set_var(key, value);
+    }
+
+    let db = test_pool();
+    create_tables(&db);
+
+    let storage: Store = Box::<MemoryStorage>::default();
+    (web::Data::new(db), web::Data::new(storage))
+}
+
+// user with home folder named after them, size of home is in MB,
+// lowest cost of hash keeps logins in tests fast
+pub fn add_user(db: &Pool, storage: &Store, name: &str, status: u8, size: u32) -> User {
+    let user = User {
+        id: 0,
+        name: name.to_string(),
+        email: format!("{}@test.com", name),
+        pass: hash(PASSWORD, 4).unwrap(),
+        size,
+        path: format!("/{}", name),
+        status,
+        verified: None,
+    };
+    storage.mkdir(&user.path).unwrap();
+
+    (execute(db, Queries::AddUser(web::Json(user))).unwrap()).none();
+    (execute(db, Queries::GetUserByEmail(format!("{}@test.com", name))).unwrap()).one()
+}
+
+// file put straight into storage, as if it was there before database knew it
+pub fn put(storage: &Store, path: &str, content: &str) {
+    let mut f = storage.write(path).unwrap();
+    f.write_all(content.as_bytes()).unwrap();
+    f.finish().unwrap();
+}
+
+// backoff after failure is over, failures themselves are still counted
+pub fn wait(db: &Pool, keys: &[String]) {
+    for key in keys {
+        let mut attempt = lockout::get_attempt(db, key);
+        attempt.last_failure -= 60;
+        (attempts::execute(db, AttemptQueries::SaveAttempt(attempt)).unwrap()).none();
+    }
+}
+
+pub fn token(db: &Pool, user: &User) -> String {
+    create_session(db, user.id).unwrap().token
+}
+
+// form with files as upload from browser sends it
+pub fn multipart(req: test::TestRequest, files: &[(&str, &str)]) -> test::TestRequest {
+    let mut body = String::new();
+    for (filename, content) in files {
+        body += &format!(
+            "--b\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\r\n{}\r\n",
+            filename, content
+        );
+    }
+    body += "--b--\r\n";
+
+    req.header(header::CONTENT_TYPE, "multipart/form-data; boundary=b")
+        .header(header::CONTENT_LENGTH, body.len())
+        .set_payload(body)
+}
@@ -0,0 +1,268 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+
+use crate::acl::{covers, Acl};
+use crate::db::{trash, Pool};
+use crate::handlers::file::free_name;
+use crate::middleware::perm;
+use crate::middleware::Must;
+use crate::models::{Conflict, ConflictOptions, Permission, Saved, TrashItem, TrashQueries};
+use crate::quota;
+use crate::reserr::ResErr;
+use crate::storage::{self, blocking, Store};
+use crate::trash::{place, purge, purge_all, put};
+use crate::versions;
+
+// item of somebody else looks like missing one
+fn find(db: &Pool, user_id: u32, req: &HttpRequest) -> Result<TrashItem, ResErr> {
+    let item = (trash::execute(db, TrashQueries::GetItem(req.match_info().query("id").to_string()))
+        .map_err(|_| ResErr::BadClientData("item not found"))?)
+    .one();
+
+    if item.user_id != user_id {
+        return Err(ResErr::BadClientData("item not found"));
+    }
+    Ok(item)
+}
+
+pub async fn get_trash(token: Must<perm::Read>, db: web::Data<Pool>) -> Result<HttpResponse, ResErr> {
+    let items = (trash::execute(&db, TrashQueries::GetUserItems(token.id))
+        .map_err(|_| ResErr::InternalError("cant get trash"))?)
+    .many();
+
+    Ok(HttpResponse::Ok().json(items))
+}
+
+pub async fn restore_item(
+    token: Must<perm::Write>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    req: HttpRequest,
+    options: web::Query<ConflictOptions>,
+) -> Result<HttpResponse, ResErr> {
+    let item = find(&db, token.id, &req)?;
+    let acl = Acl::load(&db, &token)?;
+    let (user_id, conflict) = (token.id, options.conflict);
+
+    let target = blocking(move || {
+        // root of user could change since deletion, then item goes into other home
+        let home = acl.home(&item.path)?;
+        if !covers(&home.path, &item.target) && item.size > quota::free(&db, &storage, &home, None)? {
+            return Err(ResErr::BadClientData("you dont have size"));
+        }
+
+        // folder where item was could be deleted meanwhile, it is created again
+        let (folder, name) = item.path.rsplit_once('/').unwrap_or(("", &item.path));
+        let parent = acl.resolve(folder, Permission::Write)?;
+        if !storage.exists(&parent) {
+            storage
+                .mkdir(&parent)
+                .map_err(|_| ResErr::BadClientData("cant create folder"))?;
+        }
+
+        let mut target = acl.resolve(&item.path, Permission::Write)?;
+
+        if storage.exists(&target) {
+            match conflict {
+                Conflict::Fail => return Err(ResErr::BadClientData("file already exists")),
+                Conflict::Rename => target = free_name(&acl, &storage, folder, name)?,
+                // what is in the way goes into trash instead, so nothing is lost
+                Conflict::Overwrite => {
+                    put(&db, &storage, user_id, &item.path, &target)?;
+                }
+            }
+        }
+
+        storage
+            .rename(&place(&item), &target)
+            .map_err(|_| ResErr::InternalError("cant restore item"))?;
+
+        versions::moved(&db, &storage, &place(&item), &target)?;
+
+        quota::add(&db, &item.target, -(item.size as i64))?;
+        quota::add(&db, &target, item.size as i64)?;
+
+        (trash::execute(&db, TrashQueries::DeleteItem(item.id))
+            .map_err(|_| ResErr::InternalError("cant delete from trash"))?)
+        .none();
+
+        Ok(target)
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().json(Saved {
+        files: vec![storage::name(&target)],
+    }))
+}
+
+pub async fn delete_item(
+    token: Must<perm::Delete>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ResErr> {
+    let item = find(&db, token.id, &req)?;
+
+    blocking(move || purge(&db, &storage, &item)).await?;
+
+    Ok(HttpResponse::Ok().body("deleted"))
+}
+
+pub async fn empty_trash(
+    token: Must<perm::Delete>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+) -> Result<HttpResponse, ResErr> {
+    let items = (trash::execute(&db, TrashQueries::GetUserItems(token.id))
+        .map_err(|_| ResErr::InternalError("cant get trash"))?)
+    .many();
+
+    blocking(move || purge_all(&db, &storage, items)).await?;
+
+    Ok(HttpResponse::Ok().body("trash emptied"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+    use serde_json::Value;
+    use std::io::Read;
+
+    use crate::acl::Home;
+    use crate::db::roles::UPLOADER;
+    use crate::handlers::file::{delete_file, post_file};
+    use crate::handlers::testing::{add_user, multipart, setup, token};
+
+    macro_rules! app {
+        ($db:expr, $storage:expr) => {
+            test::init_service(
+                App::new()
+                    .app_data($db.clone())
+                    .app_data($storage.clone())
+                    .route("/file/{filename:.*}", web::post().to(post_file))
+                    .route("/file/{filename:.*}", web::delete().to(delete_file))
+                    .route("/trash", web::get().to(get_trash))
+                    .route("/trash/{id}", web::post().to(restore_item))
+                    .route("/trash/{id}", web::delete().to(delete_item)),
+            )
+            .await
+        };
+    }
+
+    macro_rules! status {
+        ($app:expr, $req:expr) => {
+            test::call_service(&mut $app, $req.to_request()).await.status()
+        };
+    }
+
+    fn upload(token: &str, folder: &str, filename: &str, content: &str) -> test::TestRequest {
+        let req = test::TestRequest::post().uri(&format!("/file/{}", folder)).header("token", token);
+        multipart(req, &[(filename, content)])
+    }
+
+    fn delete(token: &str, filename: &str) -> test::TestRequest {
+        test::TestRequest::delete().uri(&format!("/file/{}", filename)).header("token", token)
+    }
+
+    fn restore(token: &str, id: &str, conflict: &str) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri(&format!("/trash/{}?conflict={}", id, conflict))
+            .header("token", token)
+    }
+
+    fn content(storage: &Store, path: &str) -> String {
+        let mut content = String::new();
+        storage.read(path).unwrap().read_to_string(&mut content).unwrap();
+        content
+    }
+
+    // tests keep at most one item in trash
+    fn only_item(db: &Pool, user_id: u32) -> TrashItem {
+        let mut items = (trash::execute(db, TrashQueries::GetUserItems(user_id)).unwrap()).many();
+        assert_eq!(items.len(), 1);
+        items.remove(0)
+    }
+
+    #[actix_rt::test]
+    async fn restore_follows_conflict_option() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        let token = token(&db, &bob);
+        let mut app = app!(db, storage);
+
+        assert_eq!(status!(app, upload(&token, "", "a.txt", "one")), 200);
+        assert_eq!(status!(app, delete(&token, "a.txt")), 200);
+        assert_eq!(status!(app, upload(&token, "", "a.txt", "two")), 200);
+        let one = only_item(&db, bob.id);
+
+        let res = test::call_service(&mut app, restore(&token, &one.id, "fail").to_request()).await;
+        assert_eq!(res.status(), 400);
+        assert_eq!(content(&storage, "/bob/a.txt"), "two");
+
+        let res = test::call_service(&mut app, restore(&token, &one.id, "rename").to_request()).await;
+        assert_eq!(res.status(), 200);
+        let saved: Value = test::read_body_json(res).await;
+        assert_eq!(saved["files"], serde_json::json!(["a (1).txt"]));
+        assert_eq!(content(&storage, "/bob/a (1).txt"), "one");
+
+        assert_eq!(status!(app, delete(&token, "a.txt")), 200);
+        assert_eq!(status!(app, upload(&token, "", "a.txt", "three")), 200);
+        let two = only_item(&db, bob.id);
+
+        // replaced file goes into trash, so nothing is lost
+        let res = test::call_service(&mut app, restore(&token, &two.id, "overwrite").to_request()).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(content(&storage, "/bob/a.txt"), "two");
+
+        let three = only_item(&db, bob.id);
+        assert_eq!(three.path, "/a.txt");
+        let res = test::call_service(&mut app, restore(&token, &three.id, "rename").to_request()).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(content(&storage, "/bob/a (2).txt"), "three");
+    }
+
+    #[actix_rt::test]
+    async fn restore_creates_missing_folders() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        let token = token(&db, &bob);
+        storage.mkdir("/bob/docs").unwrap();
+        storage.mkdir("/bob/docs/2024").unwrap();
+        let mut app = app!(db, storage);
+
+        let req = upload(&token, "docs/2024", "a.txt", "hello");
+        assert_eq!(status!(app, req), 200);
+        assert_eq!(status!(app, delete(&token, "docs/2024/a.txt")), 200);
+        storage.delete("/bob/docs").unwrap();
+
+        let item = only_item(&db, bob.id);
+        let res = test::call_service(&mut app, restore(&token, &item.id, "fail").to_request()).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(content(&storage, "/bob/docs/2024/a.txt"), "hello");
+    }
+
+    #[actix_rt::test]
+    async fn quota_is_given_back_only_by_purge() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        let token = token(&db, &bob);
+        let home = Home {
+            path: "/bob".to_string(),
+            size: 1,
+        };
+        let mut app = app!(db, storage);
+
+        let half = "a".repeat(600 * 1000);
+        assert_eq!(status!(app, upload(&token, "", "a.txt", &half)), 200);
+        let free = quota::free(&db, &storage, &home, None).unwrap();
+
+        assert_eq!(status!(app, delete(&token, "a.txt")), 200);
+        assert_eq!(quota::free(&db, &storage, &home, None).unwrap(), free);
+
+        let item = only_item(&db, bob.id);
+        let req = test::TestRequest::delete().uri(&format!("/trash/{}", item.id));
+        assert_eq!(status!(app, req.header("token", token.as_str())), 200);
+        assert_eq!(quota::free(&db, &storage, &home, None).unwrap(), free + 600 * 1000);
+        assert!(!storage.exists(&place(&item)));
+    }
+}
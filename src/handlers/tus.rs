@@ -0,0 +1,505 @@
+use actix_web::dev::HttpResponseBuilder;
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, UNIX_EPOCH};
+use std::{env, io};
+
+use crate::acl::{normalize, Acl};
+use crate::db::{uploads, Pool};
+use crate::middleware::perm;
+use crate::middleware::Must;
+use crate::models::{Permission, Upload, UploadQueries};
+use crate::quota;
+use crate::reserr::ResErr;
+use crate::storage::{blocking, Store};
+use crate::utils::random_token;
+use crate::versions;
+
+// tus 1.0, https://tus.io/protocols/resumable-upload
+const VERSION: &str = "1.0.0";
+const EXTENSIONS: &str = "creation,termination,expiration";
+
+// ids of uploads which are being written by some PATCH right now,
+// server runs as one process, so it is enough to keep them in memory
+#[derive(Default)]
+pub struct Patching(Mutex<HashSet<String>>);
+
+// upload is released when request ends, also when client disconnects in the middle
+struct Claim {
+    patching: web::Data<Patching>,
+    id: String,
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        self.patching.0.lock().unwrap().remove(&self.id);
+    }
+}
+
+// None when other request writes into upload already
+fn claim(patching: &web::Data<Patching>, id: &str) -> Option<Claim> {
+    if !patching.0.lock().unwrap().insert(id.to_string()) {
+        return None;
+    }
+    Some(Claim {
+        patching: patching.clone(),
+        id: id.to_string(),
+    })
+}
+
+fn tus(mut res: HttpResponseBuilder) -> HttpResponseBuilder {
+    res.set_header("Tus-Resumable", VERSION);
+    res
+}
+
+fn staging(id: &str) -> PathBuf {
+    PathBuf::from(env::var("TUS_DIR").unwrap()).join(id)
+}
+
+fn expires_header(upload: &Upload) -> String {
+    httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(upload.expires as u64))
+}
+
+fn number(req: &HttpRequest, name: &str) -> Option<u64> {
+    req.headers().get(name)?.to_str().ok()?.parse().ok()
+}
+
+// "key base64,key base64"
+fn metadata(req: &HttpRequest, key: &str) -> Option<String> {
+    req.headers()
+        .get("Upload-Metadata")?
+        .to_str()
+        .ok()?
+        .split(',')
+        .filter_map(|pair| pair.trim().split_once(' '))
+        .find(|(k, _)| *k == key)
+        .and_then(|(_, v)| base64::decode(v).ok())
+        .and_then(|v| String::from_utf8(v).ok())
+}
+
+// client speaking other version of protocol gets 412,
+// responses which HEAD can get have no body, http2 doesnt allow it
+fn wrong_version(req: &HttpRequest) -> Option<HttpResponse> {
+    match req.headers().get("Tus-Resumable").and_then(|v| v.to_str().ok()) {
+        Some(VERSION) => None,
+        _ => Some(
+            tus(HttpResponse::PreconditionFailed())
+                .set_header("Tus-Version", VERSION)
+                .finish(),
+        ),
+    }
+}
+
+fn remove(db: &Pool, upload: &Upload) -> Result<(), ResErr> {
+    let _ = fs::remove_file(staging(&upload.id));
+
+    (uploads::execute(db, UploadQueries::DeleteUpload(upload.id.clone()))
+        .map_err(|_| ResErr::InternalError("cant delete upload"))?)
+    .none();
+    Ok(())
+}
+
+// staging files of uploads are deleted together with them
+pub fn remove_all(db: &Pool, uploads: Vec<Upload>) -> Result<(), ResErr> {
+    for upload in uploads.iter() {
+        remove(db, upload)?;
+    }
+    Ok(())
+}
+
+// upload of somebody else looks like missing one, expired is deleted
+fn find(db: &Pool, user_id: u32, req: &HttpRequest) -> Result<Result<Upload, HttpResponse>, ResErr> {
+    let upload = match uploads::execute(db, UploadQueries::GetUpload(req.match_info().query("id").to_string())) {
+        Ok(v) => v.one(),
+        Err(_) => return Ok(Err(tus(HttpResponse::NotFound()).finish())),
+    };
+
+    if upload.user_id != user_id {
+        return Ok(Err(tus(HttpResponse::NotFound()).finish()));
+    }
+
+    if upload.expires <= Utc::now().timestamp() {
+        remove(db, &upload)?;
+        return Ok(Err(tus(HttpResponse::Gone()).finish()));
+    }
+
+    Ok(Ok(upload))
+}
+
+pub async fn options_upload() -> HttpResponse {
+    tus(HttpResponse::NoContent())
+        .set_header("Tus-Version", VERSION)
+        .set_header("Tus-Extension", EXTENSIONS)
+        .finish()
+}
+
+pub async fn create_upload(
+    token: Must<perm::Write>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ResErr> {
+    if let Some(res) = wrong_version(&req) {
+        return Ok(res);
+    }
+
+    // nothing else cleans staging area
+    let expired = (uploads::execute(&db, UploadQueries::GetExpiredUploads(Utc::now().timestamp()))
+        .map_err(|_| ResErr::InternalError("cant get uploads"))?)
+    .many();
+    remove_all(&db, expired)?;
+
+    let length = match number(&req, "Upload-Length") {
+        Some(v) => v,
+        None => return Ok(tus(HttpResponse::BadRequest()).body("Upload-Length is missing")),
+    };
+
+    let filename = metadata(&req, "filename").ok_or(ResErr::BadClientData("filename is missing"))?;
+    let folder = metadata(&req, "folder").unwrap_or_default();
+
+    let acl = Acl::load(&db, &token)?;
+    let parent = acl.resolve(&folder, Permission::Write)?;
+
+    // name of file comes from client too, so it is checked as well
+    let path = normalize(&format!("{}/{}", folder, filename))?;
+    let target = acl.resolve_new(&path, Permission::Write)?;
+    let home = acl.home(&path)?;
+
+    // bytes waiting in staging area count into quota too, so parallel uploads cant go over it
+    let free = {
+        let (db, storage) = (db.clone(), storage.clone());
+        blocking(move || {
+            if !storage.exists(&parent) {
+                return Err(ResErr::BadClientData("path dont exist"));
+            }
+            quota::free(&db, &storage, &home, None)
+        })
+        .await?
+    };
+    if length > free {
+        return Ok(tus(HttpResponse::PayloadTooLarge()).body("you dont have size"));
+    }
+
+    let now = Utc::now().timestamp();
+    let upload = Upload {
+        id: random_token(32),
+        user_id: token.id,
+        path,
+        target,
+        length,
+        offset: 0,
+        created: now,
+        expires: now + env::var("TUS_EXPIRATION").unwrap().parse::<i64>().unwrap() * 60 * 60,
+    };
+
+    fs::create_dir_all(env::var("TUS_DIR").unwrap())
+        .and_then(|_| fs::File::create(staging(&upload.id)))
+        .map_err(|_| ResErr::InternalError("cant create upload"))?;
+
+    (uploads::execute(&db, UploadQueries::AddUpload(upload.clone()))
+        .map_err(|_| ResErr::InternalError("cant create upload"))?)
+    .none();
+
+    // empty file has nothing to wait for
+    if length == 0 {
+        let uploaded = upload.clone();
+        blocking(move || finish(&db, &storage, &uploaded.target, &uploaded)).await?;
+    }
+
+    Ok(tus(HttpResponse::Created())
+        .set_header(header::LOCATION, format!("/uploads/{}", upload.id))
+        .set_header("Upload-Expires", expires_header(&upload))
+        .finish())
+}
+
+pub async fn head_upload(
+    token: Must<perm::Write>,
+    db: web::Data<Pool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ResErr> {
+    if let Some(res) = wrong_version(&req) {
+        return Ok(res);
+    }
+
+    let upload = match find(&db, token.id, &req)? {
+        Ok(v) => v,
+        Err(res) => return Ok(res),
+    };
+
+    Ok(tus(HttpResponse::Ok())
+        .set_header("Upload-Offset", upload.offset.to_string())
+        .set_header("Upload-Length", upload.length.to_string())
+        .set_header("Upload-Expires", expires_header(&upload))
+        .set_header(header::CACHE_CONTROL, "no-store")
+        .finish())
+}
+
+pub async fn patch_upload(
+    token: Must<perm::Write>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    patching: web::Data<Patching>,
+    req: HttpRequest,
+    mut body: web::Payload,
+) -> Result<HttpResponse, ResErr> {
+    if let Some(res) = wrong_version(&req) {
+        return Ok(res);
+    }
+
+    // upload is claimed before its offset is read, staging file is truncated to it,
+    // so parallel request cant cut off what other one has just written
+    let _claim = match claim(&patching, req.match_info().query("id")) {
+        Some(v) => v,
+        None => return Ok(tus(HttpResponse::Conflict()).body("upload is being written by other request")),
+    };
+
+    let upload = match find(&db, token.id, &req)? {
+        Ok(v) => v,
+        Err(res) => return Ok(res),
+    };
+
+    if req.headers().get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok())
+        != Some("application/offset+octet-stream")
+    {
+        return Ok(tus(HttpResponse::UnsupportedMediaType()).body("bad content type"));
+    }
+
+    if number(&req, "Upload-Offset") != Some(upload.offset) {
+        return Ok(tus(HttpResponse::Conflict()).body("offset dont match"));
+    }
+
+    if number(&req, "Content-Length").is_some_and(|len| upload.offset + len > upload.length) {
+        return Ok(tus(HttpResponse::PayloadTooLarge()).body("upload is longer than its length"));
+    }
+
+    // rights and quota could change since upload was created
+    let acl = Acl::load(&db, &token)?;
+    let target = acl.resolve(&upload.path, Permission::Write)?;
+
+    let home = acl.home(&upload.path)?;
+    let free = {
+        let (db, storage, id) = (db.clone(), storage.clone(), upload.id.clone());
+        blocking(move || quota::free(&db, &storage, &home, Some(&id))).await?
+    };
+    if upload.length - upload.offset > free {
+        return Ok(tus(HttpResponse::PayloadTooLarge()).body("you dont have size"));
+    }
+
+    // bytes after offset are from request which failed, they are thrown away
+    let path = staging(&upload.id);
+    let start = upload.offset;
+    let mut f = web::block(move || {
+        let mut f = OpenOptions::new().write(true).open(path)?;
+        f.set_len(start)?;
+        f.seek(SeekFrom::Start(start))?;
+        Ok::<_, io::Error>(f)
+    })
+    .await
+    .map_err(|_| ResErr::InternalError("cant open upload"))?;
+
+    let mut offset = upload.offset;
+    let mut failed = None;
+
+    while let Some(chunk) = body.next().await {
+        let data = match chunk {
+            Ok(v) => v,
+            Err(_) => {
+                failed = Some(ResErr::BadClientData("upload was interrupted"));
+                break;
+            }
+        };
+
+        if offset + data.len() as u64 > upload.length {
+            failed = Some(ResErr::BadClientData("upload is longer than its length"));
+            break;
+        }
+
+        let len = data.len() as u64;
+
+        // filesystem operations are blocking, we have to use threadpool
+        f = web::block(move || f.write_all(&data).map(|_| f))
+            .await
+            .map_err(|_| ResErr::InternalError("cant write upload"))?;
+        offset += len;
+    }
+
+    // everything what came is kept, so client can continue after failure
+    if uploads::execute(&db, UploadQueries::SetOffset(upload.id.clone(), upload.offset, offset)).is_err() {
+        return Ok(tus(HttpResponse::Conflict()).body("upload was changed by other request"));
+    }
+
+    if let Some(err) = failed {
+        return Err(err);
+    }
+
+    if offset == upload.length {
+        let uploaded = upload.clone();
+        blocking(move || finish(&db, &storage, &target, &uploaded)).await?;
+    }
+
+    Ok(tus(HttpResponse::NoContent())
+        .set_header("Upload-Offset", offset.to_string())
+        .set_header("Upload-Expires", expires_header(&upload))
+        .finish())
+}
+
+// whole file is moved into place at once, nobody sees it half written,
+// target is path of upload resolved by acl just before
+fn finish(db: &Pool, storage: &Store, target: &str, upload: &Upload) -> Result<(), ResErr> {
+    let folder = target.rsplit_once('/').map(|(f, _)| f).unwrap_or("");
+    if !storage.exists(&normalize(folder)?) {
+        return Err(ResErr::BadClientData("path dont exist"));
+    }
+
+    let checksum = fs::File::open(staging(&upload.id))
+        .and_then(|mut file| {
+            let mut hasher = Sha256::new();
+            io::copy(&mut file, &mut hasher)?;
+            Ok(hex::encode(hasher.finalize()))
+        })
+        .map_err(|_| ResErr::InternalError("cant read upload"))?;
+
+    // replaced file is kept in history of target
+    let version = versions::archive(db, storage, target)?;
+
+    if storage.import(&staging(&upload.id), target).is_err() {
+        if let Some(version) = &version {
+            versions::unarchive(db, storage, version);
+        }
+        return Err(ResErr::InternalError("cant move upload into place"));
+    }
+
+    quota::add(db, target, upload.length as i64)?;
+    versions::stored(db, target, Some(upload.user_id), checksum)?;
+
+    if version.is_some() {
+        versions::prune(db, storage, target)?;
+    }
+
+    (uploads::execute(db, UploadQueries::DeleteUpload(upload.id.clone()))
+        .map_err(|_| ResErr::InternalError("cant delete upload"))?)
+    .none();
+    Ok(())
+}
+
+pub async fn delete_upload(
+    token: Must<perm::Write>,
+    db: web::Data<Pool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ResErr> {
+    if let Some(res) = wrong_version(&req) {
+        return Ok(res);
+    }
+
+    let upload = match find(&db, token.id, &req)? {
+        Ok(v) => v,
+        Err(res) => return Ok(res),
+    };
+
+    remove(&db, &upload)?;
+
+    Ok(tus(HttpResponse::NoContent()).finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+
+    use crate::db::roles::UPLOADER;
+    use crate::handlers::testing::{add_user, setup, token};
+
+    #[test]
+    fn upload_is_written_by_one_request_at_once() {
+        let patching = web::Data::new(Patching::default());
+
+        let first = claim(&patching, "a").unwrap();
+        assert!(claim(&patching, "a").is_none());
+        assert!(claim(&patching, "b").is_some());
+
+        drop(first);
+        assert!(claim(&patching, "a").is_some());
+    }
+
+    fn tus_request(method: test::TestRequest, token: &str) -> test::TestRequest {
+        method.header("token", token).header("Tus-Resumable", VERSION)
+    }
+
+    fn patch(uri: &str, token: &str, offset: u64, body: &'static [u8]) -> test::TestRequest {
+        tus_request(test::TestRequest::patch().uri(uri), token)
+            .header("Upload-Offset", offset.to_string())
+            .header(header::CONTENT_TYPE, "application/offset+octet-stream")
+            .header(header::CONTENT_LENGTH, body.len())
+            .set_payload(body)
+    }
+
+    fn offset(res: &actix_web::dev::ServiceResponse) -> &str {
+        res.headers().get("Upload-Offset").unwrap().to_str().unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn upload_continues_from_its_offset() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        let token = token(&db, &bob);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(db.clone())
+                .app_data(storage.clone())
+                .app_data(web::Data::new(Patching::default()))
+                .route("/uploads", web::post().to(create_upload))
+                .route("/uploads/{id}", web::head().to(head_upload))
+                .route("/uploads/{id}", web::patch().to(patch_upload)),
+        )
+        .await;
+
+        // whole upload has to fit into quota of 1 MB
+        let req = tus_request(test::TestRequest::post().uri("/uploads"), &token)
+            .header("Upload-Length", "2000000")
+            .header("Upload-Metadata", format!("filename {}", base64::encode("a.txt")))
+            .to_request();
+        assert_eq!(test::call_service(&mut app, req).await.status(), 413);
+
+        let req = tus_request(test::TestRequest::post().uri("/uploads"), &token)
+            .header("Upload-Length", "10")
+            .header("Upload-Metadata", format!("filename {}", base64::encode("a.txt")))
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert_eq!(res.status(), 201);
+        let uri = res.headers().get(header::LOCATION).unwrap().to_str().unwrap().to_string();
+
+        let res = test::call_service(&mut app, patch(&uri, &token, 0, b"hello").to_request()).await;
+        assert_eq!(res.status(), 204);
+        assert_eq!(offset(&res), "5");
+
+        // client which lost response sends the same part again
+        let res = test::call_service(&mut app, patch(&uri, &token, 0, b"hello").to_request()).await;
+        assert_eq!(res.status(), 409);
+
+        let res = test::call_service(&mut app, patch(&uri, &token, 5, b"world!").to_request()).await;
+        assert_eq!(res.status(), 413);
+
+        let req = tus_request(test::TestRequest::default().method(actix_web::http::Method::HEAD).uri(&uri), &token);
+        let res = test::call_service(&mut app, req.to_request()).await;
+        assert_eq!(offset(&res), "5");
+        assert!(!storage.exists("/bob/a.txt"));
+
+        let res = test::call_service(&mut app, patch(&uri, &token, 5, b"world").to_request()).await;
+        assert_eq!(res.status(), 204);
+
+        let mut content = String::new();
+        storage.read("/bob/a.txt").unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "helloworld");
+
+        let res = test::call_service(&mut app, patch(&uri, &token, 10, b"").to_request()).await;
+        assert_eq!(res.status(), 404);
+    }
+}
@@ -1,25 +1,55 @@
-use actix_web::{web, HttpResponse};
-use bcrypt::{hash, DEFAULT_COST};
+use actix_web::{web, HttpRequest, HttpResponse};
+use bcrypt::{hash, verify as verify_pass, DEFAULT_COST};
+use chrono::Utc;
 use validator::Validate;
 
-use crate::db::{execute, Pool};
-use crate::middleware::MustLogin;
-use crate::models::{ChangingUser, Queries};
+use crate::acl::{normalize, Home};
+use crate::db::{api_keys, execute, sessions, settings, totp, Pool};
+use crate::jwt::{authorize, scope_role, API_KEY_PREFIX};
+use crate::middleware::perm::LoggedIn;
+use crate::middleware::Must;
+use crate::models::{
+    ApiKeyQueries, ChangingUser, CreatedApiKey, Me, NewApiKey, Permission, Queries, SessionQueries,
+    TotpCode, TotpQueries,
+};
+use crate::quota;
 use crate::reserr::ResErr;
+use crate::storage::{blocking, Store};
+use crate::utils::{hash_token, random_token};
+use crate::totp::{check_code, enroll, verify};
 
-pub async fn get_me(token: MustLogin, db: web::Data<Pool>) -> Result<HttpResponse, ResErr> {
+pub async fn get_me(
+    token: Must<LoggedIn>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+) -> Result<HttpResponse, ResErr> {
     let mut user = (execute(&db, Queries::GetUserById(token.id))
         .map_err(|_| ResErr::BadClientData("cant get user"))?)
     .one();
 
     user.pass = "".to_string();
 
-    Ok(HttpResponse::Ok().json(user))
+    // quota of root of user, groups have their own
+    let home = Home {
+        path: normalize(&user.path)?,
+        size: user.size,
+    };
+
+    let (used, free) = blocking(move || {
+        Ok((
+            quota::used(&db, &storage, &home.path)?,
+            quota::free(&db, &storage, &home, None)?,
+        ))
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().json(Me { used, free, user }))
 }
 
 pub async fn update_me(
-    token: MustLogin,
+    token: Must<LoggedIn>,
     db: web::Data<Pool>,
+    req: HttpRequest,
     mut user: web::Json<ChangingUser>,
 ) -> Result<HttpResponse, ResErr> {
     // error message dont work
@@ -32,6 +62,8 @@ pub async fn update_me(
         )
     })?;
 
+    let changed = !verify_pass(&user.pass, &token.pass).unwrap_or(false);
+
     user.pass =
         hash(user.pass.clone(), DEFAULT_COST).map_err(|_| ResErr::InternalError("cant hash"))?;
 
@@ -39,5 +71,186 @@ pub async fn update_me(
         .map_err(|_| ResErr::BadClientData("cant update"))?)
     .none();
 
+    // same as reset of password, only session which changed it stays
+    if changed {
+        let claims = authorize(&req).map_err(ResErr::BadClientData)?;
+
+        (sessions::execute(&db, SessionQueries::RevokeUserSessions(token.id, Some(claims.sid)))
+            .map_err(|_| ResErr::InternalError("cant revoke sessions"))?)
+        .none();
+    }
+
     Ok(HttpResponse::Ok().body("updated"))
 }
+
+pub async fn enroll_2fa(token: Must<LoggedIn>, db: web::Data<Pool>) -> Result<HttpResponse, ResErr> {
+    if let Ok(v) = totp::execute(&db, TotpQueries::GetTotp(token.id)) {
+        if v.one().enabled {
+            return Err(ResErr::BadClientData("2fa already enabled"));
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(enroll(&db, token.id, &token.email)?))
+}
+
+pub async fn confirm_2fa(
+    token: Must<LoggedIn>,
+    db: web::Data<Pool>,
+    body: web::Json<TotpCode>,
+) -> Result<HttpResponse, ResErr> {
+    let secret = (totp::execute(&db, TotpQueries::GetTotp(token.id))
+        .map_err(|_| ResErr::BadClientData("2fa not enrolled"))?)
+    .one();
+
+    if !verify(&db, token.id, &secret.secret, &body.code) {
+        return Err(ResErr::BadClientData("bad code"));
+    }
+
+    (totp::execute(&db, TotpQueries::EnableTotp(token.id))
+        .map_err(|_| ResErr::InternalError("cant enable 2fa"))?)
+    .none();
+
+    Ok(HttpResponse::Ok().body("2fa enabled"))
+}
+
+pub async fn disable_2fa(
+    token: Must<LoggedIn>,
+    db: web::Data<Pool>,
+    body: web::Json<TotpCode>,
+) -> Result<HttpResponse, ResErr> {
+    if settings::get_flag(&db, "require_2fa") {
+        return Err(ResErr::BadClientData("2fa is required"));
+    }
+
+    let secret = (totp::execute(&db, TotpQueries::GetTotp(token.id))
+        .map_err(|_| ResErr::BadClientData("2fa not enrolled"))?)
+    .one();
+
+    if !check_code(&db, token.id, &secret.secret, &body.code) {
+        return Err(ResErr::BadClientData("bad code"));
+    }
+
+    (totp::execute(&db, TotpQueries::DeleteTotp(token.id))
+        .map_err(|_| ResErr::InternalError("cant disable 2fa"))?)
+    .none();
+
+    Ok(HttpResponse::Ok().body("2fa disabled"))
+}
+
+pub async fn get_api_keys(token: Must<LoggedIn>, db: web::Data<Pool>) -> Result<HttpResponse, ResErr> {
+    let keys = (api_keys::execute(&db, ApiKeyQueries::GetUserApiKeys(token.id))
+        .map_err(|_| ResErr::BadClientData("cant get api keys"))?)
+    .many();
+
+    Ok(HttpResponse::Ok().json(keys))
+}
+
+pub async fn create_api_key(
+    token: Must<LoggedIn>,
+    db: web::Data<Pool>,
+    key: web::Json<NewApiKey>,
+) -> Result<HttpResponse, ResErr> {
+    key.validate().map_err(|err| {
+        ResErr::BadClientDataOwned(
+            err.field_errors().into_values().next().unwrap()[0]
+                .code
+                .as_ref()
+                .to_string(),
+        )
+    })?;
+
+    scope_role(&key.scope).ok_or(ResErr::BadClientData("scope must be read or write"))?;
+
+    let needed = if key.scope == "write" {
+        Permission::Write
+    } else {
+        Permission::Read
+    };
+
+    if !token.can(needed) {
+        return Err(ResErr::BadClientData("key cant have more rights than you"));
+    }
+
+    if let Some(expires) = key.expires {
+        if expires < Utc::now().timestamp() {
+            return Err(ResErr::BadClientData("key would be already expired"));
+        }
+    }
+
+    let secret = format!("{}{}", API_KEY_PREFIX, random_token(40));
+
+    let api_key = (api_keys::execute(
+        &db,
+        ApiKeyQueries::AddApiKey(token.id, key, hash_token(&secret)),
+    )
+    .map_err(|_| ResErr::BadClientData("cant create api key"))?)
+    .one();
+
+    Ok(HttpResponse::Ok().json(CreatedApiKey {
+        key: secret,
+        api_key,
+    }))
+}
+
+pub async fn delete_api_key(
+    token: Must<LoggedIn>,
+    db: web::Data<Pool>,
+    path: web::Path<(u32,)>,
+) -> Result<HttpResponse, ResErr> {
+    (api_keys::execute(&db, ApiKeyQueries::DeleteApiKey(token.id, path.into_inner().0))
+        .map_err(|_| ResErr::BadClientData("api key not found"))?)
+    .none();
+
+    Ok(HttpResponse::Ok().body("api key deleted"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+    use serde_json::json;
+
+    use crate::db::roles::UPLOADER;
+    use crate::handlers::login::check_login;
+    use crate::handlers::testing::{add_user, setup, PASSWORD};
+    use crate::jwt::create_session;
+
+    fn update(token: &str, pass: &str) -> test::TestRequest {
+        test::TestRequest::patch()
+            .uri("/user")
+            .header("token", token)
+            .set_json(&json!({ "name": "alice", "email": "alice@test.com", "pass": pass }))
+    }
+
+    fn check(token: &str) -> test::TestRequest {
+        test::TestRequest::post().uri("/check_login").header("token", token)
+    }
+
+    #[actix_rt::test]
+    async fn new_password_logs_out_other_sessions() {
+        let (db, storage) = setup();
+        let alice = add_user(&db, &storage, "alice", UPLOADER, 1);
+        let (phone, laptop) = (create_session(&db, alice.id).unwrap(), create_session(&db, alice.id).unwrap());
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(db.clone())
+                .route("/user", web::patch().to(update_me))
+                .route("/check_login", web::post().to(check_login)),
+        )
+        .await;
+
+        // same password keeps everyone logged in
+        let res = test::call_service(&mut app, update(&phone.token, PASSWORD).to_request()).await;
+        assert_eq!(res.status(), 200);
+        let res = test::call_service(&mut app, check(&laptop.token).to_request()).await;
+        assert_eq!(res.status(), 200);
+
+        let res = test::call_service(&mut app, update(&phone.token, "Changed0ne").to_request()).await;
+        assert_eq!(res.status(), 200);
+        let res = test::call_service(&mut app, check(&phone.token).to_request()).await;
+        assert_eq!(res.status(), 200);
+        let res = test::call_service(&mut app, check(&laptop.token).to_request()).await;
+        assert_eq!(res.status(), 400);
+    }
+}
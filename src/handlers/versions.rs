@@ -0,0 +1,189 @@
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use std::io;
+
+use crate::acl::{resolve, Acl};
+use crate::db::{versions, Pool};
+use crate::handlers::file::send_file;
+use crate::middleware::perm;
+use crate::middleware::Must;
+use crate::models::{Permission, Version, VersionInfo, VersionQueries};
+use crate::quota;
+use crate::reserr::ResErr;
+use crate::storage::{self, blocking, Store};
+use crate::versions::{archive, current, history, place, prune, stored, unarchive};
+
+fn find(db: &Pool, path: &str, req: &HttpRequest) -> Result<Version, ResErr> {
+    let number: u32 = req
+        .match_info()
+        .query("number")
+        .parse()
+        .map_err(|_| ResErr::BadClientData("version not found"))?;
+
+    Ok((versions::execute(db, VersionQueries::GetVersion(path.to_string(), number))
+        .map_err(|_| ResErr::BadClientData("version not found"))?)
+    .one())
+}
+
+pub async fn get_versions(
+    token: Must<perm::Read>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ResErr> {
+    let path = resolve(&db, &token, req.match_info().query("filename"), Permission::Read)?;
+
+    let list = blocking(move || {
+        let meta = storage
+            .stat(&path)
+            .map_err(|_| ResErr::BadClientData("file not found"))?;
+        if meta.is_dir {
+            return Err(ResErr::BadClientData("file not found"));
+        }
+        let file = current(&db, &storage, &path)?;
+
+        // newest first, every version is compared with content which replaced it
+        let mut newer = meta.size;
+        let mut list = Vec::new();
+        for version in history(&db, &path)?.into_iter().filter(|v| v.path == path) {
+            list.push(VersionInfo {
+                number: version.number,
+                user_id: version.user_id,
+                size: version.size,
+                size_diff: version.size as i64 - newer as i64,
+                same_as_current: version.checksum == file.checksum,
+                checksum: version.checksum,
+                created: version.created,
+            });
+            newer = version.size;
+        }
+        Ok(list)
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().json(list))
+}
+
+pub async fn get_version(
+    token: Must<perm::Read>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ResErr> {
+    let path = resolve(&db, &token, req.match_info().query("filename"), Permission::Read)?;
+    let version = find(&db, &path, &req)?;
+
+    // blob in storage has no name, so it is sent as file it was
+    let mut res = send_file(&storage, &place(&version), &req, Some(&version.checksum)).await?;
+    let headers = res.headers_mut();
+    if let Ok(v) = mime_guess::from_path(&path).first_or_octet_stream().to_string().parse() {
+        headers.insert(header::CONTENT_TYPE, v);
+    }
+    if let Ok(v) = format!("attachment; filename=\"{}\"", storage::name(&path)).parse() {
+        headers.insert(header::CONTENT_DISPOSITION, v);
+    }
+
+    Ok(res)
+}
+
+// version becomes current content again, what was current goes into history
+pub async fn restore_version(
+    token: Must<perm::Write>,
+    db: web::Data<Pool>,
+    storage: web::Data<Store>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ResErr> {
+    let acl = Acl::load(&db, &token)?;
+    let filename = req.match_info().query("filename");
+
+    let path = acl.resolve(filename, Permission::Write)?;
+    let version = find(&db, &path, &req)?;
+
+    let (home, user_id) = (acl.home(filename)?, token.id);
+
+    blocking(move || {
+        // restored content is copy, so it takes quota again
+        if version.size > quota::free(&db, &storage, &home, None)? {
+            return Err(ResErr::BadClientData("you dont have size"));
+        }
+
+        let replaced = archive(&db, &storage, &path)?;
+
+        let restored = storage.write(&path).and_then(|mut f| {
+            io::copy(&mut storage.read(&place(&version))?, &mut f)?;
+            f.finish()
+        });
+
+        if restored.is_err() {
+            if let Some(replaced) = &replaced {
+                unarchive(&db, &storage, replaced);
+            }
+            return Err(ResErr::InternalError("cant restore version"));
+        }
+
+        quota::add(&db, &path, version.size as i64)?;
+        stored(&db, &path, Some(user_id), version.checksum)?;
+        prune(&db, &storage, &path)
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().body("version restored"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+    use serde_json::Value;
+    use std::io::Read;
+
+    use crate::db::roles::UPLOADER;
+    use crate::handlers::file::post_file;
+    use crate::handlers::testing::{add_user, multipart, setup, token};
+
+    fn upload(token: &str, content: &str) -> test::TestRequest {
+        let req = test::TestRequest::post().uri("/file/?conflict=overwrite").header("token", token);
+        multipart(req, &[("a.txt", content)])
+    }
+
+    #[actix_rt::test]
+    async fn restored_version_swaps_places_with_current_content() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        let token = token(&db, &bob);
+        let mut app = test::init_service(
+            App::new()
+                .app_data(db.clone())
+                .app_data(storage.clone())
+                .route("/file/{filename:.*}", web::post().to(post_file))
+                .route("/versions/{filename:.*}", web::get().to(get_versions))
+                .route("/version/{number}/{filename:.*}", web::post().to(restore_version)),
+        )
+        .await;
+
+        for content in ["one", "two", "three"].iter() {
+            let res = test::call_service(&mut app, upload(&token, content).to_request()).await;
+            assert_eq!(res.status(), 200);
+        }
+
+        let req = test::TestRequest::post().uri("/version/1/a.txt").header("token", token.as_str());
+        assert_eq!(test::call_service(&mut app, req.to_request()).await.status(), 200);
+
+        let mut content = String::new();
+        storage.read("/bob/a.txt").unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "one");
+
+        // what was current is newest version now, nothing is lost
+        let query = VersionQueries::GetVersion("/bob/a.txt".to_string(), 3);
+        let newest = (versions::execute(&db, query).unwrap()).one();
+        let mut content = String::new();
+        storage.read(&place(&newest)).unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "three");
+
+        let req = test::TestRequest::get().uri("/versions/a.txt").header("token", token.as_str());
+        let list: Value = test::read_body_json(test::call_service(&mut app, req.to_request()).await).await;
+        let numbers: Vec<&Value> = list.as_array().unwrap().iter().map(|v| &v["number"]).collect();
+        assert_eq!(numbers, vec![3, 2, 1]);
+        assert_eq!(list[2]["same_as_current"], true);
+    }
+}
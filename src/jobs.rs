@@ -0,0 +1,376 @@
+use actix_web::web;
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use std::io::{self, Read, Write};
+use std::time::Duration;
+use std::{env, thread};
+
+use crate::acl::{covers, hidden};
+use crate::db::{jobs, Pool};
+use crate::models::{Job, JobKind, JobQueries, JobStatus};
+use crate::quota;
+use crate::reserr::ResErr;
+use crate::storage::Store;
+use crate::versions;
+
+// progress of big file is saved after every this many bytes
+const PROGRESS_STEP: u64 = 8 * 1024 * 1024;
+
+fn join(path: &str, name: &str) -> String {
+    format!("{}/{}", path.trim_end_matches('/'), name)
+}
+
+// bytes and files on path, trash and old versions are left out
+pub fn measure(storage: &Store, path: &str) -> io::Result<(u64, u32)> {
+    let meta = storage.stat(path)?;
+    if !meta.is_dir {
+        return Ok((meta.size, 1));
+    }
+
+    storage.list(path)?.iter().try_fold((0, 0), |(size, files), entry| {
+        let full = join(path, &entry.name);
+        let (s, f) = match hidden(&full) {
+            true => (0, 0),
+            false => measure(storage, &full)?,
+        };
+        Ok((size + s, files + f))
+    })
+}
+
+fn progress(db: &Pool, job: &Job) -> Result<(), ResErr> {
+    (jobs::execute(db, JobQueries::SetProgress(job.id.clone(), job.done, job.files_done))
+        .map_err(|_| ResErr::InternalError("cant save job progress"))?)
+    .none();
+    Ok(())
+}
+
+// user can cancel job while it runs, it is checked before every file
+fn cancelled(db: &Pool, id: &str) -> bool {
+    match jobs::execute(db, JobQueries::GetJob(id.to_string())) {
+        Ok(v) => v.one().status == JobStatus::Cancelled,
+        Err(_) => true,
+    }
+}
+
+fn copy_file(db: &Pool, storage: &Store, job: &mut Job, from: &str, to: &str) -> Result<(), ResErr> {
+    let mut reader = storage
+        .read(from)
+        .map_err(|_| ResErr::InternalError("cant read file"))?;
+    let mut writer = storage
+        .create(to)
+        .map_err(|_| ResErr::InternalError("cant write file"))?;
+
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    let mut size: u64 = 0;
+
+    loop {
+        let len = reader
+            .read(&mut buf)
+            .map_err(|_| ResErr::InternalError("cant read file"))?;
+        if len == 0 {
+            break;
+        }
+
+        hasher.update(&buf[..len]);
+        writer
+            .write_all(&buf[..len])
+            .map_err(|_| ResErr::InternalError("cant write file"))?;
+
+        size += len as u64;
+        job.done += len as u64;
+        if job.done / PROGRESS_STEP != (job.done - len as u64) / PROGRESS_STEP {
+            progress(db, job)?;
+        }
+    }
+
+    // something could appear on path meanwhile, it is never replaced
+    writer.finish().map_err(|err| match err.kind() {
+        io::ErrorKind::AlreadyExists => ResErr::BadClientData("file already exists"),
+        _ => ResErr::InternalError("cant write file"),
+    })?;
+
+    quota::add(db, to, size as i64)?;
+    versions::stored(db, to, Some(job.user_id), hex::encode(hasher.finalize()))?;
+
+    job.files_done += 1;
+    progress(db, job)
+}
+
+fn copy_tree(db: &Pool, storage: &Store, job: &mut Job, from: &str, to: &str) -> Result<(), ResErr> {
+    let meta = storage
+        .stat(from)
+        .map_err(|_| ResErr::BadClientData("path dont exist"))?;
+
+    if !meta.is_dir {
+        if cancelled(db, &job.id) {
+            return Err(ResErr::BadClientData("job cancelled"));
+        }
+        return copy_file(db, storage, job, from, to);
+    }
+
+    storage
+        .mkdir(to)
+        .map_err(|_| ResErr::InternalError("cant create folder"))?;
+
+    let entries = storage
+        .list(from)
+        .map_err(|_| ResErr::InternalError("cant list folder"))?;
+
+    for entry in entries.iter() {
+        let full = join(from, &entry.name);
+        if !hidden(&full) {
+            copy_tree(db, storage, job, &full, &join(to, &entry.name))?;
+        }
+    }
+    Ok(())
+}
+
+// history goes with moved files, their bytes go from one home into other
+fn move_tree(db: &Pool, storage: &Store, job: &mut Job) -> Result<(), ResErr> {
+    let size = quota::size_of(storage, &job.source)? + versions::versioned(db, &job.source)?;
+
+    storage
+        .rename(&job.source, &job.target)
+        .map_err(|_| ResErr::InternalError("cant move"))?;
+
+    versions::moved(db, storage, &job.source, &job.target)?;
+
+    quota::add(db, &job.source, -(size as i64))?;
+    quota::add(db, &job.target, size as i64)?;
+
+    job.done = job.total;
+    job.files_done = job.files;
+    progress(db, job)
+}
+
+// half done copy is removed, so failed job leaves nothing behind
+fn clean(db: &Pool, storage: &Store, job: &Job) -> Result<(), ResErr> {
+    if job.kind != JobKind::Copy || !storage.exists(&job.target) {
+        return Ok(());
+    }
+
+    let size = quota::size_of(storage, &job.target)?;
+    storage
+        .delete(&job.target)
+        .map_err(|_| ResErr::InternalError("cant delete copy"))?;
+
+    versions::forget(db, storage, &job.target)?;
+    quota::add(db, &job.target, -(size as i64))
+}
+
+// cancelled job stays cancelled, query finishes only running ones
+fn finish(db: &Pool, job: &Job, status: JobStatus, error: Option<String>) {
+    let query = JobQueries::FinishJob(job.id.clone(), status, error, Utc::now().timestamp());
+
+    if jobs::execute(db, query).is_err() {
+        eprintln!("cant finish job {}", job.id);
+    }
+}
+
+fn run(db: &Pool, storage: &Store, mut job: Job) {
+    // path was free when job was created, whatever took it since is not touched
+    if storage.exists(&job.target) {
+        return finish(db, &job, JobStatus::Failed, Some("file already exists".to_string()));
+    }
+
+    let result = match job.kind {
+        JobKind::Copy => {
+            let (source, target) = (job.source.clone(), job.target.clone());
+            copy_tree(db, storage, &mut job, &source, &target)
+        }
+        JobKind::Move => move_tree(db, storage, &mut job),
+    };
+
+    match result {
+        Ok(_) => finish(db, &job, JobStatus::Done, None),
+        Err(err) => {
+            if clean(db, storage, &job).is_err() {
+                eprintln!("cant clean after job {}", job.id);
+            }
+            finish(db, &job, JobStatus::Failed, Some(err.to_string()));
+        }
+    }
+}
+
+// job is saved and runs in its own thread, client polls it for progress
+pub fn start(db: &Pool, storage: web::Data<Store>, job: Job) -> Result<Job, ResErr> {
+    (jobs::execute(db, JobQueries::AddJob(job.clone()))
+        .map_err(|_| ResErr::InternalError("cant save job"))?)
+    .none();
+
+    let db = db.clone();
+    let running = job.clone();
+    thread::spawn(move || run(&db, &storage, running));
+
+    Ok(job)
+}
+
+// bytes which running copies into home are still going to write
+pub fn pending(db: &Pool, home: &str) -> Result<u64, ResErr> {
+    Ok((jobs::execute(db, JobQueries::GetRunningJobs)
+        .map_err(|_| ResErr::InternalError("cant get jobs"))?)
+    .many()
+    .iter()
+    .filter(|j| j.kind == JobKind::Copy && covers(home, &j.target))
+    .map(|j| j.total.saturating_sub(j.done))
+    .sum())
+}
+
+// jobs running when app stopped are failed and their copies removed at start,
+// finished jobs are forgotten after JOB_EXPIRATION hours, it is checked every hour
+pub fn start_cleanup(db: Pool, storage: web::Data<Store>) {
+    let hours: i64 = env::var("JOB_EXPIRATION").unwrap().parse().unwrap();
+
+    let interrupted = jobs::execute(&db, JobQueries::GetRunningJobs)
+        .map_err(|_| ResErr::InternalError("cant get jobs"))
+        .and_then(|rows| rows.many().iter().try_for_each(|job| clean(&db, &storage, job)))
+        .and_then(|_| {
+            let failed = JobQueries::FailRunningJobs("stopped by restart".to_string(), Utc::now().timestamp());
+            jobs::execute(&db, failed).map_err(|_| ResErr::InternalError("cant save job"))
+        });
+
+    if interrupted.is_err() {
+        eprintln!("cleaning of interrupted jobs failed");
+    }
+
+    thread::spawn(move || loop {
+        let before = Utc::now().timestamp() - hours * 60 * 60;
+
+        if jobs::execute(&db, JobQueries::DeleteFinishedJobs(before)).is_err() {
+            eprintln!("jobs cleanup failed");
+        }
+        thread::sleep(Duration::from_secs(60 * 60));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    use crate::db::files;
+    use crate::db::roles::UPLOADER;
+    use crate::handlers::testing::{add_user, put, setup};
+    use crate::models::FileQueries;
+    use crate::storage::memory::MemoryStorage;
+    use crate::storage::{Meta, Storage, Writer};
+
+    type Hook = Box<dyn Fn(&str) -> io::Result<()> + Send + Sync>;
+
+    // memory storage which lets test step in before file is read
+    struct Hooked {
+        inner: MemoryStorage,
+        on_read: Hook,
+    }
+
+    impl Storage for Hooked {
+        fn read(&self, path: &str) -> io::Result<Box<dyn Read + Send>> {
+            (self.on_read)(path)?;
+            self.inner.read(path)
+        }
+        fn write(&self, path: &str) -> io::Result<Box<dyn Writer>> {
+            self.inner.write(path)
+        }
+        fn create(&self, path: &str) -> io::Result<Box<dyn Writer>> {
+            self.inner.create(path)
+        }
+        fn list(&self, path: &str) -> io::Result<Vec<Meta>> {
+            self.inner.list(path)
+        }
+        fn stat(&self, path: &str) -> io::Result<Meta> {
+            self.inner.stat(path)
+        }
+        fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+            self.inner.rename(from, to)
+        }
+        fn delete(&self, path: &str) -> io::Result<()> {
+            self.inner.delete(path)
+        }
+        fn mkdir(&self, path: &str) -> io::Result<()> {
+            self.inner.mkdir(path)
+        }
+    }
+
+    // bob has folder with two files, second one is read after first is copied already
+    fn copy_job(db: &Pool, on_read: Hook) -> (Store, Job) {
+        let storage: Store = Box::new(Hooked {
+            inner: MemoryStorage::new(),
+            on_read,
+        });
+        let bob = add_user(db, &storage, "bob", UPLOADER, 1);
+        storage.mkdir("/bob/src").unwrap();
+        put(&storage, "/bob/src/a.txt", "one");
+        put(&storage, "/bob/src/b.txt", "two");
+
+        let job = Job {
+            id: "job".to_string(),
+            user_id: bob.id,
+            kind: JobKind::Copy,
+            from: "/src".to_string(),
+            to: "/dst".to_string(),
+            source: "/bob/src".to_string(),
+            target: "/bob/dst".to_string(),
+            status: JobStatus::Running,
+            total: 6,
+            done: 0,
+            files: 2,
+            files_done: 0,
+            error: None,
+            created: Utc::now().timestamp(),
+            finished: None,
+        };
+        (jobs::execute(db, JobQueries::AddJob(job.clone())).unwrap()).none();
+        (storage, job)
+    }
+
+    fn status(db: &Pool) -> JobStatus {
+        (jobs::execute(db, JobQueries::GetJob("job".to_string())).unwrap()).one().status
+    }
+
+    #[test]
+    fn failed_copy_leaves_nothing_behind() {
+        let (db, _) = setup();
+        let (storage, job) = copy_job(
+            &db,
+            Box::new(|path| match path {
+                "/bob/src/b.txt" => Err(io::Error::other("broken disk")),
+                _ => Ok(()),
+            }),
+        );
+        let used = quota::used(&db, &storage, "/bob").unwrap();
+
+        run(&db, &storage, job);
+
+        assert_eq!(status(&db), JobStatus::Failed);
+        assert!(!storage.exists("/bob/dst"));
+        assert!(files::execute(&db, FileQueries::GetFile("/bob/dst/a.txt".to_string())).is_err());
+        assert_eq!(quota::used(&db, &storage, "/bob").unwrap(), used);
+    }
+
+    #[test]
+    fn cancelled_copy_leaves_nothing_behind() {
+        let (db, _) = setup();
+        let cancel = db.clone();
+        let (storage, job) = copy_job(
+            &db,
+            Box::new(move |path| {
+                if path == "/bob/src/a.txt" {
+                    let query = JobQueries::FinishJob("job".to_string(), JobStatus::Cancelled, None, 0);
+                    (jobs::execute(&cancel, query).unwrap()).none();
+                }
+                Ok(())
+            }),
+        );
+        let used = quota::used(&db, &storage, "/bob").unwrap();
+
+        run(&db, &storage, job);
+
+        // first file was copied whole before job saw it was cancelled
+        assert_eq!(status(&db), JobStatus::Cancelled);
+        assert!(!storage.exists("/bob/dst"));
+        assert!(files::execute(&db, FileQueries::GetFile("/bob/dst/a.txt".to_string())).is_err());
+        assert_eq!(quota::used(&db, &storage, "/bob").unwrap(), used);
+        assert!(storage.exists("/bob/src/b.txt"));
+    }
+}
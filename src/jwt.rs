@@ -1,40 +1,164 @@
 use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
-use actix_web::HttpRequest;
+use actix_web::{web, HttpRequest};
 use std::env;
 use chrono::Utc;
-use crate::models::{Claims, Token};
+use crate::db::{api_keys, roles, sessions, Pool};
+use crate::models::{ApiKeyQueries, ChallengeClaims, Claims, SessionQueries, Token};
+use crate::utils::{hash_token, random_token};
 
-pub fn create_jwt(id: u32) -> Token {
+pub fn create_jwt(id: u32, sid: u32) -> String {
 
     let expiration = Utc::now()
-        .checked_add_signed(chrono::Duration::hours(env::var("TOKEN_EXPIRATION").unwrap().parse().unwrap()))
+        .checked_add_signed(chrono::Duration::minutes(env::var("ACCESS_TOKEN_MINUTES").unwrap().parse().unwrap()))
         .expect("valid timestamp")
         .timestamp();
 
     let claims = Claims {
-        id: id,
+        id,
+        sid,
         exp: expiration as usize,
+        scope: None,
     };
     let header = Header::new(Algorithm::HS512);
-    
-    Token {token: encode(&header, &claims, &EncodingKey::from_secret(env::var("JWT_SECRET").unwrap().as_bytes())).unwrap()}
+
+    encode(&header, &claims, &EncodingKey::from_secret(env::var("JWT_SECRET").unwrap().as_bytes())).unwrap()
+}
+
+// short lived token which proves that password was already checked
+pub fn create_challenge(id: u32) -> String {
+
+    let expiration = Utc::now()
+        .checked_add_signed(chrono::Duration::minutes(5))
+        .expect("valid timestamp")
+        .timestamp();
+
+    let claims = ChallengeClaims {
+        id,
+        challenge: true,
+        exp: expiration as usize,
+    };
+    let header = Header::new(Algorithm::HS512);
+
+    encode(&header, &claims, &EncodingKey::from_secret(env::var("JWT_SECRET").unwrap().as_bytes())).unwrap()
+}
+
+pub fn verify_challenge(challenge: &str) -> Result<u32, &'static str> {
+    match decode::<ChallengeClaims>(
+        challenge,
+        &DecodingKey::from_secret(env::var("JWT_SECRET").unwrap().as_bytes()),
+        &Validation::new(Algorithm::HS512),){
+        Ok(decoded) if decoded.claims.challenge => Ok(decoded.claims.id),
+        _ => Err("invalid challenge")
+    }
+}
+
+fn refresh_expiration() -> i64 {
+    Utc::now()
+        .checked_add_signed(chrono::Duration::hours(env::var("REFRESH_TOKEN_EXPIRATION").unwrap().parse().unwrap()))
+        .expect("valid timestamp")
+        .timestamp()
+}
+
+pub fn create_session(db: &Pool, id: u32) -> Result<Token, &'static str> {
+    let refresh_token = random_token(64);
+
+    let session = sessions::execute(db, SessionQueries::AddSession(id, hash_token(&refresh_token), refresh_expiration()))
+        .map_err(|_| "cant create session")?
+        .one();
+
+    Ok(Token {
+        token: create_jwt(id, session.id),
+        refresh_token,
+    })
+}
+
+pub fn refresh_session(db: &Pool, refresh_token: &str) -> Result<Token, &'static str> {
+    let session = sessions::execute(db, SessionQueries::GetSessionByHash(hash_token(refresh_token)))
+        .map_err(|_| "invalid refresh token")?
+        .one();
+
+    if session.revoked || session.expires < Utc::now().timestamp() {
+        return Err("session expired");
+    }
+
+    // every refresh token can be used only once
+    let refresh_token = random_token(64);
+
+    sessions::execute(db, SessionQueries::RotateSession(session.id, hash_token(&refresh_token), refresh_expiration()))
+        .map_err(|_| "cant refresh session")?
+        .none();
+
+    Ok(Token {
+        token: create_jwt(session.user_id, session.id),
+        refresh_token,
+    })
+}
+
+pub const API_KEY_PREFIX: &str = "ck_";
+
+// api key can have at most rights of this built-in role
+pub fn scope_role(scope: &str) -> Option<u8> {
+    match scope {
+        "read" => Some(roles::VIEWER),
+        "write" => Some(roles::UPLOADER),
+        _ => None,
+    }
+}
+
+fn authorize_api_key(db: &Pool, key: &str) -> Result<Claims, &'static str> {
+    let api_key = api_keys::execute(db, ApiKeyQueries::GetApiKeyByHash(hash_token(key)))
+        .map_err(|_| "invalid api key")?
+        .one();
+
+    if let Some(expires) = api_key.expires {
+        if expires < Utc::now().timestamp() {
+            return Err("api key expired");
+        }
+    }
+
+    api_keys::execute(db, ApiKeyQueries::TouchApiKey(api_key.id))
+        .map_err(|_| "cant use api key")?
+        .none();
+
+    Ok(Claims {
+        id: api_key.user_id,
+        sid: 0,
+        exp: api_key.expires.unwrap_or(0) as usize,
+        scope: Some(scope_role(&api_key.scope).ok_or("invalid api key")?),
+    })
 }
 
 pub fn authorize(req: &HttpRequest) -> Result<Claims, &'static str> {
 
-    if let Some(jwt) = get_content_type(&req) { 
+    let db = req.app_data::<web::Data<Pool>>().ok_or("cant use db")?;
+
+    if let Some(key) = get_content_type(req).filter(|v| v.starts_with(API_KEY_PREFIX)) {
+        return authorize_api_key(db, key);
+    }
+
+    let claims = if let Some(jwt) = get_content_type(req) {
         match decode::<Claims>(
-            &jwt,
+            jwt,
             &DecodingKey::from_secret(env::var("JWT_SECRET").unwrap().as_bytes()),
             &Validation::new(Algorithm::HS512),){
-            Ok(decoded) => Ok(decoded.claims),
-            Err(_) => Err("invalid token")
+            Ok(decoded) => decoded.claims,
+            Err(_) => return Err("invalid token")
         }
     } else {
-        Err("token not found")
+        return Err("token not found");
+    };
+
+    let session = sessions::execute(db, SessionQueries::GetSessionById(claims.sid))
+        .map_err(|_| "session not found")?
+        .one();
+
+    if session.revoked || session.user_id != claims.id || session.expires < Utc::now().timestamp() {
+        return Err("session revoked");
     }
+
+    Ok(claims)
 }
 
-fn get_content_type<'a>(req: &'a HttpRequest) -> Option<&'a str> {
+fn get_content_type(req: &HttpRequest) -> Option<&str> {
     req.headers().get("token")?.to_str().ok()
-}
\ No newline at end of file
+}
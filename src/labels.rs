@@ -0,0 +1,39 @@
+use std::collections::HashSet;
+
+use crate::acl::covers;
+use crate::db::{labels, Pool};
+use crate::models::{LabelFilter, LabelQueries};
+use crate::reserr::ResErr;
+use crate::versions::VERSIONS;
+
+// tags, favorites and properties belong to id of path, storage moves id with renamed file or folder,
+// into trash and back too, only old version moved aside dont take it, it stays with file on its path
+
+pub fn rename(db: &Pool, from: &str, to: &str) {
+    if covers(VERSIONS, from) || covers(VERSIONS, to) {
+        return;
+    }
+
+    if labels::execute(db, LabelQueries::MoveLabels(from.to_string(), to.to_string())).is_err() {
+        eprintln!("cant move labels");
+    }
+}
+
+pub fn remove(db: &Pool, path: &str) {
+    if labels::execute(db, LabelQueries::DeleteLabels(path.to_string())).is_err() {
+        eprintln!("cant delete labels");
+    }
+}
+
+// paths in storage with everything filter asks for, None when it asks for nothing
+pub fn matching(db: &Pool, user_id: u32, filter: LabelFilter) -> Result<Option<HashSet<String>>, ResErr> {
+    if filter.is_empty() {
+        return Ok(None);
+    }
+
+    let labeled = (labels::execute(db, LabelQueries::FindLabeled(user_id, filter))
+        .map_err(|_| ResErr::InternalError("cant get labels"))?)
+    .many();
+
+    Ok(Some(labeled.into_iter().map(|l| l.path).collect()))
+}
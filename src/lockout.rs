@@ -0,0 +1,233 @@
+use actix_web::HttpRequest;
+use chrono::Utc;
+use std::env;
+
+use crate::db::{attempts, Pool};
+use crate::models::{AttemptQueries, LoginAttempt};
+use crate::reserr::ResErr;
+
+fn config(name: &str) -> i64 {
+    env::var(name).unwrap().parse().unwrap()
+}
+
+pub fn email_key(email: &str) -> String {
+    format!("email:{}", email.trim().to_lowercase())
+}
+
+// peer address is used on purpose, forwarded headers can be faked by client
+pub fn ip_key(req: &HttpRequest) -> String {
+    match req.peer_addr() {
+        Some(addr) => format!("ip:{}", addr.ip()),
+        None => "ip:unknown".to_string(),
+    }
+}
+
+pub fn get_attempt(db: &Pool, key: &str) -> LoginAttempt {
+    match attempts::execute(db, AttemptQueries::GetAttempt(key.to_string())) {
+        Ok(v) => v.one(),
+        Err(_) => LoginAttempt {
+            key: key.to_string(),
+            ..Default::default()
+        },
+    }
+}
+
+fn backoff(failures: u32) -> i64 {
+    if failures == 0 {
+        return 0;
+    }
+    let max = config("LOGIN_LOCKOUT") * 60;
+
+    (config("LOGIN_BACKOFF") << (failures - 1).min(30)).min(max)
+}
+
+pub fn is_locked(attempt: &LoginAttempt) -> bool {
+    attempt.locked_until > Utc::now().timestamp()
+}
+
+// call before password is checked
+pub fn check(db: &Pool, keys: &[String]) -> Result<(), ResErr> {
+    let now = Utc::now().timestamp();
+
+    for key in keys {
+        let attempt = get_attempt(db, key);
+
+        if is_locked(&attempt) {
+            return Err(ResErr::TooManyRequests("too many failed logins, try it later"));
+        }
+        if attempt.last_failure + backoff(attempt.failures) > now {
+            return Err(ResErr::TooManyRequests("slow down"));
+        }
+    }
+    Ok(())
+}
+
+pub fn fail(db: &Pool, keys: &[String]) -> Result<(), ResErr> {
+    let now = Utc::now().timestamp();
+
+    for key in keys {
+        let mut attempt = get_attempt(db, key);
+
+        // old failures are forgotten
+        if attempt.last_failure + config("LOGIN_LOCKOUT") * 60 < now {
+            attempt.failures = 0;
+        }
+
+        attempt.failures += 1;
+        attempt.last_failure = now;
+
+        // one address can try more accounts than one account should ever need
+        let max = if key.starts_with("ip:") {
+            config("LOGIN_MAX_IP_ATTEMPTS")
+        } else {
+            config("LOGIN_MAX_ATTEMPTS")
+        };
+
+        if attempt.failures >= max as u32 {
+            attempt.failures = 0;
+            attempt.locked_until = now + config("LOGIN_LOCKOUT") * 60;
+        }
+
+        (attempts::execute(db, AttemptQueries::SaveAttempt(attempt))
+            .map_err(|_| ResErr::InternalError("cant save login attempt"))?)
+        .none();
+    }
+    Ok(())
+}
+
+// for requests which should be rare even when they are fine, like mails from password reset,
+// every request counts as failure, so it gets same backoff and lock as failed login
+pub fn throttle(db: &Pool, keys: &[String]) -> Result<(), ResErr> {
+    check(db, keys).map_err(|_| ResErr::TooManyRequests("too many requests, try it later"))?;
+    fail(db, keys)
+}
+
+pub fn success(db: &Pool, email: &str) -> Result<(), ResErr> {
+    let mut attempt = get_attempt(db, &email_key(email));
+
+    if attempt.failures == 0 && attempt.locked_until == 0 {
+        return Ok(());
+    }
+
+    // keep history of last unlock, only counters are cleared
+    attempt.failures = 0;
+    attempt.last_failure = 0;
+    attempt.locked_until = 0;
+
+    (attempts::execute(db, AttemptQueries::SaveAttempt(attempt))
+        .map_err(|_| ResErr::InternalError("cant save login attempt"))?)
+    .none();
+    Ok(())
+}
+
+pub fn unlock(db: &Pool, email: &str, admin: u32) -> Result<(), ResErr> {
+    let mut attempt = get_attempt(db, &email_key(email));
+
+    attempt.failures = 0;
+    attempt.last_failure = 0;
+    attempt.locked_until = 0;
+    attempt.unlocked_at = Some(Utc::now().timestamp());
+    attempt.unlocked_by = Some(admin);
+
+    (attempts::execute(db, AttemptQueries::SaveAttempt(attempt))
+        .map_err(|_| ResErr::InternalError("cant unlock user"))?)
+    .none();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_pool;
+
+    // same values in every test, they run at once and share environment
+    fn setup() -> Pool {
+        env::set_var("LOGIN_BACKOFF", "1");
+        env::set_var("LOGIN_LOCKOUT", "15");
+        env::set_var("LOGIN_MAX_ATTEMPTS", "3");
+        env::set_var("LOGIN_MAX_IP_ATTEMPTS", "10");
+
+        let db = test_pool();
+        attempts::create_table(&db);
+        db
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_lockout() {
+        setup();
+
+        assert_eq!(backoff(0), 0);
+        assert_eq!(backoff(1), 1);
+        assert_eq!(backoff(2), 2);
+        assert_eq!(backoff(5), 16);
+        assert_eq!(backoff(100), 15 * 60);
+    }
+
+    #[test]
+    fn failure_slows_down_next_try() {
+        let db = setup();
+        let keys = [email_key("a@a.com")];
+
+        assert!(check(&db, &keys).is_ok());
+        fail(&db, &keys).unwrap();
+        assert!(matches!(check(&db, &keys), Err(ResErr::TooManyRequests("slow down"))));
+    }
+
+    #[test]
+    fn too_many_failures_lock_only_that_key() {
+        let db = setup();
+        let email = [email_key("a@a.com")];
+        let ip = ["ip:127.0.0.1".to_string()];
+
+        for _ in 0..3 {
+            fail(&db, &email).unwrap();
+            fail(&db, &ip).unwrap();
+        }
+
+        assert!(is_locked(&get_attempt(&db, &email[0])));
+        assert!(!is_locked(&get_attempt(&db, &ip[0])));
+        assert!(matches!(
+            check(&db, &email),
+            Err(ResErr::TooManyRequests("too many failed logins, try it later"))
+        ));
+    }
+
+    #[test]
+    fn throttle_counts_every_request() {
+        let db = setup();
+        let keys = [format!("{}:mail", email_key("a@a.com"))];
+
+        assert!(throttle(&db, &keys).is_ok());
+        assert!(matches!(
+            throttle(&db, &keys),
+            Err(ResErr::TooManyRequests("too many requests, try it later"))
+        ));
+        assert!(check(&db, &[email_key("a@a.com")]).is_ok());
+    }
+
+    #[test]
+    fn email_key_ignores_case_and_spaces() {
+        assert_eq!(email_key(" A@A.com "), email_key("a@a.com"));
+    }
+
+    #[test]
+    fn success_and_unlock_clear_counters() {
+        let db = setup();
+        let keys = [email_key("a@a.com")];
+
+        for _ in 0..3 {
+            fail(&db, &keys).unwrap();
+        }
+        success(&db, "a@a.com").unwrap();
+        assert!(check(&db, &keys).is_ok());
+
+        for _ in 0..3 {
+            fail(&db, &keys).unwrap();
+        }
+        unlock(&db, "A@a.com", 1).unwrap();
+
+        let attempt = get_attempt(&db, &keys[0]);
+        assert!(!is_locked(&attempt));
+        assert_eq!(attempt.unlocked_by, Some(1));
+    }
+}
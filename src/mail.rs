@@ -0,0 +1,159 @@
+use lettre::message::{header::ContentType, Mailbox, Message};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{SmtpTransport, Transport};
+use std::path::PathBuf;
+use std::{env, fs};
+
+use crate::utils::random_token;
+
+pub trait MailTransport: Send + Sync {
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), String>;
+}
+
+pub type Mailer = Box<dyn MailTransport>;
+
+fn build_message(from: &Mailbox, to: &str, subject: &str, body: &str) -> Result<Message, String> {
+    Message::builder()
+        .from(from.clone())
+        .to(to.parse().map_err(|_| "bad email address".to_string())?)
+        .subject(subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(body.to_string())
+        .map_err(|err| err.to_string())
+}
+
+pub struct SmtpMailer {
+    from: Mailbox,
+    transport: SmtpTransport,
+}
+
+impl SmtpMailer {
+    pub fn new(from: Mailbox, host: &str, port: u16, user: &str, pass: &str) -> Result<SmtpMailer, String> {
+        let mut builder = SmtpTransport::starttls_relay(host)
+            .map_err(|err| err.to_string())?
+            .port(port);
+
+        if !user.is_empty() {
+            builder = builder.credentials(Credentials::new(user.to_string(), pass.to_string()));
+        }
+
+        Ok(SmtpMailer {
+            from,
+            transport: builder.build(),
+        })
+    }
+}
+
+impl MailTransport for SmtpMailer {
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), String> {
+        let message = build_message(&self.from, to, subject, body)?;
+
+        self.transport
+            .send(&message)
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+}
+
+// writes every mail into maildir, so it can be read without mail server
+pub struct FileMailer {
+    from: Mailbox,
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(from: Mailbox, dir: impl Into<PathBuf>) -> Result<FileMailer, String> {
+        let dir = dir.into();
+
+        for sub in &["tmp", "new", "cur"] {
+            fs::create_dir_all(dir.join(sub)).map_err(|err| err.to_string())?;
+        }
+
+        Ok(FileMailer { from, dir })
+    }
+}
+
+impl MailTransport for FileMailer {
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), String> {
+        let message = build_message(&self.from, to, subject, body)?;
+
+        let name = format!(
+            "{}.{}.cloud",
+            chrono::Utc::now().timestamp(),
+            random_token(16)
+        );
+        let tmp = self.dir.join("tmp").join(&name);
+
+        // maildir readers expect mail to appear in "new" at once
+        fs::write(&tmp, message.formatted()).map_err(|err| err.to_string())?;
+        fs::rename(&tmp, self.dir.join("new").join(&name)).map_err(|err| err.to_string())
+    }
+}
+
+pub fn from_env() -> Mailer {
+    let from: Mailbox = env::var("MAIL_FROM")
+        .unwrap()
+        .parse()
+        .expect("MAIL_FROM must be email address");
+
+    match env::var("MAIL_TRANSPORT").unwrap().as_str() {
+        "smtp" => Box::new(
+            SmtpMailer::new(
+                from,
+                &env::var("SMTP_HOST").unwrap(),
+                env::var("SMTP_PORT").unwrap().parse().unwrap(),
+                &env::var("SMTP_USER").unwrap_or_default(),
+                &env::var("SMTP_PASS").unwrap_or_default(),
+            )
+            .unwrap(),
+        ),
+        "file" => Box::new(FileMailer::new(from, env::var("MAIL_DIR").unwrap()).unwrap()),
+        other => panic!("unknown MAIL_TRANSPORT {}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mailer(dir: &std::path::Path) -> FileMailer {
+        FileMailer::new("Cloud <cloud@example.com>".parse().unwrap(), dir).unwrap()
+    }
+
+    #[test]
+    fn maildir_gets_mail_in_new() {
+        let dir = tempfile::tempdir().unwrap();
+        let mailer = mailer(dir.path());
+
+        mailer.send("bob@example.com", "Password reset", "open this link").unwrap();
+
+        let new: Vec<_> = fs::read_dir(dir.path().join("new")).unwrap().collect();
+        assert_eq!(new.len(), 1);
+        assert_eq!(fs::read_dir(dir.path().join("tmp")).unwrap().count(), 0);
+
+        let mail = fs::read_to_string(new[0].as_ref().unwrap().path()).unwrap();
+        assert!(mail.contains("To: bob@example.com"));
+        assert!(mail.contains("Subject: Password reset"));
+        assert!(mail.contains("open this link"));
+    }
+
+    #[test]
+    fn every_mail_is_own_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let mailer = mailer(dir.path());
+
+        for _ in 0..3 {
+            mailer.send("bob@example.com", "Verify your email", "token").unwrap();
+        }
+        assert_eq!(fs::read_dir(dir.path().join("new")).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn bad_address_is_not_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let mailer = mailer(dir.path());
+
+        assert!(mailer.send("not an address", "Subject", "body").is_err());
+        assert_eq!(fs::read_dir(dir.path().join("new")).unwrap().count(), 0);
+    }
+}
@@ -1,20 +1,35 @@
 // dependencies
 use actix_files as fs;
 use actix_files::NamedFile;
-use actix_web::{error, web, App, HttpRequest, HttpResponse, HttpServer, Result};
+use actix_web::{error, http::Method, web, App, HttpRequest, HttpResponse, HttpServer, Result};
 use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
 use r2d2_sqlite::{self, SqliteConnectionManager};
 use std::env;
 use std::path::PathBuf;
 
 // modules
+mod acl;
 mod db;
+mod extract;
+mod fulltext;
 mod handlers;
+mod jobs;
 mod jwt;
+mod labels;
+mod lockout;
+mod mail;
 mod middleware;
 mod models;
+mod quota;
 mod reserr;
+mod search;
+mod sniff;
+mod storage;
+mod totp;
+mod trash;
 mod utils;
+mod versions;
+mod zip;
 
 use db::{create_tables, Pool};
 use reserr::ResErr;
@@ -23,6 +38,13 @@ use reserr::ResErr;
 async fn main() -> std::io::Result<()> {
     dotenv::dotenv().ok();
 
+    // TOKEN_EXPIRATION was in hours, old .env has to fail here and not give tokens 60 times shorter
+    for var in ["ACCESS_TOKEN_MINUTES", "REFRESH_TOKEN_EXPIRATION"] {
+        if env::var(var).is_err() {
+            panic!("{} is not set, TOKEN_EXPIRATION in hours was replaced by ACCESS_TOKEN_MINUTES", var);
+        }
+    }
+
     let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
     builder
         .set_private_key_file("key.pem", SslFiletype::PEM)
@@ -34,6 +56,16 @@ async fn main() -> std::io::Result<()> {
 
     create_tables(&pool);
 
+    let mailer = web::Data::new(mail::from_env());
+    let storage = web::Data::new(storage::from_env(&pool));
+    let patching = web::Data::new(handlers::tus::Patching::default());
+
+    quota::start_reconcile(pool.clone(), storage.clone());
+    trash::start_purge(pool.clone(), storage.clone());
+    versions::start_prune(pool.clone(), storage.clone());
+    jobs::start_cleanup(pool.clone(), storage.clone());
+    search::start_reindex(pool.clone(), storage.clone());
+
     // Start http server
     HttpServer::new(move || {
         App::new()
@@ -47,6 +79,9 @@ async fn main() -> std::io::Result<()> {
                 .into()
             }))
             .data(pool.clone())
+            .app_data(mailer.clone())
+            .app_data(storage.clone())
+            .app_data(patching.clone())
             // admin utils
             .route("/users", web::get().to(handlers::admin::get_users))
             .route(
@@ -58,13 +93,69 @@ async fn main() -> std::io::Result<()> {
                 web::delete().to(handlers::admin::delete_user),
             )
             .route("/users/{id}", web::patch().to(handlers::admin::update_user))
+            .route(
+                "/users/{id}/lock",
+                web::delete().to(handlers::admin::unlock_user),
+            )
             .route("/users", web::post().to(handlers::admin::add_user))
+            .route("/roles", web::get().to(handlers::admin::get_roles))
+            .route("/roles", web::post().to(handlers::admin::add_role))
+            .route("/roles/{id}", web::patch().to(handlers::admin::update_role))
+            .route("/roles/{id}", web::delete().to(handlers::admin::delete_role))
+            .route("/acl/{path:.*}", web::get().to(handlers::admin::get_acl))
+            .route("/acl/{path:.*}", web::put().to(handlers::admin::set_acl))
+            .route("/settings", web::get().to(handlers::admin::get_settings))
+            .route("/settings", web::patch().to(handlers::admin::update_settings))
+            // groups
+            .route("/groups", web::get().to(handlers::group::get_groups))
+            .route("/groups", web::post().to(handlers::group::add_group))
+            .route("/groups/{id}", web::get().to(handlers::group::get_group))
+            .route("/groups/{id}", web::patch().to(handlers::group::update_group))
+            .route("/groups/{id}", web::delete().to(handlers::group::delete_group))
+            .route(
+                "/groups/{id}/members/{user_id}",
+                web::put().to(handlers::group::add_member),
+            )
+            .route(
+                "/groups/{id}/members/{user_id}",
+                web::delete().to(handlers::group::remove_member),
+            )
             // user utils
             .route("/user", web::get().to(handlers::user::get_me))
             .route("/user", web::patch().to(handlers::user::update_me))
+            .route("/user/2fa", web::post().to(handlers::user::enroll_2fa))
+            .route("/user/2fa/confirm", web::post().to(handlers::user::confirm_2fa))
+            .route("/user/2fa", web::delete().to(handlers::user::disable_2fa))
+            .route("/user/keys", web::get().to(handlers::user::get_api_keys))
+            .route("/user/keys", web::post().to(handlers::user::create_api_key))
+            .route(
+                "/user/keys/{id}",
+                web::delete().to(handlers::user::delete_api_key),
+            )
             // Login
             .route("/login", web::post().to(handlers::login::login))
+            .route("/login/2fa", web::post().to(handlers::login::login_2fa))
+            .route("/login/2fa/enroll", web::post().to(handlers::login::login_2fa_enroll))
             .route("/check_login", web::post().to(handlers::login::check_login))
+            .route("/token/refresh", web::post().to(handlers::login::refresh))
+            .route("/logout", web::post().to(handlers::login::logout))
+            .route("/logout_all", web::post().to(handlers::login::logout_all))
+            .route(
+                "/password/forgot",
+                web::post().to(handlers::password::forgot_password),
+            )
+            .route(
+                "/password/reset",
+                web::post().to(handlers::password::reset_password),
+            )
+            .route(
+                "/email/verify",
+                web::post().to(handlers::password::verify_email),
+            )
+            .route(
+                "/email/verify/resend",
+                web::post().to(handlers::password::resend_verification),
+            )
             // cloud utils
             .route(
                 "/file/{filename:.*}",
@@ -86,6 +177,16 @@ async fn main() -> std::io::Result<()> {
                 "/file/{filename:.*}",
                 web::delete().to(handlers::file::delete_file),
             )
+            // zip download
+            .route("/zip", web::post().to(handlers::archive::post_zip))
+            .route(
+                "/zip/{filename:.*}",
+                web::get().to(handlers::archive::get_zip),
+            )
+            .route(
+                "/extract/{filename:.*}",
+                web::post().to(handlers::archive::extract_archive),
+            )
             // folder utils
             .route(
                 "/folder/{filename:.*}",
@@ -100,6 +201,69 @@ async fn main() -> std::io::Result<()> {
                 web::delete().to(handlers::folder::delete_folder),
             )
             .route("/folder_tree", web::get().to(handlers::folder::get_tree))
+            .route("/stat/{filename:.*}", web::get().to(handlers::stat::get_stat))
+            .route("/search", web::get().to(handlers::search::search))
+            .route("/search/content", web::get().to(handlers::search::search_content))
+            .route("/labels/{filename:.*}", web::get().to(handlers::labels::get_labels))
+            .route("/tags/{tag}/{filename:.*}", web::put().to(handlers::labels::add_tag))
+            .route("/tags/{tag}/{filename:.*}", web::delete().to(handlers::labels::remove_tag))
+            .route("/favorites/{filename:.*}", web::put().to(handlers::labels::add_favorite))
+            .route("/favorites/{filename:.*}", web::delete().to(handlers::labels::remove_favorite))
+            .route("/properties/{key}/{filename:.*}", web::put().to(handlers::labels::set_property))
+            .route("/properties/{key}/{filename:.*}", web::delete().to(handlers::labels::remove_property))
+            // share links
+            .route("/shares", web::get().to(handlers::share::get_shares))
+            .route("/shares", web::post().to(handlers::share::create_share))
+            .route("/shares/{id}", web::delete().to(handlers::share::delete_share))
+            .route("/s/{slug}", web::get().to(handlers::share::get_shared))
+            .route("/s/{slug}", web::post().to(handlers::share::post_shared))
+            .route(
+                "/s/{slug}/{path:.*}",
+                web::get().to(handlers::share::get_shared),
+            )
+            .route(
+                "/s/{slug}/{path:.*}",
+                web::post().to(handlers::share::post_shared),
+            )
+            // resumable uploads
+            .route(
+                "/uploads",
+                web::method(Method::OPTIONS).to(handlers::tus::options_upload),
+            )
+            .route("/uploads", web::post().to(handlers::tus::create_upload))
+            .route("/uploads/{id}", web::head().to(handlers::tus::head_upload))
+            .route("/uploads/{id}", web::patch().to(handlers::tus::patch_upload))
+            .route("/uploads/{id}", web::delete().to(handlers::tus::delete_upload))
+            // trash
+            .route("/trash", web::get().to(handlers::trash::get_trash))
+            .route("/trash", web::delete().to(handlers::trash::empty_trash))
+            .route("/trash/{id}", web::post().to(handlers::trash::restore_item))
+            .route("/trash/{id}", web::delete().to(handlers::trash::delete_item))
+            // copy and move
+            .route(
+                "/copy/{filename:.*}",
+                web::post().to(handlers::jobs::copy_path),
+            )
+            .route(
+                "/move/{filename:.*}",
+                web::post().to(handlers::jobs::move_path),
+            )
+            .route("/jobs", web::get().to(handlers::jobs::get_jobs))
+            .route("/jobs/{id}", web::get().to(handlers::jobs::get_job))
+            .route("/jobs/{id}", web::delete().to(handlers::jobs::delete_job))
+            // file versions
+            .route(
+                "/versions/{filename:.*}",
+                web::get().to(handlers::versions::get_versions),
+            )
+            .route(
+                "/version/{number}/{filename:.*}",
+                web::get().to(handlers::versions::get_version),
+            )
+            .route(
+                "/version/{number}/{filename:.*}",
+                web::post().to(handlers::versions::restore_version),
+            )
             .route("/", web::get().to(index))
             .service(fs::Files::new("/", "./static"))
             .default_service(web::route().to(index))
@@ -1,219 +1,230 @@
 use actix_web::{web, dev, FromRequest, HttpRequest};
-use futures::future::{err, ok, Ready};
+use futures::future::{ready, Ready};
+use std::marker::PhantomData;
+use std::ops::Deref;
 
-use crate::db::{execute, Pool};
+use crate::db::{execute, roles, Pool};
 use crate::jwt::authorize;
-use crate::models::{Queries, User};
+use crate::models::{Permission, Queries, RoleQueries, User};
 use crate::reserr::ResErr;
 
-pub struct MustLogin {
-    pub id: u32,
-    pub name: String,
-    pub email: String,
-    pub pass: String,
-    pub size: u32,
-    pub path: String,
-    pub status: u8
+// what handler needs from user, see `perm`
+pub trait Requirement {
+    const PERMISSION: Option<Permission>;
+    const ALLOW_API_KEY: bool;
+    const ERROR: &'static str;
 }
 
-pub struct MustAdmin {
-    pub id: u32,
-    pub name: String,
-    pub email: String,
-    pub pass: String,
-    pub size: u32,
-    pub path: String,
-    pub status: u8
-}
+pub mod perm {
+    use super::Requirement;
+    use crate::models::Permission;
+
+    macro_rules! requirement {
+        ($($t:ident => $permission:expr, $api_key:expr, $error:expr;)+) => {
+            $(pub struct $t;
+
+            impl Requirement for $t {
+                const PERMISSION: Option<Permission> = $permission;
+                const ALLOW_API_KEY: bool = $api_key;
+                const ERROR: &'static str = $error;
+            })*
+        }
+    }
 
-pub struct MustAdminOrOp {
-    pub id: u32,
-    pub name: String,
-    pub email: String,
-    pub pass: String,
-    pub size: u32,
-    pub path: String,
-    pub status: u8
+    requirement! {
+        LoggedIn => None, false, "api key cant be used here";
+        Read => Some(Permission::Read), true, "cant download";
+        Write => Some(Permission::Write), true, "cant upload";
+        Delete => Some(Permission::Delete), true, "cant delete";
+        Rename => Some(Permission::Rename), true, "cant rename";
+        Share => Some(Permission::Share), true, "cant share";
+        ManageUsers => Some(Permission::ManageUsers), true, "cant manage users";
+        ManageRoles => Some(Permission::ManageRoles), true, "cant manage roles";
+        ManageSettings => Some(Permission::ManageSettings), true, "cant manage settings";
+    }
 }
 
-pub struct CanUpload {
-    pub id: u32,
-    pub name: String,
-    pub email: String,
-    pub pass: String,
-    pub size: u32,
-    pub path: String,
-    pub status: u8
+pub struct Must<R: Requirement> {
+    pub user: User,
+    pub permissions: Vec<Permission>,
+    requirement: PhantomData<R>,
 }
 
-pub struct CanDownload {
-    pub id: u32,
-    pub name: String,
-    pub email: String,
-    pub pass: String,
-    pub size: u32,
-    pub path: String,
-    pub status: u8
+impl<R: Requirement> Deref for Must<R> {
+    type Target = User;
+
+    fn deref(&self) -> &User {
+        &self.user
+    }
 }
 
-macro_rules! impl_T {
-    (for $($t:ident),+) => {
-        $(impl From<User> for $t {
-            fn from(user: User) -> Self {
-                let User { id, name, email, pass, size, path, status } = user;
-                $t {
-                    id,
-                    name,
-                    email,
-                    pass,
-                    size,
-                    path,
-                    status
-                }
-            }
-        })*
+impl<R: Requirement> Must<R> {
+    pub fn can(&self, permission: Permission) -> bool {
+        self.permissions.contains(&permission)
     }
 }
 
-impl_T!(for MustLogin, MustAdmin, MustAdminOrOp, CanUpload, CanDownload);
+pub fn role_permissions(db: &Pool, role: u8) -> Result<Vec<Permission>, ResErr> {
+    Ok((roles::execute(db, RoleQueries::GetRoleById(role))
+        .map_err(|_| ResErr::BadClientData("role dont exist"))?)
+    .one()
+    .permissions)
+}
 
-impl FromRequest for MustLogin {
-    type Error = ResErr;
-    type Future = Ready<Result<MustLogin, ResErr>>;
-    type Config = ();
+fn authenticate<R: Requirement>(req: &HttpRequest) -> Result<Must<R>, ResErr> {
+    let db = req
+        .app_data::<web::Data<Pool>>()
+        .ok_or(ResErr::BadClientData("cant use db"))?;
 
-    fn from_request(req: &HttpRequest, _payload: &mut dev::Payload) -> Self::Future {
-        if let Some(db) = req.app_data::<web::Data<Pool>>() {
-            match authorize(&req) {
-                Ok(token) => {
-                    let res = match execute(&db, Queries::GetUserById(token.id)){
-                        Ok(v) => v.one(),
-                        Err(_) => return err(ResErr::BadClientData("cant get user"))
-                    };
-                    ok(MustLogin::from(res))
-                },
-                Err(error) => err(ResErr::BadClientData(error))
-            }
-        }else{
-            err(ResErr::BadClientData("cant use db"))
-        }
+    let token = authorize(req).map_err(ResErr::BadClientData)?;
+
+    // user is known, but isnt allowed to do it
+    if token.scope.is_some() && !R::ALLOW_API_KEY {
+        return Err(ResErr::Forbidden(R::ERROR));
     }
-}
 
-//pub struct MustAdmin;
+    let user = (execute(db, Queries::GetUserById(token.id))
+        .map_err(|_| ResErr::BadClientData("cant get user"))?)
+    .one();
 
-impl FromRequest for MustAdmin {
-    type Error = ResErr;
-    type Future = Ready<Result<MustAdmin, ResErr>>;
-    type Config = ();
+    let mut permissions = role_permissions(db, user.status)?;
 
-    fn from_request(req: &HttpRequest, _payload: &mut dev::Payload) -> Self::Future {
+    // api key never has more rights than its scope
+    if let Some(scope) = token.scope {
+        let allowed = role_permissions(db, scope)?;
+        permissions.retain(|p| allowed.contains(p));
+    }
 
-        if let Some(db) = req.app_data::<web::Data<Pool>>() {
-            match authorize(&req) {
-                Ok(token) => {
-                    let res = match execute(&db, Queries::GetUserById(token.id)){
-                        Ok(v) => v.one(),
-                        Err(_) => return err(ResErr::BadClientData("cant get user"))
-                    };
-
-                    if res.status == 1 {
-                        ok(MustAdmin::from(res))
-                    }else{
-                        err(ResErr::BadClientData("must be Admin"))
-                    }
-                },
-                Err(error) => err(ResErr::BadClientData(error))
-            }
-        }else{
-            err(ResErr::BadClientData("cant use db"))
+    if let Some(permission) = R::PERMISSION {
+        if !permissions.contains(&permission) {
+            return Err(ResErr::Forbidden(R::ERROR));
         }
     }
+
+    Ok(Must {
+        user,
+        permissions,
+        requirement: PhantomData,
+    })
 }
 
-impl FromRequest for MustAdminOrOp {
+impl<R: Requirement> FromRequest for Must<R> {
     type Error = ResErr;
-    type Future = Ready<Result<MustAdminOrOp, ResErr>>;
+    type Future = Ready<Result<Must<R>, ResErr>>;
     type Config = ();
 
     fn from_request(req: &HttpRequest, _payload: &mut dev::Payload) -> Self::Future {
-
-        if let Some(db) = req.app_data::<web::Data<Pool>>() {
-            match authorize(&req) {
-                Ok(token) => {
-                    let res = match execute(&db, Queries::GetUserById(token.id)){
-                        Ok(v) => v.one(),
-                        Err(_) => return err(ResErr::BadClientData("cant get user"))
-                    };
-
-                    if res.status == 1 || res.status == 2 {
-                        ok(MustAdminOrOp::from(res))
-                    }else{
-                        err(ResErr::BadClientData("must be Admin or Op"))
-                    }
-                },
-                Err(error) => err(ResErr::BadClientData(error))
-            }
-        }else{
-            err(ResErr::BadClientData("cant use db"))
-        }
+        ready(authenticate(req))
     }
 }
 
-impl FromRequest for CanUpload {
-    type Error = ResErr;
-    type Future = Ready<Result<CanUpload, ResErr>>;
-    type Config = ();
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, App};
+    use chrono::Utc;
+
+    use crate::db::api_keys;
+    use crate::db::roles::{UPLOADER, VIEWER};
+    use crate::handlers::file::{get_file, post_file};
+    use crate::handlers::testing::{add_user, multipart, put, setup, token};
+    use crate::handlers::user::{delete_api_key, get_api_keys};
+    use crate::jwt::API_KEY_PREFIX;
+    use crate::models::{ApiKeyQueries, NewApiKey};
+    use crate::utils::{hash_token, random_token};
+
+    use super::*;
+
+    // key straight in database, so owner can have less rights than scope
+    fn add_key(db: &Pool, user_id: u32, scope: &str, expires: Option<i64>) -> (u32, String) {
+        let secret = format!("{}{}", API_KEY_PREFIX, random_token(40));
+        let key = NewApiKey {
+            name: scope.to_string(),
+            scope: scope.to_string(),
+            expires,
+        };
+        let query = ApiKeyQueries::AddApiKey(user_id, web::Json(key), hash_token(&secret));
+        let key = (api_keys::execute(db, query).unwrap()).one();
+
+        (key.id, secret)
+    }
 
-    fn from_request(req: &HttpRequest, _payload: &mut dev::Payload) -> Self::Future {
+    fn upload(key: &str) -> test::TestRequest {
+        multipart(test::TestRequest::post().uri("/file/").header("token", key), &[("a.txt", "hello")])
+    }
 
-        if let Some(db) = req.app_data::<web::Data<Pool>>() {
-            match authorize(&req) {
-                Ok(token) => {
-                    let res = match execute(&db, Queries::GetUserById(token.id)){
-                        Ok(v) => v.one(),
-                        Err(_) => return err(ResErr::BadClientData("cant get user"))
-                    };
-
-                    if res.status == 1 || res.status == 2 || res.status == 3 {
-                        ok(CanUpload::from(res))
-                    }else{
-                        err(ResErr::BadClientData("cant upload"))
-                    }
-                },
-                Err(error) => err(ResErr::BadClientData(error))
-            }
-        }else{
-            err(ResErr::BadClientData("cant use db"))
-        }
+    fn download(key: &str) -> test::TestRequest {
+        test::TestRequest::get().uri("/file/a.txt").header("token", key)
     }
-}
 
-impl FromRequest for CanDownload {
-    type Error = ResErr;
-    type Future = Ready<Result<CanDownload, ResErr>>;
-    type Config = ();
+    macro_rules! app {
+        ($db:expr, $storage:expr) => {
+            test::init_service(
+                App::new()
+                    .app_data($db.clone())
+                    .app_data($storage.clone())
+                    .route("/file/{filename:.*}", web::get().to(get_file))
+                    .route("/file/{filename:.*}", web::post().to(post_file))
+                    .route("/user/keys", web::get().to(get_api_keys))
+                    .route("/user/keys/{id}", web::delete().to(delete_api_key)),
+            )
+            .await
+        };
+    }
 
-    fn from_request(req: &HttpRequest, _payload: &mut dev::Payload) -> Self::Future {
+    #[actix_rt::test]
+    async fn read_key_cant_upload() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        let (_, read) = add_key(&db, bob.id, "read", None);
+        let (_, write) = add_key(&db, bob.id, "write", None);
+        let mut app = app!(db, storage);
+
+        assert_eq!(test::call_service(&mut app, upload(&read).to_request()).await.status(), 403);
+        assert_eq!(test::call_service(&mut app, upload(&write).to_request()).await.status(), 200);
+        assert_eq!(test::call_service(&mut app, download(&read).to_request()).await.status(), 200);
+    }
 
-        if let Some(db) = req.app_data::<web::Data<Pool>>() {
-            match authorize(&req) {
-                Ok(token) => {
-                    let res = match execute(&db, Queries::GetUserById(token.id)){
-                        Ok(v) => v.one(),
-                        Err(_) => return err(ResErr::BadClientData("cant get user"))
-                    };
-
-                    if res.status == 1 || res.status == 2 || res.status == 3 || res.status == 4 {
-                        ok(CanDownload::from(res))
-                    }else{
-                        err(ResErr::BadClientData("cant download"))
-                    }
-                },
-                Err(error) => err(ResErr::BadClientData(error))
-            }
-        }else{
-            err(ResErr::BadClientData("cant use db"))
-        }
+    #[actix_rt::test]
+    async fn revoked_or_expired_key_is_refused() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        let (id, revoked) = add_key(&db, bob.id, "write", None);
+        let (_, expired) = add_key(&db, bob.id, "write", Some(Utc::now().timestamp() - 1));
+        let (_, valid) = add_key(&db, bob.id, "write", Some(Utc::now().timestamp() + 60));
+        let token = token(&db, &bob);
+        let mut app = app!(db, storage);
+
+        assert_eq!(test::call_service(&mut app, upload(&revoked).to_request()).await.status(), 200);
+        let req = test::TestRequest::delete().uri(&format!("/user/keys/{}", id)).header("token", token);
+        assert_eq!(test::call_service(&mut app, req.to_request()).await.status(), 200);
+
+        assert_eq!(test::call_service(&mut app, download(&revoked).to_request()).await.status(), 400);
+        assert_eq!(test::call_service(&mut app, download(&expired).to_request()).await.status(), 400);
+        assert_eq!(test::call_service(&mut app, download(&valid).to_request()).await.status(), 200);
+    }
+
+    #[actix_rt::test]
+    async fn key_never_has_more_rights_than_owner() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", VIEWER, 1);
+        let (_, write) = add_key(&db, bob.id, "write", None);
+        put(&storage, "/bob/a.txt", "hello");
+        let mut app = app!(db, storage);
+
+        assert_eq!(test::call_service(&mut app, upload(&write).to_request()).await.status(), 403);
+        assert_eq!(test::call_service(&mut app, download(&write).to_request()).await.status(), 200);
     }
-}
\ No newline at end of file
+
+    #[actix_rt::test]
+    async fn key_cant_manage_account() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        let (id, write) = add_key(&db, bob.id, "write", None);
+        let mut app = app!(db, storage);
+
+        let req = test::TestRequest::get().uri("/user/keys").header("token", write.as_str());
+        assert_eq!(test::call_service(&mut app, req.to_request()).await.status(), 403);
+        let req = test::TestRequest::delete().uri(&format!("/user/keys/{}", id)).header("token", write.as_str());
+        assert_eq!(test::call_service(&mut app, req.to_request()).await.status(), 403);
+    }
+}
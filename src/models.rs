@@ -1,6 +1,7 @@
-use crate::utils::{valid_pass, validate_path};
+use crate::utils::{valid_group_name, valid_pass, validate_path};
 use actix_web::web::Json;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use validator::Validate;
 
 #[derive(Debug, Serialize, Deserialize, Clone, Validate)]
@@ -16,6 +17,9 @@ pub struct User {
     #[validate(custom = "validate_path")]
     pub path: String,
     pub status: u8,
+    // when email was verified, set only by server
+    #[serde(default, skip_deserializing)]
+    pub verified: Option<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -23,6 +27,28 @@ pub struct Rename {
     pub name: String,
 }
 
+// what happens when uploaded file has name of existing one
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Conflict {
+    #[default]
+    Fail,
+    Overwrite,
+    Rename,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ConflictOptions {
+    #[serde(default)]
+    pub conflict: Conflict,
+}
+
+// names under which files were stored, they differ from sent ones after rename
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Saved {
+    pub files: Vec<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Validate)]
 pub struct ChangingUser {
     #[validate(length(min = 4, max = 20, code = "name min 4 max 20 letters"))]
@@ -41,6 +67,9 @@ pub enum Queries {
     UpdateUserById(u32, Json<User>),
     UpdateMeById(u32, Json<ChangingUser>),
     GetUserByEmail(String),
+    UpdatePassById(u32, String),
+    SetVerifiedById(u32),
+    GetUsersByGroup(u32),
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -53,7 +82,10 @@ pub struct File {
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Claims {
     pub id: u32,
+    pub sid: u32,
     pub exp: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<u8>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -65,6 +97,32 @@ pub struct Login {
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Token {
     pub token: String,
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Refresh {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Session {
+    pub id: u32,
+    pub user_id: u32,
+    pub token_hash: String,
+    pub created: i64,
+    pub expires: i64,
+    pub revoked: bool,
+}
+
+pub enum SessionQueries {
+    AddSession(u32, String, i64),
+    GetSessionById(u32),
+    GetSessionByHash(String),
+    RotateSession(u32, String, i64),
+    RevokeSession(u32),
+    // sessions of user except one which stays
+    RevokeUserSessions(u32, Option<u32>),
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -72,3 +130,936 @@ pub struct Folder {
     pub name: String,
     pub folders: Vec<Folder>,
 }
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Totp {
+    pub user_id: u32,
+    pub secret: String,
+    pub enabled: bool,
+}
+
+pub enum TotpQueries {
+    GetTotp(u32),
+    SetSecret(u32, String, Vec<String>),
+    EnableTotp(u32),
+    DeleteTotp(u32),
+    UseRecoveryCode(u32, String),
+    UseStep(u32, i64),
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub uri: String,
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TotpCode {
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ChallengeClaims {
+    pub id: u32,
+    pub challenge: bool,
+    pub exp: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Challenge {
+    pub challenge: String,
+    pub enroll: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ChallengeLogin {
+    pub challenge: String,
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ChallengeEnroll {
+    pub challenge: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Settings {
+    pub require_2fa: bool,
+}
+
+pub enum SettingQueries {
+    GetSetting(String),
+    SetSetting(String, String),
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ApiKey {
+    pub id: u32,
+    pub user_id: u32,
+    pub name: String,
+    pub created: i64,
+    pub last_used: Option<i64>,
+    pub expires: Option<i64>,
+    pub scope: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Validate)]
+pub struct NewApiKey {
+    #[validate(length(min = 1, max = 50, code = "name min 1 max 50 letters"))]
+    pub name: String,
+    pub scope: String,
+    pub expires: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreatedApiKey {
+    pub key: String,
+    pub api_key: ApiKey,
+}
+
+pub enum ApiKeyQueries {
+    AddApiKey(u32, Json<NewApiKey>, String),
+    GetApiKeyByHash(String),
+    GetUserApiKeys(u32),
+    DeleteApiKey(u32, u32),
+    DeleteUserApiKeys(u32),
+    TouchApiKey(u32),
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct LoginAttempt {
+    #[serde(skip)]
+    pub key: String,
+    pub failures: u32,
+    pub last_failure: i64,
+    pub locked_until: i64,
+    pub unlocked_at: Option<i64>,
+    pub unlocked_by: Option<u32>,
+}
+
+pub enum AttemptQueries {
+    GetAttempt(String),
+    SaveAttempt(LoginAttempt),
+    ClearAttempts(String),
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserDetail {
+    #[serde(flatten)]
+    pub user: User,
+    pub locked: bool,
+    pub lock: LoginAttempt,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MailToken {
+    pub user_id: u32,
+    pub kind: String,
+    pub expires: i64,
+    pub used: bool,
+}
+
+pub enum MailTokenQueries {
+    Issue(u32, &'static str, String, i64),
+    Consume(&'static str, String),
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Forgot {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Validate)]
+pub struct Reset {
+    pub token: String,
+    #[validate(length(min = 8, code = "pass min 8 letters"), custom = "valid_pass")]
+    pub pass: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Verify {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    Read,
+    Write,
+    Delete,
+    Rename,
+    Share,
+    ManageUsers,
+    ManageRoles,
+    ManageSettings,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 8] = [
+        Permission::Read,
+        Permission::Write,
+        Permission::Delete,
+        Permission::Rename,
+        Permission::Share,
+        Permission::ManageUsers,
+        Permission::ManageRoles,
+        Permission::ManageSettings,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Permission::Read => "read",
+            Permission::Write => "write",
+            Permission::Delete => "delete",
+            Permission::Rename => "rename",
+            Permission::Share => "share",
+            Permission::ManageUsers => "manage_users",
+            Permission::ManageRoles => "manage_roles",
+            Permission::ManageSettings => "manage_settings",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Permission> {
+        Permission::ALL.iter().copied().find(|p| p.as_str() == name)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Role {
+    pub id: u8,
+    pub name: String,
+    pub permissions: Vec<Permission>,
+    pub builtin: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Validate)]
+pub struct NewRole {
+    #[validate(length(min = 3, max = 20, code = "name min 3 max 20 letters"))]
+    pub name: String,
+    pub permissions: Vec<Permission>,
+}
+
+pub enum RoleQueries {
+    GetRoleById(u8),
+    GetAllRoles,
+    AddRole(Json<NewRole>),
+    UpdateRoleById(u8, Json<NewRole>),
+    DeleteRoleById(u8),
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AclKind {
+    User,
+    Group,
+    Everyone,
+}
+
+impl AclKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AclKind::User => "user",
+            AclKind::Group => "group",
+            AclKind::Everyone => "everyone",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<AclKind> {
+        match name {
+            "user" => Some(AclKind::User),
+            "group" => Some(AclKind::Group),
+            "everyone" => Some(AclKind::Everyone),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AclEntry {
+    pub id: u32,
+    pub path: String,
+    pub kind: AclKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject: Option<u32>,
+    pub permissions: Vec<Permission>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct NewAclEntry {
+    pub kind: AclKind,
+    #[serde(default)]
+    pub subject: Option<u32>,
+    pub permissions: Vec<Permission>,
+}
+
+pub enum AclQueries {
+    GetEntriesByPath(String),
+    GetEntriesForUser(u32),
+    SetEntries(String, Vec<NewAclEntry>),
+    DeleteUserEntries(u32),
+    DeleteGroupEntries(u32),
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Validate)]
+pub struct Group {
+    #[serde(default)]
+    pub id: u32,
+    #[validate(
+        length(min = 3, max = 20, code = "name min 3 max 20 letters"),
+        custom = "valid_group_name"
+    )]
+    pub name: String,
+    pub size: u32,
+    #[validate(custom = "validate_path")]
+    pub path: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GroupDetail {
+    #[serde(flatten)]
+    pub group: Group,
+    pub members: Vec<User>,
+}
+
+pub enum GroupQueries {
+    GetGroupById(u32),
+    GetAllGroups,
+    GetUserGroups(u32),
+    AddGroup(Json<Group>),
+    UpdateGroupById(u32, Json<Group>),
+    DeleteGroupById(u32),
+    AddMember(u32, u32),
+    RemoveMember(u32, u32),
+    DeleteUserMemberships(u32),
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ShareMode {
+    #[default]
+    Read,
+    Upload,
+    Drop,
+}
+
+impl ShareMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ShareMode::Read => "read",
+            ShareMode::Upload => "upload",
+            ShareMode::Drop => "drop",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<ShareMode> {
+        match name {
+            "read" => Some(ShareMode::Read),
+            "upload" => Some(ShareMode::Upload),
+            "drop" => Some(ShareMode::Drop),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Share {
+    pub id: u32,
+    pub user_id: u32,
+    pub slug: String,
+    pub path: String,
+    #[serde(skip)]
+    pub pass: Option<String>,
+    pub password: bool,
+    pub mode: ShareMode,
+    pub created: i64,
+    pub expires: Option<i64>,
+    pub max_downloads: Option<u32>,
+    pub downloads: u32,
+    pub max_file_size: Option<u64>,
+    pub max_files: Option<u32>,
+    pub files: u32,
+    pub extensions: Option<Vec<String>>,
+}
+
+// what visitor of upload link needs to know
+#[derive(Debug, Serialize)]
+pub struct ShareInfo {
+    pub mode: ShareMode,
+    pub expires: Option<i64>,
+    pub max_file_size: Option<u64>,
+    pub max_files: Option<u32>,
+    pub files: u32,
+    pub extensions: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Validate)]
+pub struct NewShare {
+    pub path: String,
+    #[serde(default)]
+    #[validate(length(min = 4, code = "password min 4 letters"))]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub mode: ShareMode,
+    #[serde(default)]
+    pub expires: Option<i64>,
+    #[serde(default)]
+    pub max_downloads: Option<u32>,
+    #[serde(default)]
+    pub max_file_size: Option<u64>,
+    #[serde(default)]
+    pub max_files: Option<u32>,
+    #[serde(default)]
+    pub extensions: Option<Vec<String>>,
+}
+
+pub enum ShareQueries {
+    AddShare(u32, String, Json<NewShare>),
+    GetShareBySlug(String),
+    GetUserShares(u32),
+    DeleteShare(u32, u32),
+    DeleteUserShares(u32),
+    CountDownload(u32),
+    CountFile(u32),
+    UncountFile(u32),
+}
+
+// unfinished resumable upload, path is kept as user sees it
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Upload {
+    pub id: String,
+    pub user_id: u32,
+    pub path: String,
+    pub target: String,
+    pub length: u64,
+    pub offset: u64,
+    pub created: i64,
+    pub expires: i64,
+}
+
+pub enum UploadQueries {
+    AddUpload(Upload),
+    GetUpload(String),
+    // uploads into home
+    GetUploadsIn(String),
+    GetUserUploads(u32),
+    GetExpiredUploads(i64),
+    SetOffset(String, u64, u64),
+    DeleteUpload(String),
+}
+
+// bytes taken by files inside home folder of user or group
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Usage {
+    pub path: String,
+    pub used: u64,
+}
+
+pub enum UsageQueries {
+    GetUsage(String),
+    GetAllUsage,
+    SetUsed(String, u64),
+    AddUsed(String, i64),
+    DeleteUsage(String),
+}
+
+#[derive(Debug, Serialize)]
+pub struct Me {
+    #[serde(flatten)]
+    pub user: User,
+    pub used: u64,
+    pub free: u64,
+}
+
+// deleted file or folder waits in trash of user who deleted it, path is kept as user saw it
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TrashItem {
+    pub id: String,
+    pub user_id: u32,
+    pub path: String,
+    #[serde(skip_serializing)]
+    pub target: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub deleted: i64,
+}
+
+pub enum TrashQueries {
+    AddItem(TrashItem),
+    GetItem(String),
+    // items which came from home
+    GetItemsIn(String),
+    GetUserItems(u32),
+    GetExpiredItems(i64),
+    DeleteItem(String),
+}
+
+// who stored current content of file and its sha256, path is where it is in storage
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FileMeta {
+    pub path: String,
+    pub user_id: Option<u32>,
+    pub checksum: String,
+    pub created: i64,
+}
+
+pub enum FileQueries {
+    GetFile(String),
+    SetFile(FileMeta),
+    MoveFiles(String, String),
+    DeleteFiles(String),
+}
+
+// older content of file, number grows with every overwrite
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Version {
+    pub id: String,
+    pub path: String,
+    pub number: u32,
+    pub user_id: Option<u32>,
+    pub size: u64,
+    pub checksum: String,
+    pub created: i64,
+}
+
+pub enum VersionQueries {
+    AddVersion(Version),
+    GetVersion(String, u32),
+    GetVersions(String),
+    GetAllVersions,
+    // versions of files in home
+    GetVersionsIn(String),
+    MoveVersions(String, String),
+    DeleteVersion(String),
+}
+
+// version as client sees it, size_diff is how much bigger it is than content which replaced it
+#[derive(Debug, Serialize)]
+pub struct VersionInfo {
+    pub number: u32,
+    pub user_id: Option<u32>,
+    pub size: u64,
+    pub size_diff: i64,
+    pub checksum: String,
+    pub same_as_current: bool,
+    pub created: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    Copy,
+    Move,
+}
+
+impl JobKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobKind::Copy => "copy",
+            JobKind::Move => "move",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<JobKind> {
+        match name {
+            "copy" => Some(JobKind::Copy),
+            "move" => Some(JobKind::Move),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobStatus::Running => "running",
+            JobStatus::Done => "done",
+            JobStatus::Failed => "failed",
+            JobStatus::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<JobStatus> {
+        match name {
+            "running" => Some(JobStatus::Running),
+            "done" => Some(JobStatus::Done),
+            "failed" => Some(JobStatus::Failed),
+            "cancelled" => Some(JobStatus::Cancelled),
+            _ => None,
+        }
+    }
+}
+
+// copy or move running in background, from and to are paths as user sent them,
+// target is where it goes in storage, progress is in bytes and files
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Job {
+    pub id: String,
+    pub user_id: u32,
+    pub kind: JobKind,
+    pub from: String,
+    pub to: String,
+    #[serde(skip)]
+    pub source: String,
+    #[serde(skip)]
+    pub target: String,
+    pub status: JobStatus,
+    pub total: u64,
+    pub done: u64,
+    pub files: u32,
+    pub files_done: u32,
+    pub error: Option<String>,
+    pub created: i64,
+    pub finished: Option<i64>,
+}
+
+pub enum JobQueries {
+    AddJob(Job),
+    GetJob(String),
+    GetUserJobs(u32),
+    GetRunningJobs,
+    SetProgress(String, u64, u32),
+    // only running job can be finished, so cancel isnt overwritten by worker
+    FinishJob(String, JobStatus, Option<String>, i64),
+    // jobs which were running when app stopped
+    FailRunningJobs(String, i64),
+    DeleteFinishedJobs(i64),
+    DeleteJob(String),
+}
+
+// destination of copy or move, path as in other routes
+#[derive(Debug, Deserialize)]
+pub struct Transfer {
+    pub to: String,
+}
+
+// files and folders downloaded together as zip, paths as in other routes
+#[derive(Debug, Deserialize)]
+pub struct Selection {
+    pub paths: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryStatus {
+    Created,
+    Replaced,
+    Renamed,
+    Exists,
+    Skipped,
+}
+
+// what happened with one entry of extracted archive, path is where it was stored
+#[derive(Debug, Serialize)]
+pub struct ExtractedEntry {
+    pub name: String,
+    pub path: Option<String>,
+    pub status: EntryStatus,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Extracted {
+    pub size: u64,
+    pub entries: Vec<ExtractedEntry>,
+}
+
+// content stored once in blob store, refs counts paths which point to it
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Blob {
+    pub hash: String,
+    pub size: u64,
+    pub refs: u32,
+}
+
+pub enum BlobQueries {
+    GetBlob(String),
+    GetOrphans,
+    GetDropped,
+    DeleteBlob(String),
+}
+
+// file as user sees it, its content is blob with hash
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BlobPath {
+    pub path: String,
+    pub hash: String,
+    pub size: u64,
+    pub modified: i64,
+    pub created: i64,
+}
+
+pub enum BlobPathQueries {
+    GetPath(String),
+    GetChildren(String),
+    GetPathsOf(String),
+    AddPath(BlobPath),
+    SetPath(BlobPath),
+    MovePaths(String, String),
+    DeletePaths(String),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryType {
+    File,
+    Folder,
+}
+
+// user or group whose home path is in
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Owner {
+    pub kind: AclKind,
+    pub id: u32,
+    pub name: String,
+}
+
+// everything about file or folder, times are rfc 3339 and null when storage dont know them
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Stat {
+    pub name: String,
+    pub path: String,
+    #[serde(rename = "type")]
+    pub kind: EntryType,
+    pub size: u64,
+    pub created: Option<String>,
+    pub modified: Option<String>,
+    pub mime: Option<String>,
+    pub checksum: Option<String>,
+    pub owner: Option<Owner>,
+    pub permissions: Vec<Permission>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListOptions {
+    #[serde(default)]
+    pub extended: bool,
+    pub tag: Option<String>,
+    #[serde(default)]
+    pub favorite: bool,
+    pub property: Option<String>,
+    pub property_value: Option<String>,
+}
+
+impl ListOptions {
+    pub fn labels(&self) -> LabelFilter {
+        LabelFilter::new(&self.tag, self.favorite, &self.property, &self.property_value)
+    }
+}
+
+// file or folder in search index, path is in storage
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IndexEntry {
+    pub path: String,
+    pub name: String,
+    pub ext: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<i64>,
+    // when entry was seen last, reindex removes what it didnt see
+    pub indexed: i64,
+}
+
+// limits of search which database checks, only regex and glob are matched after
+#[derive(Debug, Clone, Default)]
+pub struct IndexFilter {
+    pub is_dir: Option<bool>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub after: Option<i64>,
+    pub before: Option<i64>,
+    pub exts: Vec<String>,
+    // lowercase part of name
+    pub name: Option<String>,
+    // labels of user which entry has to have
+    pub labeled: Option<(u32, LabelFilter)>,
+    // folders with own acl, deepest first, and whether user can read them
+    pub readable: Vec<(String, bool)>,
+}
+
+pub enum IndexQueries {
+    SetEntry(IndexEntry),
+    MoveEntries(String, String),
+    DeleteEntries(String),
+    DeleteStale(i64),
+    // root, filter, limit and offset
+    Search(String, IndexFilter, Option<(u64, u64)>),
+}
+
+// how q of search is matched against names, always without case
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchMode {
+    #[default]
+    Substring,
+    Glob,
+    Regex,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchOptions {
+    #[serde(default)]
+    pub q: String,
+    #[serde(default)]
+    pub mode: MatchMode,
+    #[serde(rename = "type")]
+    pub kind: Option<EntryType>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub modified_after: Option<String>,
+    pub modified_before: Option<String>,
+    // comma separated, without dots
+    pub ext: Option<String>,
+    pub tag: Option<String>,
+    #[serde(default)]
+    pub favorite: bool,
+    pub property: Option<String>,
+    pub property_value: Option<String>,
+    #[serde(default = "first_page")]
+    pub page: u32,
+    #[serde(default = "page_size")]
+    pub per_page: u32,
+}
+
+impl SearchOptions {
+    pub fn labels(&self) -> LabelFilter {
+        LabelFilter::new(&self.tag, self.favorite, &self.property, &self.property_value)
+    }
+}
+
+fn first_page() -> u32 {
+    1
+}
+
+fn page_size() -> u32 {
+    50
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    pub name: String,
+    pub path: String,
+    #[serde(rename = "type")]
+    pub kind: EntryType,
+    pub size: u64,
+    pub modified: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResults {
+    pub total: usize,
+    pub page: u32,
+    pub per_page: u32,
+    pub results: Vec<SearchHit>,
+}
+
+// file whose text is in content index, it is read again when its size or time changes
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ContentFile {
+    pub id: i64,
+    pub path: String,
+    pub size: u64,
+    pub modified: Option<i64>,
+}
+
+pub enum ContentQueries {
+    GetFiles,
+    SetContent(ContentFile, String),
+    MoveFiles(String, String),
+    DeleteFiles(String),
+}
+
+// score is higher for better match
+#[derive(Debug, Clone)]
+pub struct ContentHit {
+    pub path: String,
+    pub snippet: String,
+    pub score: f64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ContentSearchOptions {
+    pub q: String,
+    #[serde(default = "first_page")]
+    pub page: u32,
+    #[serde(default = "page_size")]
+    pub per_page: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ContentResult {
+    pub name: String,
+    pub path: String,
+    pub snippet: String,
+    pub score: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ContentResults {
+    pub total: usize,
+    pub page: u32,
+    pub per_page: u32,
+    pub results: Vec<ContentResult>,
+}
+
+// tags, favorite of caller and properties of file or folder, id stays the same when it is moved
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Labels {
+    pub id: Option<i64>,
+    #[serde(skip)]
+    pub path: String,
+    pub tags: Vec<String>,
+    pub favorite: bool,
+    pub properties: BTreeMap<String, String>,
+}
+
+// what labeled paths have to have, property can be only set or have exact value
+#[derive(Debug, Clone, Default)]
+pub struct LabelFilter {
+    pub tag: Option<String>,
+    pub favorite: bool,
+    pub property: Option<String>,
+    pub value: Option<String>,
+}
+
+impl LabelFilter {
+    pub fn new(tag: &Option<String>, favorite: bool, property: &Option<String>, value: &Option<String>) -> LabelFilter {
+        LabelFilter {
+            tag: tag.clone(),
+            favorite,
+            property: property.clone(),
+            value: value.clone(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tag.is_none() && !self.favorite && self.property.is_none() && self.value.is_none()
+    }
+}
+
+pub enum LabelQueries {
+    GetLabels(String, u32),
+    FindLabeled(u32, LabelFilter),
+    AddTag(String, String),
+    RemoveTag(String, String),
+    SetFavorite(String, u32, bool),
+    SetProperty(String, String, Option<String>),
+    MoveLabels(String, String),
+    DeleteLabels(String),
+    DeleteUserFavorites(u32),
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct PropertyValue {
+    #[validate(length(max = 1000, code = "value max 1000 letters"))]
+    pub value: String,
+}
@@ -0,0 +1,217 @@
+use actix_web::web;
+use std::time::Duration;
+use std::{env, thread};
+
+use crate::acl::{normalize, Home};
+use crate::db::{execute, groups, uploads, usage, Pool};
+use crate::jobs;
+use crate::models::{GroupQueries, Queries, UploadQueries, UsageQueries};
+use crate::reserr::ResErr;
+use crate::storage::Store;
+use crate::trash;
+use crate::utils::dir_size;
+use crate::versions;
+
+// files in home, their old versions and what was deleted from it into trash
+fn count(db: &Pool, storage: &Store, path: &str) -> Result<u64, ResErr> {
+    let used = dir_size(storage, path)
+        .map_err(|_| ResErr::InternalError("folder size counter is broaken"))?;
+
+    Ok(used + trash::trashed(db, path)? + versions::versioned(db, path)?)
+}
+
+// bytes used in home are kept in database, storage is walked only when home is seen first time
+pub fn used(db: &Pool, storage: &Store, path: &str) -> Result<u64, ResErr> {
+    if let Ok(v) = usage::execute(db, UsageQueries::GetUsage(path.to_string())) {
+        return Ok(v.one().used);
+    }
+
+    let used = count(db, storage, path)?;
+
+    (usage::execute(db, UsageQueries::SetUsed(path.to_string(), used))
+        .map_err(|_| ResErr::InternalError("cant save usage"))?)
+    .none();
+    Ok(used)
+}
+
+// bytes which can still be stored in home, unfinished resumable uploads and running copies
+// have their size reserved, reservation of upload `skip` is left out
+pub fn free(db: &Pool, storage: &Store, home: &Home, skip: Option<&str>) -> Result<u64, ResErr> {
+    let pending: u64 = (uploads::execute(db, UploadQueries::GetUploadsIn(home.path.clone()))
+        .map_err(|_| ResErr::InternalError("cant get uploads"))?)
+    .many()
+    .iter()
+    .filter(|u| Some(u.id.as_str()) != skip)
+    .map(|u| u.length)
+    .sum::<u64>()
+        + jobs::pending(db, &home.path)?;
+
+    Ok((home.size as u64 * 1000000).saturating_sub(used(db, storage, &home.path)? + pending))
+}
+
+// size of files on path changed, every home containing it is changed too
+pub fn add(db: &Pool, path: &str, delta: i64) -> Result<(), ResErr> {
+    (usage::execute(db, UsageQueries::AddUsed(path.to_string(), delta))
+        .map_err(|_| ResErr::InternalError("cant save usage"))?)
+    .none();
+    Ok(())
+}
+
+// bytes on path, file or whole folder, nothing when it dont exist
+pub fn size_of(storage: &Store, path: &str) -> Result<u64, ResErr> {
+    match storage.stat(path) {
+        Ok(meta) if meta.is_dir => {
+            dir_size(storage, path).map_err(|_| ResErr::InternalError("folder size counter is broaken"))
+        }
+        Ok(meta) => Ok(meta.size),
+        Err(_) => Ok(0),
+    }
+}
+
+// counters can drift when storage is changed outside of app or request fails halfway,
+// so they are counted again from storage, homes nobody uses anymore are forgotten
+pub fn reconcile(db: &Pool, storage: &Store) -> Result<(), ResErr> {
+    let users = (execute(db, Queries::GetAllUsers).map_err(|_| ResErr::InternalError("cant get users"))?)
+        .many();
+    let groups = (groups::execute(db, GroupQueries::GetAllGroups)
+        .map_err(|_| ResErr::InternalError("cant get groups"))?)
+    .many();
+
+    let mut homes = Vec::new();
+    for path in users.iter().map(|u| &u.path).chain(groups.iter().map(|g| &g.path)) {
+        homes.push(normalize(path)?);
+    }
+
+    let rows = (usage::execute(db, UsageQueries::GetAllUsage)
+        .map_err(|_| ResErr::InternalError("cant get usage"))?)
+    .many();
+
+    for row in rows {
+        let query = match homes.contains(&row.path) {
+            true => match count(db, storage, &row.path) {
+                Ok(used) => UsageQueries::SetUsed(row.path, used),
+                Err(_) => continue,
+            },
+            false => UsageQueries::DeleteUsage(row.path),
+        };
+
+        (usage::execute(db, query).map_err(|_| ResErr::InternalError("cant save usage"))?).none();
+    }
+    Ok(())
+}
+
+// runs at start, storage could change while app was down, and then every QUOTA_RECONCILE hours
+pub fn start_reconcile(db: Pool, storage: web::Data<Store>) {
+    let hours: u64 = env::var("QUOTA_RECONCILE").unwrap().parse().unwrap();
+
+    thread::spawn(move || loop {
+        if reconcile(&db, &storage).is_err() {
+            eprintln!("quota reconciliation failed");
+        }
+        thread::sleep(Duration::from_secs(hours * 60 * 60));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{test_pool, trash as trash_items, versions as history};
+    use crate::models::{TrashItem, TrashQueries, Upload, Version, VersionQueries};
+    use crate::storage::memory::MemoryStorage;
+
+    fn setup() -> Pool {
+        let db = test_pool();
+        uploads::create_table(&db);
+        usage::create_table(&db);
+        crate::db::jobs::create_table(&db);
+        trash_items::create_table(&db);
+        history::create_table(&db);
+        db
+    }
+
+    fn upload(db: &Pool, id: &str, target: &str, length: u64) {
+        let upload = Upload {
+            id: id.to_string(),
+            user_id: 1,
+            path: target.to_string(),
+            target: target.to_string(),
+            length,
+            offset: 0,
+            created: 0,
+            expires: i64::MAX,
+        };
+        uploads::execute(db, UploadQueries::AddUpload(upload)).unwrap();
+    }
+
+    fn trashed(db: &Pool, id: &str, target: &str, size: u64) {
+        let item = TrashItem {
+            id: id.to_string(),
+            user_id: 1,
+            path: format!("{}/{}", trash::TRASH, id),
+            target: target.to_string(),
+            is_dir: false,
+            size,
+            deleted: 0,
+        };
+        trash_items::execute(db, TrashQueries::AddItem(item)).unwrap();
+    }
+
+    fn versioned(db: &Pool, id: &str, path: &str, size: u64) {
+        let version = Version {
+            id: id.to_string(),
+            path: path.to_string(),
+            number: 0,
+            user_id: None,
+            size,
+            checksum: String::new(),
+            created: 0,
+        };
+        history::execute(db, VersionQueries::AddVersion(version)).unwrap();
+    }
+
+    fn home(path: &str) -> Home {
+        Home {
+            path: path.to_string(),
+            size: 1,
+        }
+    }
+
+    // home with similar name is another home, not a folder inside it
+    #[test]
+    fn only_paths_inside_home_are_counted() {
+        let db = setup();
+
+        trashed(&db, "a", "/bob/a.txt", 10);
+        trashed(&db, "b", "/bob/sub/b.txt", 20);
+        trashed(&db, "c", "/bobby/c.txt", 40);
+        versioned(&db, "a", "/bob/a.txt", 1);
+        versioned(&db, "b", "/bob-x/b.txt", 2);
+        versioned(&db, "c", &format!("{}/a/a.txt", trash::TRASH), 4);
+
+        assert_eq!(trash::trashed(&db, "/bob").unwrap(), 30);
+        assert_eq!(trash::trashed(&db, "/").unwrap(), 70);
+        assert_eq!(versions::versioned(&db, "/bob").unwrap(), 1);
+        assert_eq!(versions::versioned(&db, "/").unwrap(), 3);
+    }
+
+    #[test]
+    fn unfinished_uploads_are_reserved() {
+        let db = setup();
+        let storage: Store = Box::<MemoryStorage>::default();
+        storage.mkdir("/bob").unwrap();
+
+        upload(&db, "a", "/bob/a.bin", 100);
+        upload(&db, "b", "/bob/sub/b.bin", 200);
+        upload(&db, "c", "/bobby/c.bin", 400);
+
+        assert_eq!(free(&db, &storage, &home("/bob"), None).unwrap(), 1000000 - 300);
+        assert_eq!(free(&db, &storage, &home("/bob"), Some("b")).unwrap(), 1000000 - 100);
+        assert_eq!(free(&db, &storage, &home("/"), None).unwrap(), 1000000 - 700);
+
+        // stored bytes count into every home containing them
+        used(&db, &storage, "/").unwrap();
+        add(&db, "/bob/a.bin", 50).unwrap();
+        assert_eq!(free(&db, &storage, &home("/bob"), None).unwrap(), 1000000 - 350);
+        assert_eq!(free(&db, &storage, &home("/"), None).unwrap(), 1000000 - 750);
+    }
+}
@@ -8,14 +8,20 @@ pub enum ResErr {
     InternalError(&'static str),
     BadClientData(&'static str),
     BadClientDataOwned(String),
+    Forbidden(&'static str),
+    TooManyRequests(&'static str),
+    PreconditionFailed(&'static str),
 }
 
 impl Display for ResErr {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match &self {
-            &ResErr::InternalError(s) => write!(f, "{}", s),
-            &ResErr::BadClientData(s) => write!(f, "{}", s),
-            &ResErr::BadClientDataOwned(s) => write!(f, "{}", s),
+            ResErr::InternalError(s) => write!(f, "{}", s),
+            ResErr::BadClientData(s) => write!(f, "{}", s),
+            ResErr::BadClientDataOwned(s) => write!(f, "{}", s),
+            ResErr::Forbidden(s) => write!(f, "{}", s),
+            ResErr::TooManyRequests(s) => write!(f, "{}", s),
+            ResErr::PreconditionFailed(s) => write!(f, "{}", s),
         }
     }
 }
@@ -32,6 +38,9 @@ impl ResponseError for ResErr {
             ResErr::InternalError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ResErr::BadClientData(_) => StatusCode::BAD_REQUEST,
             ResErr::BadClientDataOwned(_) => StatusCode::BAD_REQUEST,
+            ResErr::Forbidden(_) => StatusCode::FORBIDDEN,
+            ResErr::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            ResErr::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
         }
     }
 }
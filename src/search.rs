@@ -0,0 +1,245 @@
+use actix_web::web;
+use chrono::Utc;
+use regex::{Regex, RegexBuilder};
+use std::time::{Duration, UNIX_EPOCH};
+use std::{env, thread};
+
+use crate::acl::hidden;
+use crate::db::{index, Pool};
+use crate::fulltext;
+use crate::models::{IndexEntry, IndexQueries, MatchMode};
+use crate::reserr::ResErr;
+use crate::storage::{self, Meta, Store};
+
+// names of files and folders are kept in database, so search dont walk storage,
+// storage writes every change into it and reindex finds what was changed outside of app
+
+// "archive.tar.gz" has "gz", ".bashrc" and folders have none
+fn ext(name: &str, is_dir: bool) -> String {
+    match name.rsplit_once('.') {
+        Some((base, ext)) if !is_dir && !base.is_empty() => ext.to_lowercase(),
+        _ => String::new(),
+    }
+}
+
+fn entry(path: &str, meta: &Meta, indexed: i64) -> IndexEntry {
+    let name = storage::name(path);
+
+    IndexEntry {
+        path: path.to_string(),
+        ext: ext(&name, meta.is_dir),
+        name,
+        is_dir: meta.is_dir,
+        size: meta.size,
+        modified: meta
+            .modified
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64),
+        indexed,
+    }
+}
+
+// path gets into index as it is in storage now, or is removed with everything inside when it is gone
+pub fn update(db: &Pool, storage: &Store, path: &str) {
+    if path == "/" || hidden(path) {
+        return;
+    }
+
+    let query = match storage.stat(path) {
+        Ok(meta) => IndexQueries::SetEntry(entry(path, &meta, Utc::now().timestamp())),
+        Err(_) => IndexQueries::DeleteEntries(path.to_string()),
+    };
+
+    if index::execute(db, query).is_err() {
+        eprintln!("cant update search index");
+    }
+}
+
+// same as update, for everything inside folder too
+pub fn update_tree(db: &Pool, storage: &Store, path: &str) {
+    update(db, storage, path);
+
+    if add_tree(db, storage, path, Utc::now().timestamp()).is_err() {
+        eprintln!("cant update search index");
+    }
+}
+
+pub fn remove(db: &Pool, path: &str) {
+    if index::execute(db, IndexQueries::DeleteEntries(path.to_string())).is_err() {
+        eprintln!("cant update search index");
+    }
+}
+
+pub fn rename(db: &Pool, from: &str, to: &str) {
+    if from == to {
+        return;
+    }
+
+    let moved = index::execute(db, IndexQueries::DeleteEntries(to.to_string()))
+        .and_then(|_| index::execute(db, IndexQueries::MoveEntries(from.to_string(), to.to_string())));
+
+    if moved.is_err() {
+        eprintln!("cant update search index");
+    }
+}
+
+// what cant be listed is left out, hidden folders like trash too
+fn add_tree(db: &Pool, storage: &Store, path: &str, indexed: i64) -> Result<(), ResErr> {
+    let list = match storage.list(path) {
+        Ok(list) => list,
+        Err(_) => return Ok(()),
+    };
+
+    for meta in list.iter() {
+        let full = format!("{}/{}", path.trim_end_matches('/'), meta.name);
+        if hidden(&full) {
+            continue;
+        }
+
+        (index::execute(db, IndexQueries::SetEntry(entry(&full, meta, indexed)))
+            .map_err(|_| ResErr::InternalError("cant update search index"))?)
+        .none();
+
+        if meta.is_dir {
+            add_tree(db, storage, &full, indexed)?;
+        }
+    }
+    Ok(())
+}
+
+// whole storage is walked, entries not seen since start are gone,
+// changes made meanwhile have newer time, so they stay
+pub fn reindex(db: &Pool, storage: &Store) -> Result<(), ResErr> {
+    let started = Utc::now().timestamp();
+    add_tree(db, storage, "/", started)?;
+
+    (index::execute(db, IndexQueries::DeleteStale(started))
+        .map_err(|_| ResErr::InternalError("cant update search index"))?)
+    .none();
+    Ok(())
+}
+
+pub fn start_reindex(db: Pool, storage: web::Data<Store>) {
+    let hours: u64 = env::var("SEARCH_REINDEX").unwrap().parse().unwrap();
+
+    thread::spawn(move || loop {
+        if reindex(&db, &storage).is_err() {
+            eprintln!("search reindex failed");
+        } else if fulltext::enabled() && fulltext::reindex(&db, &storage).is_err() {
+            eprintln!("content reindex failed");
+        }
+        thread::sleep(Duration::from_secs(hours * 60 * 60));
+    });
+}
+
+// glob has "*", "?" and "[...]" with "!" for negation, everything else is literal
+fn glob(pattern: &str) -> String {
+    let mut re = String::from("^");
+    let mut chars = pattern.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            '[' => {
+                let mut class = String::new();
+                let mut closed = false;
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    class.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        closed = true;
+                        break;
+                    }
+                    match c {
+                        '\\' | '[' | '^' | '&' | '~' => class.push_str(&regex::escape(&c.to_string())),
+                        c => class.push(c),
+                    }
+                }
+                match closed {
+                    true => re.push_str(&format!("[{}]", class)),
+                    false => re.push_str(&regex::escape(&format!("[{}", class))),
+                }
+            }
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    re
+}
+
+// names are matched without case, empty query matches everything
+pub enum Matcher {
+    All,
+    Substring(String),
+    Pattern(Regex),
+}
+
+impl Matcher {
+    pub fn new(query: &str, mode: MatchMode) -> Result<Matcher, ResErr> {
+        if query.is_empty() {
+            return Ok(Matcher::All);
+        }
+
+        let pattern = match mode {
+            MatchMode::Substring => return Ok(Matcher::Substring(query.to_lowercase())),
+            MatchMode::Glob => glob(query),
+            MatchMode::Regex => query.to_string(),
+        };
+
+        RegexBuilder::new(&pattern)
+            .case_insensitive(true)
+            .size_limit(1 << 20)
+            .build()
+            .map(Matcher::Pattern)
+            .map_err(|_| ResErr::BadClientData("invalid pattern"))
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            Matcher::All => true,
+            Matcher::Substring(query) => name.to_lowercase().contains(query.as_str()),
+            Matcher::Pattern(re) => re.is_match(name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_becomes_anchored_regex() {
+        assert_eq!(glob("*.txt"), r"^.*\.txt$");
+        assert_eq!(glob("file?"), "^file.$");
+        assert_eq!(glob("[abc]*"), "^[abc].*$");
+        assert_eq!(glob("[!abc]*"), "^[^abc].*$");
+        assert_eq!(glob("[a-c]"), "^[a-c]$");
+    }
+
+    // what means something in regex is literal in glob, also inside of class
+    #[test]
+    fn glob_escapes_everything_else() {
+        assert_eq!(glob("a+b (1).$"), r"^a\+b \(1\)\.\$$");
+        assert_eq!(glob("[^a]"), r"^[\^a]$");
+        assert_eq!(glob(r"[\[]"), r"^[\\\[]$");
+        assert_eq!(glob("[ab"), r"^\[ab$");
+    }
+
+    #[test]
+    fn patterns_match_whole_name_without_case() {
+        let matcher = Matcher::new("*.TXT", MatchMode::Glob).unwrap();
+        assert!(matcher.matches("notes.txt"));
+        assert!(!matcher.matches("notes.txt.bak"));
+
+        let matcher = Matcher::new("[!abc]*", MatchMode::Glob).unwrap();
+        assert!(matcher.matches("report"));
+        assert!(!matcher.matches("Apple"));
+
+        assert!(Matcher::new("a.b", MatchMode::Glob).unwrap().matches("A.B"));
+        assert!(!Matcher::new("a.b", MatchMode::Glob).unwrap().matches("axb"));
+        assert!(Matcher::new("(", MatchMode::Regex).is_err());
+    }
+}
@@ -0,0 +1,102 @@
+use mime_guess::mime;
+use std::io::Read;
+
+use crate::storage::Store;
+
+// type is found from first bytes of file, most formats start with their own signature
+const HEAD: usize = 512;
+
+// container formats which are base of many others, e.g. docx, jar and epub are zip
+const CONTAINERS: [&str; 2] = ["application/zip", "application/xml"];
+
+fn signature(head: &[u8]) -> Option<&'static str> {
+    let starts = |sig: &[u8]| head.starts_with(sig);
+    let at = |offset: usize, sig: &[u8]| head.len() >= offset + sig.len() && &head[offset..offset + sig.len()] == sig;
+
+    let mime = match () {
+        _ if starts(b"\x89PNG\r\n\x1a\n") => "image/png",
+        _ if starts(b"\xff\xd8\xff") => "image/jpeg",
+        _ if starts(b"GIF87a") || starts(b"GIF89a") => "image/gif",
+        _ if starts(b"RIFF") && at(8, b"WEBP") => "image/webp",
+        _ if starts(b"BM") && head.len() >= 14 => "image/bmp",
+        _ if starts(b"II*\0") || starts(b"MM\0*") => "image/tiff",
+        _ if starts(b"\0\0\x01\0") => "image/x-icon",
+        _ if starts(b"%PDF-") => "application/pdf",
+        _ if starts(b"PK\x03\x04") || starts(b"PK\x05\x06") => "application/zip",
+        _ if starts(b"\x1f\x8b") => "application/gzip",
+        _ if starts(b"BZh") => "application/x-bzip2",
+        _ if starts(b"\xfd7zXZ\0") => "application/x-xz",
+        _ if starts(b"7z\xbc\xaf\x27\x1c") => "application/x-7z-compressed",
+        _ if starts(b"Rar!\x1a\x07") => "application/vnd.rar",
+        _ if at(257, b"ustar") => "application/x-tar",
+        _ if starts(b"SQLite format 3\0") => "application/vnd.sqlite3",
+        _ if starts(b"\x7fELF") => "application/x-executable",
+        _ if starts(b"MZ") => "application/vnd.microsoft.portable-executable",
+        _ if starts(b"\0asm") => "application/wasm",
+        _ if starts(b"ID3") || starts(b"\xff\xfb") || starts(b"\xff\xf3") => "audio/mpeg",
+        _ if starts(b"fLaC") => "audio/flac",
+        _ if starts(b"OggS") => "audio/ogg",
+        _ if starts(b"RIFF") && at(8, b"WAVE") => "audio/wav",
+        _ if starts(b"RIFF") && at(8, b"AVI ") => "video/x-msvideo",
+        _ if at(4, b"ftypqt") => "video/quicktime",
+        _ if at(4, b"ftyp") => "video/mp4",
+        _ if starts(b"\x1a\x45\xdf\xa3") => "video/webm",
+        _ if starts(b"%!PS") => "application/postscript",
+        _ => return markup(head),
+    };
+    Some(mime)
+}
+
+// text formats which say what they are at their start
+fn markup(head: &[u8]) -> Option<&'static str> {
+    let text = String::from_utf8_lossy(head);
+    let text = text.trim_start_matches('\u{feff}').trim_start().to_lowercase();
+
+    match () {
+        _ if text.starts_with("<!doctype html") || text.starts_with("<html") => Some("text/html"),
+        _ if text.starts_with("<svg") => Some("image/svg+xml"),
+        _ if text.starts_with("<?xml") && text.contains("<svg") => Some("image/svg+xml"),
+        _ if text.starts_with("<?xml") => Some("application/xml"),
+        _ => None,
+    }
+}
+
+// no zero bytes and valid utf-8, last character can be cut in half
+pub fn is_text(head: &[u8]) -> bool {
+    if head.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(err) => err.error_len().is_none(),
+    }
+}
+
+// type only from name, listing of folder cant read every file in it
+pub fn guess(path: &str) -> String {
+    mime_guess::from_path(path).first_or_octet_stream().to_string()
+}
+
+// content wins over wrong extension, but extension tells more about files in containers,
+// like docx inside zip or rss inside xml
+pub fn mime(storage: &Store, path: &str) -> String {
+    let mut head = Vec::with_capacity(HEAD);
+    if let Ok(reader) = storage.read(path) {
+        let _ = reader.take(HEAD as u64).read_to_end(&mut head);
+    }
+
+    let by_name = mime_guess::from_path(path).first();
+    let by_content = signature(&head);
+
+    match (by_content, by_name) {
+        (Some(content), Some(name)) if content == name.essence_str() => name.to_string(),
+        (Some(content), Some(name)) if CONTAINERS.contains(&content) && name.type_() != mime::TEXT => {
+            name.to_string()
+        }
+        (Some(content), _) => content.to_string(),
+        (None, Some(name)) => name.to_string(),
+        (None, None) if head.is_empty() => "application/octet-stream".to_string(),
+        (None, None) if is_text(&head) => "text/plain".to_string(),
+        (None, None) => "application/octet-stream".to_string(),
+    }
+}
@@ -0,0 +1,300 @@
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use std::{env, fs};
+
+pub mod dedup;
+pub mod indexed;
+pub mod local;
+pub mod memory;
+pub mod s3;
+
+use actix_web::error::BlockingError;
+use actix_web::web;
+
+use crate::db::Pool;
+use crate::reserr::ResErr;
+use dedup::DedupStorage;
+use indexed::IndexedStorage;
+use local::LocalStorage;
+use memory::MemoryStorage;
+use s3::S3Storage;
+
+// every path given to storage is normalized, root of storage is "/"
+pub struct Meta {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    // not every storage knows it
+    pub created: Option<SystemTime>,
+}
+
+// file is complete only after finish, without it file can be half written or missing
+pub trait Writer: Write + Send {
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+pub trait Storage: Send + Sync {
+    fn read(&self, path: &str) -> io::Result<Box<dyn Read + Send>>;
+
+    // what was on path is replaced by finish, until then it stays untouched
+    fn write(&self, path: &str) -> io::Result<Box<dyn Writer>>;
+
+    // same as write, but finish fails with AlreadyExists when path is taken
+    fn create(&self, path: &str) -> io::Result<Box<dyn Writer>>;
+
+    fn list(&self, path: &str) -> io::Result<Vec<Meta>>;
+
+    fn stat(&self, path: &str) -> io::Result<Meta>;
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()>;
+
+    // folder is deleted with everything inside
+    fn delete(&self, path: &str) -> io::Result<()>;
+
+    // missing parents are created too
+    fn mkdir(&self, path: &str) -> io::Result<()>;
+
+    // moves finished local file into storage, it appears there at once and whole
+    fn import(&self, file: &Path, path: &str) -> io::Result<()> {
+        let mut writer = self.write(path)?;
+        io::copy(&mut fs::File::open(file)?, &mut writer)?;
+        writer.finish()?;
+
+        fs::remove_file(file)
+    }
+
+    // writer which gives path content with this sha256 without sending it again,
+    // None when storage doesnt have such content
+    fn link(&self, _hash: &str, _path: &str, _overwrite: bool) -> io::Result<Option<Box<dyn Writer>>> {
+        Ok(None)
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.stat(path).is_ok()
+    }
+
+    // only file on local disk can be sent by NamedFile, others are streamed
+    fn local_path(&self, _path: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+pub type Store = Box<dyn Storage>;
+
+// last part of path, "" for root
+pub fn name(path: &str) -> String {
+    path.rsplit('/').next().unwrap_or("").to_string()
+}
+
+// every call of storage blocks, with s3 it is http request,
+// so handlers run whole work with storage in threadpool
+pub async fn blocking<T, F>(f: F) -> Result<T, ResErr>
+where
+    F: FnOnce() -> Result<T, ResErr> + Send + 'static,
+    T: Send + 'static,
+{
+    web::block(f).await.map_err(|err| match err {
+        BlockingError::Error(err) => err,
+        BlockingError::Canceled => ResErr::InternalError("storage is not available"),
+    })
+}
+
+// with DEDUP every content is stored only once, whatever storage is under it,
+// search index sees every change made through app
+pub fn from_env(db: &Pool) -> Store {
+    let storage: Store = match env::var("STORAGE").unwrap().as_str() {
+        "local" => Box::new(LocalStorage::new(env::var("CLOUD_PATH").unwrap())),
+        "s3" => Box::new(
+            S3Storage::new(
+                &env::var("S3_ENDPOINT").unwrap(),
+                &env::var("S3_BUCKET").unwrap(),
+                &env::var("S3_REGION").unwrap(),
+                &env::var("S3_ACCESS_KEY").unwrap(),
+                &env::var("S3_SECRET_KEY").unwrap(),
+            )
+            .unwrap(),
+        ),
+        "memory" => Box::new(MemoryStorage::new()),
+        other => panic!("unknown STORAGE {}", other),
+    };
+
+    let storage: Store = match env::var("DEDUP").unwrap() == "true" {
+        true => Box::new(DedupStorage::new(storage, db.clone())),
+        false => storage,
+    };
+
+    Box::new(IndexedStorage::new(storage, db.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{blob_paths, blobs, test_pool};
+    use sha2::{Digest, Sha256};
+
+    fn put(storage: &dyn Storage, path: &str, content: &str) -> io::Result<()> {
+        let mut writer = storage.create(path)?;
+        writer.write_all(content.as_bytes())?;
+        writer.finish()
+    }
+
+    fn content(storage: &dyn Storage, path: &str) -> String {
+        let mut content = String::new();
+        storage.read(path).unwrap().read_to_string(&mut content).unwrap();
+        content
+    }
+
+    fn names(storage: &dyn Storage, path: &str) -> Vec<String> {
+        let mut names: Vec<String> = storage.list(path).unwrap().into_iter().map(|m| m.name).collect();
+        names.sort();
+        names
+    }
+
+    // handlers count on every backend doing the same, so all of them get same checks
+    fn behaves_same(storage: &dyn Storage) {
+        storage.mkdir("/a/b").unwrap();
+        assert!(storage.stat("/a/b").unwrap().is_dir);
+
+        put(storage, "/a/one.txt", "first").unwrap();
+        assert_eq!(content(storage, "/a/one.txt"), "first");
+        assert_eq!(storage.stat("/a/one.txt").unwrap().size, 5);
+        assert_eq!(names(storage, "/a"), vec!["b", "one.txt"]);
+
+        // unfinished file is not there, finished create cant replace file
+        let mut writer = storage.create("/a/two.txt").unwrap();
+        writer.write_all(b"second").unwrap();
+        assert!(!storage.exists("/a/two.txt"));
+        writer.finish().unwrap();
+        let err = put(storage, "/a/one.txt", "other").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(content(storage, "/a/one.txt"), "first");
+
+        let mut writer = storage.write("/a/one.txt").unwrap();
+        writer.write_all(b"replaced").unwrap();
+        writer.finish().unwrap();
+        assert_eq!(content(storage, "/a/one.txt"), "replaced");
+
+        assert!(storage.create("/missing/file.txt").is_err());
+        assert!(storage.read("/a/missing.txt").is_err());
+        assert!(storage.read("/a/b").is_err());
+
+        // folder is moved with everything inside
+        put(storage, "/a/b/inner.txt", "inner").unwrap();
+        storage.rename("/a/b", "/c").unwrap();
+        assert!(!storage.exists("/a/b"));
+        assert_eq!(content(storage, "/c/inner.txt"), "inner");
+
+        storage.rename("/a/two.txt", "/a/one.txt").unwrap();
+        assert_eq!(content(storage, "/a/one.txt"), "second");
+        assert!(!storage.exists("/a/two.txt"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let local = dir.path().join("imported");
+        fs::write(&local, "imported").unwrap();
+        storage.import(&local, "/c/imported.txt").unwrap();
+        assert_eq!(content(storage, "/c/imported.txt"), "imported");
+        assert!(!local.exists());
+
+        storage.delete("/c").unwrap();
+        assert!(!storage.exists("/c"));
+        assert!(!storage.exists("/c/inner.txt"));
+        assert!(storage.delete("/c").is_err());
+        assert_eq!(names(storage, "/"), vec!["a"]);
+    }
+
+    fn dedup(inner: Store) -> DedupStorage {
+        let db = test_pool();
+        blob_paths::create_table(&db);
+        blobs::create_table(&db);
+
+        DedupStorage::new(inner, db)
+    }
+
+    #[test]
+    fn memory_storage() {
+        behaves_same(&MemoryStorage::new());
+    }
+
+    #[test]
+    fn local_storage() {
+        let dir = tempfile::tempdir().unwrap();
+
+        behaves_same(&LocalStorage::new(dir.path().to_string_lossy()));
+    }
+
+    #[test]
+    fn dedup_storage() {
+        behaves_same(&dedup(Box::new(MemoryStorage::new())));
+    }
+
+    // blobs are only on disk, so local storage shows how many of them are kept
+    #[test]
+    fn dedup_keeps_content_until_last_path_is_gone() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = dedup(Box::new(LocalStorage::new(dir.path().to_string_lossy())));
+        let blobs = || {
+            fs::read_dir(dir.path().join(".blobs"))
+                .unwrap()
+                .map(|e| e.unwrap())
+                .filter(|e| e.file_name() != "tmp")
+                .map(|e| fs::read_dir(e.path()).unwrap().count())
+                .sum::<usize>()
+        };
+
+        put(&storage, "/one.txt", "same").unwrap();
+        put(&storage, "/two.txt", "same").unwrap();
+        assert_eq!(blobs(), 1);
+
+        let hash = hex::encode(Sha256::digest(b"same"));
+        let mut linked = storage.link(&hash, "/three.txt", false).unwrap().unwrap();
+        assert!(linked.write(b"x").is_err());
+        linked.finish().unwrap();
+        assert_eq!(content(&storage, "/three.txt"), "same");
+        assert_eq!(blobs(), 1);
+
+        storage.delete("/one.txt").unwrap();
+        storage.rename("/two.txt", "/moved.txt").unwrap();
+        assert_eq!(blobs(), 1);
+
+        storage.delete("/moved.txt").unwrap();
+        storage.delete("/three.txt").unwrap();
+        assert_eq!(blobs(), 0);
+        assert!(storage.link(&hash, "/four.txt", false).unwrap().is_none());
+    }
+
+    // changes look only at blobs they took last path from, other orphans wait for start
+    #[test]
+    fn dedup_sweeps_every_orphan_only_on_start() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = test_pool();
+        blob_paths::create_table(&db);
+        blobs::create_table(&db);
+        let storage = DedupStorage::new(Box::new(LocalStorage::new(dir.path().to_string_lossy())), db.clone());
+
+        let orphan = hex::encode(Sha256::digest(b"lost"));
+        fs::create_dir_all(dir.path().join(".blobs").join(&orphan[..2])).unwrap();
+        fs::write(dir.path().join(".blobs").join(&orphan[..2]).join(&orphan), "lost").unwrap();
+        db.get()
+            .unwrap()
+            .execute("INSERT INTO Blobs (hash, size, refs) VALUES(?1, 4, 0)", &[&orphan])
+            .unwrap();
+
+        put(&storage, "/a.txt", "one").unwrap();
+        let mut writer = storage.write("/a.txt").unwrap();
+        writer.write_all(b"two").unwrap();
+        writer.finish().unwrap();
+        storage.delete("/a.txt").unwrap();
+
+        let exists = |content: &[u8]| {
+            let hash = hex::encode(Sha256::digest(content));
+            dir.path().join(".blobs").join(&hash[..2]).join(&hash).exists()
+        };
+        assert!(!exists(b"one") && !exists(b"two"));
+        assert!(exists(b"lost"));
+
+        DedupStorage::new(Box::new(LocalStorage::new(dir.path().to_string_lossy())), db);
+        assert!(!exists(b"lost"));
+    }
+}
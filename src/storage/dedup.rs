@@ -0,0 +1,466 @@
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::{name, Meta, Storage, Store, Writer};
+use crate::db::{blob_paths, blobs, Pool};
+use crate::models::{BlobPath, BlobPathQueries, BlobQueries};
+use crate::utils::random_token;
+
+// content of every file is kept once in inner storage under its sha256,
+// paths of files point to it through BlobPaths, folders stay in inner storage,
+// files from before dedup was turned on stay there too and are read as they are
+const BLOBS: &str = "/.blobs";
+const TMP: &str = "/.blobs/tmp";
+
+#[derive(Clone)]
+pub struct DedupStorage {
+    inner: Arc<Store>,
+    db: Pool,
+    // blob and its refs are changed together, so blob cant be removed while it gets new path
+    lock: Arc<Mutex<()>>,
+}
+
+fn blob(hash: &str) -> String {
+    format!("{}/{}/{}", BLOBS, &hash[..2], hash)
+}
+
+fn internal(path: &str) -> bool {
+    path == BLOBS || path.starts_with(&format!("{}/", BLOBS))
+}
+
+fn parent(path: &str) -> &str {
+    match path.rsplit_once('/') {
+        Some(("", _)) | None => "/",
+        Some((parent, _)) => parent,
+    }
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "path dont exist")
+}
+
+fn db_err(_: rusqlite::Error) -> io::Error {
+    io::Error::other("cant reach blob database")
+}
+
+fn time(timestamp: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(timestamp.max(0) as u64)
+}
+
+fn to_meta(file: &BlobPath) -> Meta {
+    Meta {
+        name: name(&file.path),
+        is_dir: false,
+        size: file.size,
+        modified: Some(time(file.modified)),
+        created: Some(time(file.created)),
+    }
+}
+
+// content is hashed while it is written into temporary blob, it gets its name on finish
+struct DedupWriter {
+    storage: DedupStorage,
+    writer: Box<dyn Writer>,
+    hasher: Sha256,
+    size: u64,
+    tmp: String,
+    path: String,
+    overwrite: bool,
+}
+
+impl Write for DedupWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.writer.write(buf)?;
+        self.hasher.update(&buf[..len]);
+        self.size += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Writer for DedupWriter {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        let hash = hex::encode(self.hasher.finalize());
+        self.writer.finish()?;
+
+        self.storage
+            .store(Some(&self.tmp), &hash, self.size, &self.path, self.overwrite)
+    }
+}
+
+// path gets blob which is stored already, nothing is written
+struct LinkWriter {
+    storage: DedupStorage,
+    hash: String,
+    size: u64,
+    path: String,
+    overwrite: bool,
+}
+
+impl Write for LinkWriter {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::other("linked file has its content already"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Writer for LinkWriter {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.storage
+            .store(None, &self.hash, self.size, &self.path, self.overwrite)
+    }
+}
+
+impl DedupStorage {
+    // temporary blobs left by crash are removed, same as blobs nobody points to
+    pub fn new(inner: Store, db: Pool) -> DedupStorage {
+        let storage = DedupStorage {
+            inner: Arc::new(inner),
+            db,
+            lock: Arc::new(Mutex::new(())),
+        };
+
+        let _ = storage.inner.delete(TMP);
+        storage.inner.mkdir(TMP).unwrap();
+
+        if storage.sweep().is_err() {
+            eprintln!("cant remove unused blobs");
+        }
+        storage
+    }
+
+    fn mapped(&self, path: &str) -> Option<BlobPath> {
+        blob_paths::execute(&self.db, BlobPathQueries::GetPath(path.to_string()))
+            .ok()
+            .map(|v| v.one())
+    }
+
+    // blob is removed from inner storage and database, lock has to be held
+    fn remove(&self, hash: &str) -> io::Result<()> {
+        match self.inner.delete(&blob(hash)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => (),
+        }
+        blobs::execute(&self.db, BlobQueries::DeleteBlob(hash.to_string()))
+            .map_err(db_err)?
+            .none();
+        Ok(())
+    }
+
+    // every blob without paths is removed, it is done only on start
+    fn sweep(&self) -> io::Result<()> {
+        let orphans = blobs::execute(&self.db, BlobQueries::GetOrphans)
+            .map_err(db_err)?
+            .many();
+
+        orphans.iter().try_for_each(|orphan| self.remove(&orphan.hash))
+    }
+
+    // blobs which lost their last path by last change are removed, lock has to be held
+    fn collect(&self) -> io::Result<()> {
+        let dropped = blobs::execute(&self.db, BlobQueries::GetDropped)
+            .map_err(db_err)?
+            .many();
+
+        for blob in dropped.iter() {
+            if blob.refs > 0 {
+                // it got new path, so it only stops being dropped
+                blobs::execute(&self.db, BlobQueries::DeleteBlob(blob.hash.clone()))
+                    .map_err(db_err)?
+                    .none();
+                continue;
+            }
+            self.remove(&blob.hash)?;
+        }
+        Ok(())
+    }
+
+    // path starts pointing to blob, tmp becomes the blob when there is none with same hash yet
+    fn store(&self, tmp: Option<&str>, hash: &str, size: u64, path: &str, overwrite: bool) -> io::Result<()> {
+        let _lock = self.lock.lock().unwrap();
+
+        let checked = self.check(path, overwrite);
+        let target = blob(hash);
+
+        let placed = match (checked, tmp) {
+            (Err(err), Some(tmp)) => {
+                let _ = self.inner.delete(tmp);
+                return Err(err);
+            }
+            (Err(err), None) => return Err(err),
+            (Ok(()), None) if !self.inner.exists(&target) => return Err(not_found()),
+            (Ok(()), None) => false,
+            (Ok(()), Some(tmp)) if self.inner.exists(&target) => {
+                self.inner.delete(tmp)?;
+                false
+            }
+            (Ok(()), Some(tmp)) => {
+                self.inner.mkdir(parent(&target))?;
+                self.inner.rename(tmp, &target)?;
+                true
+            }
+        };
+
+        let now = Utc::now().timestamp();
+        let file = BlobPath {
+            path: path.to_string(),
+            hash: hash.to_string(),
+            size,
+            modified: now,
+            created: now,
+        };
+        let query = match overwrite {
+            true => BlobPathQueries::SetPath(file),
+            false => BlobPathQueries::AddPath(file),
+        };
+
+        if let Err(err) = blob_paths::execute(&self.db, query) {
+            if placed {
+                let _ = self.inner.delete(&target);
+            }
+            return Err(match err {
+                rusqlite::Error::SqliteFailure(e, _) if e.code == rusqlite::ErrorCode::ConstraintViolation => {
+                    io::Error::new(io::ErrorKind::AlreadyExists, "file already exists")
+                }
+                err => db_err(err),
+            });
+        }
+
+        // file from before dedup is replaced by blob
+        if self.inner.exists(path) {
+            self.inner.delete(path)?;
+        }
+        self.collect()
+    }
+
+    // parent has to be folder, path cant be folder and when it is created it has to be free
+    fn check(&self, path: &str, overwrite: bool) -> io::Result<()> {
+        match self.inner.stat(parent(path)) {
+            Ok(meta) if meta.is_dir => (),
+            _ => return Err(not_found()),
+        }
+
+        match self.inner.stat(path) {
+            Ok(meta) if meta.is_dir => Err(io::Error::other("path is folder")),
+            Ok(_) if !overwrite => Err(io::Error::new(io::ErrorKind::AlreadyExists, "file already exists")),
+            _ if !overwrite && self.mapped(path).is_some() => {
+                Err(io::Error::new(io::ErrorKind::AlreadyExists, "file already exists"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn writer(&self, path: &str, overwrite: bool) -> io::Result<Box<dyn Writer>> {
+        if internal(path) {
+            return Err(not_found());
+        }
+        match self.inner.stat(parent(path)) {
+            Ok(meta) if meta.is_dir => (),
+            _ => return Err(not_found()),
+        }
+
+        let tmp = format!("{}/{}", TMP, random_token(16));
+
+        Ok(Box::new(DedupWriter {
+            storage: self.clone(),
+            writer: self.inner.write(&tmp)?,
+            hasher: Sha256::new(),
+            size: 0,
+            tmp,
+            path: path.to_string(),
+            overwrite,
+        }))
+    }
+}
+
+impl Storage for DedupStorage {
+    fn read(&self, path: &str) -> io::Result<Box<dyn Read + Send>> {
+        if internal(path) {
+            return Err(not_found());
+        }
+
+        match self.mapped(path) {
+            Some(file) => self.inner.read(&blob(&file.hash)),
+            None => self.inner.read(path),
+        }
+    }
+
+    fn write(&self, path: &str) -> io::Result<Box<dyn Writer>> {
+        self.writer(path, true)
+    }
+
+    fn create(&self, path: &str) -> io::Result<Box<dyn Writer>> {
+        self.writer(path, false)
+    }
+
+    fn list(&self, path: &str) -> io::Result<Vec<Meta>> {
+        if internal(path) {
+            return Err(not_found());
+        }
+
+        let files = blob_paths::execute(&self.db, BlobPathQueries::GetChildren(path.to_string()))
+            .map_err(db_err)?
+            .many();
+
+        let mut list: Vec<Meta> = self
+            .inner
+            .list(path)?
+            .into_iter()
+            .filter(|m| !internal(&format!("{}/{}", path.trim_end_matches('/'), m.name)))
+            .filter(|m| !files.iter().any(|f| name(&f.path) == m.name))
+            .collect();
+
+        list.extend(files.iter().map(to_meta));
+        Ok(list)
+    }
+
+    fn stat(&self, path: &str) -> io::Result<Meta> {
+        if internal(path) {
+            return Err(not_found());
+        }
+
+        match self.mapped(path) {
+            Some(file) => Ok(to_meta(&file)),
+            None => self.inner.stat(path),
+        }
+    }
+
+    // like on disk, what was on new path is replaced
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        if internal(from) || internal(to) {
+            return Err(not_found());
+        }
+        let _lock = self.lock.lock().unwrap();
+
+        match self.mapped(from) {
+            Some(_) => {
+                match self.inner.stat(parent(to)) {
+                    Ok(meta) if meta.is_dir => (),
+                    _ => return Err(not_found()),
+                }
+                if from == to {
+                    return Ok(());
+                }
+                match self.inner.stat(to) {
+                    Ok(meta) if meta.is_dir => return Err(io::Error::other("path is folder")),
+                    Ok(_) => self.inner.delete(to)?,
+                    Err(_) => (),
+                }
+            }
+            // folders and files from before dedup are moved in inner storage, files inside go after them
+            None => {
+                self.inner.rename(from, to)?;
+                if from == to {
+                    return Ok(());
+                }
+            }
+        }
+
+        blob_paths::execute(&self.db, BlobPathQueries::DeletePaths(to.to_string()))
+            .map_err(db_err)?
+            .none();
+        blob_paths::execute(&self.db, BlobPathQueries::MovePaths(from.to_string(), to.to_string()))
+            .map_err(db_err)?
+            .none();
+
+        self.collect()
+    }
+
+    fn delete(&self, path: &str) -> io::Result<()> {
+        if internal(path) {
+            return Err(not_found());
+        }
+        let _lock = self.lock.lock().unwrap();
+
+        match (self.inner.exists(path), self.mapped(path)) {
+            (false, None) => return Err(not_found()),
+            (true, _) => self.inner.delete(path)?,
+            (false, Some(_)) => (),
+        }
+
+        blob_paths::execute(&self.db, BlobPathQueries::DeletePaths(path.to_string()))
+            .map_err(db_err)?
+            .none();
+
+        self.collect()
+    }
+
+    fn mkdir(&self, path: &str) -> io::Result<()> {
+        if internal(path) {
+            return Err(not_found());
+        }
+
+        let mut current = String::new();
+        for part in path.split('/').filter(|p| !p.is_empty()) {
+            current = format!("{}/{}", current, part);
+
+            if self.mapped(&current).is_some() {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, "file is in path"));
+            }
+        }
+        self.inner.mkdir(path)
+    }
+
+    // local file is hashed first, so it is moved into inner storage only when its blob is new,
+    // blob can be removed meanwhile, then file is imported as new one
+    fn import(&self, file: &Path, path: &str) -> io::Result<()> {
+        if internal(path) {
+            return Err(not_found());
+        }
+
+        let mut hasher = Sha256::new();
+        let size = io::copy(&mut fs::File::open(file)?, &mut hasher)?;
+        let hash = hex::encode(hasher.finalize());
+
+        if self.inner.exists(&blob(&hash)) && self.store(None, &hash, size, path, true).is_ok() {
+            return fs::remove_file(file);
+        }
+
+        let tmp = format!("{}/{}", TMP, random_token(16));
+        self.inner.import(file, &tmp)?;
+
+        self.store(Some(&tmp), &hash, size, path, true)
+    }
+
+    fn link(&self, hash: &str, path: &str, overwrite: bool) -> io::Result<Option<Box<dyn Writer>>> {
+        if internal(path) {
+            return Err(not_found());
+        }
+
+        let blob = match blobs::execute(&self.db, BlobQueries::GetBlob(hash.to_string())) {
+            Ok(blob) => blob.one(),
+            Err(_) => return Ok(None),
+        };
+
+        Ok(Some(Box::new(LinkWriter {
+            storage: self.clone(),
+            hash: blob.hash,
+            size: blob.size,
+            path: path.to_string(),
+            overwrite,
+        })))
+    }
+
+    // blob on disk has no name of file, so sender has to take type from path
+    fn local_path(&self, path: &str) -> Option<PathBuf> {
+        if internal(path) {
+            return None;
+        }
+
+        match self.mapped(path) {
+            Some(file) => self.inner.local_path(&blob(&file.hash)),
+            None => self.inner.local_path(path),
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+
+use super::{Meta, Storage, Store, Writer};
+use crate::acl::hidden;
+use crate::db::{index, Pool};
+use crate::fulltext;
+use crate::labels;
+use crate::models::{IndexFilter, IndexQueries};
+use crate::search;
+
+// every change made through storage goes into search index, folder changes its time too,
+// failed update of index dont fail the change, next reindex fixes it,
+// labels of paths are moved and deleted here too, so they cant be lost by any route
+#[derive(Clone)]
+pub struct IndexedStorage {
+    inner: Arc<Store>,
+    db: Pool,
+    // stored files wait here for their text to be indexed, only with FULLTEXT
+    contents: Option<mpsc::Sender<String>>,
+}
+
+fn parent(path: &str) -> &str {
+    match path.rsplit_once('/') {
+        Some(("", _)) | None => "/",
+        Some((parent, _)) => parent,
+    }
+}
+
+struct IndexWriter {
+    storage: IndexedStorage,
+    writer: Box<dyn Writer>,
+    path: String,
+}
+
+impl Write for IndexWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Writer for IndexWriter {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.writer.finish()?;
+        self.storage.stored(&self.path);
+        Ok(())
+    }
+}
+
+impl IndexedStorage {
+    pub fn new(inner: Store, db: Pool) -> IndexedStorage {
+        let inner = Arc::new(inner);
+        let contents = match fulltext::enabled() {
+            true => Some(fulltext::start_queue(db.clone(), inner.clone())),
+            false => None,
+        };
+
+        IndexedStorage { inner, db, contents }
+    }
+
+    fn changed(&self, path: &str) {
+        search::update(&self.db, &self.inner, path);
+        search::update(&self.db, &self.inner, parent(path));
+    }
+
+    // new content of file, its text is read later
+    fn stored(&self, path: &str) {
+        self.changed(path);
+
+        if let Some(contents) = &self.contents {
+            let _ = contents.send(path.to_string());
+        }
+    }
+
+    // restored folder brings back text of all files inside
+    fn stored_tree(&self, path: &str) {
+        let filter = IndexFilter {
+            is_dir: Some(false),
+            ..IndexFilter::default()
+        };
+
+        self.stored(path);
+        if let (Some(contents), Ok(files)) = (
+            &self.contents,
+            index::execute(&self.db, IndexQueries::Search(path.to_string(), filter, None)),
+        ) {
+            for file in files.many() {
+                let _ = contents.send(file.path);
+            }
+        }
+    }
+
+    fn indexed(&self, path: &str, writer: Box<dyn Writer>) -> Box<dyn Writer> {
+        Box::new(IndexWriter {
+            storage: self.clone(),
+            writer,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl Storage for IndexedStorage {
+    fn read(&self, path: &str) -> io::Result<Box<dyn Read + Send>> {
+        self.inner.read(path)
+    }
+
+    fn write(&self, path: &str) -> io::Result<Box<dyn Writer>> {
+        Ok(self.indexed(path, self.inner.write(path)?))
+    }
+
+    fn create(&self, path: &str) -> io::Result<Box<dyn Writer>> {
+        Ok(self.indexed(path, self.inner.create(path)?))
+    }
+
+    fn list(&self, path: &str) -> io::Result<Vec<Meta>> {
+        self.inner.list(path)
+    }
+
+    fn stat(&self, path: &str) -> io::Result<Meta> {
+        self.inner.stat(path)
+    }
+
+    // things put into trash leave index, restored ones come back with everything inside
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        self.inner.rename(from, to)?;
+        labels::rename(&self.db, from, to);
+
+        match (hidden(from), hidden(to)) {
+            (false, false) => {
+                search::rename(&self.db, from, to);
+                fulltext::rename(&self.db, from, to);
+                self.changed(to);
+            }
+            (false, true) => {
+                search::remove(&self.db, from);
+                fulltext::remove(&self.db, from);
+            }
+            (true, false) => {
+                search::update_tree(&self.db, &self.inner, to);
+                self.stored_tree(to);
+            }
+            (true, true) => (),
+        }
+        search::update(&self.db, &self.inner, parent(from));
+        Ok(())
+    }
+
+    fn delete(&self, path: &str) -> io::Result<()> {
+        self.inner.delete(path)?;
+
+        search::remove(&self.db, path);
+        fulltext::remove(&self.db, path);
+        labels::remove(&self.db, path);
+        search::update(&self.db, &self.inner, parent(path));
+        Ok(())
+    }
+
+    fn mkdir(&self, path: &str) -> io::Result<()> {
+        self.inner.mkdir(path)?;
+
+        // parents could be made too
+        let mut current = String::new();
+        for part in path.split('/').filter(|p| !p.is_empty()) {
+            current = format!("{}/{}", current, part);
+            search::update(&self.db, &self.inner, &current);
+        }
+        Ok(())
+    }
+
+    fn import(&self, file: &Path, path: &str) -> io::Result<()> {
+        self.inner.import(file, path)?;
+        self.stored(path);
+        Ok(())
+    }
+
+    fn link(&self, hash: &str, path: &str, overwrite: bool) -> io::Result<Option<Box<dyn Writer>>> {
+        Ok(self
+            .inner
+            .link(hash, path, overwrite)?
+            .map(|writer| self.indexed(path, writer)))
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.inner.exists(path)
+    }
+
+    fn local_path(&self, path: &str) -> Option<PathBuf> {
+        self.inner.local_path(path)
+    }
+}
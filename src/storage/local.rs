@@ -0,0 +1,172 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use super::{name, Meta, Storage, Writer};
+use crate::utils::random_token;
+
+// files are kept on disk inside root folder, same as before storage existed
+pub struct LocalStorage {
+    root: String,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<String>) -> LocalStorage {
+        LocalStorage { root: root.into() }
+    }
+
+    fn full(&self, path: &str) -> PathBuf {
+        PathBuf::from(format!("{}{}", self.root, path))
+    }
+}
+
+fn to_meta(name: String, metadata: fs::Metadata) -> Meta {
+    Meta {
+        name,
+        is_dir: metadata.is_dir(),
+        size: if metadata.is_dir() { 0 } else { metadata.len() },
+        modified: metadata.modified().ok(),
+        created: metadata.created().ok(),
+    }
+}
+
+// hidden file next to target, rename inside one folder cant cross filesystems
+fn temp_for(target: &Path) -> PathBuf {
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    target.with_file_name(format!(".{}.{}", name, random_token(8)))
+}
+
+// file is written under temporary name and renamed into place when finished,
+// so nobody sees it half written and failed write leaves old file untouched
+struct LocalWriter {
+    file: fs::File,
+    tmp: PathBuf,
+    target: PathBuf,
+    overwrite: bool,
+}
+
+impl Write for LocalWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Writer for LocalWriter {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.file.sync_all()?;
+
+        if self.overwrite {
+            return fs::rename(&self.tmp, &self.target);
+        }
+
+        // link fails when target exists, temporary name is removed by drop
+        match fs::hard_link(&self.tmp, &self.target) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Err(err),
+            // some filesystems dont have hard links
+            Err(_) if self.target.exists() => Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "file already exists",
+            )),
+            Err(_) => fs::rename(&self.tmp, &self.target),
+        }
+    }
+}
+
+impl Drop for LocalWriter {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.tmp);
+    }
+}
+
+impl LocalStorage {
+    fn writer(&self, path: &str, overwrite: bool) -> io::Result<Box<dyn Writer>> {
+        let target = self.full(path);
+        let tmp = temp_for(&target);
+
+        Ok(Box::new(LocalWriter {
+            file: fs::File::create(&tmp)?,
+            tmp,
+            target,
+            overwrite,
+        }))
+    }
+}
+
+impl Storage for LocalStorage {
+    fn read(&self, path: &str) -> io::Result<Box<dyn Read + Send>> {
+        let file = fs::File::open(self.full(path))?;
+
+        if file.metadata()?.is_dir() {
+            return Err(io::Error::other("path is folder"));
+        }
+        Ok(Box::new(file))
+    }
+
+    fn write(&self, path: &str) -> io::Result<Box<dyn Writer>> {
+        self.writer(path, true)
+    }
+
+    fn create(&self, path: &str) -> io::Result<Box<dyn Writer>> {
+        self.writer(path, false)
+    }
+
+    fn list(&self, path: &str) -> io::Result<Vec<Meta>> {
+        fs::read_dir(self.full(path))?
+            .map(|entry| {
+                let entry = entry?;
+                Ok(to_meta(
+                    entry.file_name().to_string_lossy().to_string(),
+                    entry.metadata()?,
+                ))
+            })
+            .collect()
+    }
+
+    fn stat(&self, path: &str) -> io::Result<Meta> {
+        Ok(to_meta(name(path), fs::metadata(self.full(path))?))
+    }
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        fs::rename(self.full(from), self.full(to))
+    }
+
+    fn delete(&self, path: &str) -> io::Result<()> {
+        let path = self.full(path);
+
+        if fs::metadata(&path)?.is_dir() {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_file(path)
+        }
+    }
+
+    fn mkdir(&self, path: &str) -> io::Result<()> {
+        fs::create_dir_all(self.full(path))
+    }
+
+    fn import(&self, file: &Path, path: &str) -> io::Result<()> {
+        let target = self.full(path);
+
+        if fs::rename(file, &target).is_ok() {
+            return Ok(());
+        }
+
+        // rename cant go across filesystems, so file is copied next to target first
+        let tmp = temp_for(&target);
+
+        if let Err(err) = fs::copy(file, &tmp).and_then(|_| fs::rename(&tmp, &target)) {
+            let _ = fs::remove_file(&tmp);
+            return Err(err);
+        }
+        fs::remove_file(file)
+    }
+
+    fn local_path(&self, path: &str) -> Option<PathBuf> {
+        Some(self.full(path))
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::BTreeMap;
+use std::io::{self, Cursor, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use super::{name, Meta, Storage, Writer};
+
+enum Node {
+    Dir(SystemTime),
+    // content, modified and created
+    File(Vec<u8>, SystemTime, SystemTime),
+}
+
+type Nodes = Arc<Mutex<BTreeMap<String, Node>>>;
+
+// everything is lost with restart, it is meant for tests
+pub struct MemoryStorage {
+    nodes: Nodes,
+}
+
+impl Default for MemoryStorage {
+    fn default() -> MemoryStorage {
+        MemoryStorage::new()
+    }
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        let mut nodes = BTreeMap::new();
+        nodes.insert("/".to_string(), Node::Dir(SystemTime::now()));
+
+        MemoryStorage {
+            nodes: Arc::new(Mutex::new(nodes)),
+        }
+    }
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "path dont exist")
+}
+
+fn parent(path: &str) -> &str {
+    match path.rsplit_once('/') {
+        Some(("", _)) | None => "/",
+        Some((parent, _)) => parent,
+    }
+}
+
+fn inside(folder: &str, path: &str) -> bool {
+    path != folder && (folder == "/" || path.starts_with(&format!("{}/", folder)))
+}
+
+fn to_meta(path: &str, node: &Node) -> Meta {
+    match node {
+        Node::Dir(created) => Meta {
+            name: name(path),
+            is_dir: true,
+            size: 0,
+            modified: Some(*created),
+            created: Some(*created),
+        },
+        Node::File(data, modified, created) => Meta {
+            name: name(path),
+            is_dir: false,
+            size: data.len() as u64,
+            modified: Some(*modified),
+            created: Some(*created),
+        },
+    }
+}
+
+// file appears in storage only after finish
+struct MemoryWriter {
+    nodes: Nodes,
+    path: String,
+    data: Vec<u8>,
+    overwrite: bool,
+}
+
+impl Write for MemoryWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.data.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Writer for MemoryWriter {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+
+        match nodes.get(parent(&self.path)) {
+            Some(Node::Dir(_)) => (),
+            _ => return Err(not_found()),
+        }
+
+        // replaced file keeps time when it was created
+        let now = SystemTime::now();
+        let created = match nodes.get(&self.path) {
+            Some(Node::Dir(_)) => return Err(io::Error::other("path is folder")),
+            Some(Node::File(..)) if !self.overwrite => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "file already exists",
+                ))
+            }
+            Some(Node::File(_, _, created)) => *created,
+            None => now,
+        };
+
+        nodes.insert(self.path, Node::File(self.data, now, created));
+        Ok(())
+    }
+}
+
+impl MemoryStorage {
+    fn writer(&self, path: &str, overwrite: bool) -> io::Result<Box<dyn Writer>> {
+        match self.nodes.lock().unwrap().get(parent(path)) {
+            Some(Node::Dir(_)) => (),
+            _ => return Err(not_found()),
+        }
+
+        Ok(Box::new(MemoryWriter {
+            nodes: self.nodes.clone(),
+            path: path.to_string(),
+            data: Vec::new(),
+            overwrite,
+        }))
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn read(&self, path: &str) -> io::Result<Box<dyn Read + Send>> {
+        match self.nodes.lock().unwrap().get(path) {
+            Some(Node::File(data, ..)) => Ok(Box::new(Cursor::new(data.clone()))),
+            Some(Node::Dir(_)) => Err(io::Error::other("path is folder")),
+            None => Err(not_found()),
+        }
+    }
+
+    fn write(&self, path: &str) -> io::Result<Box<dyn Writer>> {
+        self.writer(path, true)
+    }
+
+    fn create(&self, path: &str) -> io::Result<Box<dyn Writer>> {
+        self.writer(path, false)
+    }
+
+    fn list(&self, path: &str) -> io::Result<Vec<Meta>> {
+        let nodes = self.nodes.lock().unwrap();
+
+        match nodes.get(path) {
+            Some(Node::Dir(_)) => (),
+            _ => return Err(not_found()),
+        }
+
+        Ok(nodes
+            .iter()
+            .filter(|(p, _)| p.as_str() != "/" && parent(p) == path)
+            .map(|(p, node)| to_meta(p, node))
+            .collect())
+    }
+
+    fn stat(&self, path: &str) -> io::Result<Meta> {
+        self.nodes
+            .lock()
+            .unwrap()
+            .get(path)
+            .map(|node| to_meta(path, node))
+            .ok_or_else(not_found)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+
+        if !nodes.contains_key(from) {
+            return Err(not_found());
+        }
+        match nodes.get(parent(to)) {
+            Some(Node::Dir(_)) => (),
+            _ => return Err(not_found()),
+        }
+        if from == to {
+            return Ok(());
+        }
+        if inside(from, to) || inside(to, from) {
+            return Err(io::Error::other("cant move folder into itself"));
+        }
+
+        let moved: Vec<String> = nodes
+            .keys()
+            .filter(|p| p.as_str() == from || inside(from, p))
+            .cloned()
+            .collect();
+
+        // like on disk, what was on new path is replaced
+        nodes.retain(|p, _| p != to && !inside(to, p));
+
+        for path in moved {
+            let node = nodes.remove(&path).unwrap();
+            nodes.insert(format!("{}{}", to, &path[from.len()..]), node);
+        }
+        Ok(())
+    }
+
+    fn delete(&self, path: &str) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+
+        if nodes.remove(path).is_none() {
+            return Err(not_found());
+        }
+
+        nodes.retain(|p, _| !inside(path, p));
+        Ok(())
+    }
+
+    fn mkdir(&self, path: &str) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+        let mut current = String::new();
+
+        for part in path.split('/').filter(|p| !p.is_empty()) {
+            current = format!("{}/{}", current, part);
+
+            match nodes.get(&current) {
+                Some(Node::Dir(_)) => (),
+                Some(Node::File(..)) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        "file is in path",
+                    ))
+                }
+                None => {
+                    nodes.insert(current.clone(), Node::Dir(SystemTime::now()));
+                }
+            }
+        }
+        Ok(())
+    }
+}
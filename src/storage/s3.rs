@@ -0,0 +1,600 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac, NewMac};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use super::{name, Meta, Storage, Writer};
+
+// aws encodes everything except these, in path "/" is kept too
+const QUERY: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+const PATH: &AsciiSet = &QUERY.remove(b'/');
+
+// bigger objects are uploaded in parts, single put cant go over 5 GiB
+// and failed part is sent again alone
+const PART_SIZE: u64 = 64 * 1024 * 1024;
+// single copy can take at most 5 GiB, bigger object is copied in parts
+const COPY_LIMIT: u64 = 5 * 1024 * 1024 * 1024;
+const COPY_PART_SIZE: u64 = 1024 * 1024 * 1024;
+const MAX_PARTS: u64 = 10000;
+
+// folders dont exist in object store, empty object "path/" stands for folder,
+// folder also exists while there is any object inside it
+#[derive(Clone)]
+pub struct S3Storage {
+    agent: ureq::Agent,
+    endpoint: String,
+    host: String,
+    bucket: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+}
+
+struct Object {
+    key: String,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_varkey(key).expect("hmac takes key of any size");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn to_io(err: ureq::Error) -> io::Error {
+    match err {
+        ureq::Error::Status(404, _) => io::Error::new(io::ErrorKind::NotFound, "path dont exist"),
+        // only conditional put asks for precondition
+        ureq::Error::Status(412, _) => {
+            io::Error::new(io::ErrorKind::AlreadyExists, "file already exists")
+        }
+        ureq::Error::Status(code, res) => io::Error::other(format!(
+            "s3 returned {}: {}",
+            code,
+            res.into_string().unwrap_or_default()
+        )),
+        err => io::Error::other(err.to_string()),
+    }
+}
+
+fn bad_xml<E: ToString>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+// text of first element with this name in answer of store
+fn element(body: &str, tag: &str) -> io::Result<String> {
+    roxmltree::Document::parse(body)
+        .map_err(bad_xml)?
+        .descendants()
+        .find(|n| n.tag_name().name() == tag)
+        .and_then(|n| n.text())
+        .map(str::to_string)
+        .ok_or_else(|| bad_xml(format!("{} is missing in answer of s3", tag)))
+}
+
+// store takes at most 10000 parts, so huge object has bigger ones
+fn part_size(size: u64, min: u64) -> u64 {
+    min.max(size.div_ceil(MAX_PARTS))
+}
+
+// "/a/b" -> "a/b", root is ""
+fn key(path: &str) -> &str {
+    path.trim_start_matches('/')
+}
+
+// objects inside folder, "" for root
+fn prefix(path: &str) -> String {
+    match key(path) {
+        "" => String::new(),
+        key => format!("{}/", key),
+    }
+}
+
+impl S3Storage {
+    pub fn new(
+        endpoint: &str,
+        bucket: &str,
+        region: &str,
+        access_key: &str,
+        secret_key: &str,
+    ) -> Result<S3Storage, String> {
+        let endpoint = endpoint.trim_end_matches('/').to_string();
+        let host = endpoint
+            .split_once("://")
+            .map(|(_, host)| host.to_string())
+            .ok_or("S3_ENDPOINT must start with http:// or https://")?;
+
+        let tls = native_tls::TlsConnector::new().map_err(|err| err.to_string())?;
+
+        Ok(S3Storage {
+            agent: ureq::AgentBuilder::new()
+                .tls_connector(Arc::new(tls))
+                .timeout_connect(Duration::from_secs(10))
+                .build(),
+            endpoint,
+            host,
+            bucket: bucket.to_string(),
+            region: region.to_string(),
+            access_key: access_key.to_string(),
+            secret_key: secret_key.to_string(),
+        })
+    }
+
+    // request signed with aws signature v4, body is not signed so it can be streamed
+    fn request(
+        &self,
+        method: &str,
+        key: &str,
+        query: &[(&str, &str)],
+        headers: &[(&str, &str)],
+    ) -> ureq::Request {
+        let now = Utc::now();
+        let date = now.format("%Y%m%d").to_string();
+        let time = now.format("%Y%m%dT%H%M%SZ").to_string();
+
+        let uri = format!("/{}/{}", self.bucket, utf8_percent_encode(key, PATH));
+
+        let mut query: Vec<String> = query
+            .iter()
+            .map(|(k, v)| {
+                format!(
+                    "{}={}",
+                    utf8_percent_encode(k, QUERY),
+                    utf8_percent_encode(v, QUERY)
+                )
+            })
+            .collect();
+        query.sort();
+        let query = query.join("&");
+
+        let mut signed: Vec<(String, String)> = vec![
+            ("host".to_string(), self.host.clone()),
+            (
+                "x-amz-content-sha256".to_string(),
+                "UNSIGNED-PAYLOAD".to_string(),
+            ),
+            ("x-amz-date".to_string(), time.clone()),
+        ];
+        signed.extend(
+            headers
+                .iter()
+                .map(|(k, v)| (k.to_lowercase(), v.trim().to_string())),
+        );
+        signed.sort();
+
+        let canonical = format!(
+            "{}\n{}\n{}\n{}\n{}\nUNSIGNED-PAYLOAD",
+            method,
+            uri,
+            query,
+            signed
+                .iter()
+                .map(|(k, v)| format!("{}:{}\n", k, v))
+                .collect::<String>(),
+            signed
+                .iter()
+                .map(|(k, _)| k.as_str())
+                .collect::<Vec<_>>()
+                .join(";"),
+        );
+
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            time,
+            scope,
+            hex::encode(Sha256::digest(canonical.as_bytes()))
+        );
+
+        let key = hmac(format!("AWS4{}", self.secret_key).as_bytes(), &date);
+        let key = hmac(&key, &self.region);
+        let key = hmac(&key, "s3");
+        let key = hmac(&key, "aws4_request");
+
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key,
+            scope,
+            signed
+                .iter()
+                .map(|(k, _)| k.as_str())
+                .collect::<Vec<_>>()
+                .join(";"),
+            hex::encode(hmac(&key, &to_sign))
+        );
+
+        let url = match query.is_empty() {
+            true => format!("{}{}", self.endpoint, uri),
+            false => format!("{}{}?{}", self.endpoint, uri, query),
+        };
+
+        let mut req = self
+            .agent
+            .request(method, &url)
+            .set("x-amz-content-sha256", "UNSIGNED-PAYLOAD")
+            .set("x-amz-date", &time)
+            .set("authorization", &authorization);
+
+        for (k, v) in headers {
+            req = req.set(k, v);
+        }
+        req
+    }
+
+    // objects under prefix, with delimiter only direct children and their folders
+    fn list_objects(
+        &self,
+        prefix: &str,
+        delimiter: bool,
+        limit: Option<&str>,
+    ) -> io::Result<(Vec<Object>, Vec<String>)> {
+        let mut objects = Vec::new();
+        let mut folders = Vec::new();
+        let mut token: Option<String> = None;
+
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+            if delimiter {
+                query.push(("delimiter", "/"));
+            }
+            if let Some(limit) = limit {
+                query.push(("max-keys", limit));
+            }
+            if let Some(token) = &token {
+                query.push(("continuation-token", token));
+            }
+
+            let body = self
+                .request("GET", "", &query, &[])
+                .call()
+                .map_err(to_io)?
+                .into_string()?;
+            let doc = roxmltree::Document::parse(&body).map_err(bad_xml)?;
+
+            let text = |node: roxmltree::Node, tag: &str| {
+                node.children()
+                    .find(|n| n.tag_name().name() == tag)
+                    .and_then(|n| n.text())
+                    .unwrap_or("")
+                    .to_string()
+            };
+
+            let root = doc.root_element();
+            for node in root.children() {
+                match node.tag_name().name() {
+                    "Contents" => objects.push(Object {
+                        key: text(node, "Key"),
+                        size: text(node, "Size").parse().unwrap_or(0),
+                        modified: DateTime::parse_from_rfc3339(&text(node, "LastModified"))
+                            .ok()
+                            .map(SystemTime::from),
+                    }),
+                    "CommonPrefixes" => folders.push(text(node, "Prefix")),
+                    _ => (),
+                }
+            }
+
+            if limit.is_some() || text(root, "IsTruncated") != "true" {
+                return Ok((objects, folders));
+            }
+            token = Some(text(root, "NextContinuationToken"));
+        }
+    }
+
+    // object is sent with one put, or in parts when it is big,
+    // store refuses to replace object which exists already without overwrite
+    fn put(&self, key: &str, mut file: fs::File, overwrite: bool) -> io::Result<()> {
+        let size = file.seek(SeekFrom::End(0))?;
+        file.seek(SeekFrom::Start(0))?;
+
+        let headers: &[(&str, &str)] = match overwrite {
+            true => &[],
+            false => &[("if-none-match", "*")],
+        };
+
+        if size <= PART_SIZE {
+            self.request("PUT", key, &[], headers)
+                .set("content-length", &size.to_string())
+                .send(file)
+                .map_err(to_io)?;
+            return Ok(());
+        }
+
+        self.multipart(key, headers, size, PART_SIZE, |id, number, start, len| {
+            file.seek(SeekFrom::Start(start))?;
+
+            let number = number.to_string();
+            self.request("PUT", key, &[("partNumber", &number), ("uploadId", id)], &[])
+                .set("content-length", &len.to_string())
+                .send(Read::by_ref(&mut file).take(len))
+                .map_err(to_io)?
+                .header("etag")
+                .map(str::to_string)
+                .ok_or_else(|| bad_xml("etag of part is missing in answer of s3"))
+        })
+    }
+
+    // every part is made by `part` from upload id, its number, start and length and its etag is returned,
+    // upload which fails is aborted, so its parts dont stay in bucket
+    fn multipart<F>(&self, key: &str, headers: &[(&str, &str)], size: u64, min: u64, mut part: F) -> io::Result<()>
+    where
+        F: FnMut(&str, u64, u64, u64) -> io::Result<String>,
+    {
+        let body = self
+            .request("POST", key, &[("uploads", "")], &[])
+            .call()
+            .map_err(to_io)?
+            .into_string()?;
+        let id = element(&body, "UploadId")?;
+
+        let part_size = part_size(size, min);
+        let mut parts = String::new();
+        let mut done = Ok(());
+
+        for (i, start) in (0..size).step_by(part_size as usize).enumerate() {
+            match part(&id, i as u64 + 1, start, part_size.min(size - start)) {
+                Ok(etag) => parts.push_str(&format!(
+                    "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                    i + 1,
+                    etag
+                )),
+                Err(err) => {
+                    done = Err(err);
+                    break;
+                }
+            }
+        }
+
+        let done = done.and_then(|_| {
+            let body = self
+                .request("POST", key, &[("uploadId", &id)], headers)
+                .set("content-type", "application/xml")
+                .send_string(&format!("<CompleteMultipartUpload>{}</CompleteMultipartUpload>", parts))
+                .map_err(to_io)?
+                .into_string()?;
+
+            // store can fail even after it answered 200
+            match roxmltree::Document::parse(&body).map_err(bad_xml)?.root_element().tag_name().name() {
+                "Error" => Err(io::Error::other(format!("s3 returned {}", body))),
+                _ => Ok(()),
+            }
+        });
+
+        if done.is_err() {
+            let _ = self.request("DELETE", key, &[("uploadId", &id)], &[]).call();
+        }
+        done
+    }
+
+    fn copy(&self, from: &str, to: &str, size: u64) -> io::Result<()> {
+        let source = format!("/{}/{}", self.bucket, utf8_percent_encode(from, PATH));
+
+        if size <= COPY_LIMIT {
+            self.request("PUT", to, &[], &[("x-amz-copy-source", &source)])
+                .call()
+                .map_err(to_io)?;
+            return Ok(());
+        }
+
+        self.multipart(to, &[], size, COPY_PART_SIZE, |id, number, start, len| {
+            let number = number.to_string();
+            let range = format!("bytes={}-{}", start, start + len - 1);
+
+            let body = self
+                .request(
+                    "PUT",
+                    to,
+                    &[("partNumber", &number), ("uploadId", id)],
+                    &[("x-amz-copy-source", &source), ("x-amz-copy-source-range", &range)],
+                )
+                .call()
+                .map_err(to_io)?
+                .into_string()?;
+            element(&body, "ETag")
+        })
+    }
+
+    fn remove(&self, key: &str) -> io::Result<()> {
+        self.request("DELETE", key, &[], &[])
+            .call()
+            .map_err(to_io)?;
+        Ok(())
+    }
+}
+
+// object is uploaded when its size is known, until then it waits in temporary file
+struct S3Writer {
+    storage: S3Storage,
+    key: String,
+    file: std::fs::File,
+    overwrite: bool,
+}
+
+impl Write for S3Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Writer for S3Writer {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.storage.put(&self.key, self.file, self.overwrite)
+    }
+}
+
+impl Storage for S3Storage {
+    fn read(&self, path: &str) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(
+            self.request("GET", key(path), &[], &[])
+                .call()
+                .map_err(to_io)?
+                .into_reader(),
+        ))
+    }
+
+    fn write(&self, path: &str) -> io::Result<Box<dyn Writer>> {
+        Ok(Box::new(S3Writer {
+            storage: self.clone(),
+            key: key(path).to_string(),
+            file: tempfile::tempfile()?,
+            overwrite: true,
+        }))
+    }
+
+    fn create(&self, path: &str) -> io::Result<Box<dyn Writer>> {
+        Ok(Box::new(S3Writer {
+            storage: self.clone(),
+            key: key(path).to_string(),
+            file: tempfile::tempfile()?,
+            overwrite: false,
+        }))
+    }
+
+    // file is sent straight away, without copy in temporary file
+    fn import(&self, file: &Path, path: &str) -> io::Result<()> {
+        self.put(key(path), fs::File::open(file)?, true)?;
+
+        fs::remove_file(file)
+    }
+
+    fn list(&self, path: &str) -> io::Result<Vec<Meta>> {
+        if !self.stat(path)?.is_dir {
+            return Err(io::Error::other("path is file"));
+        }
+
+        let prefix = prefix(path);
+        let (objects, folders) = self.list_objects(&prefix, true, None)?;
+
+        let mut res: Vec<Meta> = folders
+            .iter()
+            .map(|folder| Meta {
+                name: folder[prefix.len()..].trim_end_matches('/').to_string(),
+                is_dir: true,
+                size: 0,
+                modified: None,
+                created: None,
+            })
+            .collect();
+
+        // marker of folder itself is not its content
+        res.extend(
+            objects
+                .into_iter()
+                .filter(|o| o.key != prefix)
+                .map(|o| Meta {
+                    name: o.key[prefix.len()..].to_string(),
+                    is_dir: false,
+                    size: o.size,
+                    modified: o.modified,
+                    created: None,
+                }),
+        );
+
+        Ok(res)
+    }
+
+    fn stat(&self, path: &str) -> io::Result<Meta> {
+        if key(path).is_empty() {
+            return Ok(Meta {
+                name: String::new(),
+                is_dir: true,
+                size: 0,
+                modified: None,
+                created: None,
+            });
+        }
+
+        match self.request("HEAD", key(path), &[], &[]).call() {
+            Ok(res) => {
+                return Ok(Meta {
+                    name: name(path),
+                    is_dir: false,
+                    size: res
+                        .header("content-length")
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or(0),
+                    modified: res
+                        .header("last-modified")
+                        .and_then(|v| httpdate::parse_http_date(v).ok()),
+                    created: None,
+                })
+            }
+            Err(ureq::Error::Status(404, _)) => (),
+            Err(err) => return Err(to_io(err)),
+        }
+
+        let (objects, _) = self.list_objects(&prefix(path), false, Some("1"))?;
+
+        match objects.first() {
+            Some(object) => Ok(Meta {
+                name: name(path),
+                is_dir: true,
+                size: 0,
+                modified: object.modified,
+                created: None,
+            }),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "path dont exist")),
+        }
+    }
+
+    // object store cant rename, everything is copied and deleted
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        let meta = self.stat(from)?;
+        if !meta.is_dir {
+            self.copy(key(from), key(to), meta.size)?;
+            return self.remove(key(from));
+        }
+
+        let (from, to) = (prefix(from), prefix(to));
+        let (objects, _) = self.list_objects(&from, false, None)?;
+
+        for object in objects.iter() {
+            self.copy(&object.key, &format!("{}{}", to, &object.key[from.len()..]), object.size)?;
+        }
+        for object in objects.iter() {
+            self.remove(&object.key)?;
+        }
+        Ok(())
+    }
+
+    fn delete(&self, path: &str) -> io::Result<()> {
+        if !self.stat(path)?.is_dir {
+            return self.remove(key(path));
+        }
+
+        let (objects, _) = self.list_objects(&prefix(path), false, None)?;
+
+        for object in objects {
+            self.remove(&object.key)?;
+        }
+        Ok(())
+    }
+
+    // every parent gets its marker, so it stays when its content is deleted
+    fn mkdir(&self, path: &str) -> io::Result<()> {
+        let mut current = String::new();
+
+        for part in key(path).split('/').filter(|p| !p.is_empty()) {
+            current = format!("{}{}/", current, part);
+
+            self.request("PUT", &current, &[], &[])
+                .send_bytes(&[])
+                .map_err(to_io)?;
+        }
+        Ok(())
+    }
+}
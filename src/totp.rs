@@ -0,0 +1,169 @@
+use base32::Alphabet;
+use chrono::Utc;
+use hmac::{Hmac, Mac, NewMac};
+use rand::{thread_rng, RngCore};
+use sha1::Sha1;
+
+use crate::db::{totp, Pool};
+use crate::models::{TotpEnrollment, TotpQueries};
+use crate::reserr::ResErr;
+use crate::utils::{hash_token, random_token};
+
+const ISSUER: &str = "Cloud";
+const STEP: i64 = 30;
+const DIGITS: u32 = 6;
+const RECOVERY_CODES: usize = 10;
+
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; 20];
+    thread_rng().fill_bytes(&mut secret);
+
+    base32::encode(Alphabet::RFC4648 { padding: false }, &secret)
+}
+
+pub fn provisioning_uri(secret: &str, email: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        ISSUER, email, secret, ISSUER, DIGITS, STEP
+    )
+}
+
+fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_varkey(secret).expect("hmac takes key of any size");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // dynamic truncation from RFC 4226
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let code = ((hash[offset] as u32 & 0x7f) << 24)
+        | ((hash[offset + 1] as u32) << 16)
+        | ((hash[offset + 2] as u32) << 8)
+        | (hash[offset + 3] as u32);
+
+    code % 10u32.pow(DIGITS)
+}
+
+// step which code belongs to, one step before and after is accepted, because clocks are never exactly same
+fn matching_step(secret: &str, code: &str, now: i64) -> Option<i64> {
+    let secret = base32::decode(Alphabet::RFC4648 { padding: false }, secret)?;
+    let code: u32 = code.trim().parse().ok()?;
+
+    let counter = now / STEP;
+
+    (counter - 1..=counter + 1)
+        .rev()
+        .find(|c| hotp(&secret, *c as u64) == code)
+}
+
+// every code works only once, so seen code cant be used again in its window
+pub fn verify(db: &Pool, user_id: u32, secret: &str, code: &str) -> bool {
+    match matching_step(secret, code, Utc::now().timestamp()) {
+        Some(step) => totp::execute(db, TotpQueries::UseStep(user_id, step)).is_ok(),
+        None => false,
+    }
+}
+
+pub fn enroll(db: &Pool, user_id: u32, email: &str) -> Result<TotpEnrollment, ResErr> {
+    let secret = generate_secret();
+    let recovery_codes: Vec<String> = (0..RECOVERY_CODES).map(|_| random_token(10)).collect();
+
+    (totp::execute(
+        db,
+        TotpQueries::SetSecret(
+            user_id,
+            secret.clone(),
+            recovery_codes.iter().map(|code| hash_token(code)).collect(),
+        ),
+    )
+    .map_err(|_| ResErr::InternalError("cant save 2fa secret"))?)
+    .none();
+
+    Ok(TotpEnrollment {
+        uri: provisioning_uri(&secret, email),
+        secret,
+        recovery_codes,
+    })
+}
+
+// code which authenticator app shows right now, tests of login need it
+#[cfg(test)]
+pub fn current_code(secret: &str) -> String {
+    let secret = base32::decode(Alphabet::RFC4648 { padding: false }, secret).unwrap();
+    format!("{:06}", hotp(&secret, (Utc::now().timestamp() / STEP) as u64))
+}
+
+// checks code from authenticator app or one of unused recovery codes
+pub fn check_code(db: &Pool, user_id: u32, secret: &str, code: &str) -> bool {
+    if verify(db, user_id, secret, code) {
+        return true;
+    }
+
+    totp::execute(db, TotpQueries::UseRecoveryCode(user_id, hash_token(code.trim()))).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_pool;
+
+    // "12345678901234567890" from RFC 4226
+    const SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    fn code(counter: i64) -> String {
+        let secret = base32::decode(Alphabet::RFC4648 { padding: false }, SECRET).unwrap();
+        format!("{:06}", hotp(&secret, counter as u64))
+    }
+
+    #[test]
+    fn hotp_matches_rfc_vectors() {
+        let expected = ["755224", "287082", "359152", "969429", "338314"];
+
+        for (counter, expected) in expected.iter().enumerate() {
+            assert_eq!(&code(counter as i64), expected);
+        }
+    }
+
+    #[test]
+    fn code_is_accepted_one_step_around() {
+        let now = 1_111_111_109;
+        let step = now / STEP;
+
+        assert_eq!(matching_step(SECRET, &code(step), now), Some(step));
+        assert_eq!(matching_step(SECRET, &code(step - 1), now), Some(step - 1));
+        assert_eq!(matching_step(SECRET, &code(step + 1), now), Some(step + 1));
+        assert_eq!(matching_step(SECRET, &code(step - 2), now), None);
+        assert_eq!(matching_step(SECRET, &code(step + 2), now), None);
+    }
+
+    #[test]
+    fn bad_code_or_secret_is_rejected() {
+        assert_eq!(matching_step(SECRET, "abc", 0), None);
+        assert_eq!(matching_step("not base32!", "123456", 0), None);
+        assert_eq!(matching_step(SECRET, &format!(" {} ", code(0)), 0), Some(0));
+    }
+
+    #[test]
+    fn code_cant_be_used_twice() {
+        let db = test_pool();
+        totp::create_table(&db);
+        (totp::execute(&db, TotpQueries::SetSecret(1, SECRET.to_string(), vec![])).unwrap()).none();
+
+        let step = Utc::now().timestamp() / STEP;
+
+        assert!(verify(&db, 1, SECRET, &code(step)));
+        assert!(!verify(&db, 1, SECRET, &code(step)));
+        assert!(!verify(&db, 1, SECRET, &code(step - 1)));
+    }
+
+    #[test]
+    fn recovery_code_works_once() {
+        let db = test_pool();
+        totp::create_table(&db);
+        (totp::execute(&db, TotpQueries::SetSecret(1, SECRET.to_string(), vec![hash_token("recover1")]))
+            .unwrap())
+        .none();
+
+        assert!(check_code(&db, 1, SECRET, "recover1"));
+        assert!(!check_code(&db, 1, SECRET, "recover1"));
+    }
+}
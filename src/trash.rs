@@ -0,0 +1,143 @@
+use actix_web::web;
+use chrono::Utc;
+use std::time::Duration;
+use std::{env, thread};
+
+use crate::db::{trash, Pool};
+use crate::models::{TrashItem, TrashQueries};
+use crate::quota;
+use crate::reserr::ResErr;
+use crate::storage::Store;
+use crate::utils::random_token;
+use crate::versions;
+
+// deleted files are kept in storage here, acl dont let anybody reach it
+pub const TRASH: &str = "/.trash";
+
+pub fn place(item: &TrashItem) -> String {
+    format!("{}/{}", TRASH, item.id)
+}
+
+// file or folder is moved into trash of user, path is how user sees it and target where it is,
+// its bytes and bytes of its old versions still count into quota of its home
+pub fn put(db: &Pool, storage: &Store, user_id: u32, path: &str, target: &str) -> Result<TrashItem, ResErr> {
+    let meta = storage
+        .stat(target)
+        .map_err(|_| ResErr::BadClientData("path dont exist"))?;
+
+    let item = TrashItem {
+        id: random_token(32),
+        user_id,
+        path: path.to_string(),
+        target: target.to_string(),
+        is_dir: meta.is_dir,
+        size: quota::size_of(storage, target)? + versions::versioned(db, target)?,
+        deleted: Utc::now().timestamp(),
+    };
+
+    storage
+        .mkdir(TRASH)
+        .and_then(|_| storage.rename(target, &place(&item)))
+        .map_err(|_| ResErr::InternalError("cant move into trash"))?;
+
+    if trash::execute(db, TrashQueries::AddItem(item.clone())).is_err() {
+        let _ = storage.rename(&place(&item), target);
+        return Err(ResErr::InternalError("cant move into trash"));
+    }
+
+    versions::moved(db, storage, target, &place(&item))?;
+    Ok(item)
+}
+
+// item is gone for good and its bytes are given back to its home
+pub fn purge(db: &Pool, storage: &Store, item: &TrashItem) -> Result<(), ResErr> {
+    if storage.exists(&place(item)) {
+        storage
+            .delete(&place(item))
+            .map_err(|_| ResErr::InternalError("cant delete from trash"))?;
+    }
+
+    versions::forget(db, storage, &place(item))?;
+    quota::add(db, &item.target, -(item.size as i64))?;
+
+    (trash::execute(db, TrashQueries::DeleteItem(item.id.clone()))
+        .map_err(|_| ResErr::InternalError("cant delete from trash"))?)
+    .none();
+    Ok(())
+}
+
+pub fn purge_all(db: &Pool, storage: &Store, items: Vec<TrashItem>) -> Result<(), ResErr> {
+    for item in items.iter() {
+        purge(db, storage, item)?;
+    }
+    Ok(())
+}
+
+// bytes in trash which came from home
+pub fn trashed(db: &Pool, home: &str) -> Result<u64, ResErr> {
+    Ok((trash::execute(db, TrashQueries::GetItemsIn(home.to_string()))
+        .map_err(|_| ResErr::InternalError("cant get trash"))?)
+    .many()
+    .iter()
+    .map(|item| item.size)
+    .sum())
+}
+
+// items older than TRASH_RETENTION days
+pub fn purge_expired(db: &Pool, storage: &Store) -> Result<(), ResErr> {
+    let days: i64 = env::var("TRASH_RETENTION").unwrap().parse().unwrap();
+    let before = Utc::now().timestamp() - days * 24 * 60 * 60;
+
+    let items = (trash::execute(db, TrashQueries::GetExpiredItems(before))
+        .map_err(|_| ResErr::InternalError("cant get trash"))?)
+    .many();
+
+    purge_all(db, storage, items)
+}
+
+// it is checked every hour
+pub fn start_purge(db: Pool, storage: web::Data<Store>) {
+    thread::spawn(move || loop {
+        if purge_expired(&db, &storage).is_err() {
+            eprintln!("trash purge failed");
+        }
+        thread::sleep(Duration::from_secs(60 * 60));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::db::roles::UPLOADER;
+    use crate::handlers::testing::{add_user, put as put_file, setup};
+
+    #[test]
+    fn old_items_are_purged_and_give_back_quota() {
+        let (db, storage) = setup();
+        let bob = add_user(&db, &storage, "bob", UPLOADER, 1);
+        put_file(&storage, "/bob/old.txt", "old");
+        put_file(&storage, "/bob/new.txt", "newer");
+
+        let old = put(&db, &storage, bob.id, "/old.txt", "/bob/old.txt").unwrap();
+        let new = put(&db, &storage, bob.id, "/new.txt", "/bob/new.txt").unwrap();
+        let days: i64 = env::var("TRASH_RETENTION").unwrap().parse().unwrap();
+        db.get()
+            .unwrap()
+            .execute(
+                "UPDATE Trash SET deleted = ?1 WHERE id = ?2",
+                rusqlite::params![Utc::now().timestamp() - days * 24 * 60 * 60 - 1, old.id],
+            )
+            .unwrap();
+
+        // deleted files still take space until they are gone for good
+        assert_eq!(trashed(&db, "/bob").unwrap(), 8);
+
+        purge_expired(&db, &storage).unwrap();
+
+        assert!(!storage.exists(&place(&old)));
+        assert!(storage.exists(&place(&new)));
+        assert!(trash::execute(&db, TrashQueries::GetItem(old.id)).is_err());
+        assert_eq!(trashed(&db, "/bob").unwrap(), 5);
+    }
+}
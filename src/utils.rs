@@ -1,10 +1,11 @@
-use std::convert::TryFrom;
-use std::env;
-use std::path::Path;
-use std::{fs, io, path::PathBuf};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sha2::{Digest, Sha256};
+use std::io;
 use validator::ValidationError;
 
+use crate::acl::hidden;
 use crate::models::Folder;
+use crate::storage::Store;
 
 pub fn validate_path(path: &str) -> Result<(), ValidationError> {
     if path.starts_with("./") {
@@ -15,22 +16,15 @@ pub fn validate_path(path: &str) -> Result<(), ValidationError> {
         return Err(ValidationError::new("you cant use '..' in path"));
     }
 
-    if !Path::new(&(env::var("CLOUD_PATH").unwrap() + path)).exists() {
-        return Err(ValidationError::new("path dont exist"));
-    }
-
+    // existence is checked by handler, it needs storage for it
     Ok(())
 }
 
-pub fn valid_path(path: &str) -> Result<(), &'static str> {
-    if path.contains("..") {
-        return Err("you cant use '..' in path");
-    }
-
-    if !Path::new(path).exists() {
-        return Err("path dont exist");
+// name of group is used in paths as "@name"
+pub fn valid_group_name(name: &str) -> Result<(), ValidationError> {
+    if !name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-') {
+        return Err(ValidationError::new("group name can have only letters, numbers, '_' and '-'"));
     }
-
     Ok(())
 }
 
@@ -41,13 +35,13 @@ pub fn valid_pass(pass: &str) -> Result<(), ValidationError> {
 
     for char in pass.chars() {
         if char.is_lowercase() {
-            num_of_lowercase = num_of_lowercase + 1
+            num_of_lowercase += 1
         };
         if char.is_uppercase() {
-            num_of_uppercase = num_of_uppercase + 1
+            num_of_uppercase += 1
         };
         if char.is_numeric() {
-            num_of_numbers = num_of_numbers + 1
+            num_of_numbers += 1
         };
     }
 
@@ -65,60 +59,58 @@ pub fn valid_pass(pass: &str) -> Result<(), ValidationError> {
     Ok(())
 }
 
-pub fn dir_size(path: impl Into<PathBuf>) -> io::Result<u32> {
-    fn dir_size(mut dir: fs::ReadDir) -> io::Result<u32> {
-        dir.try_fold(0, |acc, file| {
-            let file = file?;
-            let size = match file.metadata()? {
-                data if data.is_dir() => dir_size(fs::read_dir(file.path())?)?,
-                data => u64_to_u32(data.len()),
-            };
-            Ok(acc + size)
-        })
-    }
-
-    dir_size(fs::read_dir(path.into())?)
-}
-
-fn u64_to_u32(v: u64) -> u32 {
-    u32::try_from(v / 1000000).unwrap_or_default()
+// bytes of every file inside folder, trash and versions are counted by their records
+pub fn dir_size(storage: &Store, path: &str) -> io::Result<u64> {
+    storage.list(path)?.iter().try_fold(0, |acc, file| {
+        let full = format!("{}/{}", path.trim_end_matches('/'), file.name);
+        let size = match file.is_dir {
+            true if hidden(&full) => 0,
+            true => dir_size(storage, &full)?,
+            false => file.size,
+        };
+        Ok(acc + size)
+    })
 }
 
-fn get_folder_obj(path: String, obj: &mut Folder) {
-    if let Ok(entries) = fs::read_dir(&path) {
+fn get_folder_obj(storage: &Store, path: &str, obj: &mut Folder) {
+    if let Ok(entries) = storage.list(path) {
         for entry in entries {
-            if let Ok(entry) = entry {
-                // Here, `entry` is a `DirEntry`.
-
-                let file_name = entry.file_name().into_string().unwrap();
-
-                if file_name.contains(".") {
-                    obj.folders.push(Folder {
-                        name: file_name,
-                        folders: Vec::with_capacity(0),
-                    });
-                } else {
-                    obj.folders.push(Folder {
-                        name: file_name.clone(),
-                        folders: Vec::with_capacity(0),
-                    });
-                    get_folder_obj(
-                        format!("{}{}{}", path.clone(), "/", file_name),
-                        &mut obj.folders.last_mut().unwrap(),
-                    );
-                }
+            if hidden(&format!("{}/{}", path.trim_end_matches('/'), entry.name)) {
+                continue;
+            }
+
+            obj.folders.push(Folder {
+                name: entry.name.clone(),
+                folders: Vec::with_capacity(0),
+            });
+
+            if entry.is_dir {
+                get_folder_obj(
+                    storage,
+                    &format!("{}/{}", path.trim_end_matches('/'), entry.name),
+                    obj.folders.last_mut().unwrap(),
+                );
             }
         }
     }
 }
 
-pub fn get_folder_and_files(path: String) -> Folder {
+pub fn get_folder_and_files(storage: &Store, path: &str) -> Folder {
     let mut main_folder = Folder {
         name: String::from(""),
         folders: Vec::new(),
     };
 
-    get_folder_obj(path, &mut main_folder);
+    get_folder_obj(storage, path, &mut main_folder);
+
+    main_folder
+}
+
+pub fn random_token(len: usize) -> String {
+    thread_rng().sample_iter(&Alphanumeric).take(len).collect()
+}
 
-    return main_folder;
+// tokens are random enough, so plain sha256 is fine and can be looked up
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
 }
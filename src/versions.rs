@@ -0,0 +1,295 @@
+use actix_web::web;
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::time::{Duration, UNIX_EPOCH};
+use std::{env, io, thread};
+
+use crate::acl::covers;
+use crate::db::{files, versions, Pool};
+use crate::models::{FileMeta, FileQueries, Version, VersionQueries};
+use crate::quota;
+use crate::reserr::ResErr;
+use crate::storage::{blocking, Store};
+use crate::trash::TRASH;
+use crate::utils::random_token;
+
+// older contents of files are kept in storage here, acl dont let anybody reach it
+pub const VERSIONS: &str = "/.versions";
+
+pub fn place(version: &Version) -> String {
+    format!("{}/{}", VERSIONS, version.id)
+}
+
+// sha256 of file in storage, for files stored before checksums were kept
+pub fn checksum(storage: &Store, path: &str) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut storage.read(path)?, &mut hasher)?;
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+// who stored current content of file, files from before dont know it,
+// their checksum is counted once and kept
+pub fn current(db: &Pool, storage: &Store, path: &str) -> Result<FileMeta, ResErr> {
+    if let Ok(v) = files::execute(db, FileQueries::GetFile(path.to_string())) {
+        return Ok(v.one());
+    }
+
+    let meta = storage
+        .stat(path)
+        .map_err(|_| ResErr::BadClientData("file not found"))?;
+
+    let file = FileMeta {
+        path: path.to_string(),
+        user_id: None,
+        checksum: checksum(storage, path).map_err(|_| ResErr::InternalError("cant read file"))?,
+        created: meta
+            .modified
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_else(|| Utc::now().timestamp()),
+    };
+
+    (files::execute(db, FileQueries::SetFile(file.clone()))
+        .map_err(|_| ResErr::InternalError("cant save file metadata"))?)
+    .none();
+    Ok(file)
+}
+
+// checksum of file when it is known already, file from before checksums were kept
+// gets it counted in threadpool meanwhile, so it is not read whole before it is sent
+pub fn known(db: &web::Data<Pool>, storage: &web::Data<Store>, path: &str) -> Option<String> {
+    if let Ok(v) = files::execute(db, FileQueries::GetFile(path.to_string())) {
+        return Some(v.one().checksum);
+    }
+
+    let (db, storage, path) = (db.clone(), storage.clone(), path.to_string());
+    actix_rt::spawn(async move {
+        let _ = blocking(move || current(&db, &storage, &path)).await;
+    });
+    None
+}
+
+// new content was stored on path
+pub fn stored(db: &Pool, path: &str, user_id: Option<u32>, checksum: String) -> Result<(), ResErr> {
+    let file = FileMeta {
+        path: path.to_string(),
+        user_id,
+        checksum,
+        created: Utc::now().timestamp(),
+    };
+
+    (files::execute(db, FileQueries::SetFile(file))
+        .map_err(|_| ResErr::InternalError("cant save file metadata"))?)
+    .none();
+    Ok(())
+}
+
+// current content of file is moved into its history, so new one can take its place,
+// its bytes still count into quota
+pub fn archive(db: &Pool, storage: &Store, path: &str) -> Result<Option<Version>, ResErr> {
+    match storage.stat(path) {
+        Ok(meta) if !meta.is_dir => {
+            let file = current(db, storage, path)?;
+
+            let version = Version {
+                id: random_token(32),
+                path: path.to_string(),
+                number: 0,
+                user_id: file.user_id,
+                size: meta.size,
+                checksum: file.checksum,
+                created: file.created,
+            };
+
+            storage
+                .mkdir(VERSIONS)
+                .and_then(|_| storage.rename(path, &place(&version)))
+                .map_err(|_| ResErr::InternalError("cant keep old version"))?;
+
+            if versions::execute(db, VersionQueries::AddVersion(version.clone())).is_err() {
+                let _ = storage.rename(&place(&version), path);
+                return Err(ResErr::InternalError("cant keep old version"));
+            }
+            Ok(Some(version))
+        }
+        _ => Ok(None),
+    }
+}
+
+// new content couldnt be stored, old one is put back
+pub fn unarchive(db: &Pool, storage: &Store, version: &Version) {
+    if storage.rename(&place(version), &version.path).is_ok() {
+        let _ = versions::execute(db, VersionQueries::DeleteVersion(version.id.clone()));
+    }
+}
+
+// quota is not changed here, callers know where bytes were counted
+fn remove(db: &Pool, storage: &Store, version: &Version) -> Result<(), ResErr> {
+    if storage.exists(&place(version)) {
+        storage
+            .delete(&place(version))
+            .map_err(|_| ResErr::InternalError("cant delete version"))?;
+    }
+
+    (versions::execute(db, VersionQueries::DeleteVersion(version.id.clone()))
+        .map_err(|_| ResErr::InternalError("cant delete version"))?)
+    .none();
+    Ok(())
+}
+
+// history of file, or of every file inside folder
+pub fn history(db: &Pool, path: &str) -> Result<Vec<Version>, ResErr> {
+    Ok((versions::execute(db, VersionQueries::GetVersions(path.to_string()))
+        .map_err(|_| ResErr::InternalError("cant get versions"))?)
+    .many())
+}
+
+// everything known about path is dropped, file itself is left alone
+pub fn forget(db: &Pool, storage: &Store, path: &str) -> Result<(), ResErr> {
+    for version in history(db, path)?.iter() {
+        remove(db, storage, version)?;
+    }
+
+    (files::execute(db, FileQueries::DeleteFiles(path.to_string()))
+        .map_err(|_| ResErr::InternalError("cant delete file metadata"))?)
+    .none();
+    Ok(())
+}
+
+// file or folder was moved, its metadata and history go with it
+pub fn moved(db: &Pool, storage: &Store, from: &str, to: &str) -> Result<(), ResErr> {
+    forget(db, storage, to)?;
+
+    (files::execute(db, FileQueries::MoveFiles(from.to_string(), to.to_string()))
+        .map_err(|_| ResErr::InternalError("cant move file metadata"))?)
+    .none();
+
+    (versions::execute(db, VersionQueries::MoveVersions(from.to_string(), to.to_string()))
+        .map_err(|_| ResErr::InternalError("cant move versions"))?)
+    .none();
+    Ok(())
+}
+
+// bytes of versions of files in home, versions in trash are counted by trash
+pub fn versioned(db: &Pool, home: &str) -> Result<u64, ResErr> {
+    Ok((versions::execute(db, VersionQueries::GetVersionsIn(home.to_string()))
+        .map_err(|_| ResErr::InternalError("cant get versions"))?)
+    .many()
+    .iter()
+    .filter(|v| !covers(TRASH, &v.path))
+    .map(|v| v.size)
+    .sum())
+}
+
+// last VERSIONS_KEEP versions stay and newest version of every day for VERSIONS_DAILY days
+pub fn prune(db: &Pool, storage: &Store, path: &str) -> Result<(), ResErr> {
+    let keep: usize = env::var("VERSIONS_KEEP").unwrap().parse().unwrap();
+    let daily: i64 = env::var("VERSIONS_DAILY").unwrap().parse().unwrap();
+    let since = Utc::now().timestamp() - daily * 24 * 60 * 60;
+
+    let mut days = HashSet::new();
+
+    // newest first, so first version of each day is the one kept
+    for (i, version) in history(db, path)?.iter().filter(|v| v.path == path).enumerate() {
+        let newest_of_day = days.insert(version.created.div_euclid(24 * 60 * 60));
+
+        if i < keep || (version.created > since && newest_of_day) {
+            continue;
+        }
+
+        remove(db, storage, version)?;
+        quota::add(db, &version.path, -(version.size as i64))?;
+    }
+    Ok(())
+}
+
+// versions can get too old for daily rule, so they are checked every hour
+pub fn start_prune(db: Pool, storage: web::Data<Store>) {
+    thread::spawn(move || loop {
+        let pruned = versions::execute(&db, VersionQueries::GetAllVersions)
+            .map_err(|_| ResErr::InternalError("cant get versions"))
+            .and_then(|rows| {
+                let paths: HashSet<String> = rows
+                    .many()
+                    .into_iter()
+                    .map(|v| v.path)
+                    .filter(|p| !covers(TRASH, p))
+                    .collect();
+
+                paths.iter().try_for_each(|path| prune(&db, &storage, path))
+            });
+
+        if pruned.is_err() {
+            eprintln!("versions pruning failed");
+        }
+        thread::sleep(Duration::from_secs(60 * 60));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::db::roles::UPLOADER;
+    use crate::handlers::testing::{add_user, put, setup};
+
+    const DAY: i64 = 24 * 60 * 60;
+
+    fn add(db: &Pool, storage: &Store, path: &str, created: i64) -> Version {
+        let version = Version {
+            id: random_token(32),
+            path: path.to_string(),
+            number: 0,
+            user_id: None,
+            size: 1,
+            checksum: String::new(),
+            created,
+        };
+        put(storage, &place(&version), "v");
+        versions::execute(db, VersionQueries::AddVersion(version.clone())).unwrap();
+        version
+    }
+
+    #[test]
+    fn prune_keeps_last_versions_and_newest_of_each_day() {
+        let (db, storage) = setup();
+        add_user(&db, &storage, "bob", UPLOADER, 1);
+        storage.mkdir(VERSIONS).unwrap();
+        let keep: usize = env::var("VERSIONS_KEEP").unwrap().parse().unwrap();
+        let daily: i64 = env::var("VERSIONS_DAILY").unwrap().parse().unwrap();
+
+        // days are counted from midnight, so versions are put far from day edges and from daily limit
+        let today = Utc::now().timestamp().div_euclid(DAY) * DAY;
+        let mut kept = Vec::new();
+        let mut removed = Vec::new();
+        for days_ago in [daily + 10, daily + 1, daily - 1, 5, 2, 1].iter() {
+            let day = today - days_ago * DAY;
+            removed.push(add(&db, &storage, "/bob/a.txt", day + 3600));
+            let newer = add(&db, &storage, "/bob/a.txt", day + 7200);
+            if *days_ago < daily {
+                kept.push(newer);
+            } else {
+                removed.push(newer);
+            }
+        }
+        // last ones stay even when they are from same day
+        for i in 0..keep {
+            kept.push(add(&db, &storage, "/bob/a.txt", today + i as i64));
+        }
+        let other = add(&db, &storage, "/bob/b.txt", today - (daily + 10) * DAY);
+
+        prune(&db, &storage, "/bob/a.txt").unwrap();
+
+        let mut left: Vec<String> = history(&db, "/bob/a.txt").unwrap().into_iter().map(|v| v.id).collect();
+        let mut expected: Vec<String> = kept.iter().map(|v| v.id.clone()).collect();
+        left.sort();
+        expected.sort();
+        assert_eq!(left, expected);
+
+        assert!(kept.iter().all(|v| storage.exists(&place(v))));
+        assert!(removed.iter().all(|v| !storage.exists(&place(v))));
+        assert!(storage.exists(&place(&other)));
+    }
+}
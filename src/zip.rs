@@ -0,0 +1,358 @@
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use crc32fast::Hasher;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use std::io::{self, Read, Write};
+use std::time::SystemTime;
+
+// zip is written front to back and never seeks, so it can be streamed while it is made,
+// sizes and crc come after data of every file in data descriptor,
+// https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const DATA_DESCRIPTOR: u32 = 0x08074b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const ZIP64_END: u32 = 0x06064b50;
+const ZIP64_LOCATOR: u32 = 0x07064b50;
+const END: u32 = 0x06054b50;
+
+// bit 3 is for data descriptor, bit 11 for utf-8 names
+const FLAGS: u16 = 0x0808;
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+const VERSION: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+// made on unix, so external attributes are unix mode
+const MADE_BY: u16 = 3 << 8 | VERSION_ZIP64;
+
+// deflate can make file bit bigger, files close to 4 GB get zip64 already
+const ZIP64_SIZE: u64 = 0xff00_0000;
+const MAX32: u64 = 0xffff_ffff;
+
+struct Entry {
+    name: String,
+    is_dir: bool,
+    zip64: bool,
+    time: u16,
+    date: u16,
+    crc: u32,
+    compressed: u64,
+    size: u64,
+    offset: u64,
+}
+
+// counts bytes, offsets in zip are positions in written stream
+struct Counter<W: Write> {
+    inner: W,
+    written: u64,
+}
+
+impl<W: Write> Write for Counter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.written += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub struct ZipWriter<W: Write> {
+    out: Counter<W>,
+    entries: Vec<Entry>,
+}
+
+// dos time has 2 seconds precision and starts in 1980
+fn dos_time(modified: Option<SystemTime>) -> (u16, u16) {
+    let t: DateTime<Utc> = modified.map(DateTime::from).unwrap_or_else(Utc::now);
+    if t.year() < 1980 {
+        return (0, 1 << 5 | 1);
+    }
+
+    let time = (t.hour() << 11 | t.minute() << 5 | (t.second() / 2)) as u16;
+    let date = (((t.year() - 1980) as u32) << 9 | t.month() << 5 | t.day()) as u16;
+    (time, date)
+}
+
+fn u16le(out: &mut impl Write, v: u16) -> io::Result<()> {
+    out.write_all(&v.to_le_bytes())
+}
+
+fn u32le(out: &mut impl Write, v: u32) -> io::Result<()> {
+    out.write_all(&v.to_le_bytes())
+}
+
+fn u64le(out: &mut impl Write, v: u64) -> io::Result<()> {
+    out.write_all(&v.to_le_bytes())
+}
+
+// value which dont fit into 32 bits is in zip64 extra field instead
+fn u32_or_max(v: u64) -> u32 {
+    v.min(MAX32) as u32
+}
+
+impl<W: Write> ZipWriter<W> {
+    pub fn new(out: W) -> ZipWriter<W> {
+        ZipWriter {
+            out: Counter { inner: out, written: 0 },
+            entries: Vec::new(),
+        }
+    }
+
+    fn local_header(&mut self, entry: &Entry) -> io::Result<()> {
+        let out = &mut self.out;
+
+        u32le(out, LOCAL_HEADER)?;
+        u16le(out, if entry.zip64 { VERSION_ZIP64 } else { VERSION })?;
+        u16le(out, if entry.is_dir { 0x0800 } else { FLAGS })?;
+        u16le(out, if entry.is_dir { STORED } else { DEFLATED })?;
+        u16le(out, entry.time)?;
+        u16le(out, entry.date)?;
+        // crc and sizes are in data descriptor
+        u32le(out, 0)?;
+        u32le(out, if entry.zip64 { MAX32 as u32 } else { 0 })?;
+        u32le(out, if entry.zip64 { MAX32 as u32 } else { 0 })?;
+        u16le(out, entry.name.len() as u16)?;
+        u16le(out, if entry.zip64 { 20 } else { 0 })?;
+        out.write_all(entry.name.as_bytes())?;
+
+        if entry.zip64 {
+            u16le(out, 0x0001)?;
+            u16le(out, 16)?;
+            u64le(out, 0)?;
+            u64le(out, 0)?;
+        }
+        Ok(())
+    }
+
+    fn entry(&self, name: &str, is_dir: bool, size: u64, modified: Option<SystemTime>) -> io::Result<Entry> {
+        if name.len() > u16::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "name is too long"));
+        }
+        let (time, date) = dos_time(modified);
+
+        Ok(Entry {
+            name: name.to_string(),
+            is_dir,
+            zip64: size >= ZIP64_SIZE,
+            time,
+            date,
+            crc: 0,
+            compressed: 0,
+            size: 0,
+            offset: self.out.written,
+        })
+    }
+
+    // name of folder ends with "/"
+    pub fn add_dir(&mut self, name: &str, modified: Option<SystemTime>) -> io::Result<()> {
+        let entry = self.entry(&format!("{}/", name.trim_end_matches('/')), true, 0, modified)?;
+
+        self.local_header(&entry)?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    // size is only hint from stat, real size is counted while file is read
+    pub fn add_file(
+        &mut self,
+        name: &str,
+        size: u64,
+        modified: Option<SystemTime>,
+        file: &mut dyn Read,
+    ) -> io::Result<()> {
+        let mut entry = self.entry(name, false, size, modified)?;
+        self.local_header(&entry)?;
+
+        let start = self.out.written;
+        let mut hasher = Hasher::new();
+        let mut encoder = DeflateEncoder::new(&mut self.out, Compression::default());
+        let mut buf = vec![0; 64 * 1024];
+
+        loop {
+            let len = file.read(&mut buf)?;
+            if len == 0 {
+                break;
+            }
+            hasher.update(&buf[..len]);
+            encoder.write_all(&buf[..len])?;
+            entry.size += len as u64;
+        }
+        encoder.finish()?;
+
+        entry.crc = hasher.finalize();
+        entry.compressed = self.out.written - start;
+
+        // file grew since stat, its sizes cant be written anymore
+        if !entry.zip64 && (entry.size > MAX32 || entry.compressed > MAX32) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "file changed while zipped"));
+        }
+
+        let out = &mut self.out;
+        u32le(out, DATA_DESCRIPTOR)?;
+        u32le(out, entry.crc)?;
+        if entry.zip64 {
+            u64le(out, entry.compressed)?;
+            u64le(out, entry.size)?;
+        } else {
+            u32le(out, entry.compressed as u32)?;
+            u32le(out, entry.size as u32)?;
+        }
+
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    fn central_header(&mut self, i: usize) -> io::Result<()> {
+        let entry = &self.entries[i];
+        let out = &mut self.out;
+
+        let mut extra = Vec::new();
+        if entry.zip64 {
+            u64le(&mut extra, entry.size)?;
+            u64le(&mut extra, entry.compressed)?;
+        }
+        if entry.offset >= MAX32 {
+            u64le(&mut extra, entry.offset)?;
+        }
+
+        let (compressed, size) = match entry.zip64 {
+            true => (MAX32 as u32, MAX32 as u32),
+            false => (entry.compressed as u32, entry.size as u32),
+        };
+
+        u32le(out, CENTRAL_HEADER)?;
+        u16le(out, MADE_BY)?;
+        u16le(out, if entry.zip64 || !extra.is_empty() { VERSION_ZIP64 } else { VERSION })?;
+        u16le(out, if entry.is_dir { 0x0800 } else { FLAGS })?;
+        u16le(out, if entry.is_dir { STORED } else { DEFLATED })?;
+        u16le(out, entry.time)?;
+        u16le(out, entry.date)?;
+        u32le(out, entry.crc)?;
+        u32le(out, compressed)?;
+        u32le(out, size)?;
+        u16le(out, entry.name.len() as u16)?;
+        u16le(out, if extra.is_empty() { 0 } else { extra.len() as u16 + 4 })?;
+        // comment, disk, internal attributes
+        u16le(out, 0)?;
+        u16le(out, 0)?;
+        u16le(out, 0)?;
+        u32le(out, if entry.is_dir { 0o40755 << 16 | 0x10 } else { 0o100644 << 16 })?;
+        u32le(out, u32_or_max(entry.offset))?;
+        out.write_all(entry.name.as_bytes())?;
+
+        if !extra.is_empty() {
+            u16le(out, 0x0001)?;
+            u16le(out, extra.len() as u16)?;
+            out.write_all(&extra)?;
+        }
+        Ok(())
+    }
+
+    // central directory with list of all entries is written at the end
+    pub fn finish(mut self) -> io::Result<W> {
+        let start = self.out.written;
+        for i in 0..self.entries.len() {
+            self.central_header(i)?;
+        }
+        let size = self.out.written - start;
+        let count = self.entries.len() as u64;
+
+        let out = &mut self.out;
+        if count >= 0xffff || start >= MAX32 || size >= MAX32 {
+            let zip64_end = out.written;
+
+            u32le(out, ZIP64_END)?;
+            u64le(out, 44)?;
+            u16le(out, MADE_BY)?;
+            u16le(out, VERSION_ZIP64)?;
+            u32le(out, 0)?;
+            u32le(out, 0)?;
+            u64le(out, count)?;
+            u64le(out, count)?;
+            u64le(out, size)?;
+            u64le(out, start)?;
+
+            u32le(out, ZIP64_LOCATOR)?;
+            u32le(out, 0)?;
+            u64le(out, zip64_end)?;
+            u32le(out, 1)?;
+        }
+
+        u32le(out, END)?;
+        u16le(out, 0)?;
+        u16le(out, 0)?;
+        u16le(out, count.min(0xffff) as u16)?;
+        u16le(out, count.min(0xffff) as u16)?;
+        u32le(out, u32_or_max(size))?;
+        u32le(out, u32_or_max(start))?;
+        u16le(out, 0)?;
+
+        out.flush()?;
+        Ok(self.out.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::zip::ZipArchive;
+    use std::io::Cursor;
+
+    fn read_back(zip: Vec<u8>) -> Vec<(String, bool, Vec<u8>)> {
+        let mut archive = ZipArchive::new(Cursor::new(zip)).unwrap();
+        (0..archive.len())
+            .map(|i| {
+                let mut file = archive.by_index(i).unwrap();
+                let mut content = Vec::new();
+                file.read_to_end(&mut content).unwrap();
+                (file.name().to_string(), file.is_dir(), content)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nested_folders_are_read_back() {
+        let mut zip = ZipWriter::new(Vec::new());
+        zip.add_dir("docs", None).unwrap();
+        zip.add_dir("docs/2024/", None).unwrap();
+        zip.add_file("docs/2024/a.txt", 5, None, &mut "hello".as_bytes()).unwrap();
+        zip.add_file("docs/b.txt", 0, None, &mut "".as_bytes()).unwrap();
+        zip.add_file("c.txt", 3, None, &mut "x".repeat(100000).as_bytes()).unwrap();
+
+        let entries = read_back(zip.finish().unwrap());
+
+        let names: Vec<&str> = entries.iter().map(|(name, ..)| name.as_str()).collect();
+        assert_eq!(names, vec!["docs/", "docs/2024/", "docs/2024/a.txt", "docs/b.txt", "c.txt"]);
+        assert!(entries[0].1 && entries[1].1 && !entries[2].1);
+        assert_eq!(entries[2].2, b"hello");
+        assert!(entries[3].2.is_empty());
+        assert_eq!(entries[4].2, "x".repeat(100000).as_bytes());
+    }
+
+    // gigabytes would be needed for real sizes above 4 GB, big stat size and too many entries
+    // make zip64 records the same way
+    #[test]
+    fn zip64_entries_are_read_back() {
+        let mut zip = ZipWriter::new(Vec::new());
+        zip.add_file("big.bin", ZIP64_SIZE, None, &mut "big".as_bytes()).unwrap();
+        for i in 0..0xffff {
+            zip.add_dir(&format!("many/{}", i), None).unwrap();
+        }
+        zip.add_file("many/last.txt", 4, None, &mut "last".as_bytes()).unwrap();
+        let bytes = zip.finish().unwrap();
+
+        // local header of first file says it needs zip64
+        assert_eq!(bytes[4..6], VERSION_ZIP64.to_le_bytes());
+        let entries = read_back(bytes);
+
+        assert_eq!(entries.len(), 0xffff + 2);
+        assert_eq!((entries[0].0.as_str(), entries[0].2.as_slice()), ("big.bin", "big".as_bytes()));
+        assert_eq!(entries[1].0, "many/0/");
+        assert_eq!(entries[0xffff].0, "many/65534/");
+        let last = &entries[0x10000];
+        assert_eq!((last.0.as_str(), last.2.as_slice()), ("many/last.txt", "last".as_bytes()));
+    }
+}